@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS credential_secrets (
+    credential_id CHAR(36) NOT NULL PRIMARY KEY,
+    password VARCHAR(255) NOT NULL,
+    CONSTRAINT fk_credential_secrets FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS health_checks (
+    id BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    checked_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+);
@@ -0,0 +1,3 @@
+DROP INDEX idx_sessions_active ON sessions;
+DROP INDEX idx_sessions_expires_at ON sessions;
+DROP INDEX idx_sessions_credential_id ON sessions;
@@ -0,0 +1 @@
+ALTER TABLE credentials ADD COLUMN created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id CHAR(36) NOT NULL PRIMARY KEY DEFAULT (UUID()),
+    credential_id CHAR(36) NOT NULL,
+    key_hash VARCHAR(255) NOT NULL UNIQUE,
+    label VARCHAR(255),
+    scopes VARCHAR(1024) NOT NULL DEFAULT '',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    CONSTRAINT fk_api_keys_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_api_keys_credential_id ON api_keys (credential_id);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS password_resets (
+    id CHAR(36) NOT NULL PRIMARY KEY DEFAULT (UUID()),
+    credential_id CHAR(36) NOT NULL,
+    token_hash VARCHAR(255) NOT NULL UNIQUE,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    expires_at DATETIME(6) NOT NULL,
+    CONSTRAINT fk_password_resets_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_password_resets_credential_id ON password_resets (credential_id);
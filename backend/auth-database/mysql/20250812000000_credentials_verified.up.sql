@@ -0,0 +1 @@
+ALTER TABLE credentials ADD COLUMN verified BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS email_verifications (
+    id CHAR(36) NOT NULL PRIMARY KEY DEFAULT (UUID()),
+    credential_id CHAR(36) NOT NULL,
+    token_hash VARCHAR(255) NOT NULL UNIQUE,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+    expires_at DATETIME(6) NOT NULL,
+    CONSTRAINT fk_email_verifications_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_email_verifications_credential_id ON email_verifications (credential_id);
@@ -0,0 +1 @@
+ALTER TABLE credentials ADD COLUMN role VARCHAR NOT NULL DEFAULT 'user';
@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN ip VARCHAR;
+ALTER TABLE sessions ADD COLUMN user_agent VARCHAR;
+ALTER TABLE sessions ADD COLUMN is_new_device BOOLEAN NOT NULL DEFAULT FALSE;
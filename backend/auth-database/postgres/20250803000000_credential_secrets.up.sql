@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS credential_secrets (
+    credential_id UUID NOT NULL PRIMARY KEY,
+    password VARCHAR NOT NULL,
+    CONSTRAINT fk_credentials FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+ALTER TABLE credentials ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1,2 @@
+ALTER TABLE credentials DROP COLUMN locked_until;
+ALTER TABLE credentials DROP COLUMN failed_attempts;
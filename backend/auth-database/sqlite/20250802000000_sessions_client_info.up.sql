@@ -0,0 +1,3 @@
+ALTER TABLE sessions ADD COLUMN ip TEXT;
+ALTER TABLE sessions ADD COLUMN user_agent TEXT;
+ALTER TABLE sessions ADD COLUMN is_new_device BOOLEAN NOT NULL DEFAULT FALSE;
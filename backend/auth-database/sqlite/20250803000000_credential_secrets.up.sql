@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS credential_secrets (
+    credential_id TEXT NOT NULL PRIMARY KEY,
+    password TEXT NOT NULL,
+    FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
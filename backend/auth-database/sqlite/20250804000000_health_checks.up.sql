@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS health_checks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    checked_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
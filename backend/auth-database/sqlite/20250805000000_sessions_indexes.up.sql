@@ -0,0 +1,3 @@
+CREATE INDEX IF NOT EXISTS idx_sessions_credential_id ON sessions (credential_id);
+CREATE INDEX IF NOT EXISTS idx_sessions_expires_at ON sessions (expires_at);
+CREATE INDEX IF NOT EXISTS idx_sessions_active ON sessions (active);
@@ -0,0 +1,2 @@
+-- unix millis, set on insert; SQLite can't add a column with a non-constant default
+ALTER TABLE credentials ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+ALTER TABLE credentials ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+-- unix millis
+ALTER TABLE credentials ADD COLUMN locked_until INTEGER;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id TEXT NOT NULL PRIMARY KEY,
+    credential_id TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    label TEXT,
+    scopes TEXT NOT NULL DEFAULT '',
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    -- unix millis, set on insert
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_keys_credential_id ON api_keys (credential_id);
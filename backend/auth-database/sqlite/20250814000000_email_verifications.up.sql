@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS email_verifications (
+    id TEXT NOT NULL PRIMARY KEY,
+    credential_id TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    active BOOLEAN NOT NULL DEFAULT TRUE,
+    -- unix millis, set on insert
+    created_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    FOREIGN KEY (credential_id) REFERENCES credentials(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_verifications_credential_id ON email_verifications (credential_id);
@@ -1,2 +1,6 @@
+pub mod api_keys;
 pub mod credentials;
+pub mod email_verifications;
+pub mod password_resets;
+pub mod refresh_tokens;
 pub mod sessions;
@@ -0,0 +1,51 @@
+pub mod postgres;
+
+#[cfg(feature = "unit")]
+pub mod sqlite;
+
+#[cfg(feature = "mysql")]
+pub mod mysql;
+
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Long-lived key a machine client authenticates as its credential with. Only the hash
+/// of the key is stored.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct ApiKeysDAO {
+    pub id: Uuid,
+    pub credential_id: Uuid,
+    pub key_hash: String,
+    pub label: Option<String>,
+    /// Space separated.
+    pub scopes: String,
+    /// Cleared when the key is revoked.
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateApiKeysDAO {
+    pub credential_id: Uuid,
+    pub key_hash: String,
+    pub label: Option<String>,
+    pub scopes: String,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateApiKeysDAO {
+    pub label: Option<String>,
+    pub scopes: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApiKeysBy {
+    Id(Uuid),
+    KeyHash(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApiKeysWhere {
+    /// Active keys of the credential.
+    CredentialId(Uuid),
+}
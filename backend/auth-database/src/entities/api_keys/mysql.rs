@@ -0,0 +1,269 @@
+use crate::entities::api_keys::{
+    ApiKeysBy, ApiKeysDAO, ApiKeysWhere, CreateApiKeysDAO, UpdateApiKeysDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use sqlx::{MySql, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "api_keys";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct MySqlApiKeysDAO {
+    /// CHAR(36), MySQL has no uuid type
+    pub id: String,
+    pub credential_id: String,
+    pub key_hash: String,
+    pub label: Option<String>,
+    pub scopes: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<MySqlApiKeysDAO> for ApiKeysDAO {
+    type Error = DatabaseError;
+    fn try_from(value: MySqlApiKeysDAO) -> Result<Self, DatabaseError> {
+        Ok(ApiKeysDAO {
+            id: Uuid::from_str(&value.id)
+                .map_err(|_| DatabaseError::Unknown("Could not convert id to uuid".to_string()))?,
+            credential_id: Uuid::from_str(&value.credential_id).map_err(|_| {
+                DatabaseError::Unknown("Could not convert credential_id to uuid".to_string())
+            })?,
+            key_hash: value.key_hash,
+            label: value.label,
+            scopes: value.scopes,
+            active: value.active,
+            created_at: value.created_at,
+        })
+    }
+}
+
+async fn select_one(
+    tx: &mut Transaction<'_, MySql>,
+    key: &ApiKeysBy,
+) -> Result<Option<MySqlApiKeysDAO>, DatabaseError> {
+    let api_key = match key {
+        ApiKeysBy::Id(id) => {
+            sqlx::query_as::<_, MySqlApiKeysDAO>(checked("SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE id = ?;"))
+                .bind(id.to_string())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+        ApiKeysBy::KeyHash(hash) => {
+            sqlx::query_as::<_, MySqlApiKeysDAO>(checked("SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE key_hash = ?;"))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+    };
+
+    Ok(api_key)
+}
+
+/// MySQL has no `RETURNING`, so writes are followed by a read of the row in the same
+/// transaction.
+#[derive(Debug)]
+pub struct MySqlApiKeysRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for MySqlApiKeysRepository {
+    type Db = MySql;
+    type Entity = ApiKeysDAO;
+    type CreateInput = CreateApiKeysDAO;
+    type UpdateInput = UpdateApiKeysDAO;
+    type QueryOne = ApiKeysBy;
+    type QueryMany = ApiKeysWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let id = Uuid::new_v4();
+            sqlx::query(checked("INSERT INTO api_keys (id, credential_id, key_hash, label, scopes, created_at) VALUES (?, ?, ?, ?, ?, ?);"))
+                .bind(id.to_string())
+                .bind(input.credential_id.to_string())
+                .bind(input.key_hash)
+                .bind(input.label)
+                .bind(input.scopes)
+                // the column default is in the connection's time zone
+                .bind(Utc::now())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            let api_key = select_one(tx, &ApiKeysBy::Id(id))
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match &key {
+                ApiKeysBy::Id(id) => {
+                    sqlx::query(checked("UPDATE api_keys SET active = false WHERE id = ?;"))
+                        .bind(id.to_string())
+                        .execute(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                ApiKeysBy::KeyHash(hash) => sqlx::query(checked(
+                    "UPDATE api_keys SET active = false WHERE key_hash = ?;",
+                ))
+                .bind(hash)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let api_key = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match &key {
+                ApiKeysBy::Id(id) => sqlx::query(checked(
+                    "UPDATE api_keys SET label = ?, scopes = ? WHERE id = ?;",
+                ))
+                .bind(update.label)
+                .bind(update.scopes)
+                .bind(id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                ApiKeysBy::KeyHash(hash) => sqlx::query(checked(
+                    "UPDATE api_keys SET label = ?, scopes = ? WHERE key_hash = ?;",
+                ))
+                .bind(update.label)
+                .bind(update.scopes)
+                .bind(hash)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let api_key = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let api_key = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            select_one(tx, &key)
+                .await?
+                .map(Self::Entity::try_from)
+                .transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let api_keys = match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_as::<_, MySqlApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE credential_id = ? AND active ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(credential_id.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            api_keys.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let api_keys = match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_as::<_, MySqlApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE credential_id = ? AND active ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?;",
+                ))
+                .bind(credential_id.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            api_keys.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM api_keys WHERE credential_id = ? AND active;",
+                ))
+                .bind(credential_id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(MySqlApiKeysRepository::try_get(tx, key).await?.is_some())
+    }
+}
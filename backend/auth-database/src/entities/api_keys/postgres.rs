@@ -0,0 +1,208 @@
+use crate::entities::api_keys::{
+    ApiKeysBy, ApiKeysDAO, ApiKeysWhere, CreateApiKeysDAO, UpdateApiKeysDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::{Postgres, Transaction};
+
+const ENTITY: &str = "api_keys";
+
+#[derive(Debug)]
+pub struct PostgresApiKeysRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for PostgresApiKeysRepository {
+    type Db = Postgres;
+    type Entity = ApiKeysDAO;
+    type CreateInput = CreateApiKeysDAO;
+    type UpdateInput = UpdateApiKeysDAO;
+    type QueryOne = ApiKeysBy;
+    type QueryMany = ApiKeysWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            sqlx::query_as::<_, Self::Entity>(checked("INSERT INTO api_keys (credential_id, key_hash, label, scopes) VALUES ($1, $2, $3, $4) RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                .bind(input.credential_id)
+                .bind(input.key_hash)
+                .bind(input.label)
+                .bind(input.scopes)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match key {
+                ApiKeysBy::Id(id) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE api_keys SET active = false WHERE id = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(id)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                ApiKeysBy::KeyHash(hash) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE api_keys SET active = false WHERE key_hash = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(hash)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match key {
+                ApiKeysBy::Id(id) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE api_keys SET label = $2, scopes = $3 WHERE id = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(id)
+                        .bind(update.label)
+                        .bind(update.scopes)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                ApiKeysBy::KeyHash(hash) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE api_keys SET label = $2, scopes = $3 WHERE key_hash = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(hash)
+                        .bind(update.label)
+                        .bind(update.scopes)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            match key {
+                ApiKeysBy::Id(id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE id = $1;",
+                ))
+                .bind(id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                ApiKeysBy::KeyHash(hash) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE key_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            match key {
+                ApiKeysBy::Id(id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE id = $1;",
+                ))
+                .bind(id)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                ApiKeysBy::KeyHash(hash) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE key_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE credential_id = $1 AND active ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(credential_id)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE credential_id = $1 AND active ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(credential_id)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM api_keys WHERE credential_id = $1 AND active;",
+                ))
+                .bind(credential_id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(PostgresApiKeysRepository::try_get(tx, key).await?.is_some())
+    }
+}
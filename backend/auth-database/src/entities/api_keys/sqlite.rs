@@ -0,0 +1,259 @@
+use crate::entities::api_keys::{
+    ApiKeysBy, ApiKeysDAO, ApiKeysWhere, CreateApiKeysDAO, UpdateApiKeysDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "api_keys";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct SqliteApiKeysDAO {
+    pub id: String,
+    pub credential_id: String,
+    pub key_hash: String,
+    pub label: Option<String>,
+    pub scopes: String,
+    pub active: bool,
+    /// unix millis
+    pub created_at: i64,
+}
+
+impl TryFrom<SqliteApiKeysDAO> for ApiKeysDAO {
+    type Error = DatabaseError;
+    fn try_from(value: SqliteApiKeysDAO) -> Result<Self, DatabaseError> {
+        Ok(ApiKeysDAO {
+            id: Uuid::from_str(&value.id)
+                .map_err(|_| DatabaseError::Unknown("Could not convert id to uuid".to_string()))?,
+            credential_id: Uuid::from_str(&value.credential_id).map_err(|_| {
+                DatabaseError::Unknown("Could not convert credential_id to uuid".to_string())
+            })?,
+            key_hash: value.key_hash,
+            label: value.label,
+            scopes: value.scopes,
+            active: value.active,
+            created_at: DateTime::from_timestamp_millis(value.created_at).ok_or(
+                DatabaseError::Unknown("Could not convert created_at to DateTime<Utc>".to_string()),
+            )?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SqliteApiKeysRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for SqliteApiKeysRepository {
+    type Db = Sqlite;
+    type Entity = ApiKeysDAO;
+    type CreateInput = CreateApiKeysDAO;
+    type UpdateInput = UpdateApiKeysDAO;
+    type QueryOne = ApiKeysBy;
+    type QueryMany = ApiKeysWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let api_key = sqlx::query_as::<_, SqliteApiKeysDAO>(checked("INSERT INTO api_keys (id, credential_id, key_hash, label, scopes, created_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                .bind(Uuid::new_v4().to_string())
+                .bind(input.credential_id.to_string())
+                .bind(input.key_hash)
+                .bind(input.label)
+                .bind(input.scopes)
+                .bind(Utc::now().timestamp_millis())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            let api_key = match key {
+                ApiKeysBy::Id(id) => {
+                    sqlx::query_as::<_, SqliteApiKeysDAO>(checked("UPDATE api_keys SET active = false WHERE id = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(id.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                ApiKeysBy::KeyHash(hash) => {
+                    sqlx::query_as::<_, SqliteApiKeysDAO>(checked("UPDATE api_keys SET active = false WHERE key_hash = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(hash)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            let api_key = match key {
+                ApiKeysBy::Id(id) => {
+                    sqlx::query_as::<_, SqliteApiKeysDAO>(checked("UPDATE api_keys SET label = $2, scopes = $3 WHERE id = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(id.to_string())
+                        .bind(update.label)
+                        .bind(update.scopes)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                ApiKeysBy::KeyHash(hash) => {
+                    sqlx::query_as::<_, SqliteApiKeysDAO>(checked("UPDATE api_keys SET label = $2, scopes = $3 WHERE key_hash = $1 RETURNING id, credential_id, key_hash, label, scopes, active, created_at;"))
+                        .bind(hash)
+                        .bind(update.label)
+                        .bind(update.scopes)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let api_key = match key {
+                ApiKeysBy::Id(id) => sqlx::query_as::<_, SqliteApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE id = $1;",
+                ))
+                .bind(id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                ApiKeysBy::KeyHash(hash) => sqlx::query_as::<_, SqliteApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE key_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            Self::Entity::try_from(api_key)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            let api_key = match key {
+                ApiKeysBy::Id(id) => sqlx::query_as::<_, SqliteApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE id = $1;",
+                ))
+                .bind(id.to_string())
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                ApiKeysBy::KeyHash(hash) => sqlx::query_as::<_, SqliteApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE key_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            api_key.map(Self::Entity::try_from).transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let api_keys = match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_as::<_, SqliteApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE credential_id = $1 AND active ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(credential_id.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            api_keys.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let api_keys = match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_as::<_, SqliteApiKeysDAO>(checked(
+                    "SELECT id, credential_id, key_hash, label, scopes, active, created_at FROM api_keys WHERE credential_id = $1 AND active ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(credential_id.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            api_keys.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                ApiKeysWhere::CredentialId(credential_id) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM api_keys WHERE credential_id = $1 AND active;",
+                ))
+                .bind(credential_id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(SqliteApiKeysRepository::try_get(tx, key).await?.is_some())
+    }
+}
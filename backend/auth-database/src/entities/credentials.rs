@@ -1,31 +1,79 @@
-use sqlx::types::Uuid;
+use std::{fmt, str::FromStr};
+
+use database::traits::{DatabaseError, EntityRepository};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{
+    Database, Decode, Encode, Transaction, Type, encode::IsNull, error::BoxDynError, types::Uuid,
+};
 
 pub mod postgres;
 
 #[cfg(feature = "unit")]
 pub mod sqlite;
 
+#[cfg(feature = "mysql")]
+pub mod mysql;
+
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CredentialsDAO {
     pub id: Uuid,
     pub email: String,
     pub password: String,
     pub active: bool,
+    pub role: Role,
+    /// Consecutive wrong passwords since the last successful sign-in or lock.
+    pub failed_attempts: i32,
+    /// Sign-ins are refused until then, even with the right password.
+    pub locked_until: Option<DateTime<Utc>>,
+    /// Bumped by every [`EntityRepository::update`], see [`UpdateCredentialsDAO::version`].
+    pub version: i32,
+    /// Whether the owner proved they control the email, see [`EmailVerification`].
+    pub verified: bool,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CreateCredentialsDAO {
     pub email: String,
     pub password: String,
+    pub role: Role,
+    pub password_storage: PasswordStorage,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct UpdateCredentialsDAO {
     pub password: String,
     pub active: bool,
+    pub role: Role,
+    pub password_storage: PasswordStorage,
+    /// Version of the credential the update was based on. The update fails with
+    /// [`DatabaseError::Conflict`] if the row was changed since, instead of overwriting it.
+    pub version: i32,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Where writes put the password hash. Reads look in `credential_secrets` first and
+/// fall back to `credentials.password`, so both layouts can coexist.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PasswordStorage {
+    /// In `credentials.password`.
+    #[default]
+    Inline,
+    /// In `credential_secrets`, leaving `credentials.password` empty so the table can be
+    /// exposed to reporting without hashes.
+    Separate,
+}
+
+impl PasswordStorage {
+    /// Splits a hash into the `credentials.password` value and the `credential_secrets`
+    /// one, if any.
+    pub fn split(self, password: String) -> (String, Option<String>) {
+        match self {
+            PasswordStorage::Inline => (password, None),
+            PasswordStorage::Separate => (String::new(), Some(password)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum CredentialsBy {
     Id(Uuid),
     Email(String),
@@ -35,3 +83,140 @@ pub enum CredentialsBy {
 pub enum CredentialsWhere {
     Active(bool),
 }
+
+/// Bookkeeping of failed sign-ins used to lock credentials under brute force.
+#[database::async_trait::async_trait]
+pub trait SignInAttempts: EntityRepository {
+    /// Counts a wrong password. Reaching `threshold` consecutive failures locks the
+    /// credential until `lock_until` and starts the count over. Returns the credential's
+    /// lock afterwards, if any.
+    async fn record_failure(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+        threshold: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, DatabaseError>;
+
+    /// Clears the failure count and any lock after a successful sign-in.
+    async fn reset_failures(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<(), DatabaseError>;
+}
+
+/// Permanent removal, for erasure requests. [`EntityRepository::delete`] only deactivates.
+#[database::async_trait::async_trait]
+pub trait HardDelete: EntityRepository {
+    /// Deletes the credential row, the rows referencing it go with it through their
+    /// `ON DELETE CASCADE`. Returns the credential as it was.
+    async fn hard_delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError>;
+}
+
+/// Marks credentials whose owner proved they control the email.
+#[database::async_trait::async_trait]
+pub trait EmailVerification: EntityRepository {
+    /// Sets `verified`, returning `false` when no credential has the id.
+    async fn mark_verified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError>;
+
+    /// Clears `verified`, for a credential handed to a new owner. Returns `false` when no
+    /// credential has the id.
+    async fn mark_unverified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError>;
+}
+
+/// Authorization level of a credential, stored as lowercase text.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Role {
+    #[default]
+    User,
+    Pending,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::User, Role::Pending, Role::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Pending => "pending",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownRole(pub String);
+
+impl fmt::Display for UnknownRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known: Vec<&str> = Role::ALL.iter().map(Role::as_str).collect();
+        write!(
+            f,
+            "Unknown role `{}`, expected one of: {}",
+            self.0,
+            known.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownRole {}
+
+impl FromStr for Role {
+    type Err = UnknownRole;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.as_str() == value)
+            .ok_or_else(|| UnknownRole(value.to_string()))
+    }
+}
+
+impl<DB: Database> Type<DB> for Role
+where
+    str: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <str as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <str as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: Database> Encode<'q, DB> for Role
+where
+    &'q str: Encode<'q, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, BoxDynError> {
+        <&str as Encode<'q, DB>>::encode(self.as_str(), buf)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for Role
+where
+    &'r str: Decode<'r, DB>,
+{
+    fn decode(value: <DB as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(<&str as Decode<'r, DB>>::decode(value)?.parse()?)
+    }
+}
@@ -0,0 +1,506 @@
+use crate::entities::credentials::{
+    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsWhere, EmailVerification,
+    HardDelete, Role, SignInAttempts, UpdateCredentialsDAO,
+};
+
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{MySql, Transaction, types::Uuid};
+
+use std::str::FromStr;
+
+const ENTITY: &str = "credentials";
+
+/// Writes the hash to `credential_secrets`, or clears it there when the hash is kept
+/// on the `credentials` row.
+async fn store_secret(
+    tx: &mut Transaction<'_, MySql>,
+    credential: &mut CredentialsDAO,
+    secret: Option<String>,
+) -> Result<(), DatabaseError> {
+    match secret {
+        Some(secret) => {
+            sqlx::query(checked("INSERT INTO credential_secrets (credential_id, password) VALUES (?, ?) ON DUPLICATE KEY UPDATE password = VALUES(password);"))
+                .bind(credential.id.to_string())
+                .bind(&secret)
+                .execute(&mut **tx)
+                .await?;
+            credential.password = secret;
+        }
+        None => {
+            sqlx::query(checked(
+                "DELETE FROM credential_secrets WHERE credential_id = ?;",
+            ))
+            .bind(credential.id.to_string())
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the row's password with the one in `credential_secrets`, if any.
+async fn load_secret(
+    tx: &mut Transaction<'_, MySql>,
+    mut credential: CredentialsDAO,
+) -> Result<CredentialsDAO, DatabaseError> {
+    let secret = sqlx::query_scalar::<_, String>(checked(
+        "SELECT password FROM credential_secrets WHERE credential_id = ?;",
+    ))
+    .bind(credential.id.to_string())
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if let Some(secret) = secret {
+        credential.password = secret;
+    }
+
+    Ok(credential)
+}
+
+/// Error for an update that matched no row, `Conflict` if the credential exists at
+/// another version.
+async fn stale_update(tx: &mut Transaction<'_, MySql>, key: CredentialsBy) -> DatabaseError {
+    match MySqlCredentialsRepository::exists(tx, key).await {
+        Ok(true) => DatabaseError::Conflict("credential version changed".to_string()),
+        Ok(false) => DatabaseError::NotFound("credential".to_string()),
+        Err(e) => e,
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct MySqlCredentialsDAO {
+    /// CHAR(36), MySQL has no uuid type
+    pub id: String,
+    pub email: String,
+    pub password: String,
+    pub active: bool,
+    pub role: Role,
+    pub failed_attempts: i32,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub version: i32,
+    pub verified: bool,
+}
+
+impl From<CredentialsDAO> for MySqlCredentialsDAO {
+    fn from(value: CredentialsDAO) -> Self {
+        MySqlCredentialsDAO {
+            id: value.id.to_string(),
+            email: value.email,
+            password: value.password,
+            active: value.active,
+            role: value.role,
+            failed_attempts: value.failed_attempts,
+            locked_until: value.locked_until,
+            version: value.version,
+            verified: value.verified,
+        }
+    }
+}
+
+impl TryFrom<MySqlCredentialsDAO> for CredentialsDAO {
+    type Error = DatabaseError;
+    fn try_from(value: MySqlCredentialsDAO) -> Result<Self, Self::Error> {
+        Ok(CredentialsDAO {
+            id: Uuid::from_str(&value.id)
+                .map_err(|_| DatabaseError::Unknown("Could not convert id to uuid".to_string()))?,
+            email: value.email,
+            password: value.password,
+            active: value.active,
+            role: value.role,
+            failed_attempts: value.failed_attempts,
+            locked_until: value.locked_until,
+            version: value.version,
+            verified: value.verified,
+        })
+    }
+}
+
+/// MySQL has no `RETURNING`, so writes are followed by a read of the row in the same
+/// transaction.
+#[derive(Debug)]
+pub struct MySqlCredentialsRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for MySqlCredentialsRepository {
+    type Db = MySql;
+    type Entity = CredentialsDAO;
+    type CreateInput = CreateCredentialsDAO;
+    type UpdateInput = UpdateCredentialsDAO;
+    type QueryOne = CredentialsBy;
+    type QueryMany = CredentialsWhere;
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(MySqlCredentialsRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let (password, secret) = input.password_storage.split(input.password);
+            let id = Uuid::new_v4();
+            sqlx::query(checked(
+                "INSERT INTO credentials (id, email, password, role, created_at) VALUES (?, ?, ?, ?, ?);",
+            ))
+            .bind(id.to_string())
+            .bind(input.email)
+            .bind(password)
+            .bind(input.role)
+            .bind(Utc::now())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            let credential = sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                "SELECT id, email, password, active, role, failed_attempts, locked_until, version, verified FROM credentials WHERE id = ?;",
+            ))
+            .bind(id.to_string())
+            .fetch_one(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            let mut credential = Self::Entity::try_from(credential)?;
+            if secret.is_some() {
+                store_secret(tx, &mut credential, secret).await?;
+            }
+
+            Ok(credential)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            let credential = match key {
+                CredentialsBy::Id(uuid) => {
+                    sqlx::query(checked("UPDATE credentials SET active = false WHERE id = ?;"))
+                        .bind(uuid.to_string())
+                        .execute(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?;
+
+                    sqlx::query_as::<_, MySqlCredentialsDAO>(checked("SELECT id, email, password, active, role, failed_attempts, locked_until, version, verified FROM credentials WHERE id = ?;"))
+                        .bind(uuid.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                CredentialsBy::Email(email) => {
+                    sqlx::query(checked("UPDATE credentials SET active = false WHERE email = ?;"))
+                        .bind(&email)
+                        .execute(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?;
+
+                    sqlx::query_as::<_, MySqlCredentialsDAO>(checked("SELECT id, email, password, active, role, failed_attempts, locked_until, version, verified FROM credentials WHERE email = ?;"))
+                        .bind(email)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            load_secret(tx, Self::Entity::try_from(credential)?).await
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            let (password, secret) = update.password_storage.split(update.password);
+            let result = match &key {
+                CredentialsBy::Id(id) => sqlx::query(
+                    checked("UPDATE credentials SET password = ?, active = ?, role = ?, version = version + 1 WHERE id = ? AND version = ?;"),
+                )
+                    .bind(&password)
+                    .bind(update.active)
+                    .bind(update.role)
+                    .bind(id.to_string())
+                    .bind(update.version)
+                    .execute(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query(
+                    checked("UPDATE credentials SET password = ?, active = ?, role = ?, version = version + 1 WHERE email = ? AND version = ?;"),
+                )
+                    .bind(&password)
+                    .bind(update.active)
+                    .bind(update.role)
+                    .bind(email)
+                    .bind(update.version)
+                    .execute(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+            };
+
+            if result.rows_affected() == 0 {
+                return Err(stale_update(tx, key).await);
+            }
+
+            let credential = match &key {
+                CredentialsBy::Id(id) => sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                    "SELECT id, email, password, active, role, failed_attempts, locked_until, version, verified FROM credentials WHERE id = ?;",
+                ))
+                .bind(id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                    "SELECT id, email, password, active, role, failed_attempts, locked_until, version, verified FROM credentials WHERE email = ?;",
+                ))
+                .bind(email)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let mut credential = Self::Entity::try_from(credential)?;
+            store_secret(tx, &mut credential, secret).await?;
+
+            Ok(credential)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let credential = match key {
+                CredentialsBy::Id(id) => sqlx::query_as::<_, MySqlCredentialsDAO>(
+                    checked("SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.id = ? LIMIT 1;"),
+                )
+                .bind(id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query_as::<_, MySqlCredentialsDAO>(
+                    checked("SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.email = ? LIMIT 1;"),
+                )
+                .bind(email)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            Self::Entity::try_from(credential)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            let maybe_credential = match key {
+                CredentialsBy::Id(uuid) => sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.id = ?;",
+                ))
+                .bind(uuid.to_string())
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.email = ?;",
+                ))
+                .bind(email)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            maybe_credential.map(Self::Entity::try_from).transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let credentials = match key {
+                CredentialsWhere::Active(active) => sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.active = ? ORDER BY c.created_at DESC, c.id DESC;",
+                ))
+                .bind(active)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            credentials.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let credentials = match key {
+                CredentialsWhere::Active(active) => sqlx::query_as::<_, MySqlCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.active = ? ORDER BY c.created_at DESC, c.id DESC LIMIT ? OFFSET ?;",
+                ))
+                .bind(active)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            credentials.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                CredentialsWhere::Active(active) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM credentials WHERE active = ?;",
+                ))
+                .bind(active)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+}
+
+#[database::async_trait::async_trait]
+impl SignInAttempts for MySqlCredentialsRepository {
+    async fn record_failure(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+        threshold: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, DatabaseError> {
+        observe(ENTITY, "record_failure", async move {
+            // MySQL applies assignments left to right, so locked_until has to be set
+            // before failed_attempts changes under it.
+            sqlx::query(checked("UPDATE credentials SET locked_until = CASE WHEN failed_attempts + 1 >= ? THEN ? ELSE locked_until END, failed_attempts = CASE WHEN failed_attempts + 1 >= ? THEN 0 ELSE failed_attempts + 1 END WHERE id = ?;"))
+                .bind(threshold)
+                .bind(lock_until)
+                .bind(threshold)
+                .bind(id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            sqlx::query_scalar::<_, Option<DateTime<Utc>>>(checked(
+                "SELECT locked_until FROM credentials WHERE id = ?;",
+            ))
+            .bind(id.to_string())
+            .fetch_one(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn reset_failures(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        observe(ENTITY, "reset_failures", async move {
+            sqlx::query(checked(
+                "UPDATE credentials SET failed_attempts = 0, locked_until = NULL WHERE id = ?;",
+            ))
+            .bind(id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[database::async_trait::async_trait]
+impl EmailVerification for MySqlCredentialsRepository {
+    async fn mark_verified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        observe(ENTITY, "mark_verified", async move {
+            let result = sqlx::query(checked(
+                "UPDATE credentials SET verified = true WHERE id = ?;",
+            ))
+            .bind(id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    async fn mark_unverified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        observe(ENTITY, "mark_unverified", async move {
+            let result = sqlx::query(checked(
+                "UPDATE credentials SET verified = false WHERE id = ?;",
+            ))
+            .bind(id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+}
+
+#[database::async_trait::async_trait]
+impl HardDelete for MySqlCredentialsRepository {
+    async fn hard_delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        observe(ENTITY, "hard_delete", async move {
+            let credential = MySqlCredentialsRepository::get(tx, key).await?;
+
+            sqlx::query(checked("DELETE FROM credentials WHERE id = ?;"))
+                .bind(credential.id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(credential)
+        })
+        .await
+    }
+}
@@ -1,10 +1,75 @@
-use database::traits::{DatabaseError, EntityRepository};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::{Postgres, Transaction};
 
 use crate::entities::credentials::{
-    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsWhere, UpdateCredentialsDAO,
+    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsWhere, EmailVerification,
+    HardDelete, SignInAttempts, UpdateCredentialsDAO,
 };
 
+const ENTITY: &str = "credentials";
+
+/// Error for an update that matched no row, `Conflict` if the credential exists at
+/// another version.
+async fn stale_update(tx: &mut Transaction<'_, Postgres>, key: CredentialsBy) -> DatabaseError {
+    match PostgresCredentialsRepository::exists(tx, key).await {
+        Ok(true) => DatabaseError::Conflict("credential version changed".to_string()),
+        Ok(false) => DatabaseError::NotFound("credential".to_string()),
+        Err(e) => e,
+    }
+}
+
+/// Writes the hash to `credential_secrets`, or clears it there when the hash is kept
+/// on the `credentials` row.
+async fn store_secret(
+    tx: &mut Transaction<'_, Postgres>,
+    credential: &mut CredentialsDAO,
+    secret: Option<String>,
+) -> Result<(), DatabaseError> {
+    match secret {
+        Some(secret) => {
+            sqlx::query(checked("INSERT INTO credential_secrets (credential_id, password) VALUES ($1, $2) ON CONFLICT (credential_id) DO UPDATE SET password = excluded.password;"))
+                .bind(credential.id)
+                .bind(&secret)
+                .execute(&mut **tx)
+                .await?;
+            credential.password = secret;
+        }
+        None => {
+            sqlx::query(checked(
+                "DELETE FROM credential_secrets WHERE credential_id = $1;",
+            ))
+            .bind(credential.id)
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the row's password with the one in `credential_secrets`, if any.
+async fn load_secret(
+    tx: &mut Transaction<'_, Postgres>,
+    mut credential: CredentialsDAO,
+) -> Result<CredentialsDAO, DatabaseError> {
+    let secret = sqlx::query_scalar::<_, String>(checked(
+        "SELECT password FROM credential_secrets WHERE credential_id = $1;",
+    ))
+    .bind(credential.id)
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if let Some(secret) = secret {
+        credential.password = secret;
+    }
+
+    Ok(credential)
+}
+
 #[derive(Debug)]
 pub struct PostgresCredentialsRepository;
 
@@ -21,113 +86,200 @@ impl EntityRepository for PostgresCredentialsRepository {
         tx: &mut Transaction<'_, Self::Db>,
         input: Self::CreateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        sqlx::query_as::<_, Self::Entity>("INSERT INTO credentials (email, password) VALUES ($1, $2) RETURNING id, email, password, active;")
-            .bind(input.email)
-            .bind(input.password)
-            .fetch_one(&mut **tx)
-            .await
-            .map_err(DatabaseError::from)
+        observe(ENTITY, "insert", async move {
+            let (password, secret) = input.password_storage.split(input.password);
+            let mut credential = sqlx::query_as::<_, Self::Entity>(checked("INSERT INTO credentials (email, password, role) VALUES ($1, $2, $3) RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"))
+                .bind(input.email)
+                .bind(password)
+                .bind(input.role)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            if secret.is_some() {
+                store_secret(tx, &mut credential, secret).await?;
+            }
+
+            Ok(credential)
+        })
+        .await
     }
 
     async fn delete(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Self::Entity, DatabaseError> {
-        match key {
-            CredentialsBy::Id(uuid) => {
-                sqlx::query_as::<_, Self::Entity>("UPDATE credentials SET active = false WHERE id = $1 RETURNING id, email, password, active;")
-                    .bind(uuid)
-                    .fetch_one(&mut **tx)
+        observe(ENTITY, "delete", async move {
+            let credential = match key {
+                CredentialsBy::Id(uuid) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE credentials SET active = false WHERE id = $1 RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"))
+                        .bind(uuid)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                CredentialsBy::Email(email) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE credentials SET active = false WHERE email = $1 RETURNING id, password, email, active, role, failed_attempts, locked_until, version, verified;"))
+                        .bind(email)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+
+            };
+
+            load_secret(tx, credential).await
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            let (password, secret) = update.password_storage.split(update.password);
+            let credential = match &key {
+                CredentialsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(
+                    checked("UPDATE credentials SET password = $2, active = $3, role = $4, version = version + 1 WHERE id = $1 AND version = $5 RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"),
+                )
+                    .bind(id)
+                    .bind(&password)
+                    .bind(update.active)
+                    .bind(update.role)
+                    .bind(update.version)
+                    .fetch_optional(&mut **tx)
                     .await
-                    .map_err(DatabaseError::from)
-            },
-            CredentialsBy::Email(email) => {
-                sqlx::query_as::<_, Self::Entity>("UPDATE credentials SET active = false WHERE email = $1 RETURNING id, password, email, active;")
+                    .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query_as::<_, Self::Entity>(
+                    checked("UPDATE credentials SET password = $2, active = $3, role = $4, version = version + 1 WHERE email = $1 AND version = $5 RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"),
+                )
                     .bind(email)
-                    .fetch_one(&mut **tx)
+                    .bind(&password)
+                    .bind(update.active)
+                    .bind(update.role)
+                    .bind(update.version)
+                    .fetch_optional(&mut **tx)
                     .await
-                    .map_err(DatabaseError::from)
-            },
+                    .map_err(DatabaseError::from)?,
+            };
 
-        }
+            let Some(mut credential) = credential else {
+                return Err(stale_update(tx, key).await);
+            };
+
+            store_secret(tx, &mut credential, secret).await?;
+            Ok(credential)
+        })
+        .await
     }
 
-    async fn update(
+    async fn get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
-        update: Self::UpdateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        match key {
-            CredentialsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(
-                "UPDATE credentials SET password = $2, active = $3 WHERE id = $1 RETURNING id, email, password, active;",
-            )
+        observe(ENTITY, "get", async move {
+            match key {
+                CredentialsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(
+                    checked("SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.id = $1 LIMIT 1;"),
+                )
                 .bind(id)
-                .bind(update.password)
-                .bind(update.active)
                 .fetch_one(&mut **tx)
                 .await
                 .map_err(DatabaseError::from),
-            CredentialsBy::Email(email) => sqlx::query_as::<_, Self::Entity>(
-                "UPDATE credentials SET password = $2, active = $3 WHERE email = $1 RETURNING id, email, password, active;",
-            )
+                CredentialsBy::Email(email) => sqlx::query_as::<_, Self::Entity>(
+                    checked("SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.email = $1 LIMIT 1;"),
+                )
                 .bind(email)
-                .bind(update.password)
-                .bind(update.active)
                 .fetch_one(&mut **tx)
                 .await
                 .map_err(DatabaseError::from),
-        }
-    }
-
-    async fn get(
-        tx: &mut Transaction<'_, Self::Db>,
-        key: Self::QueryOne,
-    ) -> Result<Self::Entity, DatabaseError> {
-        match key {
-            CredentialsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(
-                "SELECT id, email, password, active FROM credentials WHERE id = $1 LIMIT 1;",
-            )
-            .bind(id)
-            .fetch_one(&mut **tx)
-            .await
-            .map_err(DatabaseError::from),
-            CredentialsBy::Email(email) => sqlx::query_as::<_, Self::Entity>(
-                "SELECT id, email, password, active FROM credentials WHERE email = $1 LIMIT 1;",
-            )
-            .bind(email)
-            .fetch_one(&mut **tx)
-            .await
-            .map_err(DatabaseError::from),
-        }
+            }
+        })
+        .await
     }
 
     async fn try_get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Option<Self::Entity>, DatabaseError> {
-        match key {
-            CredentialsBy::Id(uuid) => {
-                sqlx::query_as("SELECT id, email, password, active FROM credentials WHERE id = $1;")
-                    .bind(uuid)
-                    .fetch_optional(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)
+        observe(ENTITY, "try_get", async move {
+            match key {
+                CredentialsBy::Id(uuid) => sqlx::query_as(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.id = $1;",
+                ))
+                .bind(uuid)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                CredentialsBy::Email(email) => sqlx::query_as(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.email = $1;",
+                ))
+                .bind(email)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
             }
-            CredentialsBy::Email(email) => sqlx::query_as(
-                "SELECT id, email, password, active FROM credentials WHERE email = $1;",
-            )
-            .bind(email)
-            .fetch_optional(&mut **tx)
-            .await
-            .map_err(DatabaseError::from),
-        }
+        })
+        .await
     }
 
     async fn get_all(
-        _tx: &mut Transaction<'_, Self::Db>,
-        _key: Self::QueryMany,
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
     ) -> Result<Vec<Self::Entity>, DatabaseError> {
-        todo!()
+        observe(ENTITY, "get_all", async move {
+            match key {
+                CredentialsWhere::Active(active) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.active = $1 ORDER BY c.created_at DESC, c.id DESC;",
+                ))
+                .bind(active)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            match key {
+                CredentialsWhere::Active(active) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.active = $1 ORDER BY c.created_at DESC, c.id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(active)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                CredentialsWhere::Active(active) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM credentials WHERE active = $1;",
+                ))
+                .bind(active)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
     }
 
     async fn exists(
@@ -139,3 +291,102 @@ impl EntityRepository for PostgresCredentialsRepository {
             .is_some())
     }
 }
+
+#[database::async_trait::async_trait]
+impl SignInAttempts for PostgresCredentialsRepository {
+    async fn record_failure(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+        threshold: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, DatabaseError> {
+        observe(ENTITY, "record_failure", async move {
+            sqlx::query_scalar::<_, Option<DateTime<Utc>>>(checked("UPDATE credentials SET failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END, locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $3 ELSE locked_until END WHERE id = $1 RETURNING locked_until;"))
+                .bind(id)
+                .bind(threshold)
+                .bind(lock_until)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn reset_failures(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        observe(ENTITY, "reset_failures", async move {
+            sqlx::query(checked(
+                "UPDATE credentials SET failed_attempts = 0, locked_until = NULL WHERE id = $1;",
+            ))
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[database::async_trait::async_trait]
+impl EmailVerification for PostgresCredentialsRepository {
+    async fn mark_verified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        observe(ENTITY, "mark_verified", async move {
+            let result = sqlx::query(checked(
+                "UPDATE credentials SET verified = true WHERE id = $1;",
+            ))
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    async fn mark_unverified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        observe(ENTITY, "mark_unverified", async move {
+            let result = sqlx::query(checked(
+                "UPDATE credentials SET verified = false WHERE id = $1;",
+            ))
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+}
+
+#[database::async_trait::async_trait]
+impl HardDelete for PostgresCredentialsRepository {
+    async fn hard_delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        observe(ENTITY, "hard_delete", async move {
+            let credential = PostgresCredentialsRepository::get(tx, key).await?;
+
+            sqlx::query(checked("DELETE FROM credentials WHERE id = $1;"))
+                .bind(credential.id)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(credential)
+        })
+        .await
+    }
+}
@@ -1,9 +1,13 @@
 // #[cfg(feature = "unit")]
 use crate::entities::credentials::{
-    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsWhere, UpdateCredentialsDAO,
+    CreateCredentialsDAO, CredentialsBy, CredentialsDAO, CredentialsWhere, EmailVerification,
+    HardDelete, Role, SignInAttempts, UpdateCredentialsDAO,
 };
 
-use database::traits::{DatabaseError, EntityRepository};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::{Transaction, types::Uuid};
 
 use std::str::FromStr;
@@ -11,12 +15,78 @@ use std::str::FromStr;
 // #[cfg(feature = "unit")]
 use sqlx::Sqlite;
 
+const ENTITY: &str = "credentials";
+
+/// Writes the hash to `credential_secrets`, or clears it there when the hash is kept
+/// on the `credentials` row.
+async fn store_secret(
+    tx: &mut Transaction<'_, Sqlite>,
+    credential: &mut CredentialsDAO,
+    secret: Option<String>,
+) -> Result<(), DatabaseError> {
+    match secret {
+        Some(secret) => {
+            sqlx::query(checked("INSERT INTO credential_secrets (credential_id, password) VALUES ($1, $2) ON CONFLICT (credential_id) DO UPDATE SET password = excluded.password;"))
+                .bind(credential.id.to_string())
+                .bind(&secret)
+                .execute(&mut **tx)
+                .await?;
+            credential.password = secret;
+        }
+        None => {
+            sqlx::query(checked(
+                "DELETE FROM credential_secrets WHERE credential_id = $1;",
+            ))
+            .bind(credential.id.to_string())
+            .execute(&mut **tx)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces the row's password with the one in `credential_secrets`, if any.
+async fn load_secret(
+    tx: &mut Transaction<'_, Sqlite>,
+    mut credential: CredentialsDAO,
+) -> Result<CredentialsDAO, DatabaseError> {
+    let secret = sqlx::query_scalar::<_, String>(checked(
+        "SELECT password FROM credential_secrets WHERE credential_id = $1;",
+    ))
+    .bind(credential.id.to_string())
+    .fetch_optional(&mut **tx)
+    .await?;
+
+    if let Some(secret) = secret {
+        credential.password = secret;
+    }
+
+    Ok(credential)
+}
+
+/// Error for an update that matched no row, `Conflict` if the credential exists at
+/// another version.
+async fn stale_update(tx: &mut Transaction<'_, Sqlite>, key: CredentialsBy) -> DatabaseError {
+    match SqliteCredentialsRepository::exists(tx, key).await {
+        Ok(true) => DatabaseError::Conflict("credential version changed".to_string()),
+        Ok(false) => DatabaseError::NotFound("credential".to_string()),
+        Err(e) => e,
+    }
+}
+
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct SqliteCredentialsDAO {
     pub id: String,
     pub email: String,
     pub password: String,
     pub active: bool,
+    pub role: Role,
+    pub failed_attempts: i32,
+    /// unix millis
+    pub locked_until: Option<i64>,
+    pub version: i32,
+    pub verified: bool,
 }
 
 impl From<CredentialsDAO> for SqliteCredentialsDAO {
@@ -26,6 +96,11 @@ impl From<CredentialsDAO> for SqliteCredentialsDAO {
             email: value.email,
             password: value.password,
             active: value.active,
+            role: value.role,
+            failed_attempts: value.failed_attempts,
+            locked_until: value.locked_until.map(|until| until.timestamp_millis()),
+            version: value.version,
+            verified: value.verified,
         }
     }
 }
@@ -39,6 +114,18 @@ impl TryFrom<SqliteCredentialsDAO> for CredentialsDAO {
             email: value.email,
             password: value.password,
             active: value.active,
+            role: value.role,
+            failed_attempts: value.failed_attempts,
+            locked_until: value
+                .locked_until
+                .map(|until| {
+                    DateTime::from_timestamp_millis(until).ok_or(DatabaseError::Unknown(
+                        "Could not convert locked_until to a date".to_string(),
+                    ))
+                })
+                .transpose()?,
+            version: value.version,
+            verified: value.verified,
         })
     }
 }
@@ -69,42 +156,56 @@ impl EntityRepository for SqliteCredentialsRepository {
         tx: &mut Transaction<'_, Self::Db>,
         input: Self::CreateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        let credential = sqlx::query_as::<_, SqliteCredentialsDAO>(
-            "INSERT INTO credentials (id, email, password) VALUES ($1, $2, $3) RETURNING id, email, password, active;",
-        )
-        .bind(Uuid::new_v4().to_string())
-        .bind(input.email)
-        .bind(input.password)
-        .fetch_one(&mut **tx)
-        .await
-        .map_err(DatabaseError::from)?;
+        observe(ENTITY, "insert", async move {
+            let (password, secret) = input.password_storage.split(input.password);
+            let credential = sqlx::query_as::<_, SqliteCredentialsDAO>(
+                checked("INSERT INTO credentials (id, email, password, role, created_at) VALUES ($1, $2, $3, $4, $5) RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"),
+            )
+            .bind(Uuid::new_v4().to_string())
+            .bind(input.email)
+            .bind(password)
+            .bind(input.role)
+            .bind(Utc::now().timestamp_millis())
+            .fetch_one(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
 
-        Ok(Self::Entity::try_from(credential)?)
+            let mut credential = Self::Entity::try_from(credential)?;
+            if secret.is_some() {
+                store_secret(tx, &mut credential, secret).await?;
+            }
+
+            Ok(credential)
+        })
+        .await
     }
 
     async fn delete(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Self::Entity, DatabaseError> {
-        let credential = match key {
-            CredentialsBy::Id(uuid) => {
-                sqlx::query_as::<_, SqliteCredentialsDAO>("UPDATE credentials SET active = false WHERE id = $1 RETURNING id, email, password, active;")
-                    .bind(uuid.to_string())
-                    .fetch_one(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)?
-            },
-            CredentialsBy::Email(email) => {
-                sqlx::query_as::<_, SqliteCredentialsDAO>("UPDATE credentials SET active = false WHERE email = $1 RETURNING id, password, email, active;")
-                    .bind(email)
-                    .fetch_one(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)?
-            },
+        observe(ENTITY, "delete", async move {
+            let credential = match key {
+                CredentialsBy::Id(uuid) => {
+                    sqlx::query_as::<_, SqliteCredentialsDAO>(checked("UPDATE credentials SET active = false WHERE id = $1 RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"))
+                        .bind(uuid.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                CredentialsBy::Email(email) => {
+                    sqlx::query_as::<_, SqliteCredentialsDAO>(checked("UPDATE credentials SET active = false WHERE email = $1 RETURNING id, password, email, active, role, failed_attempts, locked_until, version, verified;"))
+                        .bind(email)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
 
-        };
+            };
 
-        Ok(Self::Entity::try_from(credential)?)
+            load_secret(tx, Self::Entity::try_from(credential)?).await
+        })
+        .await
     }
 
     async fn update(
@@ -112,86 +213,263 @@ impl EntityRepository for SqliteCredentialsRepository {
         key: Self::QueryOne,
         update: Self::UpdateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        let crendential = match key {
-            CredentialsBy::Id(id) => sqlx::query_as::<_, SqliteCredentialsDAO>(
-                "UPDATE credentials SET password = $2, active = $3 WHERE id = $1 RETURNING id, email, password, active;",
-            )
+        observe(ENTITY, "update", async move {
+            let (password, secret) = update.password_storage.split(update.password);
+            let credential = match &key {
+                CredentialsBy::Id(id) => sqlx::query_as::<_, SqliteCredentialsDAO>(
+                    checked("UPDATE credentials SET password = $2, active = $3, role = $4, version = version + 1 WHERE id = $1 AND version = $5 RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"),
+                )
+                    .bind(id.to_string())
+                    .bind(&password)
+                    .bind(update.active)
+                    .bind(update.role)
+                    .bind(update.version)
+                    .fetch_optional(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query_as::<_, SqliteCredentialsDAO>(
+                    checked("UPDATE credentials SET password = $2, active = $3, role = $4, version = version + 1 WHERE email = $1 AND version = $5 RETURNING id, email, password, active, role, failed_attempts, locked_until, version, verified;"),
+                )
+                    .bind(email.to_string())
+                    .bind(&password)
+                    .bind(update.active)
+                    .bind(update.role)
+                    .bind(update.version)
+                    .fetch_optional(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+            };
+
+            let Some(credential) = credential else {
+                return Err(stale_update(tx, key).await);
+            };
+
+            let mut credential = Self::Entity::try_from(credential)?;
+            store_secret(tx, &mut credential, secret).await?;
+
+            Ok(credential)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let credential = match key {
+                CredentialsBy::Id(id) => sqlx::query_as::<_, SqliteCredentialsDAO>(
+                    checked("SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.id = $1 LIMIT 1;"),
+                )
                 .bind(id.to_string())
-                .bind(update.password)
-                .bind(update.active)
                 .fetch_one(&mut **tx)
                 .await
                 .map_err(DatabaseError::from)?,
-            CredentialsBy::Email(email) => sqlx::query_as::<_, SqliteCredentialsDAO>(
-                "UPDATE credentials SET password = $2, active = $3 WHERE email = $1 RETURNING id, email, password, active;",
-            )
-                .bind(email.to_string())
-                .bind(update.password)
-                .bind(update.active)
+                CredentialsBy::Email(email) => sqlx::query_as::<_, SqliteCredentialsDAO>(
+                    checked("SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.email = $1 LIMIT 1;"),
+                )
+                .bind(email)
                 .fetch_one(&mut **tx)
                 .await
                 .map_err(DatabaseError::from)?,
-        };
+            };
 
-        Ok(Self::Entity::try_from(crendential)?)
+            Self::Entity::try_from(credential)
+        })
+        .await
     }
 
-    async fn get(
+    async fn try_get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
-    ) -> Result<Self::Entity, DatabaseError> {
-        let credential = match key {
-            CredentialsBy::Id(id) => sqlx::query_as::<_, SqliteCredentialsDAO>(
-                "SELECT id, email, password, active FROM credentials WHERE id = $1 LIMIT 1;",
-            )
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            let maybe_credential = match key {
+                CredentialsBy::Id(uuid) => sqlx::query_as::<_, SqliteCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.id = $1;",
+                ))
+                .bind(uuid.to_string())
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                CredentialsBy::Email(email) => sqlx::query_as::<_, SqliteCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.email = $1;",
+                ))
+                .bind(email)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            if let Some(credential) = maybe_credential {
+                Ok(Some(Self::Entity::try_from(credential)?))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let credentials = match key {
+                CredentialsWhere::Active(active) => sqlx::query_as::<_, SqliteCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.active = $1 ORDER BY c.created_at DESC, c.id DESC;",
+                ))
+                .bind(active)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            credentials.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let credentials = match key {
+                CredentialsWhere::Active(active) => sqlx::query_as::<_, SqliteCredentialsDAO>(checked(
+                    "SELECT c.id, c.email, COALESCE(s.password, c.password) AS password, c.active, c.role, c.failed_attempts, c.locked_until, c.version, c.verified FROM credentials c LEFT JOIN credential_secrets s ON s.credential_id = c.id WHERE c.active = $1 ORDER BY c.created_at DESC, c.id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(active)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            credentials.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                CredentialsWhere::Active(active) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM credentials WHERE active = $1;",
+                ))
+                .bind(active)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+}
+
+#[database::async_trait::async_trait]
+impl SignInAttempts for SqliteCredentialsRepository {
+    async fn record_failure(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+        threshold: i32,
+        lock_until: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, DatabaseError> {
+        observe(ENTITY, "record_failure", async move {
+            // locked_until is stored as unix millis
+            let locked_until = sqlx::query_scalar::<_, Option<i64>>(checked("UPDATE credentials SET failed_attempts = CASE WHEN failed_attempts + 1 >= $2 THEN 0 ELSE failed_attempts + 1 END, locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $3 ELSE locked_until END WHERE id = $1 RETURNING locked_until;"))
+                .bind(id.to_string())
+                .bind(threshold)
+                .bind(lock_until.timestamp_millis())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(locked_until.and_then(DateTime::from_timestamp_millis))
+        })
+        .await
+    }
+
+    async fn reset_failures(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<(), DatabaseError> {
+        observe(ENTITY, "reset_failures", async move {
+            sqlx::query(checked(
+                "UPDATE credentials SET failed_attempts = 0, locked_until = NULL WHERE id = $1;",
+            ))
             .bind(id.to_string())
-            .fetch_one(&mut **tx)
-            .await
-            .map_err(DatabaseError::from)?,
-            CredentialsBy::Email(email) => sqlx::query_as::<_, SqliteCredentialsDAO>(
-                "SELECT id, email, password, active FROM credentials WHERE email = $1 LIMIT 1;",
-            )
-            .bind(email)
-            .fetch_one(&mut **tx)
+            .execute(&mut **tx)
             .await
-            .map_err(DatabaseError::from)?,
-        };
+            .map_err(DatabaseError::from)?;
 
-        Ok(Self::Entity::try_from(credential)?)
+            Ok(())
+        })
+        .await
     }
+}
 
-    async fn try_get(
+#[database::async_trait::async_trait]
+impl EmailVerification for SqliteCredentialsRepository {
+    async fn mark_verified(
         tx: &mut Transaction<'_, Self::Db>,
-        key: Self::QueryOne,
-    ) -> Result<Option<Self::Entity>, DatabaseError> {
-        let maybe_credential = match key {
-            CredentialsBy::Id(uuid) => sqlx::query_as::<_, SqliteCredentialsDAO>(
-                "SELECT id, email, password, active FROM credentials WHERE id = $1;",
-            )
-            .bind(uuid.to_string())
-            .fetch_optional(&mut **tx)
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        observe(ENTITY, "mark_verified", async move {
+            let result = sqlx::query(checked(
+                "UPDATE credentials SET verified = true WHERE id = $1;",
+            ))
+            .bind(id.to_string())
+            .execute(&mut **tx)
             .await
-            .map_err(DatabaseError::from)?,
-            CredentialsBy::Email(email) => sqlx::query_as::<_, SqliteCredentialsDAO>(
-                "SELECT id, email, password, active FROM credentials WHERE email = $1;",
-            )
-            .bind(email)
-            .fetch_optional(&mut **tx)
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected() > 0)
+        })
+        .await
+    }
+
+    async fn mark_unverified(
+        tx: &mut Transaction<'_, Self::Db>,
+        id: Uuid,
+    ) -> Result<bool, DatabaseError> {
+        observe(ENTITY, "mark_unverified", async move {
+            let result = sqlx::query(checked(
+                "UPDATE credentials SET verified = false WHERE id = $1;",
+            ))
+            .bind(id.to_string())
+            .execute(&mut **tx)
             .await
-            .map_err(DatabaseError::from)?,
-        };
+            .map_err(DatabaseError::from)?;
 
-        if let Some(credential) = maybe_credential {
-            Ok(Some(Self::Entity::try_from(credential)?))
-        } else {
-            Ok(None)
-        }
+            Ok(result.rows_affected() > 0)
+        })
+        .await
     }
+}
 
-    async fn get_all(
-        _tx: &mut Transaction<'_, Self::Db>,
-        _key: Self::QueryMany,
-    ) -> Result<Vec<Self::Entity>, DatabaseError> {
-        todo!()
+#[database::async_trait::async_trait]
+impl HardDelete for SqliteCredentialsRepository {
+    async fn hard_delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: CredentialsBy,
+    ) -> Result<CredentialsDAO, DatabaseError> {
+        observe(ENTITY, "hard_delete", async move {
+            let credential = SqliteCredentialsRepository::get(tx, key).await?;
+
+            sqlx::query(checked("DELETE FROM credentials WHERE id = $1;"))
+                .bind(credential.id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(credential)
+        })
+        .await
     }
 }
@@ -0,0 +1,47 @@
+pub mod postgres;
+
+#[cfg(feature = "unit")]
+pub mod sqlite;
+
+#[cfg(feature = "mysql")]
+pub mod mysql;
+
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Single-use token proving the owner of a credential controls its email, consumed at
+/// `/verify_email`. Only the hash of the token is stored.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct EmailVerificationsDAO {
+    pub id: Uuid,
+    pub credential_id: Uuid,
+    pub token_hash: String,
+    /// Cleared once the token is used or superseded by a newer one.
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateEmailVerificationsDAO {
+    pub credential_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateEmailVerificationsDAO {
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmailVerificationsBy {
+    Id(Uuid),
+    TokenHash(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmailVerificationsWhere {
+    /// Every verification token of the credential, used ones included.
+    CredentialId(Uuid),
+}
@@ -0,0 +1,277 @@
+use crate::entities::email_verifications::{
+    CreateEmailVerificationsDAO, EmailVerificationsBy, EmailVerificationsDAO,
+    EmailVerificationsWhere, UpdateEmailVerificationsDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use sqlx::{MySql, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "email_verifications";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct MySqlEmailVerificationsDAO {
+    /// CHAR(36), MySQL has no uuid type
+    pub id: String,
+    pub credential_id: String,
+    pub token_hash: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn parse_uuid(value: &str, column: &str) -> Result<Uuid, DatabaseError> {
+    Uuid::from_str(value)
+        .map_err(|_| DatabaseError::Unknown(format!("Could not convert {column} to uuid")))
+}
+
+impl TryFrom<MySqlEmailVerificationsDAO> for EmailVerificationsDAO {
+    type Error = DatabaseError;
+    fn try_from(value: MySqlEmailVerificationsDAO) -> Result<Self, DatabaseError> {
+        Ok(EmailVerificationsDAO {
+            id: parse_uuid(&value.id, "id")?,
+            credential_id: parse_uuid(&value.credential_id, "credential_id")?,
+            token_hash: value.token_hash,
+            active: value.active,
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+        })
+    }
+}
+
+async fn select_one(
+    tx: &mut Transaction<'_, MySql>,
+    key: &EmailVerificationsBy,
+) -> Result<Option<MySqlEmailVerificationsDAO>, DatabaseError> {
+    let verification = match key {
+        EmailVerificationsBy::Id(id) => {
+            sqlx::query_as::<_, MySqlEmailVerificationsDAO>(checked("SELECT id, credential_id, token_hash, active, created_at, expires_at FROM email_verifications WHERE id = ?;"))
+                .bind(id.to_string())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+        EmailVerificationsBy::TokenHash(hash) => {
+            sqlx::query_as::<_, MySqlEmailVerificationsDAO>(checked("SELECT id, credential_id, token_hash, active, created_at, expires_at FROM email_verifications WHERE token_hash = ?;"))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+    };
+
+    Ok(verification)
+}
+
+/// MySQL has no `RETURNING`, so writes are followed by a read of the row in the same
+/// transaction.
+#[derive(Debug)]
+pub struct MySqlEmailVerificationsRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for MySqlEmailVerificationsRepository {
+    type Db = MySql;
+    type Entity = EmailVerificationsDAO;
+    type CreateInput = CreateEmailVerificationsDAO;
+    type UpdateInput = UpdateEmailVerificationsDAO;
+    type QueryOne = EmailVerificationsBy;
+    type QueryMany = EmailVerificationsWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let id = Uuid::new_v4();
+            sqlx::query(checked("INSERT INTO email_verifications (id, credential_id, token_hash, created_at, expires_at) VALUES (?, ?, ?, ?, ?);"))
+                .bind(id.to_string())
+                .bind(input.credential_id.to_string())
+                .bind(input.token_hash)
+                // the column default is in the connection's time zone
+                .bind(Utc::now())
+                .bind(input.expires_at)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            let verification = select_one(tx, &EmailVerificationsBy::Id(id))
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(verification)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match &key {
+                EmailVerificationsBy::Id(id) => sqlx::query(checked(
+                    "UPDATE email_verifications SET active = false WHERE id = ?;",
+                ))
+                .bind(id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                EmailVerificationsBy::TokenHash(hash) => sqlx::query(checked(
+                    "UPDATE email_verifications SET active = false WHERE token_hash = ?;",
+                ))
+                .bind(hash)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let verification = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(verification)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match &key {
+                EmailVerificationsBy::Id(id) => sqlx::query(checked(
+                    "UPDATE email_verifications SET expires_at = ? WHERE id = ?;",
+                ))
+                .bind(update.expires_at)
+                .bind(id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                EmailVerificationsBy::TokenHash(hash) => sqlx::query(checked(
+                    "UPDATE email_verifications SET expires_at = ? WHERE token_hash = ?;",
+                ))
+                .bind(update.expires_at)
+                .bind(hash)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let verification = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(verification)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let verification = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(verification)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            select_one(tx, &key)
+                .await?
+                .map(Self::Entity::try_from)
+                .transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let email_verifications = match key {
+                EmailVerificationsWhere::CredentialId(credential_id) => sqlx::query_as::<_, MySqlEmailVerificationsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM email_verifications WHERE credential_id = ? ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(credential_id.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            email_verifications
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let email_verifications = match key {
+                EmailVerificationsWhere::CredentialId(credential_id) => sqlx::query_as::<_, MySqlEmailVerificationsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM email_verifications WHERE credential_id = ? ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?;",
+                ))
+                .bind(credential_id.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            email_verifications
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                EmailVerificationsWhere::CredentialId(credential_id) => {
+                    sqlx::query_scalar::<_, i64>(checked(
+                        "SELECT COUNT(*) FROM email_verifications WHERE credential_id = ?;",
+                    ))
+                    .bind(credential_id.to_string())
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(MySqlEmailVerificationsRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+}
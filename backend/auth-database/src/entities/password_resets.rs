@@ -0,0 +1,47 @@
+pub mod postgres;
+
+#[cfg(feature = "unit")]
+pub mod sqlite;
+
+#[cfg(feature = "mysql")]
+pub mod mysql;
+
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Single-use token letting the owner of an email set a new password at
+/// `/password_reset/confirm`. Only the hash of the token is stored.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct PasswordResetsDAO {
+    pub id: Uuid,
+    pub credential_id: Uuid,
+    pub token_hash: String,
+    /// Cleared once the token is used or superseded by a newer one.
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreatePasswordResetsDAO {
+    pub credential_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdatePasswordResetsDAO {
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordResetsBy {
+    Id(Uuid),
+    TokenHash(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordResetsWhere {
+    /// Every reset of the credential, used ones included.
+    CredentialId(Uuid),
+}
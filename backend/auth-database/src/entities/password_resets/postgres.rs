@@ -0,0 +1,208 @@
+use crate::entities::password_resets::{
+    CreatePasswordResetsDAO, PasswordResetsBy, PasswordResetsDAO, PasswordResetsWhere,
+    UpdatePasswordResetsDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::{Postgres, Transaction};
+
+const ENTITY: &str = "password_resets";
+
+#[derive(Debug)]
+pub struct PostgresPasswordResetsRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for PostgresPasswordResetsRepository {
+    type Db = Postgres;
+    type Entity = PasswordResetsDAO;
+    type CreateInput = CreatePasswordResetsDAO;
+    type UpdateInput = UpdatePasswordResetsDAO;
+    type QueryOne = PasswordResetsBy;
+    type QueryMany = PasswordResetsWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            sqlx::query_as::<_, Self::Entity>(checked("INSERT INTO password_resets (credential_id, token_hash, expires_at) VALUES ($1, $2, $3) RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                .bind(input.credential_id)
+                .bind(input.token_hash)
+                .bind(input.expires_at)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match key {
+                PasswordResetsBy::Id(id) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE password_resets SET active = false WHERE id = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                PasswordResetsBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE password_resets SET active = false WHERE token_hash = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match key {
+                PasswordResetsBy::Id(id) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE password_resets SET expires_at = $2 WHERE id = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                PasswordResetsBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE password_resets SET expires_at = $2 WHERE token_hash = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            match key {
+                PasswordResetsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE id = $1;",
+                ))
+                .bind(id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                PasswordResetsBy::TokenHash(hash) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            match key {
+                PasswordResetsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE id = $1;",
+                ))
+                .bind(id)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                PasswordResetsBy::TokenHash(hash) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            match key {
+                PasswordResetsWhere::CredentialId(credential_id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE credential_id = $1 ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(credential_id)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            match key {
+                PasswordResetsWhere::CredentialId(credential_id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE credential_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(credential_id)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                PasswordResetsWhere::CredentialId(credential_id) => sqlx::query_scalar::<_, i64>(
+                    checked("SELECT COUNT(*) FROM password_resets WHERE credential_id = $1;"),
+                )
+                .bind(credential_id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(PostgresPasswordResetsRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+}
@@ -0,0 +1,270 @@
+use crate::entities::password_resets::{
+    CreatePasswordResetsDAO, PasswordResetsBy, PasswordResetsDAO, PasswordResetsWhere,
+    UpdatePasswordResetsDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "password_resets";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct SqlitePasswordResetsDAO {
+    pub id: String,
+    pub credential_id: String,
+    pub token_hash: String,
+    pub active: bool,
+    /// unix millis
+    pub created_at: i64,
+    /// unix millis
+    pub expires_at: i64,
+}
+
+fn parse_uuid(value: &str, column: &str) -> Result<Uuid, DatabaseError> {
+    Uuid::from_str(value)
+        .map_err(|_| DatabaseError::Unknown(format!("Could not convert {column} to uuid")))
+}
+
+fn parse_millis(value: i64, column: &str) -> Result<DateTime<Utc>, DatabaseError> {
+    DateTime::from_timestamp_millis(value).ok_or(DatabaseError::Unknown(format!(
+        "Could not convert {column} to DateTime<Utc>"
+    )))
+}
+
+impl TryFrom<SqlitePasswordResetsDAO> for PasswordResetsDAO {
+    type Error = DatabaseError;
+    fn try_from(value: SqlitePasswordResetsDAO) -> Result<Self, DatabaseError> {
+        Ok(PasswordResetsDAO {
+            id: parse_uuid(&value.id, "id")?,
+            credential_id: parse_uuid(&value.credential_id, "credential_id")?,
+            token_hash: value.token_hash,
+            active: value.active,
+            created_at: parse_millis(value.created_at, "created_at")?,
+            expires_at: parse_millis(value.expires_at, "expires_at")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SqlitePasswordResetsRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for SqlitePasswordResetsRepository {
+    type Db = Sqlite;
+    type Entity = PasswordResetsDAO;
+    type CreateInput = CreatePasswordResetsDAO;
+    type UpdateInput = UpdatePasswordResetsDAO;
+    type QueryOne = PasswordResetsBy;
+    type QueryMany = PasswordResetsWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let password_reset = sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked("INSERT INTO password_resets (id, credential_id, token_hash, created_at, expires_at) VALUES ($1, $2, $3, $4, $5) RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                .bind(Uuid::new_v4().to_string())
+                .bind(input.credential_id.to_string())
+                .bind(input.token_hash)
+                .bind(Utc::now().timestamp_millis())
+                .bind(input.expires_at.timestamp_millis())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Self::Entity::try_from(password_reset)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            let password_reset = match key {
+                PasswordResetsBy::Id(id) => {
+                    sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked("UPDATE password_resets SET active = false WHERE id = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                PasswordResetsBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked("UPDATE password_resets SET active = false WHERE token_hash = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            Self::Entity::try_from(password_reset)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            let password_reset = match key {
+                PasswordResetsBy::Id(id) => {
+                    sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked("UPDATE password_resets SET expires_at = $2 WHERE id = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id.to_string())
+                        .bind(update.expires_at.timestamp_millis())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                PasswordResetsBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked("UPDATE password_resets SET expires_at = $2 WHERE token_hash = $1 RETURNING id, credential_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .bind(update.expires_at.timestamp_millis())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            Self::Entity::try_from(password_reset)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let password_reset = match key {
+                PasswordResetsBy::Id(id) => sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE id = $1;",
+                ))
+                .bind(id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                PasswordResetsBy::TokenHash(hash) => sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            Self::Entity::try_from(password_reset)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            let password_reset = match key {
+                PasswordResetsBy::Id(id) => sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE id = $1;",
+                ))
+                .bind(id.to_string())
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                PasswordResetsBy::TokenHash(hash) => sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            password_reset.map(Self::Entity::try_from).transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let password_resets = match key {
+                PasswordResetsWhere::CredentialId(credential_id) => sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE credential_id = $1 ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(credential_id.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            password_resets
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let password_resets = match key {
+                PasswordResetsWhere::CredentialId(credential_id) => sqlx::query_as::<_, SqlitePasswordResetsDAO>(checked(
+                    "SELECT id, credential_id, token_hash, active, created_at, expires_at FROM password_resets WHERE credential_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(credential_id.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            password_resets
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                PasswordResetsWhere::CredentialId(credential_id) => sqlx::query_scalar::<_, i64>(
+                    checked("SELECT COUNT(*) FROM password_resets WHERE credential_id = $1;"),
+                )
+                .bind(credential_id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(SqlitePasswordResetsRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+}
@@ -0,0 +1,81 @@
+pub mod postgres;
+
+#[cfg(feature = "unit")]
+pub mod sqlite;
+
+#[cfg(feature = "mysql")]
+pub mod mysql;
+
+use database::traits::{DatabaseError, EntityRepository};
+use sqlx::Transaction;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Single-use token exchanged for a new session, and a new token of the same family, at
+/// `/refresh`. Only the hash of the token is stored.
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct RefreshTokensDAO {
+    pub id: Uuid,
+    pub credential_id: Uuid,
+    /// Shared by every token rotated from the same sign-in.
+    pub family_id: Uuid,
+    /// Session issued together with the token.
+    pub session_id: Uuid,
+    pub token_hash: String,
+    /// Cleared once the token is used or revoked.
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct CreateRefreshTokensDAO {
+    pub credential_id: Uuid,
+    pub family_id: Uuid,
+    pub session_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct UpdateRefreshTokensDAO {
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshTokensBy {
+    Id(Uuid),
+    TokenHash(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefreshTokensWhere {
+    /// Every token of the family, used ones included, oldest first.
+    FamilyId(Uuid),
+}
+
+/// Deactivates the tokens of revoked sessions, so a session can't be brought back with
+/// the token issued alongside it. Mirrors the revocations of
+/// [`crate::entities::sessions::ActiveSessions`].
+#[database::async_trait::async_trait]
+pub trait RefreshTokenRevocation: EntityRepository {
+    /// Deactivates the active tokens issued with `session_id`, returning how many were.
+    async fn revoke_by_session(
+        tx: &mut Transaction<'_, Self::Db>,
+        session_id: Uuid,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Deactivates every active token of `credential_id`, returning how many were.
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Deactivates every active token of `credential_id` but those issued with the
+    /// session `keep`, returning how many were.
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError>;
+}
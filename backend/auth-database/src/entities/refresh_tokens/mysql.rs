@@ -0,0 +1,340 @@
+use crate::entities::refresh_tokens::{
+    CreateRefreshTokensDAO, RefreshTokenRevocation, RefreshTokensBy, RefreshTokensDAO,
+    RefreshTokensWhere, UpdateRefreshTokensDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use sqlx::{MySql, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "refresh_tokens";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct MySqlRefreshTokensDAO {
+    /// CHAR(36), MySQL has no uuid type
+    pub id: String,
+    pub credential_id: String,
+    pub family_id: String,
+    pub session_id: String,
+    pub token_hash: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn parse_uuid(value: &str, column: &str) -> Result<Uuid, DatabaseError> {
+    Uuid::from_str(value)
+        .map_err(|_| DatabaseError::Unknown(format!("Could not convert {column} to uuid")))
+}
+
+impl TryFrom<MySqlRefreshTokensDAO> for RefreshTokensDAO {
+    type Error = DatabaseError;
+    fn try_from(value: MySqlRefreshTokensDAO) -> Result<Self, DatabaseError> {
+        Ok(RefreshTokensDAO {
+            id: parse_uuid(&value.id, "id")?,
+            credential_id: parse_uuid(&value.credential_id, "credential_id")?,
+            family_id: parse_uuid(&value.family_id, "family_id")?,
+            session_id: parse_uuid(&value.session_id, "session_id")?,
+            token_hash: value.token_hash,
+            active: value.active,
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+        })
+    }
+}
+
+async fn select_one(
+    tx: &mut Transaction<'_, MySql>,
+    key: &RefreshTokensBy,
+) -> Result<Option<MySqlRefreshTokensDAO>, DatabaseError> {
+    let refresh_token = match key {
+        RefreshTokensBy::Id(id) => {
+            sqlx::query_as::<_, MySqlRefreshTokensDAO>(checked("SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE id = ?;"))
+                .bind(id.to_string())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+        RefreshTokensBy::TokenHash(hash) => {
+            sqlx::query_as::<_, MySqlRefreshTokensDAO>(checked("SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE token_hash = ?;"))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+    };
+
+    Ok(refresh_token)
+}
+
+/// MySQL has no `RETURNING`, so writes are followed by a read of the row in the same
+/// transaction.
+#[derive(Debug)]
+pub struct MySqlRefreshTokensRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for MySqlRefreshTokensRepository {
+    type Db = MySql;
+    type Entity = RefreshTokensDAO;
+    type CreateInput = CreateRefreshTokensDAO;
+    type UpdateInput = UpdateRefreshTokensDAO;
+    type QueryOne = RefreshTokensBy;
+    type QueryMany = RefreshTokensWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let id = Uuid::new_v4();
+            sqlx::query(checked("INSERT INTO refresh_tokens (id, credential_id, family_id, session_id, token_hash, created_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?);"))
+                .bind(id.to_string())
+                .bind(input.credential_id.to_string())
+                .bind(input.family_id.to_string())
+                .bind(input.session_id.to_string())
+                .bind(input.token_hash)
+                // the column default is in the connection's time zone
+                .bind(Utc::now())
+                .bind(input.expires_at)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            let refresh_token = select_one(tx, &RefreshTokensBy::Id(id))
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match &key {
+                RefreshTokensBy::Id(id) => sqlx::query(checked(
+                    "UPDATE refresh_tokens SET active = false WHERE id = ?;",
+                ))
+                .bind(id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                RefreshTokensBy::TokenHash(hash) => sqlx::query(checked(
+                    "UPDATE refresh_tokens SET active = false WHERE token_hash = ?;",
+                ))
+                .bind(hash)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let refresh_token = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match &key {
+                RefreshTokensBy::Id(id) => sqlx::query(checked(
+                    "UPDATE refresh_tokens SET expires_at = ? WHERE id = ?;",
+                ))
+                .bind(update.expires_at)
+                .bind(id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                RefreshTokensBy::TokenHash(hash) => sqlx::query(checked(
+                    "UPDATE refresh_tokens SET expires_at = ? WHERE token_hash = ?;",
+                ))
+                .bind(update.expires_at)
+                .bind(hash)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let refresh_token = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let refresh_token = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            select_one(tx, &key)
+                .await?
+                .map(Self::Entity::try_from)
+                .transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let refresh_tokens = match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_as::<_, MySqlRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE family_id = ? ORDER BY created_at, id;",
+                ))
+                .bind(family_id.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            refresh_tokens
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let refresh_tokens = match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_as::<_, MySqlRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE family_id = ? ORDER BY created_at, id LIMIT ? OFFSET ?;",
+                ))
+                .bind(family_id.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            refresh_tokens
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM refresh_tokens WHERE family_id = ?;",
+                ))
+                .bind(family_id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(MySqlRefreshTokensRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+}
+
+#[database::async_trait::async_trait]
+impl RefreshTokenRevocation for MySqlRefreshTokensRepository {
+    async fn revoke_by_session(
+        tx: &mut Transaction<'_, Self::Db>,
+        session_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_by_session", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE session_id = ? AND active;",
+            ))
+            .bind(session_id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_all", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE credential_id = ? AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_others", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE credential_id = ? AND session_id <> ? AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .bind(keep.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+}
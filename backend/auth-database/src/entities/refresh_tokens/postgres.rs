@@ -0,0 +1,270 @@
+use crate::entities::refresh_tokens::{
+    CreateRefreshTokensDAO, RefreshTokenRevocation, RefreshTokensBy, RefreshTokensDAO,
+    RefreshTokensWhere, UpdateRefreshTokensDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::{Postgres, Transaction};
+
+const ENTITY: &str = "refresh_tokens";
+
+#[derive(Debug)]
+pub struct PostgresRefreshTokensRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for PostgresRefreshTokensRepository {
+    type Db = Postgres;
+    type Entity = RefreshTokensDAO;
+    type CreateInput = CreateRefreshTokensDAO;
+    type UpdateInput = UpdateRefreshTokensDAO;
+    type QueryOne = RefreshTokensBy;
+    type QueryMany = RefreshTokensWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            sqlx::query_as::<_, Self::Entity>(checked("INSERT INTO refresh_tokens (credential_id, family_id, session_id, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5) RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                .bind(input.credential_id)
+                .bind(input.family_id)
+                .bind(input.session_id)
+                .bind(input.token_hash)
+                .bind(input.expires_at)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match key {
+                RefreshTokensBy::Id(id) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE refresh_tokens SET active = false WHERE id = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                RefreshTokensBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE refresh_tokens SET active = false WHERE token_hash = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match key {
+                RefreshTokensBy::Id(id) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE refresh_tokens SET expires_at = $2 WHERE id = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                RefreshTokensBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE refresh_tokens SET expires_at = $2 WHERE token_hash = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            match key {
+                RefreshTokensBy::Id(id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE id = $1;",
+                ))
+                .bind(id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                RefreshTokensBy::TokenHash(hash) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            match key {
+                RefreshTokensBy::Id(id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE id = $1;",
+                ))
+                .bind(id)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+                RefreshTokensBy::TokenHash(hash) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE family_id = $1 ORDER BY created_at, id;",
+                ))
+                .bind(family_id)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE family_id = $1 ORDER BY created_at, id LIMIT $2 OFFSET $3;",
+                ))
+                .bind(family_id)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM refresh_tokens WHERE family_id = $1;",
+                ))
+                .bind(family_id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(PostgresRefreshTokensRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+}
+
+#[database::async_trait::async_trait]
+impl RefreshTokenRevocation for PostgresRefreshTokensRepository {
+    async fn revoke_by_session(
+        tx: &mut Transaction<'_, Self::Db>,
+        session_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_by_session", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE session_id = $1 AND active;",
+            ))
+            .bind(session_id)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_all", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE credential_id = $1 AND active;",
+            ))
+            .bind(credential_id)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_others", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE credential_id = $1 AND session_id <> $2 AND active;",
+            ))
+            .bind(credential_id)
+            .bind(keep)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+}
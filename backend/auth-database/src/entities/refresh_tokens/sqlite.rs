@@ -0,0 +1,335 @@
+use crate::entities::refresh_tokens::{
+    CreateRefreshTokensDAO, RefreshTokenRevocation, RefreshTokensBy, RefreshTokensDAO,
+    RefreshTokensWhere, UpdateRefreshTokensDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::{Sqlite, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "refresh_tokens";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct SqliteRefreshTokensDAO {
+    pub id: String,
+    pub credential_id: String,
+    pub family_id: String,
+    pub session_id: String,
+    pub token_hash: String,
+    pub active: bool,
+    /// unix millis
+    pub created_at: i64,
+    /// unix millis
+    pub expires_at: i64,
+}
+
+fn parse_uuid(value: &str, column: &str) -> Result<Uuid, DatabaseError> {
+    Uuid::from_str(value)
+        .map_err(|_| DatabaseError::Unknown(format!("Could not convert {column} to uuid")))
+}
+
+fn parse_millis(value: i64, column: &str) -> Result<DateTime<Utc>, DatabaseError> {
+    DateTime::from_timestamp_millis(value).ok_or(DatabaseError::Unknown(format!(
+        "Could not convert {column} to DateTime<Utc>"
+    )))
+}
+
+impl TryFrom<SqliteRefreshTokensDAO> for RefreshTokensDAO {
+    type Error = DatabaseError;
+    fn try_from(value: SqliteRefreshTokensDAO) -> Result<Self, DatabaseError> {
+        Ok(RefreshTokensDAO {
+            id: parse_uuid(&value.id, "id")?,
+            credential_id: parse_uuid(&value.credential_id, "credential_id")?,
+            family_id: parse_uuid(&value.family_id, "family_id")?,
+            session_id: parse_uuid(&value.session_id, "session_id")?,
+            token_hash: value.token_hash,
+            active: value.active,
+            created_at: parse_millis(value.created_at, "created_at")?,
+            expires_at: parse_millis(value.expires_at, "expires_at")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SqliteRefreshTokensRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for SqliteRefreshTokensRepository {
+    type Db = Sqlite;
+    type Entity = RefreshTokensDAO;
+    type CreateInput = CreateRefreshTokensDAO;
+    type UpdateInput = UpdateRefreshTokensDAO;
+    type QueryOne = RefreshTokensBy;
+    type QueryMany = RefreshTokensWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let refresh_token = sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked("INSERT INTO refresh_tokens (id, credential_id, family_id, session_id, token_hash, created_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                .bind(Uuid::new_v4().to_string())
+                .bind(input.credential_id.to_string())
+                .bind(input.family_id.to_string())
+                .bind(input.session_id.to_string())
+                .bind(input.token_hash)
+                .bind(Utc::now().timestamp_millis())
+                .bind(input.expires_at.timestamp_millis())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            let refresh_token = match key {
+                RefreshTokensBy::Id(id) => {
+                    sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked("UPDATE refresh_tokens SET active = false WHERE id = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                RefreshTokensBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked("UPDATE refresh_tokens SET active = false WHERE token_hash = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            let refresh_token = match key {
+                RefreshTokensBy::Id(id) => {
+                    sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked("UPDATE refresh_tokens SET expires_at = $2 WHERE id = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(id.to_string())
+                        .bind(update.expires_at.timestamp_millis())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                RefreshTokensBy::TokenHash(hash) => {
+                    sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked("UPDATE refresh_tokens SET expires_at = $2 WHERE token_hash = $1 RETURNING id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at;"))
+                        .bind(hash)
+                        .bind(update.expires_at.timestamp_millis())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let refresh_token = match key {
+                RefreshTokensBy::Id(id) => sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE id = $1;",
+                ))
+                .bind(id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                RefreshTokensBy::TokenHash(hash) => sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            Self::Entity::try_from(refresh_token)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            let refresh_token = match key {
+                RefreshTokensBy::Id(id) => sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE id = $1;",
+                ))
+                .bind(id.to_string())
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                RefreshTokensBy::TokenHash(hash) => sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE token_hash = $1;",
+                ))
+                .bind(hash)
+                .fetch_optional(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            refresh_token.map(Self::Entity::try_from).transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let refresh_tokens = match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE family_id = $1 ORDER BY created_at, id;",
+                ))
+                .bind(family_id.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            refresh_tokens
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let refresh_tokens = match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_as::<_, SqliteRefreshTokensDAO>(checked(
+                    "SELECT id, credential_id, family_id, session_id, token_hash, active, created_at, expires_at FROM refresh_tokens WHERE family_id = $1 ORDER BY created_at, id LIMIT $2 OFFSET $3;",
+                ))
+                .bind(family_id.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            refresh_tokens
+                .into_iter()
+                .map(Self::Entity::try_from)
+                .collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                RefreshTokensWhere::FamilyId(family_id) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM refresh_tokens WHERE family_id = $1;",
+                ))
+                .bind(family_id.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(SqliteRefreshTokensRepository::try_get(tx, key)
+            .await?
+            .is_some())
+    }
+}
+
+#[database::async_trait::async_trait]
+impl RefreshTokenRevocation for SqliteRefreshTokensRepository {
+    async fn revoke_by_session(
+        tx: &mut Transaction<'_, Self::Db>,
+        session_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_by_session", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE session_id = $1 AND active;",
+            ))
+            .bind(session_id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_all", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE credential_id = $1 AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_others", async move {
+            let result = sqlx::query(checked(
+                "UPDATE refresh_tokens SET active = false WHERE credential_id = $1 AND session_id <> $2 AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .bind(keep.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+}
@@ -3,6 +3,11 @@ pub mod postgres;
 #[cfg(feature = "unit")]
 pub mod sqlite;
 
+#[cfg(feature = "mysql")]
+pub mod mysql;
+
+use database::traits::{DatabaseError, EntityRepository};
+use sqlx::Transaction;
 use sqlx::types::Uuid;
 use sqlx::types::chrono::{DateTime, Utc};
 
@@ -13,24 +18,92 @@ pub struct SessionsDAO {
     pub expires_at: DateTime<Utc>,
     pub credential_id: Uuid,
     pub active: bool,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether the credential had never signed in from `ip` before this session.
+    pub is_new_device: bool,
+    /// Hash of the client the session is bound to, `None` when it isn't bound.
+    pub fingerprint: Option<String>,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct CreateSessionsDAO {
     pub expires_at: DateTime<Utc>,
     pub credential_id: Uuid,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub is_new_device: bool,
+    /// Hash of the client the session is bound to, `None` when it isn't bound.
+    pub fingerprint: Option<String>,
 }
 
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
-pub struct UpdateSessionsDAO {}
+pub struct UpdateSessionsDAO {
+    pub expires_at: DateTime<Utc>,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum SessionsBy {
     Id(Uuid),
     CredentialId(Uuid),
+    /// Any session of the credential started from the given ip.
+    CredentialIp(Uuid, String),
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum SessionsWhere {
     CredentialId(Uuid),
 }
+
+/// Sessions a bulk operation applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionsScope {
+    Credential(Uuid),
+    All,
+}
+
+/// Session queries that don't fit the generic [`EntityRepository`] methods.
+#[database::async_trait::async_trait]
+pub trait ActiveSessions: EntityRepository {
+    /// Counts sessions of `credential_id` that are active and not expired yet.
+    async fn count_active(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<i64, DatabaseError>;
+
+    /// Deactivates every active session of `credential_id`, returning how many were.
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Deactivates every active session of `credential_id` but `keep`, returning how
+    /// many were.
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Moves the expiry of every active, unexpired session in `scope` to `expires_at` in
+    /// one statement, returning how many were changed.
+    async fn set_expiry(
+        tx: &mut Transaction<'_, Self::Db>,
+        scope: SessionsScope,
+        expires_at: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Deletes the rows of sessions that expired before `now`, returning how many were.
+    /// Sessions an active, unexpired refresh token was issued with are kept.
+    async fn delete_expired(
+        tx: &mut Transaction<'_, Self::Db>,
+        now: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError>;
+
+    /// Deletes the rows of every session of `credential_id`, active or not, returning
+    /// how many were.
+    async fn delete_by_credential(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError>;
+}
@@ -0,0 +1,434 @@
+use crate::entities::sessions::{
+    ActiveSessions, CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsScope, SessionsWhere,
+    UpdateSessionsDAO,
+};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use sqlx::{MySql, Transaction};
+use std::str::FromStr;
+
+const ENTITY: &str = "sessions";
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct MySqlSessionsDAO {
+    /// CHAR(36), MySQL has no uuid type
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub credential_id: String,
+    pub active: bool,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub is_new_device: bool,
+    pub fingerprint: Option<String>,
+}
+
+impl TryFrom<MySqlSessionsDAO> for SessionsDAO {
+    type Error = DatabaseError;
+    fn try_from(value: MySqlSessionsDAO) -> Result<Self, DatabaseError> {
+        Ok(SessionsDAO {
+            id: Uuid::from_str(&value.id)
+                .map_err(|_| DatabaseError::Unknown("Could not convert id to uuid".to_string()))?,
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            credential_id: Uuid::from_str(&value.credential_id).map_err(|_| {
+                DatabaseError::Unknown("Could not convert credential_id to uuid".to_string())
+            })?,
+            active: value.active,
+            ip: value.ip,
+            user_agent: value.user_agent,
+            is_new_device: value.is_new_device,
+            fingerprint: value.fingerprint,
+        })
+    }
+}
+
+impl From<SessionsDAO> for MySqlSessionsDAO {
+    fn from(value: SessionsDAO) -> Self {
+        MySqlSessionsDAO {
+            id: value.id.to_string(),
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            credential_id: value.credential_id.to_string(),
+            active: value.active,
+            ip: value.ip,
+            user_agent: value.user_agent,
+            is_new_device: value.is_new_device,
+            fingerprint: value.fingerprint,
+        }
+    }
+}
+
+/// First session matching `key`, the one a write through `key` is reported as.
+async fn select_one(
+    tx: &mut Transaction<'_, MySql>,
+    key: &SessionsBy,
+) -> Result<Option<MySqlSessionsDAO>, DatabaseError> {
+    let session = match key {
+        SessionsBy::Id(uuid) => {
+            sqlx::query_as::<_, MySqlSessionsDAO>(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE id = ? LIMIT 1;"))
+                .bind(uuid.to_string())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+        SessionsBy::CredentialId(uuid) => {
+            sqlx::query_as::<_, MySqlSessionsDAO>(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = ? LIMIT 1;"))
+                .bind(uuid.to_string())
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+        SessionsBy::CredentialIp(uuid, ip) => {
+            sqlx::query_as::<_, MySqlSessionsDAO>(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = ? AND ip = ? LIMIT 1;"))
+                .bind(uuid.to_string())
+                .bind(ip)
+                .fetch_optional(&mut **tx)
+                .await?
+        }
+    };
+
+    Ok(session)
+}
+
+/// MySQL has no `RETURNING`, so writes are followed by a read of the row in the same
+/// transaction.
+#[derive(Debug)]
+pub struct MySqlSessionsRepository;
+
+#[database::async_trait::async_trait]
+impl EntityRepository for MySqlSessionsRepository {
+    type Db = MySql;
+    type Entity = SessionsDAO;
+    type CreateInput = CreateSessionsDAO;
+    type UpdateInput = UpdateSessionsDAO;
+    type QueryOne = SessionsBy;
+    type QueryMany = SessionsWhere;
+
+    async fn insert(
+        tx: &mut Transaction<'_, Self::Db>,
+        input: Self::CreateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "insert", async move {
+            let id = Uuid::new_v4();
+            sqlx::query(checked("INSERT INTO sessions (id, expires_at, credential_id, ip, user_agent, is_new_device, fingerprint, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?);"))
+                .bind(id.to_string())
+                .bind(input.expires_at)
+                .bind(input.credential_id.to_string())
+                .bind(input.ip)
+                .bind(input.user_agent)
+                .bind(input.is_new_device)
+                .bind(input.fingerprint)
+                // the column default is in the connection's time zone
+                .bind(Utc::now())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            let session = select_one(tx, &SessionsBy::Id(id))
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(session)
+        })
+        .await
+    }
+
+    async fn delete(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "delete", async move {
+            match &key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query(checked("UPDATE sessions SET active = false WHERE id = ?;"))
+                        .bind(uuid.to_string())
+                        .execute(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                SessionsBy::CredentialId(uuid) => sqlx::query(checked(
+                    "UPDATE sessions SET active = false WHERE credential_id = ?;",
+                ))
+                .bind(uuid.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                SessionsBy::CredentialIp(uuid, ip) => sqlx::query(checked(
+                    "UPDATE sessions SET active = false WHERE credential_id = ? AND ip = ?;",
+                ))
+                .bind(uuid.to_string())
+                .bind(ip)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let session = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(session)
+        })
+        .await
+    }
+
+    async fn update(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "update", async move {
+            match &key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query(checked("UPDATE sessions SET expires_at = ? WHERE id = ?;"))
+                        .bind(update.expires_at)
+                        .bind(uuid.to_string())
+                        .execute(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                SessionsBy::CredentialId(uuid) => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = ? WHERE credential_id = ?;",
+                ))
+                .bind(update.expires_at)
+                .bind(uuid.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+                SessionsBy::CredentialIp(uuid, ip) => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = ? WHERE credential_id = ? AND ip = ?;",
+                ))
+                .bind(update.expires_at)
+                .bind(uuid.to_string())
+                .bind(ip)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            let session = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(session)
+        })
+        .await
+    }
+
+    async fn get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Self::Entity, DatabaseError> {
+        observe(ENTITY, "get", async move {
+            let session = select_one(tx, &key)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?;
+
+            Self::Entity::try_from(session)
+        })
+        .await
+    }
+
+    async fn try_get(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<Option<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "try_get", async move {
+            select_one(tx, &key)
+                .await?
+                .map(Self::Entity::try_from)
+                .transpose()
+        })
+        .await
+    }
+
+    async fn get_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_all", async move {
+            let sessions = match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_as::<_, MySqlSessionsDAO>(checked(
+                    "SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = ? ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(uuid.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            sessions.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let sessions = match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_as::<_, MySqlSessionsDAO>(checked(
+                    "SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = ? ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?;",
+                ))
+                .bind(uuid.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            sessions.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM sessions WHERE credential_id = ?;",
+                ))
+                .bind(uuid.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn exists(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+    ) -> Result<bool, DatabaseError> {
+        Ok(MySqlSessionsRepository::try_get(tx, key).await?.is_some())
+    }
+}
+
+#[database::async_trait::async_trait]
+impl ActiveSessions for MySqlSessionsRepository {
+    async fn count_active(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count_active", async move {
+            sqlx::query_scalar::<_, i64>(checked("SELECT COUNT(*) FROM sessions WHERE credential_id = ? AND active AND expires_at > ?;"))
+                .bind(credential_id.to_string())
+                .bind(Utc::now())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_all", async move {
+            let result = sqlx::query(checked(
+                "UPDATE sessions SET active = false WHERE credential_id = ? AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_others", async move {
+            let result = sqlx::query(checked(
+                "UPDATE sessions SET active = false WHERE credential_id = ? AND id <> ? AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .bind(keep.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn set_expiry(
+        tx: &mut Transaction<'_, Self::Db>,
+        scope: SessionsScope,
+        expires_at: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "set_expiry", async move {
+            let now = Utc::now();
+            let query = match scope {
+                SessionsScope::Credential(credential_id) => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = ? WHERE credential_id = ? AND active AND expires_at > ?;",
+                ))
+                .bind(expires_at)
+                .bind(credential_id.to_string())
+                .bind(now),
+                SessionsScope::All => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = ? WHERE active AND expires_at > ?;",
+                ))
+                .bind(expires_at)
+                .bind(now),
+            };
+
+            let result = query.execute(&mut **tx).await.map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn delete_expired(
+        tx: &mut Transaction<'_, Self::Db>,
+        now: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "delete_expired", async move {
+            // A session still backing a live refresh token is kept, `/refresh` refuses
+            // tokens whose session is gone.
+            let result = sqlx::query(checked("DELETE FROM sessions WHERE expires_at < ? AND id NOT IN (SELECT session_id FROM refresh_tokens WHERE active AND expires_at > ?);"))
+                .bind(now)
+                .bind(now)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn delete_by_credential(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "delete_by_credential", async move {
+            let result = sqlx::query(checked("DELETE FROM sessions WHERE credential_id = ?;"))
+                .bind(credential_id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+}
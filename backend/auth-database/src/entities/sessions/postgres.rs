@@ -1,9 +1,16 @@
 use crate::entities::sessions::{
-    CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsWhere, UpdateSessionsDAO,
+    ActiveSessions, CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsScope, SessionsWhere,
+    UpdateSessionsDAO,
 };
-use database::traits::{DatabaseError, EntityRepository};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::{Postgres, Transaction};
 
+const ENTITY: &str = "sessions";
+
 #[derive(Debug)]
 pub struct PostgresSessionsRepository;
 
@@ -20,94 +27,213 @@ impl EntityRepository for PostgresSessionsRepository {
         tx: &mut Transaction<'_, Self::Db>,
         input: Self::CreateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        sqlx::query_as::<_, Self::Entity>("INSERT INTO sessions (expires_at, credential_id) VALUES ($1, $2) RETURNING id, created_at, expires_at, credential_id, active;")
-            .bind(input.expires_at)
-            .bind(input.credential_id)
-            .fetch_one(&mut **tx)
-            .await
-            .map_err(DatabaseError::from)
+        observe(ENTITY, "insert", async move {
+            sqlx::query_as::<_, Self::Entity>(checked("INSERT INTO sessions (expires_at, credential_id, ip, user_agent, is_new_device, fingerprint) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                .bind(input.expires_at)
+                .bind(input.credential_id)
+                .bind(input.ip)
+                .bind(input.user_agent)
+                .bind(input.is_new_device)
+                .bind(input.fingerprint)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
     }
 
     async fn delete(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Self::Entity, DatabaseError> {
-        match key {
-            SessionsBy::Id(uuid) => {
-                sqlx::query_as::<_, Self::Entity>("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active;")
-                    .bind(uuid)
-                    .fetch_one(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)
-            },
-            SessionsBy::CredentialId(uuid) => {
-                sqlx::query_as::<_, Self::Entity>("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active;")
-                    .bind(uuid)
-                    .fetch_one(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)
-            },
+        observe(ENTITY, "delete", async move {
+            match key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
+                SessionsBy::CredentialId(uuid) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
+                SessionsBy::CredentialIp(uuid, ip) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE sessions SET active = false WHERE credential_id = $1 AND ip = $2 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid)
+                        .bind(ip)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
 
-        }
+            }
+        })
+        .await
     }
 
     async fn update(
-        _tx: &mut Transaction<'_, Self::Db>,
-        _key: Self::QueryOne,
-        _update: Self::UpdateInput,
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        unreachable!("")
+        observe(ENTITY, "update", async move {
+            match key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE sessions SET expires_at = $2 WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
+                SessionsBy::CredentialId(uuid) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE sessions SET expires_at = $2 WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
+                SessionsBy::CredentialIp(uuid, ip) => {
+                    sqlx::query_as::<_, Self::Entity>(checked("UPDATE sessions SET expires_at = $3 WHERE credential_id = $1 AND ip = $2 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid)
+                        .bind(ip)
+                        .bind(update.expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
+            }
+        })
+        .await
     }
 
     async fn get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Self::Entity, DatabaseError> {
-        match key {
-            SessionsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(
-                "SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE id = $1 LIMIT 1;",
-            )
-                .bind(id)
-                .fetch_one(&mut **tx)
-                .await
-                .map_err(DatabaseError::from),
-            SessionsBy::CredentialId(uuid) => sqlx::query_as::<_, Self::Entity>(
-                "SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE credential_id = $1 LIMIT 1;",
-            )
-                .bind(uuid)
-                .fetch_one(&mut **tx)
-                .await
-                .map_err(DatabaseError::from),
-        }
+        observe(ENTITY, "get", async move {
+            match key {
+                SessionsBy::Id(id) => sqlx::query_as::<_, Self::Entity>(
+                    checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE id = $1 LIMIT 1;"),
+                )
+                    .bind(id)
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from),
+                SessionsBy::CredentialId(uuid) => sqlx::query_as::<_, Self::Entity>(
+                    checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 LIMIT 1;"),
+                )
+                    .bind(uuid)
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from),
+                SessionsBy::CredentialIp(uuid, ip) => sqlx::query_as::<_, Self::Entity>(
+                    checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 AND ip = $2 LIMIT 1;"),
+                )
+                    .bind(uuid)
+                    .bind(ip)
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from),
+            }
+        })
+        .await
     }
 
     async fn try_get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Option<Self::Entity>, DatabaseError> {
-        match key {
-            SessionsBy::Id(uuid) => {
-                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE id = $1 LIMIT 1;")
-                    .bind(uuid)
-                    .fetch_optional(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)
-            },
-            SessionsBy::CredentialId(uuid) => {
-                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE credential_id = $1 LIMIT 1;")
-                    .bind(uuid)
-                    .fetch_optional(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)
+        observe(ENTITY, "try_get", async move {
+            match key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query_as(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE id = $1 LIMIT 1;"))
+                        .bind(uuid)
+                        .fetch_optional(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                },
+                SessionsBy::CredentialId(uuid) => {
+                    sqlx::query_as(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 LIMIT 1;"))
+                        .bind(uuid)
+                        .fetch_optional(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
+                SessionsBy::CredentialIp(uuid, ip) => {
+                    sqlx::query_as(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 AND ip = $2 LIMIT 1;"))
+                        .bind(uuid)
+                        .bind(ip)
+                        .fetch_optional(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)
+                }
             }
-        }
+        })
+        .await
     }
 
     async fn get_all(
-        _tx: &mut Transaction<'_, Self::Db>,
-        _key: Self::QueryMany,
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
     ) -> Result<Vec<Self::Entity>, DatabaseError> {
-        todo!()
+        observe(ENTITY, "get_all", async move {
+            match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(uuid)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_as::<_, Self::Entity>(checked(
+                    "SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(uuid)
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM sessions WHERE credential_id = $1;",
+                ))
+                .bind(uuid)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
     }
 
     async fn exists(
@@ -119,3 +245,117 @@ impl EntityRepository for PostgresSessionsRepository {
             .is_some())
     }
 }
+
+#[database::async_trait::async_trait]
+impl ActiveSessions for PostgresSessionsRepository {
+    async fn count_active(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count_active", async move {
+            sqlx::query_scalar::<_, i64>(checked("SELECT COUNT(*) FROM sessions WHERE credential_id = $1 AND active AND expires_at > now();"))
+                .bind(credential_id)
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_all", async move {
+            let result = sqlx::query(checked(
+                "UPDATE sessions SET active = false WHERE credential_id = $1 AND active;",
+            ))
+            .bind(credential_id)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_others", async move {
+            let result = sqlx::query(checked(
+                "UPDATE sessions SET active = false WHERE credential_id = $1 AND id <> $2 AND active;",
+            ))
+            .bind(credential_id)
+            .bind(keep)
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn set_expiry(
+        tx: &mut Transaction<'_, Self::Db>,
+        scope: SessionsScope,
+        expires_at: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "set_expiry", async move {
+            let query = match scope {
+                SessionsScope::Credential(credential_id) => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = $1 WHERE credential_id = $2 AND active AND expires_at > now();",
+                ))
+                .bind(expires_at)
+                .bind(credential_id),
+                SessionsScope::All => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = $1 WHERE active AND expires_at > now();",
+                ))
+                .bind(expires_at),
+            };
+
+            let result = query.execute(&mut **tx).await.map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn delete_expired(
+        tx: &mut Transaction<'_, Self::Db>,
+        now: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "delete_expired", async move {
+            // A session still backing a live refresh token is kept, `/refresh` refuses
+            // tokens whose session is gone.
+            let result = sqlx::query(checked("DELETE FROM sessions WHERE expires_at < $1 AND id NOT IN (SELECT session_id FROM refresh_tokens WHERE active AND expires_at > $1);"))
+                .bind(now)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn delete_by_credential(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "delete_by_credential", async move {
+            let result = sqlx::query(checked("DELETE FROM sessions WHERE credential_id = $1;"))
+                .bind(credential_id)
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+}
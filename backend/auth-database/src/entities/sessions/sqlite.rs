@@ -1,13 +1,18 @@
 use crate::entities::sessions::{
-    CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsWhere, UpdateSessionsDAO,
+    ActiveSessions, CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsScope, SessionsWhere,
+    UpdateSessionsDAO,
 };
-use database::traits::{DatabaseError, EntityRepository};
+use database::guard::checked;
+use database::metrics::observe;
+use database::traits::{DatabaseError, EntityRepository, Pagination};
 use sqlx::types::Uuid;
-use sqlx::types::chrono::DateTime;
+use sqlx::types::chrono::{DateTime, Utc};
 
 use sqlx::{Sqlite, Transaction};
 use std::str::FromStr;
 
+const ENTITY: &str = "sessions";
+
 #[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
 pub struct SqliteSessionsDAO {
     pub id: String,
@@ -15,6 +20,10 @@ pub struct SqliteSessionsDAO {
     pub expires_at: i64,
     pub credential_id: String,
     pub active: bool,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub is_new_device: bool,
+    pub fingerprint: Option<String>,
 }
 
 impl TryFrom<SqliteSessionsDAO> for SessionsDAO {
@@ -33,6 +42,10 @@ impl TryFrom<SqliteSessionsDAO> for SessionsDAO {
                 DatabaseError::Unknown("Could not convert credential_id to uuid".to_string())
             })?,
             active: value.active,
+            ip: value.ip,
+            user_agent: value.user_agent,
+            is_new_device: value.is_new_device,
+            fingerprint: value.fingerprint,
         })
     }
 }
@@ -45,6 +58,10 @@ impl From<SessionsDAO> for SqliteSessionsDAO {
             expires_at: value.expires_at.timestamp_millis(),
             credential_id: value.credential_id.to_string(),
             active: value.active,
+            ip: value.ip,
+            user_agent: value.user_agent,
+            is_new_device: value.is_new_device,
+            fingerprint: value.fingerprint,
         }
     }
 }
@@ -53,6 +70,10 @@ impl From<SessionsDAO> for SqliteSessionsDAO {
 pub struct SqliteCreateSessionsDAO {
     pub expires_at: i64,
     pub credential_id: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub is_new_device: bool,
+    pub fingerprint: Option<String>,
 }
 
 impl From<CreateSessionsDAO> for SqliteCreateSessionsDAO {
@@ -60,6 +81,10 @@ impl From<CreateSessionsDAO> for SqliteCreateSessionsDAO {
         SqliteCreateSessionsDAO {
             expires_at: value.expires_at.timestamp_millis(),
             credential_id: value.credential_id.to_string(),
+            ip: value.ip,
+            user_agent: value.user_agent,
+            is_new_device: value.is_new_device,
+            fingerprint: value.fingerprint,
         }
     }
 }
@@ -80,107 +105,235 @@ impl EntityRepository for SqliteSessionsRepository {
         tx: &mut Transaction<'_, Self::Db>,
         input: Self::CreateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        let input: SqliteCreateSessionsDAO = input.into();
-        let result = sqlx::query_as::<_, SqliteSessionsDAO>("INSERT INTO sessions (id, expires_at, credential_id) VALUES ($1, $2, $3) RETURNING id, created_at, expires_at, credential_id, active;")
-            .bind(Uuid::new_v4().to_string())
-            .bind(input.expires_at)
-            .bind(input.credential_id)
-            .fetch_one(&mut **tx)
-            .await
-            .map_err(DatabaseError::from)?;
+        observe(ENTITY, "insert", async move {
+            let input: SqliteCreateSessionsDAO = input.into();
+            let result = sqlx::query_as::<_, SqliteSessionsDAO>(checked("INSERT INTO sessions (id, expires_at, credential_id, ip, user_agent, is_new_device, fingerprint, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                .bind(Uuid::new_v4().to_string())
+                .bind(input.expires_at)
+                .bind(input.credential_id)
+                .bind(input.ip)
+                .bind(input.user_agent)
+                .bind(input.is_new_device)
+                .bind(input.fingerprint)
+                // the column default is in seconds, everything else here is millis
+                .bind(Utc::now().timestamp_millis())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
 
-        Ok(Self::Entity::try_from(result)?)
+            Self::Entity::try_from(result)
+        })
+        .await
     }
 
     async fn delete(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Self::Entity, DatabaseError> {
-        let session = match key {
-            SessionsBy::Id(uuid) => {
-                sqlx::query_as::<_, SqliteSessionsDAO>("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active;")
-                    .bind(uuid.to_string())
-                    .fetch_one(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)?
-            },
-            SessionsBy::CredentialId(uuid) => {
-                sqlx::query_as::<_, SqliteSessionsDAO>("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active;")
-                    .bind(uuid.to_string())
-                    .fetch_one(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)?
-            },
-        };
+        observe(ENTITY, "delete", async move {
+            let session = match key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("UPDATE sessions SET active = false WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                SessionsBy::CredentialId(uuid) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("UPDATE sessions SET active = false WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid.to_string())
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                SessionsBy::CredentialIp(uuid, ip) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("UPDATE sessions SET active = false WHERE credential_id = $1 AND ip = $2 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid.to_string())
+                        .bind(ip)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+            };
 
-        Ok(Self::Entity::try_from(session)?)
+            Self::Entity::try_from(session)
+        })
+        .await
     }
 
     async fn update(
-        _tx: &mut Transaction<'_, Self::Db>,
-        _key: Self::QueryOne,
-        _update: Self::UpdateInput,
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryOne,
+        update: Self::UpdateInput,
     ) -> Result<Self::Entity, DatabaseError> {
-        unreachable!("")
+        observe(ENTITY, "update", async move {
+            let expires_at = update.expires_at.timestamp_millis();
+            let session = match key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("UPDATE sessions SET expires_at = $2 WHERE id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid.to_string())
+                        .bind(expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                SessionsBy::CredentialId(uuid) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("UPDATE sessions SET expires_at = $2 WHERE credential_id = $1 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid.to_string())
+                        .bind(expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                SessionsBy::CredentialIp(uuid, ip) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("UPDATE sessions SET expires_at = $3 WHERE credential_id = $1 AND ip = $2 RETURNING id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint;"))
+                        .bind(uuid.to_string())
+                        .bind(ip)
+                        .bind(expires_at)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+            };
+
+            Self::Entity::try_from(session)
+        })
+        .await
     }
 
     async fn get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Self::Entity, DatabaseError> {
-        let session = match key {
-            SessionsBy::Id(id) => sqlx::query_as::<_, SqliteSessionsDAO>(
-                "SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE id = $1 LIMIT 1;",
-            )
-                .bind(id.to_string())
-                .fetch_one(&mut **tx)
-                .await
-                .map_err(DatabaseError::from)?,
-            SessionsBy::CredentialId(uuid) => sqlx::query_as::<_, SqliteSessionsDAO>(
-                "SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE credential_id = $1 LIMIT 1;",
-            )
-                .bind(uuid.to_string())
-                .fetch_one(&mut **tx)
-                .await
-                .map_err(DatabaseError::from)?,
-        };
+        observe(ENTITY, "get", async move {
+            let session = match key {
+                SessionsBy::Id(id) => sqlx::query_as::<_, SqliteSessionsDAO>(
+                    checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE id = $1 LIMIT 1;"),
+                )
+                    .bind(id.to_string())
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+                SessionsBy::CredentialId(uuid) => sqlx::query_as::<_, SqliteSessionsDAO>(
+                    checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 LIMIT 1;"),
+                )
+                    .bind(uuid.to_string())
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+                SessionsBy::CredentialIp(uuid, ip) => sqlx::query_as::<_, SqliteSessionsDAO>(
+                    checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 AND ip = $2 LIMIT 1;"),
+                )
+                    .bind(uuid.to_string())
+                    .bind(ip)
+                    .fetch_one(&mut **tx)
+                    .await
+                    .map_err(DatabaseError::from)?,
+            };
 
-        Ok(Self::Entity::try_from(session)?)
+            Self::Entity::try_from(session)
+        })
+        .await
     }
 
     async fn try_get(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Option<Self::Entity>, DatabaseError> {
-        let maybe_session = match key {
-            SessionsBy::Id(uuid) => {
-                sqlx::query_as::<_, SqliteSessionsDAO>("SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE id = $1 LIMIT 1;")
-                    .bind(uuid.to_string())
-                    .fetch_optional(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)?
-            },
-            SessionsBy::CredentialId(uuid) => {
-                sqlx::query_as("SELECT id, created_at, expires_at, credential_id, active FROM sessions WHERE credential_id = $1 LIMIT 1;")
-                    .bind(uuid.to_string())
-                    .fetch_optional(&mut **tx)
-                    .await
-                    .map_err(DatabaseError::from)?
-            }
-        };
+        observe(ENTITY, "try_get", async move {
+            let maybe_session = match key {
+                SessionsBy::Id(uuid) => {
+                    sqlx::query_as::<_, SqliteSessionsDAO>(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE id = $1 LIMIT 1;"))
+                        .bind(uuid.to_string())
+                        .fetch_optional(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                },
+                SessionsBy::CredentialId(uuid) => {
+                    sqlx::query_as(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 LIMIT 1;"))
+                        .bind(uuid.to_string())
+                        .fetch_optional(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+                SessionsBy::CredentialIp(uuid, ip) => {
+                    sqlx::query_as(checked("SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 AND ip = $2 LIMIT 1;"))
+                        .bind(uuid.to_string())
+                        .bind(ip)
+                        .fetch_optional(&mut **tx)
+                        .await
+                        .map_err(DatabaseError::from)?
+                }
+            };
 
-        if let Some(s) = maybe_session {
-            Ok(Some(Self::Entity::try_from(s)?))
-        } else {
-            Ok(None)
-        }
+            if let Some(s) = maybe_session {
+                Ok(Some(Self::Entity::try_from(s)?))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
     }
 
     async fn get_all(
-        _tx: &mut Transaction<'_, Self::Db>,
-        _key: Self::QueryMany,
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
     ) -> Result<Vec<Self::Entity>, DatabaseError> {
-        todo!()
+        observe(ENTITY, "get_all", async move {
+            let sessions = match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_as::<_, SqliteSessionsDAO>(checked(
+                    "SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 ORDER BY created_at DESC, id DESC;",
+                ))
+                .bind(uuid.to_string())
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            sessions.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError> {
+        observe(ENTITY, "get_page", async move {
+            let sessions = match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_as::<_, SqliteSessionsDAO>(checked(
+                    "SELECT id, created_at, expires_at, credential_id, active, ip, user_agent, is_new_device, fingerprint FROM sessions WHERE credential_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2 OFFSET $3;",
+                ))
+                .bind(uuid.to_string())
+                .bind(page.limit)
+                .bind(page.offset)
+                .fetch_all(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?,
+            };
+
+            sessions.into_iter().map(Self::Entity::try_from).collect()
+        })
+        .await
+    }
+
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count", async move {
+            match key {
+                SessionsWhere::CredentialId(uuid) => sqlx::query_scalar::<_, i64>(checked(
+                    "SELECT COUNT(*) FROM sessions WHERE credential_id = $1;",
+                ))
+                .bind(uuid.to_string())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from),
+            }
+        })
+        .await
     }
 
     async fn exists(
@@ -190,3 +343,123 @@ impl EntityRepository for SqliteSessionsRepository {
         Ok(SqliteSessionsRepository::try_get(tx, key).await?.is_some())
     }
 }
+
+#[database::async_trait::async_trait]
+impl ActiveSessions for SqliteSessionsRepository {
+    async fn count_active(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<i64, DatabaseError> {
+        observe(ENTITY, "count_active", async move {
+            // expires_at is stored as unix millis
+            sqlx::query_scalar::<_, i64>(checked("SELECT COUNT(*) FROM sessions WHERE credential_id = $1 AND active AND expires_at > $2;"))
+                .bind(credential_id.to_string())
+                .bind(Utc::now().timestamp_millis())
+                .fetch_one(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)
+        })
+        .await
+    }
+
+    async fn revoke_all(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_all", async move {
+            let result = sqlx::query(checked(
+                "UPDATE sessions SET active = false WHERE credential_id = $1 AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn revoke_others(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+        keep: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "revoke_others", async move {
+            let result = sqlx::query(checked(
+                "UPDATE sessions SET active = false WHERE credential_id = $1 AND id <> $2 AND active;",
+            ))
+            .bind(credential_id.to_string())
+            .bind(keep.to_string())
+            .execute(&mut **tx)
+            .await
+            .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn set_expiry(
+        tx: &mut Transaction<'_, Self::Db>,
+        scope: SessionsScope,
+        expires_at: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "set_expiry", async move {
+            // expires_at is stored as unix millis
+            let now = Utc::now().timestamp_millis();
+            let query = match scope {
+                SessionsScope::Credential(credential_id) => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = $1 WHERE credential_id = $3 AND active AND expires_at > $2;",
+                ))
+                .bind(expires_at.timestamp_millis())
+                .bind(now)
+                .bind(credential_id.to_string()),
+                SessionsScope::All => sqlx::query(checked(
+                    "UPDATE sessions SET expires_at = $1 WHERE active AND expires_at > $2;",
+                ))
+                .bind(expires_at.timestamp_millis())
+                .bind(now),
+            };
+
+            let result = query.execute(&mut **tx).await.map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn delete_expired(
+        tx: &mut Transaction<'_, Self::Db>,
+        now: DateTime<Utc>,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "delete_expired", async move {
+            // A session still backing a live refresh token is kept, `/refresh` refuses
+            // tokens whose session is gone.
+            let result = sqlx::query(checked("DELETE FROM sessions WHERE expires_at < $1 AND id NOT IN (SELECT session_id FROM refresh_tokens WHERE active AND expires_at > $1);"))
+                .bind(now.timestamp_millis())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    async fn delete_by_credential(
+        tx: &mut Transaction<'_, Self::Db>,
+        credential_id: Uuid,
+    ) -> Result<u64, DatabaseError> {
+        observe(ENTITY, "delete_by_credential", async move {
+            let result = sqlx::query(checked("DELETE FROM sessions WHERE credential_id = $1;"))
+                .bind(credential_id.to_string())
+                .execute(&mut **tx)
+                .await
+                .map_err(DatabaseError::from)?;
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+}
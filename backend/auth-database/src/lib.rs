@@ -1,13 +1,13 @@
+use database::guard::checked;
 use database::traits::{BaseDatabase, DatabaseError};
 use sqlx::{Database, Pool};
 
-#[cfg(not(feature = "unit"))]
-use sqlx::PgPool;
+use crate::pool::PoolConfig;
+use crate::ssl::SslOptions;
 
 pub mod entities;
-
-#[cfg(feature = "unit")]
-use sqlx::SqlitePool;
+pub mod pool;
+pub mod ssl;
 
 #[cfg(feature = "unit")]
 pub use crate::entities::credentials::sqlite::SqliteCredentialsRepository as CredentialsRepository;
@@ -15,40 +15,1833 @@ pub use crate::entities::credentials::sqlite::SqliteCredentialsRepository as Cre
 #[cfg(feature = "unit")]
 pub use crate::entities::sessions::sqlite::SqliteSessionsRepository as SessionsRepository;
 
-#[cfg(not(feature = "unit"))]
+#[cfg(feature = "unit")]
+pub use crate::entities::api_keys::sqlite::SqliteApiKeysRepository as ApiKeysRepository;
+
+#[cfg(feature = "unit")]
+pub use crate::entities::refresh_tokens::sqlite::SqliteRefreshTokensRepository as RefreshTokensRepository;
+
+#[cfg(feature = "unit")]
+pub use crate::entities::password_resets::sqlite::SqlitePasswordResetsRepository as PasswordResetsRepository;
+
+#[cfg(feature = "unit")]
+pub use crate::entities::email_verifications::sqlite::SqliteEmailVerificationsRepository as EmailVerificationsRepository;
+
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
 pub use crate::entities::credentials::postgres::PostgresCredentialsRepository as CredentialsRepository;
 
-#[cfg(not(feature = "unit"))]
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
 pub use crate::entities::sessions::postgres::PostgresSessionsRepository as SessionsRepository;
 
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
+pub use crate::entities::api_keys::postgres::PostgresApiKeysRepository as ApiKeysRepository;
+
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
+pub use crate::entities::refresh_tokens::postgres::PostgresRefreshTokensRepository as RefreshTokensRepository;
+
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
+pub use crate::entities::password_resets::postgres::PostgresPasswordResetsRepository as PasswordResetsRepository;
+
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
+pub use crate::entities::email_verifications::postgres::PostgresEmailVerificationsRepository as EmailVerificationsRepository;
+
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub use crate::entities::credentials::mysql::MySqlCredentialsRepository as CredentialsRepository;
+
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub use crate::entities::sessions::mysql::MySqlSessionsRepository as SessionsRepository;
+
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub use crate::entities::api_keys::mysql::MySqlApiKeysRepository as ApiKeysRepository;
+
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub use crate::entities::refresh_tokens::mysql::MySqlRefreshTokensRepository as RefreshTokensRepository;
+
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub use crate::entities::password_resets::mysql::MySqlPasswordResetsRepository as PasswordResetsRepository;
+
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub use crate::entities::email_verifications::mysql::MySqlEmailVerificationsRepository as EmailVerificationsRepository;
+
 pub use database::*;
 
 #[cfg(feature = "unit")]
 pub type DB = sqlx::Sqlite;
 
-#[cfg(not(feature = "unit"))]
+#[cfg(not(any(feature = "unit", feature = "mysql")))]
 pub type DB = sqlx::Postgres;
 
+#[cfg(all(feature = "mysql", not(feature = "unit")))]
+pub type DB = sqlx::MySql;
+
 pub struct AuthDatabase;
 
 impl<DB: Database> BaseDatabase<DB> for AuthDatabase {}
 
 impl AuthDatabase {
     pub async fn connect(url: &str) -> Result<Pool<DB>, DatabaseError> {
-        #[cfg(feature = "unit")]
+        Self::connect_with(url, &PoolConfig::default()).await
+    }
+
+    /// Like [`AuthDatabase::connect`], sizing the pool after `config`.
+    pub async fn connect_with(url: &str, config: &PoolConfig) -> Result<Pool<DB>, DatabaseError> {
+        Self::connect_with_ssl(url, &SslOptions::default(), config).await
+    }
+
+    /// Like [`AuthDatabase::connect_with`], applying `ssl` to the Postgres connection.
+    /// SQLite and MySQL ignore it.
+    pub async fn connect_with_ssl(
+        url: &str,
+        ssl: &SslOptions,
+        config: &PoolConfig,
+    ) -> Result<Pool<DB>, DatabaseError> {
+        #[cfg(any(feature = "unit", feature = "mysql"))]
         {
-            let pool = SqlitePool::connect(url).await?;
-            sqlx::migrate!("./sqlite")
-                .run(&pool)
-                .await
-                .expect("Failed to run sqlite migrations");
+            let _ = ssl;
+            let options: <<DB as Database>::Connection as sqlx::Connection>::Options =
+                url.parse()?;
+            let pool = config
+                .options::<DB>()
+                .connect_with(config.connect_options(options))
+                .await?;
+            Self::migrate(&pool).await?;
             Ok(pool)
         }
 
-        #[cfg(not(feature = "unit"))]
+        #[cfg(not(any(feature = "unit", feature = "mysql")))]
         {
-            let pool = PgPool::connect(url).await?;
+            let pool = config
+                .options::<DB>()
+                .connect_with(config.connect_options(ssl.connect_options(url)?))
+                .await?;
+            Self::migrate(&pool).await?;
             Ok(pool)
         }
     }
+
+    /// Applies the migrations of the enabled backend that haven't run on `pool` yet.
+    ///
+    /// They're embedded at compile time, so a missing migrations directory fails the build.
+    /// Failures at runtime, e.g. an applied migration that was edited since, are returned
+    /// as [`DatabaseError::MigrationFailed`] naming the offending version.
+    pub async fn migrate(pool: &Pool<DB>) -> Result<(), DatabaseError> {
+        #[cfg(feature = "unit")]
+        let migrator = sqlx::migrate!("./sqlite");
+
+        #[cfg(all(feature = "mysql", not(feature = "unit")))]
+        let migrator = sqlx::migrate!("./mysql");
+
+        #[cfg(not(any(feature = "unit", feature = "mysql")))]
+        let migrator = sqlx::migrate!("./postgres");
+
+        migrator
+            .run(pool)
+            .await
+            .map_err(|e| DatabaseError::MigrationFailed(e.to_string()))
+    }
+
+    /// Checks the database answers a read.
+    pub async fn ping(pool: &Pool<DB>) -> Result<(), DatabaseError> {
+        sqlx::query(checked("SELECT 1;")).execute(pool).await?;
+        Ok(())
+    }
+
+    /// Checks the database accepts writes by inserting and deleting a `health_checks`
+    /// row, catching read-only replicas or full disks that still answer reads.
+    pub async fn check_writes(pool: &Pool<DB>) -> Result<(), DatabaseError> {
+        Self::named_transaction(pool, "check_writes", |tx| {
+            Box::pin(async move {
+                #[cfg(not(all(feature = "mysql", not(feature = "unit"))))]
+                {
+                    let id = sqlx::query_scalar::<_, i64>(checked(
+                        "INSERT INTO health_checks DEFAULT VALUES RETURNING id;",
+                    ))
+                    .fetch_one(&mut **tx)
+                    .await?;
+
+                    sqlx::query(checked("DELETE FROM health_checks WHERE id = $1;"))
+                        .bind(id)
+                        .execute(&mut **tx)
+                        .await?;
+                }
+
+                // MySQL has neither DEFAULT VALUES nor RETURNING
+                #[cfg(all(feature = "mysql", not(feature = "unit")))]
+                {
+                    let id = sqlx::query(checked("INSERT INTO health_checks () VALUES ();"))
+                        .execute(&mut **tx)
+                        .await?
+                        .last_insert_id();
+
+                    sqlx::query(checked("DELETE FROM health_checks WHERE id = ?;"))
+                        .bind(id)
+                        .execute(&mut **tx)
+                        .await?;
+                }
+
+                Ok(())
+            })
+        })
+        .await
+    }
+}
+
+#[cfg(feature = "unit")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::credentials::{
+        CreateCredentialsDAO, CredentialsBy, CredentialsDAO, PasswordStorage, Role,
+        UpdateCredentialsDAO,
+    };
+    use database::traits::EntityRepository;
+
+    #[tokio::test]
+    async fn imperative_transaction_rollback() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "rollback@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+        AuthDatabase::rollback(tx).await.unwrap();
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let exists = CredentialsRepository::exists(&mut tx, CredentialsBy::Id(credential.id))
+            .await
+            .unwrap();
+        AuthDatabase::commit(tx).await.unwrap();
+
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn repository_operations_record_latency() {
+        use database::metrics::DB_OPERATION_DURATION_SECONDS;
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "metrics@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+        CredentialsRepository::get(&mut tx, CredentialsBy::Id(credential.id))
+            .await
+            .unwrap();
+        AuthDatabase::commit(tx).await.unwrap();
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let observations = |operation: &str| {
+            snapshot.iter().find_map(|(key, _, _, value)| {
+                let key = key.key();
+                let matches = key.name() == DB_OPERATION_DURATION_SECONDS
+                    && key
+                        .labels()
+                        .any(|l| l.key() == "entity" && l.value() == "credentials")
+                    && key
+                        .labels()
+                        .any(|l| l.key() == "operation" && l.value() == operation);
+
+                match value {
+                    DebugValue::Histogram(values) if matches => Some(values.len()),
+                    _ => None,
+                }
+            })
+        };
+
+        assert_eq!(observations("insert"), Some(1));
+        assert_eq!(observations("get"), Some(1));
+        assert_eq!(observations("delete"), None);
+    }
+
+    /// Fields recorded so far on every `db.transaction` span, in creation order. Spans
+    /// are read while still open since the SQLite worker can keep them alive.
+    #[derive(Clone, Default)]
+    struct TransactionSpans(std::sync::Arc<std::sync::Mutex<Vec<(tracing::span::Id, SpanFields)>>>);
+
+    #[derive(Debug, Default)]
+    struct SpanFields(std::collections::HashMap<&'static str, String>);
+
+    impl tracing::field::Visit for SpanFields {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name(), format!("{value:?}"));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for TransactionSpans {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if attrs.metadata().name() == "db.transaction" {
+                let mut fields = SpanFields::default();
+                attrs.record(&mut fields);
+                self.0.lock().unwrap().push((id.clone(), fields));
+            }
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut spans = self.0.lock().unwrap();
+            if let Some((_, fields)) = spans.iter_mut().rev().find(|(span, _)| span == id) {
+                values.record(fields);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn transactions_are_traced() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = TransactionSpans::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let failed = AuthDatabase::named_transaction(&pool, "failing", |_| {
+            Box::pin(async move { Err::<(), _>(DatabaseError::NotImplemented) })
+        })
+        .await;
+        assert!(failed.is_err());
+        AuthDatabase::check_writes(&pool).await.unwrap();
+
+        let spans = spans.0.lock().unwrap();
+        let [(_, SpanFields(failing)), (_, SpanFields(check_writes))] = spans.as_slice() else {
+            panic!("expected two transaction spans, got {spans:?}");
+        };
+
+        assert_eq!(failing["operation"], "failing");
+        assert_eq!(failing["rolled_back"], "true");
+        assert!(failing.contains_key("begin_seconds"));
+        assert!(!failing.contains_key("commit_seconds"));
+
+        assert_eq!(check_writes["operation"], "check_writes");
+        assert_eq!(check_writes["rolled_back"], "false");
+        assert!(check_writes.contains_key("commit_seconds"));
+    }
+
+    #[tokio::test]
+    async fn imperative_transaction_commit() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "commit@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+        AuthDatabase::commit(tx).await.unwrap();
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let exists = CredentialsRepository::exists(&mut tx, CredentialsBy::Id(credential.id))
+            .await
+            .unwrap();
+
+        assert!(exists);
+    }
+
+    #[tokio::test]
+    async fn count_active_sessions() {
+        use crate::entities::sessions::{ActiveSessions, CreateSessionsDAO, SessionsBy};
+        use sqlx::types::chrono::Utc;
+        use std::time::Duration;
+
+        let hours = |n: u64| Duration::from_secs(n * 60 * 60);
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "count@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut sessions = Vec::new();
+        for expires_at in [
+            Utc::now() + hours(1),
+            Utc::now() + hours(2),
+            Utc::now() + hours(3),
+            Utc::now() - hours(1),
+        ] {
+            let session = SessionsRepository::insert(
+                &mut tx,
+                CreateSessionsDAO {
+                    expires_at,
+                    credential_id: credential.id,
+                    ip: None,
+                    user_agent: None,
+                    is_new_device: false,
+                    fingerprint: None,
+                },
+            )
+            .await
+            .unwrap();
+            sessions.push(session);
+        }
+        SessionsRepository::delete(&mut tx, SessionsBy::Id(sessions[2].id))
+            .await
+            .unwrap();
+
+        let count = SessionsRepository::count_active(&mut tx, credential.id)
+            .await
+            .unwrap();
+        let other = SessionsRepository::count_active(&mut tx, sqlx::types::Uuid::new_v4())
+            .await
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(other, 0);
+    }
+
+    #[tokio::test]
+    async fn credentials_start_unverified() {
+        use crate::entities::credentials::EmailVerification;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "verified@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        let marked = CredentialsRepository::mark_verified(&mut tx, credential.id)
+            .await
+            .unwrap();
+        let marked_again = CredentialsRepository::mark_verified(&mut tx, credential.id)
+            .await
+            .unwrap();
+        let unknown = CredentialsRepository::mark_verified(&mut tx, sqlx::types::Uuid::new_v4())
+            .await
+            .unwrap();
+        let fetched = CredentialsRepository::get(&mut tx, CredentialsBy::Id(credential.id))
+            .await
+            .unwrap();
+
+        assert!(!credential.verified);
+        assert!(marked && marked_again && !unknown);
+        assert!(fetched.verified);
+    }
+
+    #[tokio::test]
+    async fn revoked_api_keys_are_not_listed() {
+        use crate::entities::api_keys::{ApiKeysBy, ApiKeysWhere, CreateApiKeysDAO};
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "keys@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut keys = Vec::new();
+        for hash in ["first", "second"] {
+            let key = ApiKeysRepository::insert(
+                &mut tx,
+                CreateApiKeysDAO {
+                    credential_id: credential.id,
+                    key_hash: hash.to_string(),
+                    label: None,
+                    scopes: "read write".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+            keys.push(key);
+        }
+        let revoked = ApiKeysRepository::delete(&mut tx, ApiKeysBy::Id(keys[0].id))
+            .await
+            .unwrap();
+
+        let listed = ApiKeysRepository::get_all(&mut tx, ApiKeysWhere::CredentialId(credential.id))
+            .await
+            .unwrap();
+        let fetched = ApiKeysRepository::get(&mut tx, ApiKeysBy::KeyHash("first".to_string()))
+            .await
+            .unwrap();
+
+        assert!(!revoked.active);
+        assert_eq!(listed, vec![keys[1].clone()]);
+        assert_eq!(fetched, revoked);
+    }
+
+    #[tokio::test]
+    async fn refresh_token_families_list_used_tokens() {
+        use crate::entities::refresh_tokens::{
+            CreateRefreshTokensDAO, RefreshTokensBy, RefreshTokensWhere,
+        };
+        use sqlx::types::Uuid;
+        use sqlx::types::chrono::Utc;
+        use std::time::Duration;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "refresh@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        let family_id = Uuid::new_v4();
+        let mut tokens = Vec::new();
+        for hash in ["first", "second"] {
+            let token = RefreshTokensRepository::insert(
+                &mut tx,
+                CreateRefreshTokensDAO {
+                    credential_id: credential.id,
+                    family_id,
+                    session_id: Uuid::new_v4(),
+                    token_hash: hash.to_string(),
+                    expires_at: Utc::now() + Duration::from_secs(60),
+                },
+            )
+            .await
+            .unwrap();
+            tokens.push(token);
+        }
+        let used = RefreshTokensRepository::delete(&mut tx, RefreshTokensBy::Id(tokens[0].id))
+            .await
+            .unwrap();
+
+        let family =
+            RefreshTokensRepository::get_all(&mut tx, RefreshTokensWhere::FamilyId(family_id))
+                .await
+                .unwrap();
+        let fetched =
+            RefreshTokensRepository::get(&mut tx, RefreshTokensBy::TokenHash("first".to_string()))
+                .await
+                .unwrap();
+
+        assert!(!used.active);
+        assert_eq!(family.len(), 2);
+        assert!(family.contains(&used) && family.contains(&tokens[1]));
+        assert_eq!(fetched, used);
+    }
+
+    #[tokio::test]
+    async fn delete_expired_sessions() {
+        use crate::entities::sessions::{ActiveSessions, CreateSessionsDAO, SessionsBy};
+        use sqlx::types::chrono::Utc;
+        use std::time::Duration;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "purge@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        let hour = Duration::from_secs(60 * 60);
+        let mut sessions = Vec::new();
+        for expires_at in [Utc::now() - hour, Utc::now() + hour] {
+            let session = SessionsRepository::insert(
+                &mut tx,
+                CreateSessionsDAO {
+                    expires_at,
+                    credential_id: credential.id,
+                    ip: None,
+                    user_agent: None,
+                    is_new_device: false,
+                    fingerprint: None,
+                },
+            )
+            .await
+            .unwrap();
+            sessions.push(session.id);
+        }
+
+        let deleted = SessionsRepository::delete_expired(&mut tx, Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+
+        let expired = SessionsRepository::exists(&mut tx, SessionsBy::Id(sessions[0]))
+            .await
+            .unwrap();
+        let live = SessionsRepository::exists(&mut tx, SessionsBy::Id(sessions[1]))
+            .await
+            .unwrap();
+        assert!(!expired);
+        assert!(live);
+    }
+
+    #[tokio::test]
+    async fn transaction_commit_failure_is_distinct() {
+        use crate::entities::sessions::{ActiveSessions, CreateSessionsDAO};
+        use sqlx::types::{Uuid, chrono::Utc};
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let credential_id = Uuid::new_v4();
+
+        // With deferred foreign keys the dangling session is only rejected on commit.
+        let result = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                sqlx::query("PRAGMA defer_foreign_keys = ON;")
+                    .execute(&mut **tx)
+                    .await?;
+                SessionsRepository::insert(
+                    tx,
+                    CreateSessionsDAO {
+                        expires_at: Utc::now(),
+                        credential_id,
+                        ip: None,
+                        user_agent: None,
+                        is_new_device: false,
+                        fingerprint: None,
+                    },
+                )
+                .await
+            })
+        })
+        .await;
+
+        assert!(matches!(result, Err(DatabaseError::CommitFailed(_))));
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let count = SessionsRepository::count_active(&mut tx, credential_id)
+            .await
+            .unwrap();
+
+        assert_eq!(count, 0);
+    }
+
+    type IdFuture<'a> = std::pin::Pin<
+        Box<dyn Future<Output = Result<sqlx::types::Uuid, DatabaseError>> + Send + 'a>,
+    >;
+
+    /// Inserts the same credential on every attempt, failing the first `failures` ones.
+    fn retried_sign_up(
+        attempts: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        failures: usize,
+        error: fn() -> DatabaseError,
+    ) -> impl for<'a> Fn(&'a mut sqlx::Transaction<'_, DB>) -> IdFuture<'a> + Send + Sync {
+        move |tx| {
+            let attempts = attempts.clone();
+            Box::pin(async move {
+                let credential = CredentialsRepository::insert(
+                    tx,
+                    CreateCredentialsDAO {
+                        email: "retry@gmail.com".to_string(),
+                        password: "Ej42fkj!yI!Cj9".to_string(),
+                        role: Role::User,
+                        password_storage: PasswordStorage::Inline,
+                    },
+                )
+                .await?;
+
+                if attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < failures {
+                    return Err(error());
+                }
+
+                Ok(credential.id)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn transaction_retries_serialization_failure() {
+        use std::sync::{Arc, atomic::AtomicUsize};
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        // The first attempt's insert must be rolled back, or the retry hits the unique email.
+        let id = AuthDatabase::transaction_with_retry(
+            &pool,
+            1,
+            retried_sign_up(attempts.clone(), 1, || DatabaseError::SerializationFailure),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        assert!(
+            CredentialsRepository::exists(&mut tx, CredentialsBy::Id(id))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_retries_lost_connections_with_backoff() {
+        use std::sync::{Arc, atomic::AtomicUsize, atomic::Ordering};
+        use std::time::Instant;
+
+        for error in [
+            || DatabaseError::ConnectionNotAvailable,
+            || DatabaseError::CommunicationError,
+        ] {
+            // every attempt inserts the same email
+            let pool = AuthDatabase::connect(":memory:").await.unwrap();
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let start = Instant::now();
+            let result = AuthDatabase::transaction_with_retry(
+                &pool,
+                3,
+                retried_sign_up(attempts.clone(), 2, error),
+            )
+            .await;
+
+            assert!(result.is_ok(), "{:?}", error());
+            assert_eq!(attempts.load(Ordering::SeqCst), 3);
+            assert!(start.elapsed() >= traits::RETRY_BASE_DELAY * 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn transaction_retry_gives_up() {
+        use std::sync::{Arc, atomic::AtomicUsize, atomic::Ordering};
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let result = AuthDatabase::transaction_with_retry(
+            &pool,
+            1,
+            retried_sign_up(attempts.clone(), 2, || DatabaseError::SerializationFailure),
+        )
+        .await;
+
+        assert!(matches!(result, Err(DatabaseError::SerializationFailure)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let result = AuthDatabase::transaction_with_retry(
+            &pool,
+            3,
+            retried_sign_up(attempts.clone(), 1, || DatabaseError::Busy),
+        )
+        .await;
+
+        assert!(matches!(result, Err(DatabaseError::Busy)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let result = AuthDatabase::transaction_with_retry(
+            &pool,
+            3,
+            retried_sign_up(attempts.clone(), 1, || {
+                DatabaseError::QueryFailed("syntax error".to_string())
+            }),
+        )
+        .await;
+
+        assert!(matches!(result, Err(DatabaseError::QueryFailed(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let exists = CredentialsRepository::exists(
+            &mut tx,
+            CredentialsBy::Email("retry@gmail.com".to_string()),
+        )
+        .await
+        .unwrap();
+
+        assert!(!exists);
+    }
+
+    /// The raw `credentials.password` column, without the `credential_secrets` fallback.
+    async fn row_password(tx: &mut sqlx::Transaction<'_, DB>, id: sqlx::types::Uuid) -> String {
+        sqlx::query_scalar::<_, String>("SELECT password FROM credentials WHERE id = $1;")
+            .bind(id.to_string())
+            .fetch_one(&mut **tx)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn separate_password_storage() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "secret@gmail.com".to_string(),
+                password: "hash".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Separate,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(credential.password, "hash");
+        assert_eq!(row_password(&mut tx, credential.id).await, "");
+
+        let fetched = CredentialsRepository::get(&mut tx, CredentialsBy::Email(credential.email))
+            .await
+            .unwrap();
+        assert_eq!(fetched.password, "hash");
+
+        let updated = CredentialsRepository::update(
+            &mut tx,
+            CredentialsBy::Id(credential.id),
+            UpdateCredentialsDAO {
+                password: "rotated".to_string(),
+                active: true,
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+                version: credential.version,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.password, "rotated");
+        assert_eq!(row_password(&mut tx, credential.id).await, "rotated");
+
+        let secrets = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM credential_secrets;")
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(secrets, 0);
+
+        let deactivated = CredentialsRepository::delete(&mut tx, CredentialsBy::Id(credential.id))
+            .await
+            .unwrap();
+        assert_eq!(deactivated.password, "rotated");
+    }
+
+    /// Rows of `table` whose `column` is `id`, inactive ones included.
+    async fn count_rows(
+        tx: &mut sqlx::Transaction<'_, DB>,
+        table: &str,
+        column: &str,
+        id: sqlx::types::Uuid,
+    ) -> i64 {
+        let query = format!("SELECT COUNT(*) FROM {table} WHERE {column} = $1;");
+        sqlx::query_scalar::<_, i64>(&query)
+            .bind(id.to_string())
+            .fetch_one(&mut **tx)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hard_delete_removes_the_credential_and_its_rows() {
+        use crate::entities::credentials::HardDelete;
+        use crate::entities::sessions::{ActiveSessions, CreateSessionsDAO};
+        use sqlx::types::chrono::Utc;
+        use std::time::Duration;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let mut credentials = Vec::new();
+        for email in ["erased@gmail.com", "kept@gmail.com"] {
+            let credential = CredentialsRepository::insert(
+                &mut tx,
+                CreateCredentialsDAO {
+                    email: email.to_string(),
+                    password: "hash".to_string(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Separate,
+                },
+            )
+            .await
+            .unwrap();
+            for _ in 0..2 {
+                SessionsRepository::insert(
+                    &mut tx,
+                    CreateSessionsDAO {
+                        expires_at: Utc::now() + Duration::from_secs(60 * 60),
+                        credential_id: credential.id,
+                        ip: None,
+                        user_agent: None,
+                        is_new_device: false,
+                        fingerprint: None,
+                    },
+                )
+                .await
+                .unwrap();
+            }
+            credentials.push(credential);
+        }
+        let (erased, kept) = (&credentials[0], &credentials[1]);
+
+        let deleted_sessions = SessionsRepository::delete_by_credential(&mut tx, erased.id)
+            .await
+            .unwrap();
+        let deleted = CredentialsRepository::hard_delete(&mut tx, CredentialsBy::Id(erased.id))
+            .await
+            .unwrap();
+        let missing =
+            CredentialsRepository::hard_delete(&mut tx, CredentialsBy::Id(erased.id)).await;
+
+        let rows = count_rows(&mut tx, "credentials", "id", erased.id).await;
+        let sessions = count_rows(&mut tx, "sessions", "credential_id", erased.id).await;
+        let secrets = count_rows(&mut tx, "credential_secrets", "credential_id", erased.id).await;
+        let kept_sessions = count_rows(&mut tx, "sessions", "credential_id", kept.id).await;
+
+        assert_eq!(deleted_sessions, 2);
+        assert_eq!(deleted, *erased);
+        assert!(matches!(missing, Err(DatabaseError::NotFound(_))));
+        assert_eq!((rows, sessions, secrets), (0, 0, 0));
+        assert_eq!(kept_sessions, 2);
+        assert!(
+            CredentialsRepository::try_get(&mut tx, CredentialsBy::Id(kept.id))
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn health_checks() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        AuthDatabase::ping(&pool).await.unwrap();
+        AuthDatabase::check_writes(&pool).await.unwrap();
+
+        let rows = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM health_checks;")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+
+        sqlx::query("CREATE TRIGGER block_writes BEFORE INSERT ON health_checks BEGIN SELECT RAISE(ABORT, 'read-only'); END;")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        AuthDatabase::ping(&pool).await.unwrap();
+        assert!(AuthDatabase::check_writes(&pool).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn get_all_orders_newest_first_then_by_id() {
+        use crate::entities::credentials::CredentialsWhere;
+        use crate::entities::sessions::{CreateSessionsDAO, SessionsWhere};
+        use sqlx::types::chrono::Utc;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+
+        let mut credentials = Vec::new();
+        for n in 0..4 {
+            let credential = CredentialsRepository::insert(
+                &mut tx,
+                CreateCredentialsDAO {
+                    email: format!("order{n}@gmail.com"),
+                    password: "Ej42fkj!yI!Cj9".to_string(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
+                },
+            )
+            .await
+            .unwrap();
+            credentials.push(credential);
+        }
+        for _ in 0..4 {
+            SessionsRepository::insert(
+                &mut tx,
+                CreateSessionsDAO {
+                    expires_at: Utc::now(),
+                    credential_id: credentials[0].id,
+                    ip: None,
+                    user_agent: None,
+                    is_new_device: false,
+                    fingerprint: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        // every row shares a timestamp except the last credential, which is newer
+        sqlx::query("UPDATE credentials SET created_at = 1000 WHERE id <> $1;")
+            .bind(credentials[3].id.to_string())
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE credentials SET created_at = 2000 WHERE id = $1;")
+            .bind(credentials[3].id.to_string())
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE sessions SET created_at = 1000 WHERE credential_id = $1;")
+            .bind(credentials[0].id.to_string())
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let first = CredentialsRepository::get_all(&mut tx, CredentialsWhere::Active(true))
+            .await
+            .unwrap();
+        let second = CredentialsRepository::get_all(&mut tx, CredentialsWhere::Active(true))
+            .await
+            .unwrap();
+        let mut tied: Vec<_> = credentials[..3].iter().map(|c| c.id).collect();
+        tied.sort_by_key(|id| std::cmp::Reverse(id.to_string()));
+        let expected: Vec<_> = std::iter::once(credentials[3].id).chain(tied).collect();
+
+        assert_eq!(first, second);
+        assert_eq!(first.iter().map(|c| c.id).collect::<Vec<_>>(), expected);
+
+        let first =
+            SessionsRepository::get_all(&mut tx, SessionsWhere::CredentialId(credentials[0].id))
+                .await
+                .unwrap();
+        let second =
+            SessionsRepository::get_all(&mut tx, SessionsWhere::CredentialId(credentials[0].id))
+                .await
+                .unwrap();
+        let mut expected: Vec<_> = first.iter().map(|s| s.id).collect();
+        expected.sort_by_key(|id| std::cmp::Reverse(id.to_string()));
+
+        assert_eq!(first.len(), 4);
+        assert_eq!(first, second);
+        assert_eq!(first.iter().map(|s| s.id).collect::<Vec<_>>(), expected);
+    }
+
+    #[tokio::test]
+    async fn sessions_get_all_newest_first() {
+        use crate::entities::sessions::{CreateSessionsDAO, SessionsWhere};
+        use sqlx::types::chrono::Utc;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "list@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut sessions = Vec::new();
+        for _ in 0..2 {
+            let session = SessionsRepository::insert(
+                &mut tx,
+                CreateSessionsDAO {
+                    expires_at: Utc::now(),
+                    credential_id: credential.id,
+                    ip: None,
+                    user_agent: None,
+                    is_new_device: false,
+                    fingerprint: None,
+                },
+            )
+            .await
+            .unwrap();
+            sessions.push(session.id);
+        }
+        sqlx::query("UPDATE sessions SET created_at = created_at - 60000 WHERE id = $1;")
+            .bind(sessions[0].to_string())
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+
+        let listed =
+            SessionsRepository::get_all(&mut tx, SessionsWhere::CredentialId(credential.id))
+                .await
+                .unwrap();
+
+        assert_eq!(
+            listed.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![sessions[1], sessions[0]]
+        );
+        assert!(listed[0].created_at > listed[1].created_at);
+    }
+
+    #[tokio::test]
+    async fn sessions_get_page() {
+        use crate::entities::sessions::{CreateSessionsDAO, SessionsWhere};
+        use database::traits::Pagination;
+        use sqlx::types::chrono::Utc;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "page@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..10 {
+            SessionsRepository::insert(
+                &mut tx,
+                CreateSessionsDAO {
+                    expires_at: Utc::now(),
+                    credential_id: credential.id,
+                    ip: None,
+                    user_agent: None,
+                    is_new_device: false,
+                    fingerprint: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let key = || SessionsWhere::CredentialId(credential.id);
+        let all = SessionsRepository::get_all(&mut tx, key()).await.unwrap();
+        let page = |n: i64| Pagination {
+            limit: 3,
+            offset: 3 * (n - 1),
+        };
+
+        let second = SessionsRepository::get_page(&mut tx, key(), page(2))
+            .await
+            .unwrap();
+        assert_eq!(
+            second.iter().map(|s| s.id).collect::<Vec<_>>(),
+            all[3..6].iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+
+        let last = SessionsRepository::get_page(&mut tx, key(), page(4))
+            .await
+            .unwrap();
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].id, all[9].id);
+
+        let past_the_end = SessionsRepository::get_page(&mut tx, key(), page(5))
+            .await
+            .unwrap();
+        assert!(past_the_end.is_empty());
+    }
+
+    #[tokio::test]
+    async fn credentials_get_all_filters_by_active() {
+        use crate::entities::credentials::CredentialsWhere;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+
+        let mut ids = Vec::new();
+        for n in 0..5 {
+            let credential = CredentialsRepository::insert(
+                &mut tx,
+                CreateCredentialsDAO {
+                    email: format!("partition{n}@gmail.com"),
+                    password: "Ej42fkj!yI!Cj9".to_string(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
+                },
+            )
+            .await
+            .unwrap();
+            ids.push(credential.id);
+        }
+        for id in [ids[1], ids[3]] {
+            CredentialsRepository::delete(&mut tx, CredentialsBy::Id(id))
+                .await
+                .unwrap();
+        }
+
+        let active = CredentialsRepository::get_all(&mut tx, CredentialsWhere::Active(true))
+            .await
+            .unwrap();
+        let inactive = CredentialsRepository::get_all(&mut tx, CredentialsWhere::Active(false))
+            .await
+            .unwrap();
+        let sorted = |credentials: &[CredentialsDAO]| {
+            let mut ids: Vec<_> = credentials.iter().map(|c| c.id).collect();
+            ids.sort();
+            ids
+        };
+        let expected = |picked: &[usize]| {
+            let mut expected: Vec<_> = picked.iter().map(|&n| ids[n]).collect();
+            expected.sort();
+            expected
+        };
+
+        assert!(active.iter().all(|c| c.active));
+        assert!(inactive.iter().all(|c| !c.active));
+        assert_eq!(sorted(&active), expected(&[0, 2, 4]));
+        assert_eq!(sorted(&inactive), expected(&[1, 3]));
+    }
+
+    #[tokio::test]
+    async fn count_applies_the_filter() {
+        use crate::entities::credentials::CredentialsWhere;
+        use crate::entities::sessions::{CreateSessionsDAO, SessionsWhere};
+        use sqlx::types::{Uuid, chrono::Utc};
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+
+        let mut ids = Vec::new();
+        for n in 0..3 {
+            let credential = CredentialsRepository::insert(
+                &mut tx,
+                CreateCredentialsDAO {
+                    email: format!("count{n}@gmail.com"),
+                    password: "Ej42fkj!yI!Cj9".to_string(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
+                },
+            )
+            .await
+            .unwrap();
+            ids.push(credential.id);
+        }
+        CredentialsRepository::delete(&mut tx, CredentialsBy::Id(ids[2]))
+            .await
+            .unwrap();
+
+        for credential_id in [ids[0], ids[0], ids[0], ids[1]] {
+            SessionsRepository::insert(
+                &mut tx,
+                CreateSessionsDAO {
+                    expires_at: Utc::now(),
+                    credential_id,
+                    ip: None,
+                    user_agent: None,
+                    is_new_device: false,
+                    fingerprint: None,
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        for (active, expected) in [(true, 2), (false, 1)] {
+            let count = CredentialsRepository::count(&mut tx, CredentialsWhere::Active(active))
+                .await
+                .unwrap();
+            assert_eq!(count, expected);
+        }
+
+        for (credential_id, expected) in [(ids[0], 3), (ids[1], 1), (Uuid::new_v4(), 0)] {
+            let count =
+                SessionsRepository::count(&mut tx, SessionsWhere::CredentialId(credential_id))
+                    .await
+                    .unwrap();
+            assert_eq!(count, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn session_update_extends_expiry() {
+        use crate::entities::sessions::{CreateSessionsDAO, SessionsBy, UpdateSessionsDAO};
+        use sqlx::types::chrono::{DateTime, Utc};
+        use std::time::Duration;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "sliding@gmail.com".to_string(),
+                password: "Ej42fkj!yI!Cj9".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+        let session = SessionsRepository::insert(
+            &mut tx,
+            CreateSessionsDAO {
+                expires_at: Utc::now() + Duration::from_secs(60),
+                credential_id: credential.id,
+                ip: None,
+                user_agent: None,
+                is_new_device: false,
+                fingerprint: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        // sqlite keeps millisecond precision
+        let expires_at = DateTime::from_timestamp_millis(
+            (Utc::now() + Duration::from_secs(3600)).timestamp_millis(),
+        )
+        .unwrap();
+        let updated = SessionsRepository::update(
+            &mut tx,
+            SessionsBy::Id(session.id),
+            UpdateSessionsDAO { expires_at },
+        )
+        .await
+        .unwrap();
+        let fetched = SessionsRepository::get(&mut tx, SessionsBy::Id(session.id))
+            .await
+            .unwrap();
+
+        assert_eq!(updated.expires_at, expires_at);
+        assert_eq!(fetched.expires_at, expires_at);
+        assert_eq!(updated.created_at, session.created_at);
+        assert!(
+            SessionsRepository::update(
+                &mut tx,
+                SessionsBy::Id(sqlx::types::Uuid::new_v4()),
+                UpdateSessionsDAO { expires_at },
+            )
+            .await
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_credential_updates_only_one_wins() {
+        use database::traits::DatabaseError;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email: "versioned@gmail.com".to_string(),
+                password: "hash".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap();
+        AuthDatabase::commit(tx).await.unwrap();
+        assert_eq!(credential.version, 0);
+
+        // A password change and an admin edit, both based on the version read above.
+        let password_change = UpdateCredentialsDAO {
+            password: "rotated".to_string(),
+            active: true,
+            role: Role::User,
+            password_storage: PasswordStorage::Inline,
+            version: credential.version,
+        };
+        let admin_edit = UpdateCredentialsDAO {
+            password: credential.password.clone(),
+            active: true,
+            role: Role::Admin,
+            password_storage: PasswordStorage::Inline,
+            version: credential.version,
+        };
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let updated = CredentialsRepository::update(
+            &mut tx,
+            CredentialsBy::Id(credential.id),
+            password_change,
+        )
+        .await
+        .unwrap();
+        AuthDatabase::commit(tx).await.unwrap();
+        assert_eq!(updated.version, 1);
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let stale = CredentialsRepository::update(
+            &mut tx,
+            CredentialsBy::Email(credential.email.clone()),
+            admin_edit,
+        )
+        .await;
+        assert!(
+            matches!(stale, Err(DatabaseError::Conflict(_))),
+            "{stale:?}"
+        );
+
+        let current = CredentialsRepository::get(&mut tx, CredentialsBy::Id(credential.id))
+            .await
+            .unwrap();
+        assert_eq!(current.password, "rotated");
+        assert_eq!(current.role, Role::User);
+        assert_eq!(current.version, 1);
+
+        let missing = CredentialsRepository::update(
+            &mut tx,
+            CredentialsBy::Id(sqlx::types::Uuid::new_v4()),
+            UpdateCredentialsDAO {
+                password: "hash".to_string(),
+                active: true,
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+                version: 0,
+            },
+        )
+        .await;
+        assert!(
+            matches!(missing, Err(DatabaseError::NotFound(_))),
+            "{missing:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn single_connection_pool_serializes_access() {
+        use std::time::Duration;
+
+        let config = PoolConfig {
+            max_connections: 1,
+            acquire_timeout: Duration::from_millis(100),
+            ..PoolConfig::default()
+        };
+        let pool = AuthDatabase::connect_with(":memory:", &config)
+            .await
+            .unwrap();
+        assert_eq!(pool.options().get_max_connections(), 1);
+
+        let held = AuthDatabase::begin(&pool).await.unwrap();
+        let waiting = AuthDatabase::begin(&pool).await;
+        assert!(
+            matches!(waiting, Err(DatabaseError::ConnectionNotAvailable)),
+            "{waiting:?}"
+        );
+
+        AuthDatabase::commit(held).await.unwrap();
+        let next = AuthDatabase::begin(&pool).await.unwrap();
+        AuthDatabase::commit(next).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn migrate_is_idempotent() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        AuthDatabase::migrate(&pool).await.unwrap();
+
+        let applied = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM _sqlx_migrations WHERE success = TRUE;",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let migrations = sqlx::migrate!("./sqlite")
+            .iter()
+            .filter(|migration| migration.migration_type.is_up_migration())
+            .count();
+        assert_eq!(applied as usize, migrations);
+    }
+
+    #[tokio::test]
+    async fn migrate_returns_failures_instead_of_panicking() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let version = sqlx::query_scalar::<_, i64>("SELECT MIN(version) FROM _sqlx_migrations;")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = X'00' WHERE version = $1;")
+            .bind(version)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let error = AuthDatabase::migrate(&pool).await.unwrap_err();
+
+        assert!(
+            matches!(&error, DatabaseError::MigrationFailed(message) if message.contains(&version.to_string())),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn sessions_indexes_exist() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        let indexes = sqlx::query_scalar::<_, String>(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'sessions' ORDER BY name;",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        for index in [
+            "idx_sessions_active",
+            "idx_sessions_credential_id",
+            "idx_sessions_expires_at",
+        ] {
+            assert!(indexes.iter().any(|name| name == index), "missing {index}");
+        }
+    }
+}
+
+#[cfg(all(feature = "integration", not(feature = "mysql")))]
+#[cfg(test)]
+mod integration_tests {
+    use super::*;
+
+    /// `(name, type, not null)` of every column of `table`, sorted by name.
+    type Columns = Vec<(String, String, bool)>;
+
+    /// Type SQLite stores a Postgres column type as, see the `sqlite` migrations.
+    fn sqlite_type(postgres: &str) -> &'static str {
+        match postgres {
+            "uuid" | "character varying" | "text" => "TEXT",
+            // unix seconds or millis
+            "timestamp with time zone" | "integer" | "bigint" => "INTEGER",
+            "boolean" => "BOOLEAN",
+            other => panic!("no SQLite mapping for Postgres type {other}"),
+        }
+    }
+
+    async fn postgres_columns(pool: &sqlx::PgPool, table: &str) -> Columns {
+        let rows = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT column_name::TEXT, data_type::TEXT, is_nullable::TEXT
+            FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1
+            ORDER BY column_name;",
+        )
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .unwrap();
+
+        rows.into_iter()
+            .map(|(name, data_type, nullable)| {
+                (name, sqlite_type(&data_type).to_string(), nullable == "NO")
+            })
+            .collect()
+    }
+
+    async fn sqlite_columns(pool: &sqlx::SqlitePool, table: &str) -> Columns {
+        // Primary keys are implicitly NOT NULL in Postgres but not in SQLite.
+        sqlx::query_as::<_, (String, String, bool)>(
+            "SELECT name, type, \"notnull\" OR pk > 0 FROM pragma_table_info($1) ORDER BY name;",
+        )
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sqlite_and_postgres_schemas_match() {
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        let postgres = AuthDatabase::connect(&database_url).await.unwrap();
+
+        let sqlite = sqlx::SqlitePool::connect(":memory:").await.unwrap();
+        sqlx::migrate!("./sqlite").run(&sqlite).await.unwrap();
+
+        for table in [
+            "credentials",
+            "sessions",
+            "credential_secrets",
+            "api_keys",
+            "refresh_tokens",
+            "password_resets",
+            "email_verifications",
+        ] {
+            let expected = postgres_columns(&postgres, table).await;
+            assert!(!expected.is_empty(), "{table} is missing from Postgres");
+            assert_eq!(
+                sqlite_columns(&sqlite, table).await,
+                expected,
+                "{table} differs between the sqlite and postgres migrations"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_creates_the_schema() {
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        let pool = AuthDatabase::connect(&database_url).await.unwrap();
+
+        let tables = sqlx::query_scalar::<_, String>(
+            "SELECT table_name::TEXT FROM information_schema.tables
+            WHERE table_schema = current_schema();",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        for table in [
+            "credentials",
+            "sessions",
+            "credential_secrets",
+            "health_checks",
+            "api_keys",
+            "refresh_tokens",
+            "password_resets",
+            "email_verifications",
+        ] {
+            assert!(tables.iter().any(|name| name == table), "missing {table}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_indexes_exist() {
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        let pool = AuthDatabase::connect(&database_url).await.unwrap();
+
+        let indexes = sqlx::query_scalar::<_, String>(
+            "SELECT indexname::TEXT FROM pg_indexes WHERE tablename = 'sessions' ORDER BY indexname;",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        for index in [
+            "idx_sessions_active",
+            "idx_sessions_credential_id",
+            "idx_sessions_expires_at",
+        ] {
+            assert!(indexes.iter().any(|name| name == index), "missing {index}");
+        }
+    }
+}
+
+#[cfg(all(feature = "integration", feature = "mysql", not(feature = "unit")))]
+#[cfg(test)]
+mod mysql_integration_tests {
+    use super::*;
+    use crate::entities::credentials::{
+        CreateCredentialsDAO, CredentialsBy, PasswordStorage, Role, SignInAttempts,
+        UpdateCredentialsDAO,
+    };
+    use crate::entities::sessions::{ActiveSessions, CreateSessionsDAO, SessionsBy};
+    use database::traits::EntityRepository;
+    use sqlx::types::chrono::Utc;
+    use std::time::Duration;
+
+    async fn connect() -> Pool<DB> {
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    fn credential(email: &str, password_storage: PasswordStorage) -> CreateCredentialsDAO {
+        CreateCredentialsDAO {
+            email: email.to_string(),
+            password: "Ej42fkj!yI!Cj9".to_string(),
+            role: Role::User,
+            password_storage,
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_creates_the_schema() {
+        let pool = connect().await;
+
+        let tables = sqlx::query_scalar::<_, String>(
+            "SELECT CAST(table_name AS CHAR) FROM information_schema.tables
+            WHERE table_schema = DATABASE();",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+
+        for table in [
+            "credentials",
+            "sessions",
+            "credential_secrets",
+            "health_checks",
+            "api_keys",
+            "refresh_tokens",
+            "password_resets",
+            "email_verifications",
+        ] {
+            assert!(tables.iter().any(|name| name == table), "missing {table}");
+        }
+
+        AuthDatabase::check_writes(&pool).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn credentials_round_trip() {
+        let pool = connect().await;
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+
+        let created = CredentialsRepository::insert(
+            &mut tx,
+            credential("mysql-round-trip@gmail.com", PasswordStorage::Separate),
+        )
+        .await
+        .unwrap();
+        let fetched = CredentialsRepository::get(&mut tx, CredentialsBy::Id(created.id))
+            .await
+            .unwrap();
+        assert_eq!(fetched, created);
+        assert_eq!(fetched.password, "Ej42fkj!yI!Cj9");
+
+        let update = UpdateCredentialsDAO {
+            password: "new-hash".to_string(),
+            active: true,
+            role: Role::Admin,
+            password_storage: PasswordStorage::Inline,
+            version: created.version,
+        };
+        let updated = CredentialsRepository::update(
+            &mut tx,
+            CredentialsBy::Email(created.email.clone()),
+            update.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.version, created.version + 1);
+        assert_eq!(updated.role, Role::Admin);
+
+        let stale = CredentialsRepository::update(&mut tx, CredentialsBy::Id(created.id), update)
+            .await
+            .unwrap_err();
+        assert!(matches!(stale, DatabaseError::Conflict(_)), "{stale:?}");
+
+        let deleted = CredentialsRepository::delete(&mut tx, CredentialsBy::Id(created.id))
+            .await
+            .unwrap();
+        assert!(!deleted.active);
+
+        AuthDatabase::rollback(tx).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn record_failure_locks_at_the_threshold() {
+        let pool = connect().await;
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+
+        let created = CredentialsRepository::insert(
+            &mut tx,
+            credential("mysql-lockout@gmail.com", PasswordStorage::Inline),
+        )
+        .await
+        .unwrap();
+        let lock_until = Utc::now() + Duration::from_secs(15 * 60);
+
+        let first = CredentialsRepository::record_failure(&mut tx, created.id, 2, lock_until)
+            .await
+            .unwrap();
+        assert_eq!(first, None);
+
+        let second = CredentialsRepository::record_failure(&mut tx, created.id, 2, lock_until)
+            .await
+            .unwrap()
+            .expect("locked at the threshold");
+        assert_eq!(second.timestamp_micros(), lock_until.timestamp_micros());
+
+        let locked = CredentialsRepository::get(&mut tx, CredentialsBy::Id(created.id))
+            .await
+            .unwrap();
+        assert_eq!(locked.failed_attempts, 0);
+
+        AuthDatabase::rollback(tx).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn sessions_round_trip() {
+        let pool = connect().await;
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+
+        let owner = CredentialsRepository::insert(
+            &mut tx,
+            credential("mysql-sessions@gmail.com", PasswordStorage::Inline),
+        )
+        .await
+        .unwrap();
+        let session = |expires_at| CreateSessionsDAO {
+            expires_at,
+            credential_id: owner.id,
+            ip: Some("127.0.0.1".to_string()),
+            user_agent: None,
+            is_new_device: false,
+            fingerprint: None,
+        };
+
+        let kept =
+            SessionsRepository::insert(&mut tx, session(Utc::now() + Duration::from_secs(3600)))
+                .await
+                .unwrap();
+        SessionsRepository::insert(&mut tx, session(Utc::now() + Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        SessionsRepository::insert(&mut tx, session(Utc::now() - Duration::from_secs(3600)))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            SessionsRepository::get(&mut tx, SessionsBy::Id(kept.id))
+                .await
+                .unwrap(),
+            kept
+        );
+        assert_eq!(
+            SessionsRepository::count_active(&mut tx, owner.id)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            SessionsRepository::revoke_others(&mut tx, owner.id, kept.id)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            SessionsRepository::delete_expired(&mut tx, Utc::now())
+                .await
+                .unwrap(),
+            1
+        );
+
+        AuthDatabase::rollback(tx).await.unwrap();
+    }
 }
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use log::LevelFilter;
+use sqlx::{ConnectOptions, Database, pool::PoolOptions};
+
+/// Size and timeouts of the connection pool, the defaults are sqlx's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    /// Connections kept open even while idle.
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing.
+    pub acquire_timeout: Duration,
+    /// Idle connections above `min_connections` are closed after this long, never when
+    /// `None`.
+    pub idle_timeout: Option<Duration>,
+    /// Logs the SQL text of every statement at `info` under the `sqlx::query` target,
+    /// instead of sqlx's `debug`. Nothing here redacts them: emails and hashes stay out
+    /// of the logs because sqlx never includes bind values, and queries bind them.
+    pub log_statements: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+            log_statements: false,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn options<DB: Database>(&self) -> PoolOptions<DB> {
+        PoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+            .idle_timeout(self.idle_timeout)
+    }
+
+    /// Applies [`PoolConfig::log_statements`] to the options of each connection.
+    pub fn connect_options<O: ConnectOptions>(&self, options: O) -> O {
+        if self.log_statements {
+            options.log_statements(LevelFilter::Info)
+        } else {
+            options
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Kept out of the crate's unit tests: the SQLite worker thread only sees a global
+//! subscriber, which would also collect every other test's queries.
+#![cfg(feature = "unit")]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use auth_database::entities::credentials::{CreateCredentialsDAO, PasswordStorage, Role};
+use auth_database::pool::PoolConfig;
+use auth_database::traits::{BaseDatabase, EntityRepository};
+use auth_database::{AuthDatabase, CredentialsRepository};
+use tracing_subscriber::layer::SubscriberExt;
+
+#[derive(Debug, Default)]
+struct Fields(HashMap<&'static str, String>);
+
+impl tracing::field::Visit for Fields {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+}
+
+/// Level and fields of every `sqlx::query` event.
+#[derive(Clone, Default)]
+struct QueryEvents(Arc<Mutex<Vec<(tracing::Level, String)>>>);
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for QueryEvents {
+    fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        if event.metadata().target() == "sqlx::query" {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let fields = format!("{:?}", fields.0);
+            self.0
+                .lock()
+                .unwrap()
+                .push((*event.metadata().level(), fields));
+        }
+    }
+}
+
+#[tokio::test]
+async fn logged_statements_leave_out_bind_values() {
+    let events = QueryEvents::default();
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(events.clone()))
+        .unwrap();
+
+    let email = "logged-statement@gmail.com";
+    let config = PoolConfig {
+        log_statements: true,
+        ..PoolConfig::default()
+    };
+    let pool = AuthDatabase::connect_with(":memory:", &config)
+        .await
+        .unwrap();
+    AuthDatabase::transaction(&pool, |tx| {
+        Box::pin(async move {
+            CredentialsRepository::insert(
+                tx,
+                CreateCredentialsDAO {
+                    email: email.to_string(),
+                    password: "$argon2id$logged-statement".to_string(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
+                },
+            )
+            .await
+        })
+    })
+    .await
+    .unwrap();
+
+    let events = events.0.lock().unwrap().clone();
+    let inserts: Vec<_> = events
+        .iter()
+        .filter(|(_, fields)| fields.contains("INSERT INTO credentials"))
+        .collect();
+    assert!(!inserts.is_empty());
+    assert!(
+        inserts
+            .iter()
+            .all(|(level, _)| *level == tracing::Level::INFO)
+    );
+
+    // Nothing redacts the statements, this checks that sqlx keeps bind values out of
+    // what `log_statements` emits.
+    assert!(
+        events
+            .iter()
+            .all(|(_, fields)| !fields.contains(email) && !fields.contains("logged-statement"))
+    );
+}
@@ -1,17 +1,51 @@
+use std::{collections::HashSet, fmt, io, path::Path};
+
 use argon2::{
-    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    Algorithm, Argon2, PasswordHash, PasswordHasher, PasswordVerifier, Version,
     password_hash::{SaltString, rand_core::OsRng},
 };
 
-use crate::server::ServerError;
+use crate::config::{Argon2Params, AuthConfig, Pepper};
+use crate::server::{ServerError, ServerResult};
 use regex::Regex;
 
 pub const MIN_LEN_PASSOWRD: usize = 6;
 pub const SESSION_KEY: &str = "ssid";
+pub const CSRF_KEY: &str = "csrf";
+/// Header echoing the CSRF cookie, checked by [`crate::middleware::csrf`].
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const REFRESH_KEY: &str = "rtid";
+
+/// Argon2 keyed with the pepper, if any. Argon2 mixes the key into the hash next to the
+/// password and salt, and it is not part of the PHC string.
+fn argon2<'a>(
+    algorithm: Algorithm,
+    version: Version,
+    params: argon2::Params,
+    pepper: Option<&'a Pepper>,
+) -> ServerResult<Argon2<'a>> {
+    match pepper {
+        Some(pepper) => Argon2::new_with_secret(pepper.as_bytes(), algorithm, version, params)
+            .map_err(|e| ServerError::InternalServerError(e.to_string())),
+        None => Ok(Argon2::new(algorithm, version, params)),
+    }
+}
 
-pub fn hash_password(password: &str) -> Result<String, ServerError> {
+/// Hashes with the variant and cost of `params`, which end up in the returned PHC string.
+pub fn hash_password(
+    password: &str,
+    params: &Argon2Params,
+    pepper: Option<&Pepper>,
+) -> ServerResult<String> {
     let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
+    let argon2 = argon2(
+        params.algorithm,
+        params.version,
+        params
+            .to_params()
+            .map_err(|e| ServerError::InternalServerError(e.to_string()))?,
+        pepper,
+    )?;
 
     Ok(argon2
         .hash_password(password.as_bytes(), &salt)
@@ -19,20 +53,246 @@ pub fn hash_password(password: &str) -> Result<String, ServerError> {
         .to_string())
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, ServerError> {
+/// Checks `password` against a PHC string, with the variant and cost stored in it rather than
+/// the configured ones, so hashes keep verifying after [`Argon2Params`] change. The pepper
+/// has to be the one the hash was made with.
+pub fn verify_password(password: &str, hash: &str, pepper: Option<&Pepper>) -> ServerResult<bool> {
     let parsed_hash =
         PasswordHash::new(hash).map_err(|e| ServerError::InternalServerError(e.to_string()))?;
 
-    Ok(Argon2::default()
-        .verify_password(password.as_bytes(), &parsed_hash)
-        .is_ok())
+    Ok(argon2(
+        Algorithm::default(),
+        Version::default(),
+        argon2::Params::default(),
+        pepper,
+    )?
+    .verify_password(password.as_bytes(), &parsed_hash)
+    .is_ok())
+}
+
+/// Complexity rules new passwords are checked against on sign-up and password changes.
+/// Only the length is enforced by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Minimum length in characters.
+    pub min_len: usize,
+    pub require_upper: bool,
+    pub require_lower: bool,
+    pub require_digit: bool,
+    /// Requires a character that is neither alphanumeric nor whitespace.
+    pub require_symbol: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_len: MIN_LEN_PASSOWRD,
+            require_upper: false,
+            require_lower: false,
+            require_digit: false,
+            require_symbol: false,
+        }
+    }
+}
+
+/// Rule of a [`PasswordPolicy`] a password fails, displayed as what the password needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyViolation {
+    TooShort(usize),
+    MissingUpper,
+    MissingLower,
+    MissingDigit,
+    MissingSymbol,
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyViolation::TooShort(min_len) => write!(f, "at least {min_len} characters"),
+            PolicyViolation::MissingUpper => write!(f, "an uppercase letter"),
+            PolicyViolation::MissingLower => write!(f, "a lowercase letter"),
+            PolicyViolation::MissingDigit => write!(f, "a digit"),
+            PolicyViolation::MissingSymbol => write!(f, "a symbol"),
+        }
+    }
+}
+
+/// Checks `password` against every rule of `policy`, returning all the ones it fails.
+pub fn is_valid_password(
+    policy: &PasswordPolicy,
+    password: &str,
+) -> Result<(), Vec<PolicyViolation>> {
+    let has = |predicate: fn(&char) -> bool| password.chars().any(|c| predicate(&c));
+    let rules = [
+        (
+            password.chars().count() < policy.min_len,
+            PolicyViolation::TooShort(policy.min_len),
+        ),
+        (
+            policy.require_upper && !has(|c| c.is_uppercase()),
+            PolicyViolation::MissingUpper,
+        ),
+        (
+            policy.require_lower && !has(|c| c.is_lowercase()),
+            PolicyViolation::MissingLower,
+        ),
+        (
+            policy.require_digit && !has(|c| c.is_ascii_digit()),
+            PolicyViolation::MissingDigit,
+        ),
+        (
+            policy.require_symbol && !has(|c| !c.is_alphanumeric() && !c.is_whitespace()),
+            PolicyViolation::MissingSymbol,
+        ),
+    ];
+
+    let violations: Vec<_> = rules
+        .into_iter()
+        .filter_map(|(violated, violation)| violated.then_some(violation))
+        .collect();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// `400` listing what a password rejected by [`is_valid_password`] is missing.
+pub fn invalid_password(violations: Vec<PolicyViolation>) -> ServerError {
+    let missing: Vec<_> = violations.iter().map(ToString::to_string).collect();
+
+    ServerError::BadRequest(format!(
+        "Invalid Password Format: needs {}",
+        missing.join(", ")
+    ))
+}
+
+/// Whether `password` has the shape of a client pre-hash, a lowercase hex SHA-256.
+pub fn is_prehashed(password: &str) -> bool {
+    password.len() == 64
+        && password
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+/// `400` for a password sent as typed while
+/// [`crate::config::ClientPrehashConfig`] is set.
+pub fn check_prehashed(config: &AuthConfig, password: &str) -> ServerResult<()> {
+    if config.client_prehash.is_some() && !is_prehashed(password) {
+        return Err(ServerError::BadRequest(
+            "Password Must Be Pre-Hashed".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks a new password against the policy and the blocklist, or only that it is
+/// pre-hashed when the client pre-hashes passwords, the server can't read them then.
+pub fn check_new_password(config: &AuthConfig, password: &str) -> ServerResult<()> {
+    if config.client_prehash.is_some() {
+        return check_prehashed(config, password);
+    }
+
+    is_valid_password(&config.password_policy, password).map_err(invalid_password)?;
+
+    let is_blocked = config
+        .password_blocklist
+        .as_ref()
+        .is_some_and(|blocklist| blocklist.contains(password));
+
+    if is_blocked {
+        return Err(ServerError::UnprocessableEntity(
+            "Password Is Too Common".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Known-breached passwords rejected on sign-up, compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct PasswordBlocklist {
+    passwords: HashSet<String>,
+}
+
+impl PasswordBlocklist {
+    /// Reads one password per line, blank lines are skipped.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(std::fs::read_to_string(path)?.lines().collect())
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        self.passwords.contains(&password.to_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.passwords.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passwords.is_empty()
+    }
+}
+
+impl<S: AsRef<str>> FromIterator<S> for PasswordBlocklist {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        Self {
+            passwords: iter
+                .into_iter()
+                .map(|password| password.as_ref().trim().to_lowercase())
+                .filter(|password| !password.is_empty())
+                .collect(),
+        }
+    }
+}
+
+/// Email domains sign-up is restricted to or refused for, compared case-insensitively.
+///
+/// `example.com` only matches that domain, `*.example.com` matches any of its
+/// subdomains but not `example.com` itself.
+#[derive(Debug, Clone, Default)]
+pub struct EmailDomains {
+    domains: Vec<String>,
+}
+
+impl EmailDomains {
+    /// Whether the domain of `email` matches one of the entries.
+    pub fn contains(&self, email: &str) -> bool {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+        let domain = domain.to_lowercase();
+
+        self.domains
+            .iter()
+            .any(|entry| match entry.strip_prefix("*.") {
+                Some(parent) => domain
+                    .strip_suffix(parent)
+                    .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+                None => *entry == domain,
+            })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
 }
 
-pub fn is_valid_password(password: &str) -> bool {
-    password.len() >= MIN_LEN_PASSOWRD
+impl<S: AsRef<str>> FromIterator<S> for EmailDomains {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        Self {
+            domains: iter
+                .into_iter()
+                .map(|domain| domain.as_ref().trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect(),
+        }
+    }
 }
 
-pub fn is_valid_email(email: &str) -> Result<bool, ServerError> {
+pub fn is_valid_email(email: &str) -> ServerResult<bool> {
     let regex = Regex::new(r"^[a-zA-Z0-9._%+-]+@[a-zA-Z0-9.-]+\.[a-zA-Z]{2,}$")
         .map_err(|e| ServerError::InternalServerError(e.to_string()))?;
 
@@ -94,12 +354,234 @@ mod tests {
     #[test]
     fn valid_password() {
         let password = "anaksfdb3434bbc";
-        assert!(is_valid_password(password));
+        assert_eq!(
+            is_valid_password(&PasswordPolicy::default(), password),
+            Ok(())
+        );
     }
 
     #[test]
     fn invalid_password() {
         let password = "anak3";
-        assert!(!is_valid_password(password));
+        assert_eq!(
+            is_valid_password(&PasswordPolicy::default(), password),
+            Err(vec![PolicyViolation::TooShort(MIN_LEN_PASSOWRD)])
+        );
+    }
+
+    #[test]
+    fn password_min_len_counts_characters() {
+        let policy = PasswordPolicy {
+            min_len: 4,
+            ..PasswordPolicy::default()
+        };
+
+        assert_eq!(
+            is_valid_password(&policy, "ñññ"),
+            Err(vec![PolicyViolation::TooShort(4)])
+        );
+        assert_eq!(is_valid_password(&policy, "ññññ"), Ok(()));
+    }
+
+    #[test]
+    fn password_policy_rules() {
+        let rules = [
+            (
+                PasswordPolicy {
+                    require_upper: true,
+                    ..PasswordPolicy::default()
+                },
+                "lowercase1!",
+                "Lowercase1!",
+                PolicyViolation::MissingUpper,
+            ),
+            (
+                PasswordPolicy {
+                    require_lower: true,
+                    ..PasswordPolicy::default()
+                },
+                "UPPERCASE1!",
+                "UPPERCASe1!",
+                PolicyViolation::MissingLower,
+            ),
+            (
+                PasswordPolicy {
+                    require_digit: true,
+                    ..PasswordPolicy::default()
+                },
+                "NoDigitsHere!",
+                "OneDigit1!",
+                PolicyViolation::MissingDigit,
+            ),
+            (
+                PasswordPolicy {
+                    require_symbol: true,
+                    ..PasswordPolicy::default()
+                },
+                "No Symbols 1",
+                "Symbol_1",
+                PolicyViolation::MissingSymbol,
+            ),
+        ];
+
+        for (policy, failing, passing, violation) in rules {
+            assert_eq!(
+                is_valid_password(&policy, failing),
+                Err(vec![violation]),
+                "{violation:?}"
+            );
+            assert_eq!(is_valid_password(&policy, passing), Ok(()), "{violation:?}");
+        }
+    }
+
+    #[test]
+    fn password_policy_reports_every_violation() {
+        let policy = PasswordPolicy {
+            min_len: 12,
+            require_upper: true,
+            require_lower: true,
+            require_digit: true,
+            require_symbol: true,
+        };
+
+        let violations = is_valid_password(&policy, "abc").unwrap_err();
+        assert_eq!(
+            violations,
+            vec![
+                PolicyViolation::TooShort(12),
+                PolicyViolation::MissingUpper,
+                PolicyViolation::MissingDigit,
+                PolicyViolation::MissingSymbol,
+            ]
+        );
+
+        let ServerError::BadRequest(message) = super::invalid_password(violations) else {
+            panic!("expected a bad request");
+        };
+        assert_eq!(
+            message,
+            "Invalid Password Format: needs at least 12 characters, an uppercase letter, a digit, a symbol"
+        );
+        assert_eq!(is_valid_password(&policy, "Str0ng!Passw0rd"), Ok(()));
+    }
+
+    #[test]
+    fn custom_argon2_params_hash_and_verify() {
+        let params = Argon2Params {
+            m_cost: 8 * 1024,
+            t_cost: 1,
+            p_cost: 2,
+            ..Argon2Params::default()
+        };
+
+        let hash = hash_password("Ej4a2fkj!yI!Cj9", &params, None).unwrap();
+
+        assert!(hash.contains("m=8192,t=1,p=2"), "{hash}");
+        assert!(verify_password("Ej4a2fkj!yI!Cj9", &hash, None).unwrap());
+        assert!(!verify_password("Wrong4a2fkj!yI", &hash, None).unwrap());
+    }
+
+    #[test]
+    fn configured_argon2_variant_is_in_the_hash() {
+        for (algorithm, version, prefix) in [
+            (Algorithm::Argon2i, Version::V0x13, "$argon2i$v=19$"),
+            (Algorithm::Argon2d, Version::V0x10, "$argon2d$v=16$"),
+            (Algorithm::Argon2id, Version::V0x13, "$argon2id$v=19$"),
+        ] {
+            let params = Argon2Params {
+                algorithm,
+                version,
+                ..Argon2Params::default()
+            };
+            let pepper = Pepper::new("server-side secret");
+
+            let hash = hash_password("Ej4a2fkj!yI!Cj9", &params, Some(&pepper)).unwrap();
+
+            assert!(hash.starts_with(prefix), "{hash}");
+            assert!(verify_password("Ej4a2fkj!yI!Cj9", &hash, Some(&pepper)).unwrap());
+            assert!(!verify_password("Wrong4a2fkj!yI", &hash, Some(&pepper)).unwrap());
+        }
+    }
+
+    #[test]
+    fn peppered_hash_needs_the_same_pepper() {
+        let pepper = Pepper::new("server-side secret");
+        let hash =
+            hash_password("Ej4a2fkj!yI!Cj9", &Argon2Params::default(), Some(&pepper)).unwrap();
+
+        assert!(verify_password("Ej4a2fkj!yI!Cj9", &hash, Some(&pepper)).unwrap());
+        assert!(!verify_password("Ej4a2fkj!yI!Cj9", &hash, None).unwrap());
+        assert!(
+            !verify_password(
+                "Ej4a2fkj!yI!Cj9",
+                &hash,
+                Some(&Pepper::new("another secret"))
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn unpeppered_hash_verifies_without_pepper() {
+        let hash = hash_password("Ej4a2fkj!yI!Cj9", &Argon2Params::default(), None).unwrap();
+
+        assert!(verify_password("Ej4a2fkj!yI!Cj9", &hash, None).unwrap());
+        assert!(!verify_password("Ej4a2fkj!yI!Cj9", &hash, Some(&Pepper::new("secret"))).unwrap());
+    }
+
+    #[test]
+    fn invalid_argon2_params_fail_to_hash() {
+        let params = Argon2Params {
+            m_cost: 1,
+            ..Argon2Params::default()
+        };
+
+        assert!(hash_password("Ej4a2fkj!yI!Cj9", &params, None).is_err());
+    }
+
+    #[test]
+    fn blocklist_rejects_listed_passwords() {
+        let blocklist: PasswordBlocklist = ["123456", "Password1", "  qwerty  ", ""]
+            .into_iter()
+            .collect();
+
+        assert_eq!(blocklist.len(), 3);
+        assert!(blocklist.contains("123456"));
+        assert!(blocklist.contains("password1"));
+        assert!(blocklist.contains("QWERTY"));
+        assert!(!blocklist.contains("asdjfnaksdf87"));
+    }
+
+    #[test]
+    fn blocklist_from_file() {
+        let path = std::env::temp_dir().join(format!("auth-blocklist-{}.txt", std::process::id()));
+        std::fs::write(&path, "letmein\n\niloveyou\r\n").unwrap();
+
+        let blocklist = PasswordBlocklist::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(blocklist.len(), 2);
+        assert!(blocklist.contains("letmein"));
+        assert!(blocklist.contains("iloveyou"));
+    }
+
+    #[test]
+    fn email_domains_match_exact_and_wildcard_entries() {
+        let domains: EmailDomains = ["Acme.com", "*.corp.example", " "].into_iter().collect();
+
+        assert!(domains.contains("jane@acme.com"));
+        assert!(domains.contains("jane@ACME.COM"));
+        assert!(!domains.contains("jane@eu.acme.com"));
+        assert!(!domains.contains("jane@notacme.com"));
+        assert!(domains.contains("jane@eu.corp.example"));
+        assert!(domains.contains("jane@a.b.corp.example"));
+        assert!(!domains.contains("jane@corp.example"));
+        assert!(!domains.contains("jane@evilcorp.example"));
+        assert!(!domains.contains("not-an-email"));
+    }
+
+    #[test]
+    fn blocklist_missing_file() {
+        assert!(PasswordBlocklist::from_file("/nonexistent/blocklist.txt").is_err());
     }
 }
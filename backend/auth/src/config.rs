@@ -0,0 +1,575 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use auth_database::{
+    entities::credentials::{PasswordStorage, Role},
+    pool::PoolConfig,
+    ssl::SslOptions,
+};
+use axum::http::HeaderValue;
+use cookie::SameSite;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+
+use crate::common::{
+    CSRF_KEY, EmailDomains, PasswordBlocklist, PasswordPolicy, REFRESH_KEY, SESSION_KEY,
+};
+
+/// Runtime options for the auth server, built from [`crate::Args`] at startup.
+#[derive(Debug, Clone, Default)]
+pub struct AuthConfig {
+    /// Rewrites validation failures (400/422) of every route as `200 { ok: false, error }`
+    /// for legacy clients that can't handle 4xx responses. Off by default.
+    pub legacy_validation_ok: bool,
+    /// Answers malformed request bodies with a generic message instead of the parser's,
+    /// which echoes field names and positions. Off by default.
+    pub hide_parse_errors: bool,
+    pub cookie: CookieConfig,
+    /// Issues a readable CSRF cookie next to the session cookie when set, and requires it
+    /// echoed on state-changing requests, see [`crate::middleware::csrf`].
+    pub csrf: Option<CsrfConfig>,
+    /// Only accepts passwords pre-hashed by the client and mounts `/prehash_salt` when set.
+    pub client_prehash: Option<ClientPrehashConfig>,
+    /// Role assigned to credentials created through `/sign_up`.
+    pub default_role: Role,
+    /// Lets `/sign_up` with the email of a deactivated credential reactivate it with the
+    /// new password and [`AuthConfig::default_role`], instead of answering like a taken
+    /// email. Off by default, since whoever controls the email then gets the account back.
+    pub reactivate_on_sign_up: bool,
+    pub existing_session_policy: ExistingSessionPolicy,
+    /// Naming of the fields in JSON responses, the DTOs' snake_case by default.
+    pub json_case: JsonCase,
+    pub trailing_slash: TrailingSlash,
+    /// Rules new passwords must meet, a failure is answered with `400` listing them.
+    pub password_policy: PasswordPolicy,
+    /// Passwords rejected on sign-up with `422 Password Is Too Common`.
+    pub password_blocklist: Option<Arc<PasswordBlocklist>>,
+    /// Only emails from these domains can sign up, any domain when empty.
+    pub allowed_email_domains: EmailDomains,
+    /// Emails from these domains can't sign up, checked after the allow list.
+    pub blocked_email_domains: EmailDomains,
+    pub features: FeatureFlags,
+    /// Origins browsers may call the API from, with credentials so the session cookie is
+    /// sent. Same-origin only when empty.
+    pub allowed_origins: Vec<HeaderValue>,
+    /// Reads the client ip from the first `X-Forwarded-For` entry instead of the peer
+    /// address. Only enable behind a proxy that overwrites the header.
+    pub trust_proxy: bool,
+    pub database_ssl: SslOptions,
+    pub database_pool: PoolConfig,
+    /// Cost of new password hashes.
+    pub argon2: Argon2Params,
+    /// Keys password hashes with a secret kept outside the database. Hashes made with a
+    /// pepper only verify with that same pepper.
+    pub password_pepper: Option<Pepper>,
+    /// Where new and updated password hashes are written.
+    pub password_storage: PasswordStorage,
+    /// Makes `/ready` also insert and delete a `health_checks` row, off by default since
+    /// every probe then writes to the database.
+    pub readiness_write_check: bool,
+    pub session: SessionConfig,
+    /// Issues a refresh token cookie next to the session on `/sign_in` and mounts
+    /// `/refresh` when set.
+    pub refresh_token: Option<RefreshTokenConfig>,
+    pub password_reset: PasswordResetConfig,
+    pub email_verification: EmailVerificationConfig,
+    pub lockout: LockoutConfig,
+    /// Applied by [`crate::server::App::run`] through
+    /// [`crate::server::AppState::with_rate_limit`], no limit when `None`.
+    pub rate_limit: Option<RateLimitConfig>,
+    pub shutdown: ShutdownConfig,
+    /// Holds every response back for a random delay when set, see
+    /// [`crate::middleware::response_jitter`].
+    pub response_jitter: Option<ResponseJitterConfig>,
+    /// Serves plain HTTP on this Unix domain socket instead of the TCP address when set,
+    /// e.g. for a sidecar. A stale socket file left at the path is replaced.
+    #[cfg(unix)]
+    pub unix_socket: Option<std::path::PathBuf>,
+    /// Returns an access token from `/sign_in` and accepts it as `Authorization: Bearer`
+    /// when set.
+    #[cfg(feature = "jwt")]
+    pub jwt: Option<crate::jwt::JwtConfig>,
+    /// Serves TLS in-process and requires client certificates signed by its CA when set.
+    #[cfg(feature = "mtls")]
+    pub mtls: Option<crate::mtls::MtlsConfig>,
+}
+
+/// Per-endpoint switches consulted when building the router, disabled routes are not
+/// mounted and answer `404`. Endpoints are enabled unless set otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    endpoints: HashMap<String, bool>,
+}
+
+impl FeatureFlags {
+    /// Endpoints that can be toggled, named after their path without the leading `/`.
+    pub const ENDPOINTS: [&str; 19] = [
+        "sign_up",
+        "sign_in",
+        "sign_out",
+        "change_password",
+        "me",
+        "me/export",
+        "admin",
+        "health_check",
+        "health",
+        "metrics",
+        "sessions",
+        "sessions/count",
+        "ready",
+        "api_keys",
+        "refresh",
+        "password_reset",
+        "prehash_salt",
+        "verify_email",
+        "account",
+    ];
+
+    pub fn set(&mut self, endpoint: impl Into<String>, enabled: bool) {
+        self.endpoints.insert(endpoint.into(), enabled);
+    }
+
+    pub fn disable(mut self, endpoint: impl Into<String>) -> Self {
+        self.set(endpoint, false);
+        self
+    }
+
+    pub fn is_enabled(&self, endpoint: &str) -> bool {
+        self.endpoints.get(endpoint).copied().unwrap_or(true)
+    }
+}
+
+/// Argon2 variant and cost parameters used when hashing passwords. Verification reads
+/// them from the stored hash instead, so changing them only affects new hashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Argon2id unless compliance mandates Argon2i or Argon2d.
+    pub algorithm: argon2::Algorithm,
+    pub version: argon2::Version,
+    /// Memory size in KiB, at least `8 * p_cost`.
+    pub m_cost: u32,
+    /// Number of iterations.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Argon2Params {
+    pub fn to_params(&self) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+    }
+}
+
+impl Default for Argon2Params {
+    fn default() -> Self {
+        Self {
+            algorithm: argon2::Algorithm::default(),
+            version: argon2::Version::default(),
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Server-side secret mixed into every password hash, so a leaked database can't be
+/// cracked offline without it.
+#[derive(Clone)]
+pub struct Pepper(Vec<u8>);
+
+impl Pepper {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Pepper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Pepper(<redacted>)")
+    }
+}
+
+/// Lifetime of sessions issued by `/sign_in`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// How long a session, and the cookie carrying it, stays valid.
+    pub ttl: Duration,
+    /// How often expired session rows are deleted, never when `None`.
+    pub purge_interval: Option<Duration>,
+    /// Binds sessions to the [`crate::extractors::client_fingerprint`] they were started
+    /// with, a cookie replayed from a materially different client is rejected.
+    pub bind_fingerprint: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60 * 24),
+            purge_interval: Some(Duration::from_secs(60 * 60)),
+            bind_fingerprint: false,
+        }
+    }
+}
+
+/// Single-use refresh tokens, each exchange at `/refresh` revokes the token and its
+/// session and issues new ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshTokenConfig {
+    /// How long a token can be exchanged. Rotation issues tokens with a fresh lifetime.
+    pub ttl: Duration,
+    pub cookie_name: String,
+}
+
+impl Default for RefreshTokenConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60 * 24 * 30),
+            cookie_name: REFRESH_KEY.to_string(),
+        }
+    }
+}
+
+/// Tokens issued by `/password_reset/request`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordResetConfig {
+    /// How long a token can be used, requesting another one invalidates it earlier.
+    pub ttl: Duration,
+}
+
+impl Default for PasswordResetConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Tokens issued on `/sign_up` and consumed by `/verify_email`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmailVerificationConfig {
+    pub ttl: Duration,
+    /// Refuses `/sign_in` with `403 Email Not Verified` until the email is verified. Off by
+    /// default. Credentials created before verification existed start unverified too.
+    pub required: bool,
+}
+
+impl Default for EmailVerificationConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60 * 60 * 24),
+            required: false,
+        }
+    }
+}
+
+/// Locks a credential after too many consecutive wrong passwords on `/sign_in`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutConfig {
+    /// Consecutive failures that trigger the lock, `0` disables locking.
+    pub threshold: u32,
+    /// How long sign-ins are refused once locked, even with the right password.
+    pub duration: Duration,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            duration: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+/// Requests `/sign_in` and `/sign_up` accept per client ip, and optionally per posted
+/// email, within a sliding window. Further ones are answered with `429`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub limit: u32,
+    pub window: Duration,
+    /// Also counts requests per `email` in the body, so a single account can't be
+    /// targeted from many ips.
+    pub by_email: bool,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            limit: 10,
+            window: Duration::from_secs(60),
+            by_email: true,
+        }
+    }
+}
+
+/// How the server stops once asked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownConfig {
+    /// Time in-flight requests get to finish after the shutdown signal, connections still
+    /// open afterwards are closed.
+    pub grace: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Range the delay added by [`crate::middleware::response_jitter`] is drawn from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseJitterConfig {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl ResponseJitterConfig {
+    /// Longest delay that can be configured, users would notice more.
+    pub const MAX: Duration = Duration::from_secs(1);
+
+    /// Delay drawn uniformly between `min` and `max`, both included.
+    pub fn sample(&self) -> Duration {
+        let span = self.max.saturating_sub(self.min).as_micros() as u64;
+        if span == 0 {
+            return self.min;
+        }
+
+        self.min + Duration::from_micros(OsRng.next_u64() % (span + 1))
+    }
+}
+
+/// Preset of the hardening options, picked with `--security-profile`. Options set
+/// individually take precedence over it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SecurityProfile {
+    /// Local development over plain HTTP, without rate limits.
+    Dev,
+    /// Secure cookies. No rate limit, which is keyed on the client ip and so needs
+    /// `--trust-proxy` behind a reverse proxy.
+    #[default]
+    Balanced,
+    /// Strict same-site cookies, a rate limit, complex passwords and no parser details
+    /// in errors. Behind a reverse proxy it also needs `--trust-proxy`, or every client
+    /// shares the proxy's limit.
+    Strict,
+}
+
+impl SecurityProfile {
+    /// Whether the session cookie is only sent over HTTPS.
+    pub fn cookie_secure(self) -> bool {
+        self != SecurityProfile::Dev
+    }
+
+    pub fn cookie_same_site(self) -> SameSite {
+        match self {
+            SecurityProfile::Dev | SecurityProfile::Balanced => SameSite::Lax,
+            SecurityProfile::Strict => SameSite::Strict,
+        }
+    }
+
+    /// Requests per client ip and window, 0 when unlimited.
+    pub fn rate_limit(self) -> u32 {
+        match self {
+            SecurityProfile::Dev | SecurityProfile::Balanced => 0,
+            SecurityProfile::Strict => 5,
+        }
+    }
+
+    pub fn password_policy(self) -> PasswordPolicy {
+        match self {
+            SecurityProfile::Dev | SecurityProfile::Balanced => PasswordPolicy::default(),
+            SecurityProfile::Strict => PasswordPolicy {
+                min_len: 12,
+                require_upper: true,
+                require_lower: true,
+                require_digit: true,
+                require_symbol: true,
+            },
+        }
+    }
+
+    pub fn hide_parse_errors(self) -> bool {
+        self == SecurityProfile::Strict
+    }
+}
+
+/// How `/sign_in` treats a request that already carries a valid session cookie.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExistingSessionPolicy {
+    /// Ignore the current session and issue a new one.
+    #[default]
+    CreateNew,
+    /// Return the current session instead of creating another one.
+    Reuse,
+    /// Respond with `409 Conflict`.
+    Reject,
+}
+
+/// How paths ending in `/` are routed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TrailingSlash {
+    /// Route `/sign_in/` like `/sign_in`, by rewriting the path before routing.
+    #[default]
+    Trim,
+    /// Only the paths as declared, `/sign_in/` is a `404`.
+    Strict,
+}
+
+/// Format of the log lines written to stdout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Multi-line and colored, for reading in a terminal.
+    #[default]
+    Pretty,
+    /// One JSON object per line, with the fields of its spans, for log collectors.
+    Json,
+}
+
+/// Field naming of JSON responses, applied by [`crate::middleware::json_case`] to every
+/// object key, except in streamed bodies which rename their own. Request bodies are still
+/// read as snake_case.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum JsonCase {
+    /// `credential_id`, as the DTOs are declared.
+    #[default]
+    Snake,
+    /// `credentialId`, for JavaScript clients.
+    Camel,
+}
+
+impl JsonCase {
+    /// Serializes `value` with this naming.
+    pub fn to_value<T: Serialize>(self, value: &T) -> serde_json::Result<Value> {
+        serde_json::to_value(value).map(|value| self.rename(value))
+    }
+
+    /// Single snake_case `key` with this naming.
+    pub fn key(self, key: &str) -> String {
+        match self {
+            JsonCase::Snake => key.to_string(),
+            JsonCase::Camel => snake_to_camel(key),
+        }
+    }
+
+    /// Renames the keys of every object in `value`, which must use snake_case.
+    pub fn rename(self, value: Value) -> Value {
+        match (self, value) {
+            (JsonCase::Snake, value) => value,
+            (JsonCase::Camel, Value::Object(fields)) => Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (self.key(&key), self.rename(value)))
+                    .collect(),
+            ),
+            (JsonCase::Camel, Value::Array(items)) => {
+                Value::Array(items.into_iter().map(|item| self.rename(item)).collect())
+            }
+            (JsonCase::Camel, value) => value,
+        }
+    }
+}
+
+fn snake_to_camel(key: &str) -> String {
+    let mut words = key.split('_');
+    let mut camel = words.next().unwrap_or_default().to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+/// Name and attributes of the session cookie.
+#[derive(Debug, Clone)]
+pub struct CookieConfig {
+    pub name: String,
+    pub path: String,
+    pub domain: Option<String>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            name: SESSION_KEY.to_string(),
+            path: "/".to_string(),
+            domain: None,
+            secure: true,
+            http_only: true,
+            same_site: None,
+        }
+    }
+}
+
+/// Passwords are sent as `hex(SHA-256(password + salt))` instead of as typed, with the
+/// salt from `/prehash_salt`, and the server Argon2-hashes that pre-hash like a password.
+///
+/// The salt is an HMAC of the lowercased email keyed by `secret`, so every email,
+/// registered or not, gets a stable salt without a lookup. Changing the secret, or turning
+/// the mode on or off, locks out the existing passwords. The server never sees the
+/// password itself, so the password policy and blocklist are left to the client.
+#[derive(Clone)]
+pub struct ClientPrehashConfig {
+    pub secret: Vec<u8>,
+}
+
+impl ClientPrehashConfig {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Hex salt the client appends to the password of `email` before hashing it.
+    pub fn salt(&self, email: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(email.to_lowercase().as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+impl std::fmt::Debug for ClientPrehashConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientPrehashConfig")
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Double-submit CSRF cookie, its value is an HMAC of the session id keyed by `secret`.
+#[derive(Clone)]
+pub struct CsrfConfig {
+    pub cookie_name: String,
+    pub secret: Vec<u8>,
+}
+
+impl CsrfConfig {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            cookie_name: CSRF_KEY.to_string(),
+            secret: secret.into(),
+        }
+    }
+}
+
+impl std::fmt::Debug for CsrfConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CsrfConfig")
+            .field("cookie_name", &self.cookie_name)
+            .field("secret", &"<redacted>")
+            .finish()
+    }
+}
@@ -0,0 +1,559 @@
+use auth_database::entities::sessions::SessionsDAO;
+use axum::http::{
+    HeaderMap,
+    header::{COOKIE, SET_COOKIE},
+    response,
+};
+use cookie::{
+    Cookie,
+    time::{OffsetDateTime, PrimitiveDateTime},
+};
+use sqlx::types::chrono::{DateTime, Utc};
+
+use hmac::{Hmac, Mac};
+use session::SessionSecret;
+use sha2::Sha256;
+
+use crate::{
+    config::{AuthConfig, CookieConfig, CsrfConfig, RefreshTokenConfig},
+    server::{ServerError, ServerResult},
+};
+
+const MAX_NANOSECOND: u32 = 999_999_999;
+
+pub trait ChronoToTime {
+    /// Converts to an [`OffsetDateTime`], clamping instead of failing.
+    ///
+    /// `chrono` accepts a wider range of years than `time` (±9999), so values outside that
+    /// range saturate to [`PrimitiveDateTime::MIN`]/[`PrimitiveDateTime::MAX`]. Leap seconds,
+    /// which `chrono` encodes as a nanosecond value above one second, are truncated to the
+    /// last nanosecond of the second. Session expiries are always near-now, so this never
+    /// clamps in practice and the hot sign-in path doesn't need an error branch.
+    fn to_offset_datetime(&self) -> OffsetDateTime;
+
+    /// Strict conversion that fails instead of clamping out-of-range values.
+    fn try_to_offset_datetime(&self) -> ServerResult<OffsetDateTime>;
+}
+
+impl ChronoToTime for DateTime<Utc> {
+    fn to_offset_datetime(&self) -> OffsetDateTime {
+        let nanosecond = self.timestamp_subsec_nanos().min(MAX_NANOSECOND);
+
+        match OffsetDateTime::from_unix_timestamp(self.timestamp()) {
+            Ok(dt) => dt.replace_nanosecond(nanosecond).unwrap_or(dt),
+            Err(_) if self.timestamp() < 0 => PrimitiveDateTime::MIN.assume_utc(),
+            Err(_) => PrimitiveDateTime::MAX.assume_utc(),
+        }
+    }
+
+    fn try_to_offset_datetime(&self) -> ServerResult<OffsetDateTime> {
+        OffsetDateTime::from_unix_timestamp(self.timestamp())
+            .and_then(|dt| dt.replace_nanosecond(self.timestamp_subsec_nanos()))
+            .map_err(|e| ServerError::InternalServerError(e.to_string()))
+    }
+}
+
+/// What a response does to the session cookie, and to the CSRF cookie that goes with it
+/// when [`AuthConfig::csrf`] is set. Handlers pick one instead of building the
+/// `Set-Cookie` values, so a sign-out can't forget one of the cookies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieAction {
+    /// Sets the cookie of a newly issued session, expiring with it.
+    Fresh(SessionSecret, OffsetDateTime),
+    /// Replaces the cookie of a session that was rotated out by its successor's, at
+    /// `/refresh`. The same cookie as [`Self::Fresh`] under the same name, so the old
+    /// value is overwritten rather than left next to it.
+    Rotated(SessionSecret, OffsetDateTime),
+    /// Empties the cookie and expires it, so the browser drops it. The refresh token
+    /// cookie is dropped too when [`AuthConfig::refresh_token`] is set.
+    Cleared,
+}
+
+impl CookieAction {
+    pub fn fresh(session: &SessionsDAO) -> Self {
+        Self::Fresh(session.id.into(), session.expires_at.to_offset_datetime())
+    }
+
+    pub fn rotated(session: &SessionsDAO) -> Self {
+        Self::Rotated(session.id.into(), session.expires_at.to_offset_datetime())
+    }
+
+    /// `Set-Cookie` values of the action, the session cookie first.
+    pub fn cookies(&self, config: &AuthConfig) -> Vec<Cookie<'static>> {
+        let cookie = &config.cookie;
+        match self {
+            Self::Fresh(secret, expires_at) | Self::Rotated(secret, expires_at) => {
+                let id = secret.expose().to_string();
+                let mut cookies = vec![build_session_cookie(cookie, &id, *expires_at)];
+                if let Some(csrf) = &config.csrf {
+                    cookies.push(build_csrf_cookie(cookie, csrf, &id, *expires_at));
+                }
+                cookies
+            }
+            Self::Cleared => {
+                let mut cookies = vec![clear_session_cookie(cookie)];
+                if let Some(csrf) = &config.csrf {
+                    cookies.push(clear_csrf_cookie(cookie, csrf));
+                }
+                if let Some(refresh) = &config.refresh_token {
+                    cookies.push(clear_refresh_cookie(cookie, refresh));
+                }
+                cookies
+            }
+        }
+    }
+
+    /// Adds the [`Self::cookies`] to `response`.
+    pub fn apply(&self, config: &AuthConfig, response: response::Builder) -> response::Builder {
+        self.cookies(config)
+            .into_iter()
+            .fold(response, |response, cookie| {
+                response.header(SET_COOKIE, cookie.to_string())
+            })
+    }
+}
+
+/// Builds the `Set-Cookie` value carrying a freshly issued session.
+pub fn build_session_cookie(
+    config: &CookieConfig,
+    value: &str,
+    expires_at: OffsetDateTime,
+) -> Cookie<'static> {
+    session_cookie(config, value.to_string())
+        .expires(expires_at)
+        .max_age(max_age_until(expires_at))
+        .build()
+}
+
+/// Seconds left until `expires_at`, rounded to the nearest one so a session issued with
+/// a TTL gets that TTL back as its `Max-Age`.
+fn max_age_until(expires_at: OffsetDateTime) -> cookie::time::Duration {
+    let left = (expires_at - OffsetDateTime::now_utc())
+        .as_seconds_f64()
+        .round();
+    cookie::time::Duration::seconds(left.max(0.0) as i64)
+}
+
+/// Builds a `Set-Cookie` value that makes the browser drop the session cookie.
+pub fn clear_session_cookie(config: &CookieConfig) -> Cookie<'static> {
+    session_cookie(config, String::new())
+        .expires(OffsetDateTime::UNIX_EPOCH)
+        .max_age(cookie::time::Duration::ZERO)
+        .build()
+}
+
+fn find_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<Cookie<'a>> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+        .find(|cookie| cookie.name() == name)
+}
+
+/// Reads the session cookie value from the request `Cookie` headers, if present.
+pub fn parse_session_cookie(headers: &HeaderMap, config: &CookieConfig) -> Option<String> {
+    find_cookie(headers, &config.name).map(|cookie| cookie.value().to_string())
+}
+
+/// Reads and parses the session cookie, `None` when it is missing or not a session id.
+pub fn parse_session_secret(headers: &HeaderMap, config: &CookieConfig) -> Option<SessionSecret> {
+    find_cookie(headers, &config.name).and_then(|cookie| SessionSecret::parse(cookie.value()))
+}
+
+/// Derives the CSRF token bound to a session id.
+pub fn csrf_token(csrf: &CsrfConfig, session_id: &str) -> String {
+    hex::encode(csrf_mac(csrf, session_id).finalize().into_bytes())
+}
+
+/// Checks, in constant time, that `token` is the CSRF token of `session_id`.
+pub fn verify_csrf_token(csrf: &CsrfConfig, session_id: &str, token: &str) -> bool {
+    let Ok(token) = hex::decode(token) else {
+        return false;
+    };
+
+    csrf_mac(csrf, session_id).verify_slice(&token).is_ok()
+}
+
+/// Builds the CSRF cookie, it shares the session cookie attributes but stays readable from JS.
+pub fn build_csrf_cookie(
+    config: &CookieConfig,
+    csrf: &CsrfConfig,
+    session_id: &str,
+    expires_at: OffsetDateTime,
+) -> Cookie<'static> {
+    cookie_builder(
+        config,
+        csrf.cookie_name.clone(),
+        csrf_token(csrf, session_id),
+    )
+    .http_only(false)
+    .expires(expires_at)
+    .build()
+}
+
+pub fn clear_csrf_cookie(config: &CookieConfig, csrf: &CsrfConfig) -> Cookie<'static> {
+    cookie_builder(config, csrf.cookie_name.clone(), String::new())
+        .http_only(false)
+        .expires(OffsetDateTime::UNIX_EPOCH)
+        .max_age(cookie::time::Duration::ZERO)
+        .build()
+}
+
+/// Builds the refresh token cookie. It is only sent to `/refresh`, under the session
+/// cookie path.
+pub fn build_refresh_cookie(
+    config: &CookieConfig,
+    refresh: &RefreshTokenConfig,
+    value: &str,
+    expires_at: OffsetDateTime,
+) -> Cookie<'static> {
+    refresh_cookie(config, refresh, value.to_string())
+        .expires(expires_at)
+        .build()
+}
+
+pub fn clear_refresh_cookie(
+    config: &CookieConfig,
+    refresh: &RefreshTokenConfig,
+) -> Cookie<'static> {
+    refresh_cookie(config, refresh, String::new())
+        .expires(OffsetDateTime::UNIX_EPOCH)
+        .max_age(cookie::time::Duration::ZERO)
+        .build()
+}
+
+/// Reads the refresh token cookie value from the request `Cookie` headers, if present.
+pub fn parse_refresh_cookie(headers: &HeaderMap, refresh: &RefreshTokenConfig) -> Option<String> {
+    find_cookie(headers, &refresh.cookie_name).map(|cookie| cookie.value().to_string())
+}
+
+fn refresh_cookie(
+    config: &CookieConfig,
+    refresh: &RefreshTokenConfig,
+    value: String,
+) -> cookie::CookieBuilder<'static> {
+    let path = format!("{}/refresh", config.path.trim_end_matches('/'));
+    cookie_builder(config, refresh.cookie_name.clone(), value)
+        .path(path)
+        .http_only(true)
+}
+
+fn csrf_mac(csrf: &CsrfConfig, session_id: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(&csrf.secret).expect("HMAC accepts keys of any size");
+    mac.update(session_id.as_bytes());
+    mac
+}
+
+fn session_cookie(config: &CookieConfig, value: String) -> cookie::CookieBuilder<'static> {
+    cookie_builder(config, config.name.clone(), value)
+}
+
+fn cookie_builder(
+    config: &CookieConfig,
+    name: String,
+    value: String,
+) -> cookie::CookieBuilder<'static> {
+    let mut builder = Cookie::build((name, value))
+        .path(config.path.clone())
+        .secure(config.secure)
+        .http_only(config.http_only);
+
+    if let Some(domain) = &config.domain {
+        builder = builder.domain(domain.clone());
+    }
+
+    if let Some(same_site) = config.same_site {
+        builder = builder.same_site(same_site);
+    }
+
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{CSRF_KEY, REFRESH_KEY, SESSION_KEY};
+    use axum::http::HeaderValue;
+    use cookie::SameSite;
+
+    #[test]
+    fn to_offset_datetime_epoch() {
+        let epoch = DateTime::<Utc>::UNIX_EPOCH;
+
+        assert_eq!(epoch.to_offset_datetime(), OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(
+            epoch.try_to_offset_datetime().unwrap(),
+            OffsetDateTime::UNIX_EPOCH
+        );
+    }
+
+    #[test]
+    fn to_offset_datetime_now() {
+        let now = Utc::now();
+        let converted = now.to_offset_datetime();
+
+        assert_eq!(converted.unix_timestamp(), now.timestamp());
+        assert_eq!(converted.nanosecond(), now.timestamp_subsec_nanos());
+        assert_eq!(now.try_to_offset_datetime().unwrap(), converted);
+    }
+
+    #[test]
+    fn to_offset_datetime_far_future_clamps() {
+        let far_future = DateTime::<Utc>::from_timestamp(400_000_000_000, 0).unwrap();
+
+        assert_eq!(
+            far_future.to_offset_datetime(),
+            PrimitiveDateTime::MAX.assume_utc()
+        );
+        assert!(far_future.try_to_offset_datetime().is_err());
+    }
+
+    #[test]
+    fn to_offset_datetime_far_past_clamps() {
+        let far_past = DateTime::<Utc>::from_timestamp(-400_000_000_000, 0).unwrap();
+
+        assert_eq!(
+            far_past.to_offset_datetime(),
+            PrimitiveDateTime::MIN.assume_utc()
+        );
+        assert!(far_past.try_to_offset_datetime().is_err());
+    }
+
+    #[test]
+    fn to_offset_datetime_leap_second_truncates() {
+        let leap_second = DateTime::<Utc>::from_timestamp(1_483_228_799, 1_500_000_000).unwrap();
+        let converted = leap_second.to_offset_datetime();
+
+        assert_eq!(converted.unix_timestamp(), 1_483_228_799);
+        assert_eq!(converted.nanosecond(), MAX_NANOSECOND);
+    }
+
+    #[test]
+    fn build_session_cookie_default_attributes() {
+        let config = CookieConfig::default();
+        let expires_at = OffsetDateTime::from_unix_timestamp(1_900_000_000).unwrap();
+        let cookie = build_session_cookie(&config, "abc", expires_at);
+
+        assert_eq!(cookie.name(), SESSION_KEY);
+        assert_eq!(cookie.value(), "abc");
+        assert_eq!(cookie.path(), Some("/"));
+        assert!(cookie.secure().unwrap());
+        assert!(cookie.http_only().unwrap());
+        assert_eq!(cookie.expires_datetime(), Some(expires_at));
+        assert_eq!(cookie.domain(), None);
+        assert_eq!(cookie.same_site(), None);
+    }
+
+    #[test]
+    fn build_session_cookie_custom_attributes() {
+        let config = CookieConfig {
+            name: "session".to_string(),
+            path: "/auth".to_string(),
+            domain: Some("example.com".to_string()),
+            secure: false,
+            http_only: true,
+            same_site: Some(SameSite::Strict),
+        };
+        let cookie = build_session_cookie(&config, "abc", OffsetDateTime::now_utc());
+
+        assert_eq!(cookie.name(), "session");
+        assert_eq!(cookie.path(), Some("/auth"));
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert!(!cookie.secure().unwrap());
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+    }
+
+    #[test]
+    fn clear_session_cookie_expires_immediately() {
+        let cookie = clear_session_cookie(&CookieConfig::default());
+
+        assert_eq!(cookie.name(), SESSION_KEY);
+        assert_eq!(cookie.value(), "");
+        assert_eq!(cookie.max_age(), Some(cookie::time::Duration::ZERO));
+        assert!(cookie.expires_datetime().unwrap() < OffsetDateTime::now_utc());
+    }
+
+    #[test]
+    fn parse_session_cookie_round_trip() {
+        let config = CookieConfig::default();
+        let cookie = build_session_cookie(&config, "session-value", OffsetDateTime::now_utc());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; {}", cookie.stripped())).unwrap(),
+        );
+
+        assert_eq!(
+            parse_session_cookie(&headers, &config),
+            Some("session-value".to_string())
+        );
+    }
+
+    #[test]
+    fn csrf_token_validates_against_session() {
+        let csrf = CsrfConfig::new("secret");
+        let token = csrf_token(&csrf, "session-a");
+
+        assert!(verify_csrf_token(&csrf, "session-a", &token));
+        assert!(!verify_csrf_token(&csrf, "session-b", &token));
+        assert!(!verify_csrf_token(
+            &CsrfConfig::new("other"),
+            "session-a",
+            &token
+        ));
+        assert!(!verify_csrf_token(&csrf, "session-a", "not-hex"));
+    }
+
+    #[test]
+    fn csrf_cookie_is_readable() {
+        let config = CookieConfig::default();
+        let csrf = CsrfConfig::new("secret");
+        let cookie = build_csrf_cookie(&config, &csrf, "session-a", OffsetDateTime::now_utc());
+
+        assert_eq!(cookie.name(), CSRF_KEY);
+        assert_eq!(cookie.value(), csrf_token(&csrf, "session-a"));
+        assert!(!cookie.http_only().unwrap());
+        assert!(cookie.secure().unwrap());
+
+        let cleared = clear_csrf_cookie(&config, &csrf);
+        assert_eq!(cleared.value(), "");
+        assert_eq!(cleared.max_age(), Some(cookie::time::Duration::ZERO));
+    }
+
+    #[test]
+    fn refresh_cookie_is_scoped_to_refresh() {
+        let config = CookieConfig {
+            path: "/auth/".to_string(),
+            http_only: false,
+            ..CookieConfig::default()
+        };
+        let refresh = RefreshTokenConfig::default();
+        let cookie = build_refresh_cookie(&config, &refresh, "rt_abc", OffsetDateTime::now_utc());
+
+        assert_eq!(cookie.name(), REFRESH_KEY);
+        assert_eq!(cookie.path(), Some("/auth/refresh"));
+        assert!(cookie.http_only().unwrap());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(&cookie.stripped().to_string()).unwrap(),
+        );
+        assert_eq!(
+            parse_refresh_cookie(&headers, &refresh),
+            Some("rt_abc".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_session_cookie_missing() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("theme=dark"));
+
+        assert_eq!(
+            parse_session_cookie(&headers, &CookieConfig::default()),
+            None
+        );
+        assert_eq!(
+            parse_session_cookie(&HeaderMap::new(), &CookieConfig::default()),
+            None
+        );
+    }
+
+    /// `Set-Cookie` headers `action` adds to an empty response.
+    fn set_cookies(action: CookieAction, config: &AuthConfig) -> Vec<String> {
+        let response = action
+            .apply(config, axum::http::Response::builder())
+            .body(())
+            .unwrap();
+
+        response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn fresh_and_rotated_set_the_session_cookie() {
+        let secret = SessionSecret::from(sqlx::types::Uuid::new_v4());
+        let expires_at = OffsetDateTime::from_unix_timestamp(1_900_000_000).unwrap();
+        let config = AuthConfig::default();
+
+        for action in [
+            CookieAction::Fresh(secret, expires_at),
+            CookieAction::Rotated(secret, expires_at),
+        ] {
+            let headers = set_cookies(action, &config);
+            let [header] = &headers[..] else {
+                panic!("unexpected cookies: {headers:?}");
+            };
+            let cookie = Cookie::parse(header.as_str()).unwrap();
+
+            assert_eq!(cookie.name(), SESSION_KEY);
+            assert_eq!(cookie.value(), secret.expose().to_string());
+            assert_eq!(cookie.expires_datetime(), Some(expires_at));
+            assert!(cookie.http_only().unwrap());
+            let left = expires_at - OffsetDateTime::now_utc();
+            assert!((cookie.max_age().unwrap() - left).whole_seconds().abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn fresh_sets_the_csrf_cookie_of_the_session() {
+        let secret = SessionSecret::from(sqlx::types::Uuid::new_v4());
+        let expires_at = OffsetDateTime::from_unix_timestamp(1_900_000_000).unwrap();
+        let csrf = CsrfConfig::new("secret");
+        let config = AuthConfig {
+            csrf: Some(csrf.clone()),
+            ..AuthConfig::default()
+        };
+
+        let headers = set_cookies(CookieAction::Fresh(secret, expires_at), &config);
+        let [session, csrf_cookie] = &headers[..] else {
+            panic!("unexpected cookies: {headers:?}");
+        };
+        let session = Cookie::parse(session.as_str()).unwrap();
+        let csrf_cookie = Cookie::parse(csrf_cookie.as_str()).unwrap();
+
+        assert_eq!(session.name(), SESSION_KEY);
+        assert_eq!(csrf_cookie.name(), CSRF_KEY);
+        assert!(verify_csrf_token(
+            &csrf,
+            &secret.expose().to_string(),
+            csrf_cookie.value()
+        ));
+        assert_eq!(csrf_cookie.expires_datetime(), Some(expires_at));
+    }
+
+    #[test]
+    fn cleared_expires_every_cookie() {
+        let config = AuthConfig {
+            csrf: Some(CsrfConfig::new("secret")),
+            refresh_token: Some(RefreshTokenConfig::default()),
+            ..AuthConfig::default()
+        };
+
+        let headers = set_cookies(CookieAction::Cleared, &config);
+        let names: Vec<_> = headers
+            .iter()
+            .map(|header| {
+                let cookie = Cookie::parse(header.as_str()).unwrap();
+                assert_eq!(cookie.value(), "", "{header}");
+                assert_eq!(cookie.max_age(), Some(cookie::time::Duration::ZERO));
+                assert_eq!(cookie.expires_datetime(), Some(OffsetDateTime::UNIX_EPOCH));
+                cookie.name().to_string()
+            })
+            .collect();
+        assert_eq!(names, [SESSION_KEY, CSRF_KEY, REFRESH_KEY]);
+
+        let headers = set_cookies(CookieAction::Cleared, &AuthConfig::default());
+        assert_eq!(headers.len(), 1);
+        assert!(headers[0].starts_with(&format!("{SESSION_KEY}=;")));
+    }
+}
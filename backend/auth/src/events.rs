@@ -0,0 +1,133 @@
+use auth_database::entities::{credentials::CredentialsDAO, sessions::SessionsDAO};
+
+use crate::server::BoxFuture;
+
+/// Receives authentication events once their transaction committed, e.g. to send
+/// verification or welcome emails.
+///
+/// The response waits for the sink and its outcome can't undo the event, so slow work
+/// such as delivering an email is better spawned. Every method does nothing by default.
+pub trait AuthEventSink: Send + Sync {
+    /// A credential was created, or reactivated through `/sign_up`.
+    fn on_signed_up<'a>(&'a self, credential: &'a CredentialsDAO) -> BoxFuture<'a, ()> {
+        let _ = credential;
+        Box::pin(async {})
+    }
+
+    /// `credential` signed in through `/sign_in`, starting `session`.
+    fn on_signed_in<'a>(
+        &'a self,
+        credential: &'a CredentialsDAO,
+        session: &'a SessionsDAO,
+    ) -> BoxFuture<'a, ()> {
+        let _ = (credential, session);
+        Box::pin(async {})
+    }
+}
+
+/// [`AuthEventSink`] ignoring every event, the default of
+/// [`crate::server::AppState`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopEventSink;
+
+impl AuthEventSink for NoopEventSink {}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::AuthEventSink;
+    use crate::server::{App, AppState, BoxFuture};
+    use auth_database::AuthDatabase;
+    use auth_database::entities::{credentials::CredentialsDAO, sessions::SessionsDAO};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use tower::util::ServiceExt;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Event {
+        SignedUp(CredentialsDAO),
+        SignedIn(CredentialsDAO, SessionsDAO),
+    }
+
+    #[derive(Default)]
+    struct RecordingSink {
+        events: Mutex<Vec<Event>>,
+    }
+
+    impl AuthEventSink for RecordingSink {
+        fn on_signed_up<'a>(&'a self, credential: &'a CredentialsDAO) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                let event = Event::SignedUp(credential.clone());
+                self.events.lock().unwrap().push(event);
+            })
+        }
+
+        fn on_signed_in<'a>(
+            &'a self,
+            credential: &'a CredentialsDAO,
+            session: &'a SessionsDAO,
+        ) -> BoxFuture<'a, ()> {
+            Box::pin(async move {
+                let event = Event::SignedIn(credential.clone(), session.clone());
+                self.events.lock().unwrap().push(event);
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_receives_sign_ups_and_sign_ins() {
+        #[cfg(feature = "unit")]
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        #[cfg(feature = "integration")]
+        let pool = {
+            dotenvy::dotenv().ok();
+            let database_url = std::env::var("AUTH_DATABASE_URL")
+                .expect("AUTH_DATABASE_URL must be set for integration tests");
+            AuthDatabase::connect(&database_url).await.unwrap()
+        };
+
+        let sink = Arc::new(RecordingSink::default());
+        let app = App::router(AppState::new(pool).with_event_sink(sink.clone())).await;
+        let post = |uri: &str, password: &str| {
+            let body = serde_json::json!({ "email": "events@gmail.com", "password": password });
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let sign_up = app
+            .clone()
+            .oneshot(post("/sign_up", "Ej4a2fkj!yI!Cj9"))
+            .await;
+        let wrong_password = app
+            .clone()
+            .oneshot(post("/sign_in", "wrong-password"))
+            .await;
+        let sign_in = app.oneshot(post("/sign_in", "Ej4a2fkj!yI!Cj9")).await;
+
+        assert_eq!(sign_up.unwrap().status(), StatusCode::OK);
+        assert_eq!(wrong_password.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(sign_in.unwrap().status(), StatusCode::OK);
+
+        let events = sink.events.lock().unwrap().clone();
+        let [
+            Event::SignedUp(signed_up),
+            Event::SignedIn(signed_in, session),
+        ] = &events[..]
+        else {
+            panic!("unexpected events: {events:?}");
+        };
+        assert_eq!(signed_up.email, "events@gmail.com");
+        assert_eq!(signed_in.id, signed_up.id);
+        assert_eq!(session.credential_id, signed_up.id);
+        assert!(session.active);
+    }
+}
@@ -0,0 +1,908 @@
+use std::{
+    collections::BTreeSet,
+    net::{IpAddr, SocketAddr},
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use auth_database::{
+    AuthDatabase, CredentialsRepository, SessionsRepository,
+    entities::credentials::{CredentialsDAO, Role},
+    traits::{BaseDatabase, EntityRepository},
+};
+use axum::{
+    extract::{ConnectInfo, FromRequest, FromRequestParts},
+    http::{
+        HeaderMap,
+        header::{ACCEPT_LANGUAGE, USER_AGENT},
+        request::Parts,
+    },
+};
+use session::{SessionSecret, find_session_credential, find_valid_session};
+use sha2::{Digest, Sha256};
+use sqlx::Transaction;
+use sqlx::types::Uuid;
+use tokio::sync::{Mutex, OwnedMappedMutexGuard, OwnedMutexGuard};
+
+use crate::{
+    cookies::parse_session_secret,
+    scopes::Scope,
+    server::{AppState, ServerError},
+};
+
+/// [`axum::Json`] rejecting with [`ServerError`], so malformed bodies get the same
+/// `{ message }` response as every other error.
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(ServerError))]
+pub struct Json<T>(pub T);
+
+/// Credential behind the request's session cookie, or its API key or access token when
+/// [`crate::middleware::api_key_auth`] or `jwt_auth` already authenticated it.
+///
+/// Loaded once per request and cached in the request extensions, so any later
+/// extraction in the same request reuses it instead of querying again. Requests
+/// without a valid session, or whose credential is inactive, are rejected with `401`.
+#[derive(Debug, Clone)]
+pub struct Authenticated(pub CredentialsDAO);
+
+impl<DB> FromRequestParts<Arc<AppState<DB>>> for Authenticated
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<DB>>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(authenticated) = parts.extensions.get::<Authenticated>() {
+            return Ok(authenticated.clone());
+        }
+
+        let Some(secret) = parse_session_secret(&parts.headers, &state.config.cookie) else {
+            return Err(ServerError::Unauthorized);
+        };
+
+        let fingerprint = session_fingerprint(state, &parts.headers);
+        let credential = AuthDatabase::named_transaction(&state.pool, "authenticate", |tx| {
+            Box::pin(async move {
+                find_session_credential(tx, secret.expose(), fingerprint.as_deref()).await
+            })
+        })
+        .await
+        .map_err(ServerError::from)?;
+
+        let Some(credential) = credential else {
+            return Err(ServerError::Unauthorized);
+        };
+
+        parts.extensions.insert(Principal {
+            credential_id: credential.id,
+            role: credential.role,
+            auth_method: AuthMethod::Session,
+            session_id: Some(secret.public_id()),
+            scopes: Scope::of_role(credential.role),
+        });
+        let authenticated = Authenticated(credential);
+        parts.extensions.insert(authenticated.clone());
+
+        Ok(authenticated)
+    }
+}
+
+/// How a request proved who it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// Session cookie issued by `/sign_in`.
+    Session,
+    /// `Authorization: ApiKey` header, see [`crate::middleware::api_key_auth`].
+    ApiKey,
+    /// `Authorization: Bearer` access token, see [`crate::middleware::jwt_auth`].
+    #[cfg(feature = "jwt")]
+    Jwt,
+}
+
+/// Caller of a request, the same whichever [`AuthMethod`] it used, so handlers and logs
+/// don't need to know how it authenticated.
+///
+/// Inserted in the request extensions when the credential is loaded, e.g. by
+/// [`Authenticated`], and extracted the same way. mTLS client certificates identify a
+/// service rather than a credential, so they don't produce one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub credential_id: Uuid,
+    pub role: Role,
+    pub auth_method: AuthMethod,
+    /// Public id of the session, see [`SessionSecret::public_id`], never the secret.
+    pub session_id: Option<String>,
+    /// What the caller may do, see [`Scope`].
+    pub scopes: BTreeSet<Scope>,
+}
+
+impl<DB> FromRequestParts<Arc<AppState<DB>>> for Principal
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<DB>>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(principal) = parts.extensions.get::<Principal>() {
+            return Ok(principal.clone());
+        }
+
+        Authenticated::from_request_parts(parts, state).await?;
+
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or(ServerError::Unauthorized)
+    }
+}
+
+/// Valid session behind the request's session cookie, for handlers that only need to
+/// know who is calling.
+///
+/// Cheaper than [`Authenticated`] since the credential isn't loaded, which also means
+/// a deactivated credential's sessions pass until they expire or are revoked. Cached in
+/// the request extensions like [`Authenticated`], and rejected with `401`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthSession {
+    pub session: SessionSecret,
+    pub credential_id: Uuid,
+}
+
+impl<DB> FromRequestParts<Arc<AppState<DB>>> for AuthSession
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<DB>>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(auth) = parts.extensions.get::<AuthSession>() {
+            return Ok(*auth);
+        }
+
+        let Some(secret) = parse_session_secret(&parts.headers, &state.config.cookie) else {
+            return Err(ServerError::Unauthorized);
+        };
+
+        let fingerprint = session_fingerprint(state, &parts.headers);
+        let session = AuthDatabase::named_transaction(&state.pool, "auth_session", |tx| {
+            Box::pin(async move {
+                find_valid_session(tx, secret.expose(), fingerprint.as_deref()).await
+            })
+        })
+        .await
+        .map_err(ServerError::from)?;
+
+        let Some(session) = session else {
+            return Err(ServerError::Unauthorized);
+        };
+
+        let auth = AuthSession {
+            session: secret,
+            credential_id: session.credential_id,
+        };
+        parts.extensions.insert(auth);
+
+        Ok(auth)
+    }
+}
+
+/// [`Authenticated`] credential holding the admin role, other roles are rejected with `403`.
+#[derive(Debug, Clone)]
+pub struct Admin(pub CredentialsDAO);
+
+impl<DB> FromRequestParts<Arc<AppState<DB>>> for Admin
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<DB>>,
+    ) -> Result<Self, Self::Rejection> {
+        let Authenticated(credential) = Authenticated::from_request_parts(parts, state).await?;
+
+        if credential.role != Role::Admin {
+            return Err(ServerError::Forbidden);
+        }
+
+        Ok(Admin(credential))
+    }
+}
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+const SEC_CH_UA_PLATFORM: &str = "sec-ch-ua-platform";
+
+/// SHA-256 of the headers that describe the client, hex encoded.
+///
+/// Digits are dropped from the user agent and only the preferred language is kept, so
+/// browser updates don't change it while another browser or platform does.
+pub fn client_fingerprint(headers: &HeaderMap) -> String {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+
+    let user_agent: String = header(USER_AGENT.as_str())
+        .chars()
+        .filter(|c| !c.is_ascii_digit())
+        .collect();
+    let language = header(ACCEPT_LANGUAGE.as_str())
+        .split([',', ';'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let platform = header(SEC_CH_UA_PLATFORM);
+
+    let mut hasher = Sha256::new();
+    for part in [user_agent.as_str(), &language, platform] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// [`client_fingerprint`] of the request when sessions are bound to one, see
+/// [`crate::config::SessionConfig::bind_fingerprint`].
+pub fn session_fingerprint<DB>(state: &AppState<DB>, headers: &HeaderMap) -> Option<String>
+where
+    DB: sqlx::Database,
+{
+    state
+        .config
+        .session
+        .bind_fingerprint
+        .then(|| client_fingerprint(headers))
+}
+
+/// Ip and user agent of the caller, recorded on sign-in.
+///
+/// The ip comes from the first `X-Forwarded-For` entry when
+/// [`crate::config::AuthConfig::trust_proxy`] is set and from the peer address otherwise,
+/// it's `None` when neither is available.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// Fingerprint new sessions are bound to, see [`session_fingerprint`].
+    pub fingerprint: Option<String>,
+}
+
+impl<DB> FromRequestParts<Arc<AppState<DB>>> for ClientInfo
+where
+    DB: sqlx::Database,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<DB>>,
+    ) -> Result<Self, Self::Rejection> {
+        let forwarded_ip = || {
+            parts
+                .headers
+                .get(X_FORWARDED_FOR)?
+                .to_str()
+                .ok()?
+                .split(',')
+                .next()?
+                .trim()
+                .parse()
+                .ok()
+        };
+        let peer_ip = || {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(address)| address.ip())
+        };
+
+        let ip = if state.config.trust_proxy {
+            forwarded_ip().or_else(peer_ip)
+        } else {
+            peer_ip()
+        };
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Self {
+            ip,
+            user_agent,
+            fingerprint: session_fingerprint(state, &parts.headers),
+        })
+    }
+}
+
+/// Transaction of the request, shared by [`Tx`] and [`crate::middleware::transaction`].
+pub struct TxSlot<DB: sqlx::Database>(Arc<Mutex<Option<Transaction<'static, DB>>>>);
+
+impl<DB: sqlx::Database> TxSlot<DB> {
+    /// Takes the transaction out, `None` when the handler didn't extract one.
+    pub async fn take(&self) -> Option<Transaction<'static, DB>> {
+        self.0.lock().await.take()
+    }
+}
+
+impl<DB: sqlx::Database> Clone for TxSlot<DB> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<DB: sqlx::Database> Default for TxSlot<DB> {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(None)))
+    }
+}
+
+type TxGuard<DB> =
+    OwnedMappedMutexGuard<Option<Transaction<'static, DB>>, Transaction<'static, DB>>;
+
+/// Transaction scoped to the request, begun on extraction and ended by
+/// [`crate::middleware::transaction`] once the handler returned: committed when the
+/// response is a success or redirect, rolled back otherwise, so an `Err` from the
+/// handler undoes its writes. A failed commit turns the response into an error.
+///
+/// Derefs to the [`Transaction`], so repositories take `&mut tx` directly. Extract it
+/// after the extractors that query on their own, with a single connection they would
+/// wait on it. Extracting it twice in one request is rejected with `500`.
+pub struct Tx<DB: sqlx::Database>(TxGuard<DB>);
+
+impl<DB> FromRequestParts<Arc<AppState<DB>>> for Tx<DB>
+where
+    DB: sqlx::Database,
+{
+    type Rejection = ServerError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<DB>>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(slot) = parts.extensions.get::<TxSlot<DB>>() else {
+            tracing::error!("Tx extracted on a route without the transaction middleware");
+            return Err(ServerError::InternalServerError(
+                "Internal Server Error".to_string(),
+            ));
+        };
+
+        let Ok(mut guard) = slot.0.clone().try_lock_owned() else {
+            tracing::error!("Tx extracted twice in the same request");
+            return Err(ServerError::InternalServerError(
+                "Internal Server Error".to_string(),
+            ));
+        };
+
+        if guard.is_none() {
+            *guard = Some(AuthDatabase::begin(&state.pool).await?);
+        }
+
+        OwnedMutexGuard::try_map(guard, Option::as_mut)
+            .map(Tx)
+            .map_err(|_| ServerError::InternalServerError("Internal Server Error".to_string()))
+    }
+}
+
+impl<DB: sqlx::Database> Deref for Tx<DB> {
+    type Target = Transaction<'static, DB>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<DB: sqlx::Database> DerefMut for Tx<DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::SESSION_KEY;
+    use sqlx::types::Uuid;
+    use std::time::Duration;
+
+    use auth_database::entities::{
+        credentials::{
+            CreateCredentialsDAO, CredentialsBy, PasswordStorage, Role, UpdateCredentialsDAO,
+        },
+        sessions::{CreateSessionsDAO, SessionsBy, UpdateSessionsDAO},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::get,
+    };
+    use http_body_util::BodyExt;
+    use sqlx::{Pool, types::chrono::Utc};
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    use sqlx::Sqlite;
+
+    #[cfg(feature = "integration")]
+    use sqlx::Postgres;
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> Pool<Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> Pool<Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    async fn whoami(Authenticated(credential): Authenticated) -> String {
+        credential.email
+    }
+
+    async fn protected(auth: AuthSession) -> String {
+        auth.credential_id.to_string()
+    }
+
+    async fn insert_session<DB>(pool: &sqlx::Pool<DB>, email: &str, active: bool) -> Uuid
+    where
+        DB: sqlx::Database,
+        CredentialsRepository: EntityRepository<Db = DB>,
+        SessionsRepository: EntityRepository<Db = DB>,
+    {
+        let email = email.to_string();
+        AuthDatabase::transaction(pool, |tx| {
+            Box::pin(async move {
+                let credential = CredentialsRepository::insert(
+                    tx,
+                    CreateCredentialsDAO {
+                        email,
+                        password: "hash".to_string(),
+                        role: Role::User,
+                        password_storage: PasswordStorage::Inline,
+                    },
+                )
+                .await?;
+
+                if !active {
+                    CredentialsRepository::update(
+                        tx,
+                        CredentialsBy::Id(credential.id),
+                        UpdateCredentialsDAO {
+                            password: credential.password,
+                            active: false,
+                            role: credential.role,
+                            password_storage: PasswordStorage::Inline,
+                            version: credential.version,
+                        },
+                    )
+                    .await?;
+                }
+
+                let session = SessionsRepository::insert(
+                    tx,
+                    CreateSessionsDAO {
+                        credential_id: credential.id,
+                        expires_at: Utc::now() + Duration::from_secs(60 * 60),
+                        ip: None,
+                        user_agent: None,
+                        is_new_device: false,
+                        fingerprint: None,
+                    },
+                )
+                .await?;
+
+                Ok::<_, auth_database::traits::DatabaseError>(session.id)
+            })
+        })
+        .await
+        .unwrap()
+    }
+
+    fn request(cookie: Option<String>) -> Request<Body> {
+        let mut request = Request::builder().uri("/whoami");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn authenticated_yields_session_credential() {
+        let pool = pool().await;
+        let session_id = insert_session(&pool, "whoami@mail.com", true).await;
+        let mut app = Router::new()
+            .route("/whoami", get(whoami))
+            .with_state(Arc::new(AppState::new(pool)))
+            .into_service();
+
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(request(Some(format!("{SESSION_KEY}={session_id}"))))
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(&bytes[..], b"whoami@mail.com");
+    }
+
+    #[tokio::test]
+    async fn authenticated_rejects_unauthenticated_requests() {
+        let pool = pool().await;
+        let inactive = insert_session(&pool, "inactive@mail.com", false).await;
+        let mut app = Router::new()
+            .route("/whoami", get(whoami))
+            .with_state(Arc::new(AppState::new(pool)))
+            .into_service();
+
+        let cookies = [
+            None,
+            Some(format!("{SESSION_KEY}=not-a-uuid")),
+            Some(format!("{SESSION_KEY}={}", Uuid::new_v4())),
+            Some(format!("{SESSION_KEY}={inactive}")),
+        ];
+
+        for cookie in cookies {
+            let description = format!("{cookie:?}");
+            let response = app
+                .ready()
+                .await
+                .unwrap()
+                .call(request(cookie))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "failed for cookie: {description}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_cookie_is_rejected_without_a_query() {
+        let pool = pool().await;
+        let app = Router::new()
+            .route("/whoami", get(whoami))
+            .with_state(Arc::new(AppState::new(pool.clone())));
+        // Any database access fails with `503` from here on.
+        pool.close().await;
+
+        let oversized = format!("{SESSION_KEY}={}", "a".repeat(10 * 1024));
+        let started = std::time::Instant::now();
+        let response = app.clone().oneshot(request(Some(oversized))).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        let well_formed = format!("{SESSION_KEY}={}", Uuid::new_v4());
+        let response = app.oneshot(request(Some(well_formed))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn authenticated_is_cached_in_extensions() {
+        let pool = pool().await;
+        let session_id = insert_session(&pool, "cached@mail.com", true).await;
+        let state = Arc::new(AppState::new(pool));
+        let (mut parts, _) = request(Some(format!("{SESSION_KEY}={session_id}"))).into_parts();
+
+        let first = Authenticated::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+        assert!(parts.extensions.get::<Authenticated>().is_some());
+
+        // Without the cookie the second extraction can only succeed from the cache.
+        parts.headers.remove(header::COOKIE);
+        let second = Authenticated::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+
+        assert_eq!(first.0, second.0);
+        assert_eq!(second.0.email, "cached@mail.com");
+    }
+
+    #[tokio::test]
+    async fn session_populates_the_principal() {
+        let pool = pool().await;
+        let session_id = insert_session(&pool, "principal@mail.com", true).await;
+        let state = Arc::new(AppState::new(pool));
+        let (mut parts, _) = request(Some(format!("{SESSION_KEY}={session_id}"))).into_parts();
+
+        let principal = Principal::from_request_parts(&mut parts, &state)
+            .await
+            .unwrap();
+        let Authenticated(credential) = parts.extensions.get::<Authenticated>().unwrap().clone();
+
+        assert_eq!(
+            principal,
+            Principal {
+                credential_id: credential.id,
+                role: Role::User,
+                auth_method: AuthMethod::Session,
+                session_id: Some(SessionSecret::from(session_id).public_id()),
+                scopes: Scope::of_role(Role::User),
+            }
+        );
+        assert_eq!(parts.extensions.get::<Principal>(), Some(&principal));
+
+        let (mut anonymous, _) = request(None).into_parts();
+        assert!(matches!(
+            Principal::from_request_parts(&mut anonymous, &state).await,
+            Err(ServerError::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn auth_session_yields_credential_id() {
+        let pool = pool().await;
+        let session_id = insert_session(&pool, "protected@mail.com", true).await;
+        let expired = insert_session(&pool, "protected-expired@mail.com", true).await;
+        let (credential_id, _) = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                let session = SessionsRepository::get(tx, SessionsBy::Id(session_id)).await?;
+                SessionsRepository::update(
+                    tx,
+                    SessionsBy::Id(expired),
+                    UpdateSessionsDAO {
+                        expires_at: Utc::now() - Duration::from_secs(60),
+                    },
+                )
+                .await?;
+
+                Ok::<_, auth_database::traits::DatabaseError>((session.credential_id, ()))
+            })
+        })
+        .await
+        .unwrap();
+        let app = Router::new()
+            .route("/protected", get(protected))
+            .with_state(Arc::new(AppState::new(pool)));
+        let request = |cookie: Option<String>| {
+            let mut request = Request::builder().uri("/protected");
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request(Some(format!("{SESSION_KEY}={session_id}"))))
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(&bytes[..], credential_id.to_string().as_bytes());
+
+        for cookie in [None, Some(format!("{SESSION_KEY}={expired}"))] {
+            let description = format!("{cookie:?}");
+            let response = app.clone().oneshot(request(cookie)).await.unwrap();
+
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "failed for cookie: {description}"
+            );
+        }
+    }
+
+    /// Inserts a credential with the email in the path, failing after the insert when
+    /// `fail` is set.
+    async fn insert_then<DB>(
+        axum::extract::Path((email, fail)): axum::extract::Path<(String, bool)>,
+        mut tx: Tx<DB>,
+    ) -> Result<StatusCode, ServerError>
+    where
+        DB: sqlx::Database,
+        CredentialsRepository: EntityRepository<Db = DB, CreateInput = CreateCredentialsDAO>,
+    {
+        CredentialsRepository::insert(
+            &mut tx,
+            CreateCredentialsDAO {
+                email,
+                password: "hash".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await?;
+
+        if fail {
+            return Err(ServerError::BadRequest("Failed After Insert".to_string()));
+        }
+        Ok(StatusCode::CREATED)
+    }
+
+    #[tokio::test]
+    async fn tx_commits_on_ok_and_rolls_back_on_err() {
+        let pool = pool().await;
+        let mut app = Router::new()
+            .route("/insert/{email}/{fail}", axum::routing::post(insert_then))
+            .layer(axum::middleware::from_fn(
+                crate::middleware::transaction::<auth_database::DB>,
+            ))
+            .with_state(Arc::new(AppState::new(pool.clone())))
+            .into_service();
+
+        let committed = format!("committed-{}@gmail.com", Uuid::new_v4());
+        let rolled_back = format!("rolled-back-{}@gmail.com", Uuid::new_v4());
+        for (email, fail, status) in [
+            (&committed, false, StatusCode::CREATED),
+            (&rolled_back, true, StatusCode::BAD_REQUEST),
+        ] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(format!("/insert/{email}/{fail}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        let exists = |email: String| {
+            AuthDatabase::transaction(&pool, |tx| {
+                Box::pin(async move {
+                    CredentialsRepository::exists(tx, CredentialsBy::Email(email)).await
+                })
+            })
+        };
+        assert!(exists(committed).await.unwrap());
+        assert!(!exists(rolled_back).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn tx_needs_the_transaction_middleware() {
+        let mut app = Router::new()
+            .route("/insert/{email}/{fail}", axum::routing::post(insert_then))
+            .with_state(Arc::new(AppState::new(pool().await)))
+            .into_service();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/insert/missing@gmail.com/false")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    const CHROME: &str = "Mozilla/5.0 (X11; Linux x86_64) Chrome/120.0.6099.71 Safari/537.36";
+    const CHROME_UPDATED: &str =
+        "Mozilla/5.0 (X11; Linux x86_64) Chrome/121.0.6167.85 Safari/537.36";
+    const FIREFOX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0";
+
+    fn client_headers(user_agent: &str, language: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, user_agent.parse().unwrap());
+        headers.insert(ACCEPT_LANGUAGE, language.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn fingerprint_survives_browser_updates() {
+        let fingerprint = client_fingerprint(&client_headers(CHROME, "en-US,en;q=0.9"));
+
+        for same in [
+            client_headers(CHROME_UPDATED, "en-US,en;q=0.9"),
+            client_headers(CHROME, "en-us;q=0.9, fr"),
+        ] {
+            assert_eq!(client_fingerprint(&same), fingerprint, "{same:?}");
+        }
+
+        let mut other_platform = client_headers(CHROME, "en-US");
+        other_platform.insert(SEC_CH_UA_PLATFORM, "\"Windows\"".parse().unwrap());
+        for other in [
+            client_headers(FIREFOX, "en-US"),
+            client_headers(CHROME, "pt-BR"),
+            other_platform,
+            HeaderMap::new(),
+        ] {
+            assert_ne!(client_fingerprint(&other), fingerprint, "{other:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn bound_session_rejects_other_clients() {
+        use crate::config::{AuthConfig, SessionConfig};
+        use crate::server::App;
+
+        let config = |bind_fingerprint| AuthConfig {
+            session: SessionConfig {
+                bind_fingerprint,
+                ..SessionConfig::default()
+            },
+            ..AuthConfig::default()
+        };
+
+        for bind_fingerprint in [true, false] {
+            let state = AppState::new(pool().await).with_config(config(bind_fingerprint));
+            let mut app = App::router(state).await.into_service();
+            let body =
+                serde_json::json!({ "email": "bound@mail.com", "password": "Ej4a2fkj!yI!Cj9" });
+
+            let mut cookie = None;
+            for uri in ["/sign_up", "/sign_in"] {
+                let request = Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(USER_AGENT, CHROME)
+                    .body(Body::from(body.to_string()))
+                    .unwrap();
+                let response = app.ready().await.unwrap().call(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                cookie = response.headers().get(header::SET_COOKIE).map(|value| {
+                    value
+                        .to_str()
+                        .unwrap()
+                        .split(';')
+                        .next()
+                        .unwrap()
+                        .to_string()
+                });
+            }
+            let cookie = cookie.unwrap();
+
+            for (user_agent, bound_status) in [
+                (CHROME, StatusCode::OK),
+                (CHROME_UPDATED, StatusCode::OK),
+                (FIREFOX, StatusCode::UNAUTHORIZED),
+            ] {
+                // `/me` goes through `Authenticated`, `/sessions` through `AuthSession`.
+                for uri in ["/me", "/sessions"] {
+                    let request = Request::builder()
+                        .uri(uri)
+                        .header(header::COOKIE, &cookie)
+                        .header(USER_AGENT, user_agent)
+                        .body(Body::empty())
+                        .unwrap();
+                    let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+                    let expected = if bind_fingerprint {
+                        bound_status
+                    } else {
+                        StatusCode::OK
+                    };
+                    assert_eq!(response.status(), expected, "{uri} {user_agent}");
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,18 @@
+pub mod account;
+pub mod admin;
+pub mod api_keys;
+pub mod change_password;
 pub mod dto;
+pub mod export;
 pub mod health_check;
+pub mod me;
+pub mod metrics;
+pub mod password_reset;
+pub mod prehash_salt;
+pub mod ready;
+pub mod refresh;
+pub mod sessions;
 pub mod sign_in;
+pub mod sign_out;
 pub mod sign_up;
+pub mod verify_email;
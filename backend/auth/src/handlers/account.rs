@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use auth_database::entities::credentials::{CredentialsBy, HardDelete};
+use auth_database::entities::sessions::ActiveSessions;
+use auth_database::traits::{BaseDatabase, DatabaseError};
+use auth_database::{AuthDatabase, CredentialsRepository, SessionsRepository};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Response, StatusCode};
+
+use crate::cookies::CookieAction;
+use crate::extractors::AuthSession;
+use crate::server::{AppState, ServerError, ServerResult};
+
+/// Permanently deletes the caller's credential along with its sessions, then tells the
+/// browser to drop its cookies. Unlike deactivation the rows are gone, so the email can
+/// sign up again as a new account.
+pub async fn delete_account<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    auth: AuthSession,
+) -> ServerResult<Response<Body>>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: HardDelete<Db = DB>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+{
+    let id = auth.credential_id;
+
+    let sessions = AuthDatabase::named_transaction(&state.pool, "delete_account", |tx| {
+        Box::pin(async move {
+            let sessions = SessionsRepository::delete_by_credential(tx, id).await?;
+            match CredentialsRepository::hard_delete(tx, CredentialsBy::Id(id)).await {
+                Ok(_) => Ok(sessions),
+                Err(e) if matches!(e.kind(), DatabaseError::NotFound(_)) => {
+                    Err(ServerError::Unauthorized)
+                }
+                Err(e) => Err(ServerError::from(e)),
+            }
+        })
+    })
+    .await?;
+    tracing::info!(credential = %id, sessions, "Account deleted");
+
+    let response = Response::builder().status(StatusCode::NO_CONTENT);
+
+    CookieAction::Cleared
+        .apply(&state.config, response)
+        .body(Body::empty())
+        .map_err(|e| {
+            tracing::error!("Error building request: {:#?}", e);
+            ServerError::InternalServerError("Internal Server Error".to_string())
+        })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::server::App;
+    use auth_database::entities::credentials::CredentialsBy;
+    use auth_database::entities::sessions::SessionsBy;
+    use auth_database::traits::{BaseDatabase, DatabaseError, EntityRepository};
+    use auth_database::{AuthDatabase, CredentialsRepository, SessionsRepository};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use sqlx::types::Uuid;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    const EMAIL: &str = "delete@gmail.com";
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> sqlx::Pool<sqlx::Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> sqlx::Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    async fn call(app: &mut RouterIntoService<Body>, request: Request<Body>) -> StatusCode {
+        app.ready()
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    fn credentials(uri: &str) -> Request<Body> {
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Signs in as [`EMAIL`], returning the session cookie to send back.
+    async fn sign_in(app: &mut RouterIntoService<Body>) -> String {
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(credentials("/sign_in"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+        Cookie::parse(set_cookie.to_str().unwrap().to_string())
+            .unwrap()
+            .stripped()
+            .to_string()
+    }
+
+    fn with_cookie(method: &str, uri: &str, cookie: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn deleted_account_rows_are_gone() {
+        let pool = pool().await;
+        let mut app = App::app(pool.clone()).await.into_service();
+
+        assert_eq!(
+            call(&mut app, credentials("/sign_up")).await,
+            StatusCode::OK
+        );
+        let cookie = sign_in(&mut app).await;
+        let other_device = sign_in(&mut app).await;
+
+        let id = AuthDatabase::named_transaction(&pool, "credential_id", |tx| {
+            Box::pin(async move {
+                CredentialsRepository::get(tx, CredentialsBy::Email(EMAIL.to_string())).await
+            })
+        })
+        .await
+        .unwrap()
+        .id;
+
+        let deleted = call(&mut app, with_cookie("DELETE", "/account", &cookie)).await;
+        assert_eq!(deleted, StatusCode::NO_CONTENT);
+
+        // Gone, not just deactivated.
+        let (credential, session) = AuthDatabase::named_transaction(&pool, "deleted_rows", |tx| {
+            Box::pin(async move {
+                let credential = CredentialsRepository::try_get(tx, CredentialsBy::Id(id)).await?;
+                let session = SessionsRepository::try_get(tx, SessionsBy::CredentialId(id)).await?;
+                Ok::<_, DatabaseError>((credential, session))
+            })
+        })
+        .await
+        .unwrap();
+        assert!(credential.is_none());
+        assert!(session.is_none());
+
+        for cookie in [&cookie, &other_device] {
+            let me = call(&mut app, with_cookie("GET", "/me", cookie)).await;
+            assert_eq!(me, StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(
+            call(&mut app, credentials("/sign_in")).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // The email is free again.
+        assert_eq!(
+            call(&mut app, credentials("/sign_up")).await,
+            StatusCode::OK
+        );
+        assert_ne!(sign_in(&mut app).await, cookie);
+    }
+
+    #[tokio::test]
+    async fn delete_account_requires_a_session() {
+        let mut app = App::app(pool().await).await.into_service();
+
+        let request = Request::builder()
+            .method("DELETE")
+            .uri("/account")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(call(&mut app, request).await, StatusCode::UNAUTHORIZED);
+
+        let forged = format!("{}={}", crate::common::SESSION_KEY, Uuid::new_v4());
+        let request = with_cookie("DELETE", "/account", &forged);
+        assert_eq!(call(&mut app, request).await, StatusCode::UNAUTHORIZED);
+    }
+}
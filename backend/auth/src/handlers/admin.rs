@@ -0,0 +1,500 @@
+use std::{str::FromStr, sync::Arc};
+
+use auth_database::{
+    AuthDatabase, CredentialsRepository, RefreshTokensRepository, SessionsRepository,
+    entities::{
+        credentials::{CredentialsBy, Role, UpdateCredentialsDAO},
+        refresh_tokens::RefreshTokenRevocation,
+        sessions::{ActiveSessions, SessionsScope},
+    },
+    traits::{BaseDatabase, EntityRepository},
+};
+use axum::extract::{Path, State};
+use sqlx::types::{
+    Uuid,
+    chrono::{DateTime, Utc},
+};
+
+use crate::{
+    extractors::{Admin, Json},
+    handlers::dto::{
+        PublicCredentialsDTO, RevokedSessionsDTO, SetSessionsExpiryDTO, UpdateRoleDTO,
+        UpdatedSessionsDTO,
+    },
+    server::{AppState, ServerError, ServerResult},
+};
+
+fn parse_id(id: &str) -> ServerResult<Uuid> {
+    Uuid::parse_str(id).map_err(|_| ServerError::BadRequest("Invalid Credential Id".to_string()))
+}
+
+/// Changes a credential's role and revokes its sessions, so it has to sign in again and
+/// no session keeps the authorization of the previous role.
+pub async fn update_role<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Admin(admin): Admin,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateRoleDTO>,
+) -> ServerResult<PublicCredentialsDTO>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    let id = parse_id(&id)?;
+    let role = Role::from_str(&payload.role).map_err(|e| ServerError::BadRequest(e.to_string()))?;
+    let password_storage = state.config.password_storage;
+
+    let (credential, revoked) = AuthDatabase::named_transaction(&state.pool, "update_role", |tx| {
+        Box::pin(async move {
+            let credential = CredentialsRepository::get(tx, CredentialsBy::Id(id)).await?;
+            if credential.role == role {
+                return Ok((credential, 0));
+            }
+
+            let credential = CredentialsRepository::update(
+                tx,
+                CredentialsBy::Id(id),
+                UpdateCredentialsDAO {
+                    password: credential.password,
+                    active: credential.active,
+                    role,
+                    password_storage,
+                    version: credential.version,
+                },
+            )
+            .await?;
+            let revoked = session::revoke_all(tx, id).await?;
+
+            Ok::<_, ServerError>((credential, revoked))
+        })
+    })
+    .await?;
+
+    tracing::info!(
+        admin = %admin.id,
+        credential = %credential.id,
+        role = %credential.role,
+        revoked_sessions = revoked,
+        "Role changed"
+    );
+
+    Ok(PublicCredentialsDTO::from(credential))
+}
+
+/// Signs a credential out everywhere by deactivating all of its sessions.
+pub async fn revoke_sessions<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Admin(admin): Admin,
+    Path(id): Path<String>,
+) -> ServerResult<RevokedSessionsDTO>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    let id = parse_id(&id)?;
+
+    let revoked_sessions = AuthDatabase::named_transaction(&state.pool, "revoke_sessions", |tx| {
+        Box::pin(async move {
+            if !CredentialsRepository::exists(tx, CredentialsBy::Id(id)).await? {
+                return Err(ServerError::NotFound("Not Found".to_string()));
+            }
+
+            Ok(session::revoke_all(tx, id).await?)
+        })
+    })
+    .await?;
+
+    tracing::info!(
+        admin = %admin.id,
+        credential = %id,
+        revoked_sessions,
+        "Sessions revoked"
+    );
+
+    Ok(RevokedSessionsDTO { revoked_sessions })
+}
+
+/// Moves the expiry of the active sessions of one credential, or of every credential
+/// when confirmed, to `expires_in_seconds` from now. Extends them after an incident
+/// forced short sessions, or shortens them all at once.
+pub async fn set_sessions_expiry<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Admin(admin): Admin,
+    Json(payload): Json<SetSessionsExpiryDTO>,
+) -> ServerResult<UpdatedSessionsDTO>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+{
+    let scope = match payload.credential.as_str() {
+        "all" if !payload.confirm_all => {
+            return Err(ServerError::BadRequest(
+                "Confirmation Required For All Sessions".to_string(),
+            ));
+        }
+        "all" => SessionsScope::All,
+        id => SessionsScope::Credential(parse_id(id)?),
+    };
+    let expires_at = i64::try_from(payload.expires_in_seconds)
+        .ok()
+        .and_then(|seconds| seconds.checked_mul(1000))
+        .and_then(|millis| millis.checked_add(Utc::now().timestamp_millis()))
+        .and_then(DateTime::from_timestamp_millis)
+        .ok_or_else(|| ServerError::BadRequest("Invalid Expiry".to_string()))?;
+
+    let updated_sessions =
+        AuthDatabase::named_transaction(&state.pool, "set_sessions_expiry", |tx| {
+            Box::pin(async move {
+                if let SessionsScope::Credential(id) = scope
+                    && !CredentialsRepository::exists(tx, CredentialsBy::Id(id)).await?
+                {
+                    return Err(ServerError::NotFound("Not Found".to_string()));
+                }
+
+                Ok(SessionsRepository::set_expiry(tx, scope, expires_at).await?)
+            })
+        })
+        .await?;
+
+    tracing::info!(
+        admin = %admin.id,
+        scope = ?scope,
+        %expires_at,
+        updated_sessions,
+        "Sessions expiry changed"
+    );
+
+    Ok(UpdatedSessionsDTO { updated_sessions })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::common::hash_password;
+    use crate::config::Argon2Params;
+    use crate::server::App;
+    use auth_database::{
+        AuthDatabase, CredentialsRepository, SessionsRepository,
+        entities::{
+            credentials::{CreateCredentialsDAO, PasswordStorage, Role},
+            sessions::SessionsWhere,
+        },
+        traits::{BaseDatabase, EntityRepository},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sqlx::Pool;
+    use sqlx::types::{
+        Uuid,
+        chrono::{DateTime, Utc},
+    };
+    use std::time::Duration;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    use sqlx::Sqlite;
+
+    #[cfg(feature = "integration")]
+    use sqlx::Postgres;
+
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    #[cfg(feature = "unit")]
+    async fn setup() -> (Pool<Sqlite>, Router) {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    #[cfg(feature = "integration")]
+    async fn setup() -> (Pool<Postgres>, Router) {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        let pool = AuthDatabase::connect(&database_url).await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    async fn insert_credential<DB>(pool: &Pool<DB>, email: &str, role: Role) -> Uuid
+    where
+        DB: sqlx::Database,
+        CredentialsRepository: EntityRepository<Db = DB>,
+    {
+        let email = email.to_string();
+        AuthDatabase::transaction(pool, |tx| {
+            Box::pin(async move {
+                let credential = CreateCredentialsDAO {
+                    email,
+                    password: hash_password(PASSWORD, &Argon2Params::default(), None).unwrap(),
+                    role,
+                    password_storage: PasswordStorage::Inline,
+                };
+
+                Ok::<_, auth_database::traits::DatabaseError>(
+                    CredentialsRepository::insert(tx, credential).await?.id,
+                )
+            })
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn sign_in(app: &mut RouterIntoService<Body>, email: &str) -> String {
+        let body = serde_json::json!({ "email": email, "password": PASSWORD });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+        Cookie::parse(set_cookie.to_str().unwrap().to_string())
+            .unwrap()
+            .stripped()
+            .to_string()
+    }
+
+    async fn call(
+        app: &mut RouterIntoService<Body>,
+        method: &str,
+        uri: &str,
+        cookie: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+
+        (
+            parts.status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    async fn session_expiries<DB>(pool: &Pool<DB>, credential: Uuid) -> Vec<DateTime<Utc>>
+    where
+        DB: sqlx::Database,
+        SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+    {
+        AuthDatabase::transaction(pool, |tx| {
+            Box::pin(async move {
+                SessionsRepository::get_all(tx, SessionsWhere::CredentialId(credential)).await
+            })
+        })
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|session| session.expires_at)
+        .collect()
+    }
+
+    /// Whether `expires_at` is `expires_in` from now, give or take a minute.
+    fn expires_in(expires_at: DateTime<Utc>, expires_in: Duration) -> bool {
+        let expected = Utc::now() + expires_in;
+        let minute = Duration::from_secs(60);
+
+        expires_at > expected - minute && expires_at < expected + minute
+    }
+
+    #[tokio::test]
+    async fn role_change_revokes_sessions() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        insert_credential(&pool, "admin@gmail.com", Role::Admin).await;
+        let user = insert_credential(&pool, "promoted@gmail.com", Role::User).await;
+        let admin_cookie = sign_in(&mut app, "admin@gmail.com").await;
+        let user_cookie = sign_in(&mut app, "promoted@gmail.com").await;
+
+        let (status, _) = call(&mut app, "GET", "/me", &user_cookie, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, json) = call(
+            &mut app,
+            "PUT",
+            &format!("/admin/credentials/{user}/role"),
+            &admin_cookie,
+            Some(serde_json::json!({ "role": "admin" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json.get("role").unwrap(), "admin");
+
+        let (status, _) = call(&mut app, "GET", "/me", &user_cookie, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let user_cookie = sign_in(&mut app, "promoted@gmail.com").await;
+        let (status, json) = call(&mut app, "GET", "/me", &user_cookie, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json.get("role").unwrap(), "admin");
+    }
+
+    #[tokio::test]
+    async fn unchanged_role_keeps_sessions() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        insert_credential(&pool, "admin-same@gmail.com", Role::Admin).await;
+        let user = insert_credential(&pool, "same@gmail.com", Role::User).await;
+        let admin_cookie = sign_in(&mut app, "admin-same@gmail.com").await;
+        let user_cookie = sign_in(&mut app, "same@gmail.com").await;
+
+        let (status, _) = call(
+            &mut app,
+            "PUT",
+            &format!("/admin/credentials/{user}/role"),
+            &admin_cookie,
+            Some(serde_json::json!({ "role": "user" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = call(&mut app, "GET", "/me", &user_cookie, None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn revoke_sessions_signs_out_everywhere() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        insert_credential(&pool, "admin-revoke@gmail.com", Role::Admin).await;
+        let user = insert_credential(&pool, "revoked@gmail.com", Role::User).await;
+        let admin_cookie = sign_in(&mut app, "admin-revoke@gmail.com").await;
+        let first = sign_in(&mut app, "revoked@gmail.com").await;
+        let second = sign_in(&mut app, "revoked@gmail.com").await;
+
+        let uri = format!("/admin/credentials/{user}/revoke_sessions");
+        let (status, json) = call(&mut app, "POST", &uri, &admin_cookie, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json.get("revoked_sessions").unwrap(), 2);
+
+        for cookie in [first, second] {
+            let (status, _) = call(&mut app, "GET", "/me", &cookie, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        let unknown = format!("/admin/credentials/{}/revoke_sessions", Uuid::new_v4());
+        let (status, _) = call(&mut app, "POST", &unknown, &admin_cookie, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_require_admin_role() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let user = insert_credential(&pool, "not-admin@gmail.com", Role::User).await;
+        let cookie = sign_in(&mut app, "not-admin@gmail.com").await;
+
+        let uri = format!("/admin/credentials/{user}/revoke_sessions");
+        let (status, _) = call(&mut app, "POST", &uri, &cookie, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = call(&mut app, "POST", &uri, "", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn set_sessions_expiry_of_one_credential() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        insert_credential(&pool, "admin-expiry@gmail.com", Role::Admin).await;
+        let extended = insert_credential(&pool, "extended@gmail.com", Role::User).await;
+        let untouched = insert_credential(&pool, "untouched@gmail.com", Role::User).await;
+        let admin_cookie = sign_in(&mut app, "admin-expiry@gmail.com").await;
+        sign_in(&mut app, "extended@gmail.com").await;
+        sign_in(&mut app, "extended@gmail.com").await;
+        sign_in(&mut app, "untouched@gmail.com").await;
+
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        let body = serde_json::json!({
+            "credential": extended.to_string(),
+            "expires_in_seconds": week.as_secs(),
+        });
+        let uri = "/admin/sessions/expiry";
+        let (status, json) = call(&mut app, "PUT", uri, &admin_cookie, Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json.get("updated_sessions").unwrap(), 2);
+
+        let expiries = session_expiries(&pool, extended).await;
+        assert_eq!(expiries.len(), 2);
+        assert!(
+            expiries.iter().all(|&at| expires_in(at, week)),
+            "{expiries:?}"
+        );
+
+        let expiries = session_expiries(&pool, untouched).await;
+        assert!(
+            expiries.iter().all(|&at| !expires_in(at, week)),
+            "{expiries:?}"
+        );
+
+        let body = serde_json::json!({
+            "credential": Uuid::new_v4().to_string(),
+            "expires_in_seconds": 60,
+        });
+        let (status, _) = call(&mut app, "PUT", uri, &admin_cookie, Some(body)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn set_sessions_expiry_of_all_credentials_needs_confirmation() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let admin = insert_credential(&pool, "admin-expiry-all@gmail.com", Role::Admin).await;
+        let user = insert_credential(&pool, "shortened@gmail.com", Role::User).await;
+        let admin_cookie = sign_in(&mut app, "admin-expiry-all@gmail.com").await;
+        sign_in(&mut app, "shortened@gmail.com").await;
+
+        let uri = "/admin/sessions/expiry";
+        let body = serde_json::json!({ "credential": "all", "expires_in_seconds": 600 });
+        let (status, _) = call(&mut app, "PUT", uri, &admin_cookie, Some(body)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let day = Duration::from_secs(24 * 60 * 60);
+        let expiries = session_expiries(&pool, user).await;
+        assert!(
+            expiries.iter().all(|&at| expires_in(at, day)),
+            "{expiries:?}"
+        );
+
+        let body = serde_json::json!({
+            "credential": "all",
+            "expires_in_seconds": 600,
+            "confirm_all": true,
+        });
+        let (status, json) = call(&mut app, "PUT", uri, &admin_cookie, Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(json.get("updated_sessions").unwrap().as_u64().unwrap() >= 2);
+
+        let ten_minutes = Duration::from_secs(600);
+        for credential in [admin, user] {
+            let expiries = session_expiries(&pool, credential).await;
+            assert!(!expiries.is_empty());
+            assert!(
+                expiries.iter().all(|&at| expires_in(at, ten_minutes)),
+                "{expiries:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use auth_database::{
+    ApiKeysRepository, AuthDatabase, CredentialsRepository,
+    entities::{
+        api_keys::{ApiKeysBy, ApiKeysWhere, CreateApiKeysDAO},
+        credentials::CredentialsBy,
+    },
+    traits::{BaseDatabase, EntityRepository},
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use sha2::{Digest, Sha256};
+use sqlx::types::Uuid;
+
+use crate::{
+    extractors::{AuthSession, Json, Tx},
+    handlers::dto::{ApiKeyDTO, ApiKeysDTO, CreateApiKeyDTO, NewApiKeyDTO},
+    scopes::Scope,
+    server::{AppState, ServerError, ServerResult},
+};
+
+const KEY_PREFIX: &str = "ak_";
+const KEY_BYTES: usize = 32;
+
+/// What `api_keys.key_hash` stores for `key`. Keys are random enough that a fast hash
+/// is as good as a password hash here, and it keeps the lookup a single indexed read.
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn generate_api_key() -> String {
+    let mut bytes = [0u8; KEY_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{KEY_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Mints a key for the signed in credential. The key is only in this response, the
+/// database keeps its hash. Keys can't mint other keys, a session is required.
+///
+/// Unknown scopes answer `400` and scopes the credential's role lacks `403`.
+pub async fn create<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    auth: AuthSession,
+    Json(payload): Json<CreateApiKeyDTO>,
+) -> ServerResult<(StatusCode, NewApiKeyDTO)>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+    ApiKeysRepository: EntityRepository<Db = DB, CreateInput = CreateApiKeysDAO>,
+{
+    let scopes = payload
+        .scopes
+        .iter()
+        .map(|scope| scope.parse::<Scope>())
+        .collect::<Result<BTreeSet<_>, _>>()
+        .map_err(|e| ServerError::BadRequest(e.to_string()))?;
+
+    let key = generate_api_key();
+    let input = CreateApiKeysDAO {
+        credential_id: auth.credential_id,
+        key_hash: hash_api_key(&key),
+        label: payload.label,
+        scopes: scopes
+            .iter()
+            .map(Scope::as_str)
+            .collect::<Vec<_>>()
+            .join(" "),
+    };
+
+    let api_key = AuthDatabase::named_transaction(&state.pool, "create_api_key", |tx| {
+        Box::pin(async move {
+            let credential =
+                CredentialsRepository::try_get(tx, CredentialsBy::Id(input.credential_id))
+                    .await?
+                    .filter(|credential| credential.active)
+                    .ok_or(ServerError::Unauthorized)?;
+
+            if !scopes.is_subset(&Scope::of_role(credential.role)) {
+                return Err(ServerError::Forbidden);
+            }
+
+            Ok(ApiKeysRepository::insert(tx, input).await?)
+        })
+    })
+    .await?;
+
+    Ok((
+        StatusCode::CREATED,
+        NewApiKeyDTO {
+            key,
+            api_key: ApiKeyDTO::from(api_key),
+        },
+    ))
+}
+
+/// Active keys of the signed in credential, newest first, without the keys themselves.
+pub async fn list<DB>(auth: AuthSession, mut tx: Tx<DB>) -> ServerResult<ApiKeysDTO>
+where
+    DB: sqlx::Database,
+    ApiKeysRepository: EntityRepository<Db = DB, QueryMany = ApiKeysWhere>,
+{
+    let api_keys =
+        ApiKeysRepository::get_all(&mut tx, ApiKeysWhere::CredentialId(auth.credential_id)).await?;
+
+    Ok(ApiKeysDTO {
+        api_keys: api_keys.into_iter().map(ApiKeyDTO::from).collect(),
+    })
+}
+
+/// Revokes one of the caller's keys, requests using it are rejected from then on. Keys
+/// of other credentials and already revoked keys answer `404` like unknown ids.
+pub async fn revoke<DB>(
+    auth: AuthSession,
+    Path(id): Path<String>,
+    mut tx: Tx<DB>,
+) -> ServerResult<StatusCode>
+where
+    DB: sqlx::Database,
+    ApiKeysRepository: EntityRepository<Db = DB, QueryOne = ApiKeysBy>,
+{
+    let id = Uuid::parse_str(&id).map_err(|_| ServerError::NotFound("Not Found".to_string()))?;
+
+    let api_key = ApiKeysRepository::try_get(&mut tx, ApiKeysBy::Id(id)).await?;
+    if !api_key.is_some_and(|key| key.active && key.credential_id == auth.credential_id) {
+        return Err(ServerError::NotFound("Not Found".to_string()));
+    }
+
+    ApiKeysRepository::delete(&mut tx, ApiKeysBy::Id(id)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use super::hash_api_key;
+    use crate::extractors::{AuthMethod, Principal};
+    use crate::scopes::Scope;
+    use crate::server::{App, AppState};
+    use auth_database::{
+        ApiKeysRepository, AuthDatabase,
+        entities::api_keys::ApiKeysBy,
+        traits::{BaseDatabase, EntityRepository},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, Response, StatusCode, header},
+        middleware::from_fn_with_state,
+        routing::{RouterIntoService, get},
+    };
+    use cookie::Cookie;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sqlx::Pool;
+    use std::{collections::BTreeSet, sync::Arc};
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    async fn setup() -> Pool<sqlx::Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn setup() -> Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    async fn sign_in(app: &mut RouterIntoService<Body>, email: &str) -> String {
+        let body = serde_json::json!({ "email": email, "password": "Ej4a2fkj!yI!Cj9" });
+        for uri in ["/sign_up", "/sign_in"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            if uri == "/sign_in" {
+                let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+                return Cookie::parse(set_cookie.to_str().unwrap().to_string())
+                    .unwrap()
+                    .stripped()
+                    .to_string();
+            }
+        }
+        unreachable!()
+    }
+
+    async fn mint(app: &mut RouterIntoService<Body>, cookie: &str, body: Value) -> Response<Body> {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/api_keys")
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    async fn call(
+        app: &mut RouterIntoService<Body>,
+        method: &str,
+        uri: &str,
+        header: (header::HeaderName, &str),
+    ) -> Response<Body> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header.0, header.1)
+            .body(Body::empty())
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    async fn json(response: Response<Body>) -> Value {
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn minted_key_is_stored_hashed() {
+        let pool = setup().await;
+        let mut app = App::app(pool.clone()).await.into_service();
+        let cookie = sign_in(&mut app, "mint-key@gmail.com").await;
+
+        let response = mint(
+            &mut app,
+            &cookie,
+            serde_json::json!({ "label": "ci", "scopes": ["sessions:read", "credentials:read"] }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let minted = json(response).await;
+        let key = minted["key"].as_str().unwrap().to_string();
+        assert_eq!(minted["label"], "ci");
+        assert_eq!(
+            minted["scopes"],
+            serde_json::json!(["credentials:read", "sessions:read"])
+        );
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let stored = ApiKeysRepository::get(&mut tx, ApiKeysBy::KeyHash(hash_api_key(&key)))
+            .await
+            .unwrap();
+        assert_ne!(stored.key_hash, key);
+        assert_eq!(stored.id.to_string(), minted["id"].as_str().unwrap());
+
+        let response = mint(
+            &mut app,
+            &cookie,
+            serde_json::json!({ "scopes": ["bogus"] }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // beyond what the user role allows
+        let response = mint(
+            &mut app,
+            &cookie,
+            serde_json::json!({ "scopes": ["admin"] }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = mint(&mut app, "", serde_json::json!({})).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn key_is_shown_only_once() {
+        let mut app = App::app(setup().await).await.into_service();
+        let cookie = sign_in(&mut app, "list-keys@gmail.com").await;
+
+        let minted = json(mint(&mut app, &cookie, serde_json::json!({})).await).await;
+        let key = minted["key"].as_str().unwrap();
+
+        let response = call(&mut app, "GET", "/api_keys", (header::COOKIE, &cookie)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let listed: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(listed["api_keys"].as_array().unwrap().len(), 1);
+        assert_eq!(listed["api_keys"][0]["id"], minted["id"]);
+        assert!(listed["api_keys"][0].get("key").is_none());
+        assert!(!String::from_utf8_lossy(&bytes).contains(key));
+    }
+
+    #[tokio::test]
+    async fn valid_key_authenticates() {
+        let mut app = App::app(setup().await).await.into_service();
+        let cookie = sign_in(&mut app, "use-key@gmail.com").await;
+        let minted = json(
+            mint(
+                &mut app,
+                &cookie,
+                serde_json::json!({ "scopes": ["credentials:read"] }),
+            )
+            .await,
+        )
+        .await;
+        let key = minted["key"].as_str().unwrap();
+
+        let authorization = format!("ApiKey {key}");
+        let response = call(
+            &mut app,
+            "GET",
+            "/me",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await["email"], "use-key@gmail.com");
+
+        let response = call(
+            &mut app,
+            "GET",
+            "/me",
+            (header::AUTHORIZATION, "ApiKey ak_unknown"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // keys can't mint keys
+        let response = call(
+            &mut app,
+            "POST",
+            "/api_keys",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn revoked_key_is_rejected() {
+        let mut app = App::app(setup().await).await.into_service();
+        let cookie = sign_in(&mut app, "revoke-key@gmail.com").await;
+        let stranger = sign_in(&mut app, "revoke-key-stranger@gmail.com").await;
+        let minted = json(
+            mint(
+                &mut app,
+                &cookie,
+                serde_json::json!({ "scopes": ["credentials:read"] }),
+            )
+            .await,
+        )
+        .await;
+        let authorization = format!("ApiKey {}", minted["key"].as_str().unwrap());
+        let uri = format!("/api_keys/{}", minted["id"].as_str().unwrap());
+
+        let response = call(&mut app, "DELETE", &uri, (header::COOKIE, &stranger)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = call(
+            &mut app,
+            "GET",
+            "/me",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(&mut app, "DELETE", &uri, (header::COOKIE, &cookie)).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = call(&mut app, "DELETE", &uri, (header::COOKIE, &cookie)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = call(
+            &mut app,
+            "GET",
+            "/me",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn key_populates_the_principal() {
+        let pool = setup().await;
+        let mut app = App::app(pool.clone()).await.into_service();
+        let cookie = sign_in(&mut app, "principal-key@gmail.com").await;
+        let minted = json(
+            mint(
+                &mut app,
+                &cookie,
+                serde_json::json!({ "scopes": ["credentials:read"] }),
+            )
+            .await,
+        )
+        .await;
+        let authorization = format!("ApiKey {}", minted["key"].as_str().unwrap());
+
+        let state = Arc::new(AppState::new(pool));
+        let mut app = Router::new()
+            .route(
+                "/principal",
+                get(|principal: Principal| async move {
+                    assert_eq!(principal.auth_method, AuthMethod::ApiKey);
+                    assert_eq!(principal.session_id, None);
+                    assert_eq!(principal.scopes, BTreeSet::from([Scope::CredentialsRead]));
+                    principal.credential_id.to_string()
+                }),
+            )
+            .layer(from_fn_with_state(
+                state.clone(),
+                crate::middleware::api_key_auth,
+            ))
+            .with_state(state)
+            .into_service();
+
+        let response = call(
+            &mut app,
+            "GET",
+            "/principal",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(&mut app, "GET", "/me", (header::COOKIE, &cookie)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn key_needs_the_endpoint_scope() {
+        let mut app = App::app(setup().await).await.into_service();
+        let cookie = sign_in(&mut app, "scoped-key@gmail.com").await;
+        let minted = json(
+            mint(
+                &mut app,
+                &cookie,
+                serde_json::json!({ "scopes": ["sessions:read"] }),
+            )
+            .await,
+        )
+        .await;
+        let authorization = format!("ApiKey {}", minted["key"].as_str().unwrap());
+
+        let response = call(
+            &mut app,
+            "GET",
+            "/sessions/count",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call(
+            &mut app,
+            "GET",
+            "/me",
+            (header::AUTHORIZATION, &authorization),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // the session has every scope of the user role
+        let response = call(&mut app, "GET", "/me", (header::COOKIE, &cookie)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use auth_database::entities::credentials::{CredentialsBy, UpdateCredentialsDAO};
+use auth_database::entities::refresh_tokens::RefreshTokenRevocation;
+use auth_database::entities::sessions::ActiveSessions;
+use auth_database::traits::{BaseDatabase, EntityRepository};
+use auth_database::{
+    AuthDatabase, CredentialsRepository, RefreshTokensRepository, SessionsRepository,
+};
+use axum::extract::State;
+
+use crate::common::{check_new_password, check_prehashed, hash_password, verify_password};
+use crate::extractors::{AuthSession, Json};
+use crate::handlers::dto::{ChangePasswordDTO, RevokedSessionsDTO};
+use crate::server::{AppState, ServerError, ServerResult};
+
+/// Replaces the caller's password after checking the current one, then signs every
+/// other session of the credential out. The session making the request stays valid.
+pub async fn change_password<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    auth: AuthSession,
+    Json(payload): Json<ChangePasswordDTO>,
+) -> ServerResult<RevokedSessionsDTO>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    check_prehashed(&state.config, &payload.old_password)?;
+    check_new_password(&state.config, &payload.new_password)?;
+
+    let password_storage = state.config.password_storage;
+    let argon2 = state.config.argon2;
+    let pepper = state.config.password_pepper.clone();
+    let id = auth.credential_id;
+
+    let revoked_sessions = AuthDatabase::named_transaction(&state.pool, "change_password", |tx| {
+        Box::pin(async move {
+            let Some(credential) = CredentialsRepository::try_get(tx, CredentialsBy::Id(id))
+                .await?
+                .filter(|credential| credential.active)
+            else {
+                return Err(ServerError::Unauthorized);
+            };
+
+            if !verify_password(&payload.old_password, &credential.password, pepper.as_ref())? {
+                return Err(ServerError::Unauthorized);
+            }
+
+            let hash = hash_password(&payload.new_password, &argon2, pepper.as_ref())?;
+            CredentialsRepository::update(
+                tx,
+                CredentialsBy::Id(id),
+                UpdateCredentialsDAO {
+                    password: hash,
+                    active: credential.active,
+                    role: credential.role,
+                    password_storage,
+                    version: credential.version,
+                },
+            )
+            .await?;
+
+            Ok(session::revoke_others(tx, id, auth.session).await?)
+        })
+    })
+    .await?;
+
+    tracing::info!(credential = %id, revoked_sessions, "Password changed");
+
+    Ok(RevokedSessionsDTO { revoked_sessions })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::common::hash_password;
+    use crate::config::{Argon2Params, AuthConfig};
+    use crate::server::{App, AppState};
+    use auth_database::{
+        AuthDatabase, CredentialsRepository,
+        entities::credentials::{CreateCredentialsDAO, PasswordStorage, Role},
+        traits::{BaseDatabase, EntityRepository},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sqlx::Pool;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    use sqlx::Sqlite;
+
+    #[cfg(feature = "integration")]
+    use sqlx::Postgres;
+
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+    const NEW_PASSWORD: &str = "Lq8!vX2m#Tz5pW";
+
+    #[cfg(feature = "unit")]
+    async fn setup() -> (Pool<Sqlite>, Router) {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    #[cfg(feature = "integration")]
+    async fn setup() -> (Pool<Postgres>, Router) {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        let pool = AuthDatabase::connect(&database_url).await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    async fn insert_credential<DB>(pool: &Pool<DB>, email: &str)
+    where
+        DB: sqlx::Database,
+        CredentialsRepository: EntityRepository<Db = DB>,
+    {
+        let email = email.to_string();
+        AuthDatabase::transaction(pool, |tx| {
+            Box::pin(async move {
+                let credential = CreateCredentialsDAO {
+                    email,
+                    password: hash_password(PASSWORD, &Argon2Params::default(), None).unwrap(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
+                };
+
+                CredentialsRepository::insert(tx, credential).await
+            })
+        })
+        .await
+        .unwrap();
+    }
+
+    async fn sign_in(
+        app: &mut RouterIntoService<Body>,
+        email: &str,
+        password: &str,
+    ) -> Option<String> {
+        let body = serde_json::json!({ "email": email, "password": password });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        if response.status() != StatusCode::OK {
+            return None;
+        }
+
+        let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+        let cookie = Cookie::parse(set_cookie.to_str().unwrap().to_string()).unwrap();
+        Some(cookie.stripped().to_string())
+    }
+
+    async fn change_password(
+        app: &mut RouterIntoService<Body>,
+        cookie: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> (StatusCode, Value) {
+        let body = serde_json::json!({
+            "old_password": old_password,
+            "new_password": new_password,
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/change_password")
+            .header(header::COOKIE, cookie)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+
+        (
+            parts.status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    async fn me_status(app: &mut RouterIntoService<Body>, cookie: &str) -> StatusCode {
+        let request = Request::builder()
+            .uri("/me")
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+
+        app.ready()
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn wrong_old_password_is_unauthorized() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let email = "change-password-wrong@gmail.com";
+        insert_credential(&pool, email).await;
+        let cookie = sign_in(&mut app, email, PASSWORD).await.unwrap();
+
+        let (status, _) = change_password(&mut app, &cookie, "Wrong!Password1", NEW_PASSWORD).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        assert!(sign_in(&mut app, email, PASSWORD).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn weak_new_password_is_rejected() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let email = "change-password-weak@gmail.com";
+        insert_credential(&pool, email).await;
+        let cookie = sign_in(&mut app, email, PASSWORD).await.unwrap();
+
+        let (status, body) = change_password(&mut app, &cookie, PASSWORD, "abc").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["message"],
+            "Invalid Password Format: needs at least 6 characters"
+        );
+
+        assert!(sign_in(&mut app, email, PASSWORD).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn change_password_revokes_other_sessions() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let email = "change-password@gmail.com";
+        insert_credential(&pool, email).await;
+        let current = sign_in(&mut app, email, PASSWORD).await.unwrap();
+        let other = sign_in(&mut app, email, PASSWORD).await.unwrap();
+
+        let (status, body) = change_password(&mut app, &current, PASSWORD, NEW_PASSWORD).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["revoked_sessions"], 1);
+
+        assert_eq!(me_status(&mut app, &current).await, StatusCode::OK);
+        assert_eq!(me_status(&mut app, &other).await, StatusCode::UNAUTHORIZED);
+        assert!(sign_in(&mut app, email, PASSWORD).await.is_none());
+        assert!(sign_in(&mut app, email, NEW_PASSWORD).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn change_password_needs_a_session() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+
+        let (status, _) = change_password(&mut app, "ssid=garbage", PASSWORD, NEW_PASSWORD).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn weak_new_password_is_rewritten_for_legacy_clients() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            legacy_validation_ok: true,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool.clone()).with_config(config))
+            .await
+            .into_service();
+        let email = "change-password-legacy@gmail.com";
+        insert_credential(&pool, email).await;
+        let cookie = sign_in(&mut app, email, PASSWORD).await.unwrap();
+
+        let (status, body) = change_password(&mut app, &cookie, PASSWORD, "abc").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ok"], false);
+        assert_eq!(
+            body["error"],
+            "Invalid Password Format: needs at least 6 characters"
+        );
+
+        assert!(sign_in(&mut app, email, PASSWORD).await.is_some());
+    }
+}
@@ -1,6 +1,9 @@
-use auth_database::entities::{credentials::CredentialsDAO, sessions::SessionsDAO};
+use auth_database::entities::{
+    api_keys::ApiKeysDAO, credentials::CredentialsDAO, sessions::SessionsDAO,
+};
 use axum::response::IntoResponse;
 use serde::{Deserialize, Serialize};
+use session::SessionSecret;
 
 #[derive(Debug, Deserialize)]
 pub struct CreateCredentialDTO {
@@ -14,6 +17,7 @@ pub struct CredentialsDTO {
     pub email: String,
     pub password: String,
     pub active: bool,
+    pub role: String,
 }
 
 impl From<CredentialsDAO> for CredentialsDTO {
@@ -23,6 +27,7 @@ impl From<CredentialsDAO> for CredentialsDTO {
             email: value.email,
             password: value.password,
             active: value.active,
+            role: value.role.to_string(),
         }
     }
 }
@@ -33,29 +38,266 @@ impl IntoResponse for CredentialsDTO {
     }
 }
 
+/// Credential as shown to its owner, without the password hash.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PublicCredentialsDTO {
+    pub id: String,
+    pub email: String,
+    pub active: bool,
+    pub role: String,
+}
+
+impl From<CredentialsDAO> for PublicCredentialsDTO {
+    fn from(value: CredentialsDAO) -> Self {
+        Self {
+            id: value.id.to_string(),
+            email: value.email,
+            active: value.active,
+            role: value.role.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for PublicCredentialsDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SessionsDTO {
+    /// Public id, the session secret is only ever sent in the `HttpOnly` cookie.
     pub id: String,
     pub credential_id: String,
     pub expires_at: String,
     pub created_at: String,
     pub active: bool,
+    pub is_new_device: bool,
 }
 
 impl From<SessionsDAO> for SessionsDTO {
     fn from(value: SessionsDAO) -> Self {
         Self {
-            id: value.id.to_string(),
+            id: SessionSecret::from(value.id).public_id(),
             credential_id: value.credential_id.to_string(),
             expires_at: value.expires_at.to_string(),
             created_at: value.created_at.to_string(),
             active: value.active,
+            is_new_device: value.is_new_device,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActiveSessionsDTO {
+    pub active_sessions: i64,
+}
+
+impl IntoResponse for ActiveSessionsDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
+/// Session as listed to its owner, identified by its public id rather than the secret.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceSessionDTO {
+    pub id: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+/// Session as exported to its owner by `/me/export`, inactive and expired ones included.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedSessionDTO {
+    /// Public id, never the session secret itself.
+    pub id: String,
+    pub created_at: String,
+    pub expires_at: String,
+    pub active: bool,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub is_new_device: bool,
+}
+
+impl From<SessionsDAO> for ExportedSessionDTO {
+    fn from(value: SessionsDAO) -> Self {
+        Self {
+            id: SessionSecret::from(value.id).public_id(),
+            created_at: value.created_at.to_string(),
+            expires_at: value.expires_at.to_string(),
+            active: value.active,
+            ip: value.ip,
+            user_agent: value.user_agent,
+            is_new_device: value.is_new_device,
         }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceSessionsDTO {
+    pub sessions: Vec<DeviceSessionDTO>,
+}
+
+impl IntoResponse for DeviceSessionsDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRoleDTO {
+    pub role: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokedSessionsDTO {
+    pub revoked_sessions: u64,
+}
+
+impl IntoResponse for RevokedSessionsDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetSessionsExpiryDTO {
+    /// Credential id, or `all` for the sessions of every credential.
+    pub credential: String,
+    pub expires_in_seconds: u64,
+    /// Has to be set when `credential` is `all`.
+    #[serde(default)]
+    pub confirm_all: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdatedSessionsDTO {
+    pub updated_sessions: u64,
+}
+
+impl IntoResponse for UpdatedSessionsDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordDTO {
+    pub old_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequestDTO {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetConfirmDTO {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationDTO {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailDTO {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrehashSaltRequestDTO {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrehashSaltDTO {
+    pub salt: String,
+}
+
+impl IntoResponse for PrehashSaltDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SignInDTO {
     pub email: String,
     pub password: String,
 }
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateApiKeyDTO {
+    pub label: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// Key as listed to its owner, the key itself is only returned by [`NewApiKeyDTO`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyDTO {
+    pub id: String,
+    pub label: Option<String>,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+}
+
+impl From<ApiKeysDAO> for ApiKeyDTO {
+    fn from(value: ApiKeysDAO) -> Self {
+        Self {
+            id: value.id.to_string(),
+            label: value.label,
+            scopes: value
+                .scopes
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            created_at: value.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeysDTO {
+    pub api_keys: Vec<ApiKeyDTO>,
+}
+
+impl IntoResponse for ApiKeysDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
+
+/// Body of `/sign_in` and `/refresh` when access tokens are enabled, see [`crate::jwt`].
+#[cfg(feature = "jwt")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessTokenDTO {
+    pub access_token: String,
+    /// Always `Bearer`.
+    pub token_type: String,
+    /// Seconds until the token expires.
+    pub expires_in: u64,
+}
+
+/// Freshly minted key, the only response that ever carries `key`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewApiKeyDTO {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyDTO,
+}
+
+impl IntoResponse for NewApiKeyDTO {
+    fn into_response(self) -> axum::response::Response {
+        axum::Json::from(self).into_response()
+    }
+}
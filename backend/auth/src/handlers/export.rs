@@ -0,0 +1,393 @@
+use std::io;
+use std::sync::Arc;
+
+use auth_database::entities::api_keys::ApiKeysWhere;
+use auth_database::entities::credentials::CredentialsBy;
+use auth_database::entities::sessions::SessionsWhere;
+use auth_database::traits::{BaseDatabase, DatabaseError, EntityRepository, Pagination};
+use auth_database::{ApiKeysRepository, AuthDatabase, CredentialsRepository, SessionsRepository};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{Response, StatusCode, header};
+use futures_util::{StreamExt, stream};
+use serde::Serialize;
+use sqlx::types::Uuid;
+
+use crate::config::JsonCase;
+use crate::extractors::AuthSession;
+use crate::handlers::dto::{ApiKeyDTO, ExportedSessionDTO, PublicCredentialsDTO};
+use crate::server::{AppState, ServerError, ServerResult};
+
+/// Rows read per query, so a heavy account is never held in memory whole.
+const PAGE_SIZE: i64 = 100;
+
+/// Array of the export being written, in the order they appear.
+#[derive(Debug, Clone, Copy)]
+enum Section {
+    Sessions,
+    ApiKeys,
+}
+
+impl Section {
+    /// Text closing the section and the one that follows it, if any.
+    fn closing(self, case: JsonCase) -> (String, Option<Section>) {
+        match self {
+            Section::Sessions => (
+                format!("],\"{}\":[", case.key("api_keys")),
+                Some(Section::ApiKeys),
+            ),
+            Section::ApiKeys => ("]}".to_string(), None),
+        }
+    }
+}
+
+/// `row` with the configured [`JsonCase`], which the middleware leaves to streamed bodies.
+fn to_value(case: JsonCase, row: impl Serialize) -> ServerResult<serde_json::Value> {
+    case.to_value(&row).map_err(|e| {
+        tracing::error!("Error serializing the export: {:#?}", e);
+        ServerError::InternalServerError("Internal Server Error".to_string())
+    })
+}
+
+/// Rows of `section` inside `page`, each in its own transaction.
+async fn rows<DB>(
+    state: &AppState<DB>,
+    credential_id: Uuid,
+    section: Section,
+    page: Pagination,
+) -> ServerResult<Vec<serde_json::Value>>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+    ApiKeysRepository: EntityRepository<Db = DB, QueryMany = ApiKeysWhere>,
+{
+    let case = state.config.json_case;
+    AuthDatabase::named_transaction(&state.pool, "export", |tx| {
+        Box::pin(async move {
+            match section {
+                Section::Sessions => SessionsRepository::get_page(
+                    tx,
+                    SessionsWhere::CredentialId(credential_id),
+                    page,
+                )
+                .await?
+                .into_iter()
+                .map(|session| to_value(case, ExportedSessionDTO::from(session)))
+                .collect(),
+                Section::ApiKeys => {
+                    ApiKeysRepository::get_page(tx, ApiKeysWhere::CredentialId(credential_id), page)
+                        .await?
+                        .into_iter()
+                        .map(|api_key| to_value(case, ApiKeyDTO::from(api_key)))
+                        .collect()
+                }
+            }
+        })
+    })
+    .await
+}
+
+/// Next piece of the export: one page of `section` starting at `offset`, closing the
+/// section once a page comes back short. Returns where to continue from.
+async fn chunk<DB>(
+    state: &AppState<DB>,
+    credential_id: Uuid,
+    section: Section,
+    offset: i64,
+) -> ServerResult<(String, Option<(Section, i64)>)>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+    ApiKeysRepository: EntityRepository<Db = DB, QueryMany = ApiKeysWhere>,
+{
+    let page = Pagination {
+        limit: PAGE_SIZE,
+        offset,
+    };
+    let rows = rows(state, credential_id, section, page).await?;
+
+    let mut chunk = String::new();
+    for (i, row) in rows.iter().enumerate() {
+        if offset > 0 || i > 0 {
+            chunk.push(',');
+        }
+        chunk.push_str(&row.to_string());
+    }
+
+    let read = rows.len() as i64;
+    if read < PAGE_SIZE {
+        let (closing, next) = section.closing(state.config.json_case);
+        chunk.push_str(&closing);
+        return Ok((chunk, next.map(|section| (section, 0))));
+    }
+
+    Ok((chunk, Some((section, offset + read))))
+}
+
+/// Everything stored about the signed in credential as one JSON document: the
+/// credential without its password hash, all its sessions by public id and its active
+/// API keys without their hashes.
+///
+/// The body is streamed a page at a time. Each page is its own transaction, so rows
+/// written while the export runs may be skipped or repeated. A database error halfway
+/// through aborts the body, leaving the client with truncated JSON.
+pub async fn export<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    auth: AuthSession,
+) -> ServerResult<Response<Body>>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+    ApiKeysRepository: EntityRepository<Db = DB, QueryMany = ApiKeysWhere>,
+{
+    let credential_id = auth.credential_id;
+    let credential = AuthDatabase::named_transaction(&state.pool, "export", |tx| {
+        Box::pin(async move {
+            match CredentialsRepository::get(tx, CredentialsBy::Id(credential_id)).await {
+                Ok(credential) => Ok(credential),
+                Err(e) if matches!(e.kind(), DatabaseError::NotFound(_)) => {
+                    Err(ServerError::Unauthorized)
+                }
+                Err(e) => Err(ServerError::from(e)),
+            }
+        })
+    })
+    .await?;
+    let head = format!(
+        "{{\"credential\":{},\"sessions\":[",
+        to_value(
+            state.config.json_case,
+            PublicCredentialsDTO::from(credential)
+        )?
+    );
+
+    let rest = stream::unfold(Some((Section::Sessions, 0)), move |cursor| {
+        let state = state.clone();
+        async move {
+            let (section, offset) = cursor?;
+            match chunk(&state, credential_id, section, offset).await {
+                Ok((chunk, next)) => Some((Ok(chunk), next)),
+                Err(e) => {
+                    tracing::error!(credential = %credential_id, ?section, "Export aborted: {:?}", e);
+                    Some((Err(io::Error::other("export aborted")), None))
+                }
+            }
+        }
+    });
+    let body = Body::from_stream(stream::once(async { Ok(head) }).chain(rest));
+
+    tracing::info!(credential = %credential_id, "Exporting account");
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"export.json\"",
+        )
+        .body(body)
+        .map_err(|e| {
+            tracing::error!("Error building request: {:#?}", e);
+            ServerError::InternalServerError("Internal Server Error".to_string())
+        })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use super::PAGE_SIZE;
+    use crate::config::{AuthConfig, JsonCase};
+    use crate::server::{App, AppState};
+    use auth_database::entities::credentials::CredentialsBy;
+    use auth_database::entities::sessions::CreateSessionsDAO;
+    use auth_database::traits::{BaseDatabase, DatabaseError, EntityRepository};
+    use auth_database::{AuthDatabase, CredentialsRepository, SessionsRepository};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sqlx::types::chrono::Utc;
+    use std::time::Duration;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    const OWNER: &str = "export@gmail.com";
+    const OTHER: &str = "other-export@gmail.com";
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> sqlx::Pool<sqlx::Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> sqlx::Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    async fn send(
+        app: &mut RouterIntoService<Body>,
+        request: Request<Body>,
+    ) -> axum::response::Response {
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    fn post(uri: &str, body: Value, cookie: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    /// Signs up and in with `email`, returning the session cookie to send back.
+    async fn signed_in(app: &mut RouterIntoService<Body>, email: &str) -> String {
+        let body = serde_json::json!({ "email": email, "password": PASSWORD });
+        let sign_up = send(app, post("/sign_up", body.clone(), None)).await;
+        assert_eq!(sign_up.status(), StatusCode::OK);
+
+        let response = send(app, post("/sign_in", body, None)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+        Cookie::parse(set_cookie.to_str().unwrap().to_string())
+            .unwrap()
+            .stripped()
+            .to_string()
+    }
+
+    async fn export(app: &mut RouterIntoService<Body>, cookie: &str) -> (String, Value) {
+        let request = Request::builder()
+            .uri("/me/export")
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = send(app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        let json = serde_json::from_str(&text).unwrap();
+        (text, json)
+    }
+
+    #[tokio::test]
+    async fn export_only_contains_the_callers_data() {
+        let mut app = App::app(pool().await).await.into_service();
+
+        let cookie = signed_in(&mut app, OWNER).await;
+        let other = signed_in(&mut app, OTHER).await;
+        for (cookie, label) in [(&cookie, "owner key"), (&other, "other key")] {
+            let body = serde_json::json!({ "label": label });
+            let created = send(&mut app, post("/api_keys", body, Some(cookie))).await;
+            assert_eq!(created.status(), StatusCode::CREATED);
+        }
+
+        let (text, json) = export(&mut app, &cookie).await;
+
+        assert_eq!(json["credential"]["email"], OWNER);
+        assert!(json["credential"].get("password").is_none());
+        assert!(!text.contains("$argon2"));
+        assert!(!text.contains("key_hash"));
+
+        let sessions = json["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(!text.contains(cookie.split('=').nth(1).unwrap()));
+
+        let api_keys = json["api_keys"].as_array().unwrap();
+        assert_eq!(api_keys.len(), 1);
+        assert_eq!(api_keys[0]["label"], "owner key");
+
+        assert!(!text.contains(OTHER));
+        assert!(!text.contains("other key"));
+    }
+
+    #[tokio::test]
+    async fn export_pages_through_every_session() {
+        let pool = pool().await;
+        let mut app = App::app(pool.clone()).await.into_service();
+        let cookie = signed_in(&mut app, OWNER).await;
+
+        // Enough to span pages, expired and revoked ones are exported too.
+        let extra = PAGE_SIZE + 20;
+        AuthDatabase::named_transaction(&pool, "export_sessions", |tx| {
+            Box::pin(async move {
+                let credential =
+                    CredentialsRepository::get(tx, CredentialsBy::Email(OWNER.to_string())).await?;
+                for _ in 0..extra {
+                    let session = CreateSessionsDAO {
+                        expires_at: Utc::now() - Duration::from_secs(60),
+                        credential_id: credential.id,
+                        ip: None,
+                        user_agent: None,
+                        is_new_device: false,
+                        fingerprint: None,
+                    };
+                    SessionsRepository::insert(tx, session).await?;
+                }
+                Ok::<_, DatabaseError>(())
+            })
+        })
+        .await
+        .unwrap();
+
+        let (_, json) = export(&mut app, &cookie).await;
+        let sessions = json["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len() as i64, extra + 1);
+
+        let mut ids: Vec<_> = sessions.iter().map(|s| s["id"].as_str().unwrap()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len() as i64, extra + 1);
+    }
+
+    #[tokio::test]
+    async fn export_streams_with_camel_case_keys() {
+        let config = AuthConfig {
+            json_case: JsonCase::Camel,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool().await).with_config(config))
+            .await
+            .into_service();
+        let cookie = signed_in(&mut app, OWNER).await;
+        let body = serde_json::json!({ "label": "owner key" });
+        let created = send(&mut app, post("/api_keys", body, Some(&cookie))).await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+
+        let (text, json) = export(&mut app, &cookie).await;
+
+        assert!(!text.contains("api_keys"));
+        assert!(!text.contains("created_at"));
+        assert_eq!(json["credential"]["email"], OWNER);
+        assert_eq!(json["sessions"].as_array().unwrap().len(), 1);
+        let api_keys = json["apiKeys"].as_array().unwrap();
+        assert_eq!(api_keys.len(), 1);
+        assert!(api_keys[0]["createdAt"].is_string());
+    }
+
+    #[tokio::test]
+    async fn export_requires_a_session() {
+        let mut app = App::app(pool().await).await.into_service();
+
+        let request = Request::builder()
+            .uri("/me/export")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            send(&mut app, request).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
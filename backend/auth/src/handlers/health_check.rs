@@ -1,5 +1,87 @@
-use axum::http::StatusCode;
+use std::sync::Arc;
+
+use auth_database::{AuthDatabase, DB};
+use axum::{extract::State, http::StatusCode};
+
+use crate::server::{AppState, ServerError, ServerResult};
 
 pub async fn health_check() -> StatusCode {
     StatusCode::OK
 }
+
+/// Probe for load balancers, `503` when a `SELECT 1` through the pool fails. Unlike
+/// `/ready` it never writes.
+pub async fn health(State(state): State<Arc<AppState<DB>>>) -> ServerResult<StatusCode> {
+    if let Err(e) = AuthDatabase::ping(&state.pool).await {
+        tracing::warn!("Health ping failed: {:?}", e);
+        return Err(ServerError::ServiceUnavailable(
+            "Database Unavailable".to_string(),
+        ));
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::server::{App, AppState};
+    use auth_database::AuthDatabase;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> sqlx::Pool<sqlx::Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> sqlx::Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("/health")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn health_pings_the_database() {
+        let mut app = App::router(AppState::new(pool().await))
+            .await
+            .into_service();
+
+        let response = app.ready().await.unwrap().call(request()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn health_reports_unreachable_database() {
+        let pool = pool().await;
+        let mut app = App::router(AppState::new(pool.clone()))
+            .await
+            .into_service();
+        pool.close().await;
+
+        let response = app.ready().await.unwrap().call(request()).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json.get("message").unwrap(), "Database Unavailable");
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{extractors::Authenticated, handlers::dto::PublicCredentialsDTO};
+
+/// Credential behind the session cookie, `401` when the session is missing, expired or
+/// inactive.
+pub async fn me(Authenticated(credential): Authenticated) -> PublicCredentialsDTO {
+    PublicCredentialsDTO::from(credential)
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::common::SESSION_KEY;
+    use crate::server::App;
+    use auth_database::{
+        AuthDatabase, CredentialsRepository, SessionsRepository,
+        entities::{credentials::CredentialsBy, sessions::CreateSessionsDAO},
+        traits::{BaseDatabase, EntityRepository},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use sqlx::Pool;
+    use sqlx::types::chrono::Utc;
+    use std::time::Duration;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    use sqlx::Sqlite;
+
+    #[cfg(feature = "integration")]
+    use sqlx::Postgres;
+
+    #[cfg(feature = "unit")]
+    async fn setup() -> (Pool<Sqlite>, Router) {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    #[cfg(feature = "integration")]
+    async fn setup() -> (Pool<Postgres>, Router) {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        let pool = AuthDatabase::connect(&database_url).await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    /// Signs up and in with `email`, returning the session cookie to send back.
+    async fn signed_in(app: &mut RouterIntoService<Body>, email: &str) -> String {
+        let body = serde_json::json!({
+            "email": email,
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        for uri in ["/sign_up", "/sign_in"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            if let Some(set_cookie) = response.headers().get(header::SET_COOKIE) {
+                return Cookie::parse(set_cookie.to_str().unwrap().to_string())
+                    .unwrap()
+                    .stripped()
+                    .to_string();
+            }
+        }
+
+        unreachable!("sign_in always sets the session cookie")
+    }
+
+    fn me_request(cookie: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().uri("/me");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn me_returns_credential_without_password() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+        let cookie = signed_in(&mut app, "me@gmail.com").await;
+
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(me_request(Some(&cookie)))
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(json.get("email").unwrap(), "me@gmail.com");
+        assert_eq!(json.get("active").unwrap(), true);
+        assert_eq!(json.get("role").unwrap(), "user");
+        assert!(json.get("password").is_none());
+    }
+
+    #[tokio::test]
+    async fn me_without_session() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+
+        for cookie in [None, Some(format!("{SESSION_KEY}=not-a-uuid"))] {
+            let response = app
+                .ready()
+                .await
+                .unwrap()
+                .call(me_request(cookie.as_deref()))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn me_after_sign_out() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+        let cookie = signed_in(&mut app, "me-signed-out@gmail.com").await;
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_out")
+            .header(header::COOKIE, &cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(me_request(Some(&cookie)))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn me_with_expired_session() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        signed_in(&mut app, "me-expired@gmail.com").await;
+
+        let session = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                let credential = CredentialsRepository::get(
+                    tx,
+                    CredentialsBy::Email("me-expired@gmail.com".to_string()),
+                )
+                .await?;
+                SessionsRepository::insert(
+                    tx,
+                    CreateSessionsDAO {
+                        expires_at: Utc::now() - Duration::from_secs(60),
+                        credential_id: credential.id,
+                        ip: None,
+                        user_agent: None,
+                        is_new_device: false,
+                        fingerprint: None,
+                    },
+                )
+                .await
+            })
+        })
+        .await
+        .unwrap();
+
+        let cookie = format!("{SESSION_KEY}={}", session.id);
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(me_request(Some(&cookie)))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
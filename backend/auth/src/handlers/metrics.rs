@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::IntoResponse,
+};
+
+use crate::server::AppState;
+
+/// Content type of the Prometheus text exposition format.
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub async fn metrics<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+) -> Result<impl IntoResponse, StatusCode>
+where
+    DB: sqlx::Database,
+{
+    state
+        .metrics
+        .as_ref()
+        .map(|handle| ([(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], handle.render()))
+        .ok_or(StatusCode::NOT_FOUND)
+}
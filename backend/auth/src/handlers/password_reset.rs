@@ -0,0 +1,420 @@
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use auth_database::entities::credentials::{CredentialsBy, SignInAttempts, UpdateCredentialsDAO};
+use auth_database::entities::password_resets::{
+    CreatePasswordResetsDAO, PasswordResetsBy, PasswordResetsDAO, PasswordResetsWhere,
+};
+use auth_database::entities::refresh_tokens::RefreshTokenRevocation;
+use auth_database::entities::sessions::ActiveSessions;
+use auth_database::traits::{BaseDatabase, EntityRepository};
+use auth_database::{
+    AuthDatabase, CredentialsRepository, PasswordResetsRepository, RefreshTokensRepository,
+    SessionsRepository,
+};
+use axum::extract::State;
+use axum::http::StatusCode;
+use sha2::{Digest, Sha256};
+use sqlx::Transaction;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::common::{check_new_password, hash_password, is_valid_email};
+use crate::extractors::Json;
+use crate::handlers::dto::{PasswordResetConfirmDTO, PasswordResetRequestDTO};
+use crate::server::{AppState, ServerError, ServerResult};
+
+const TOKEN_PREFIX: &str = "pr_";
+const TOKEN_BYTES: usize = 32;
+
+/// Token handed to [`crate::server::PasswordResetHook`], the database only keeps its
+/// hash.
+#[derive(Clone)]
+pub struct PasswordResetToken {
+    pub credential_id: Uuid,
+    pub email: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What `password_resets.token_hash` stores for `token`, see
+/// [`crate::handlers::api_keys::hash_api_key`].
+pub fn hash_reset_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn generate_reset_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Issues a reset token for the credential of `email` and hands it to
+/// [`crate::server::AppState::on_password_reset`], invalidating the ones issued before.
+///
+/// Answers `200` whether or not the email has an active account, so it can't be used to
+/// find out which emails are registered.
+pub async fn request<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Json(PasswordResetRequestDTO { email }): Json<PasswordResetRequestDTO>,
+) -> ServerResult<StatusCode>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+    PasswordResetsRepository: EntityRepository<
+            Db = DB,
+            Entity = PasswordResetsDAO,
+            CreateInput = CreatePasswordResetsDAO,
+            QueryOne = PasswordResetsBy,
+            QueryMany = PasswordResetsWhere,
+        >,
+{
+    if !is_valid_email(&email)? {
+        return Err(ServerError::BadRequest("Invalid Email Format".to_string()));
+    };
+
+    let ttl = state.config.password_reset.ttl;
+
+    let issued = AuthDatabase::named_transaction(&state.pool, "password_reset_request", |tx| {
+        Box::pin(async move {
+            let Some(credential) = CredentialsRepository::try_get(tx, CredentialsBy::Email(email))
+                .await?
+                .filter(|credential| credential.active)
+            else {
+                return Ok(None);
+            };
+
+            invalidate_resets(tx, credential.id).await?;
+
+            let token = generate_reset_token();
+            let reset = PasswordResetsRepository::insert(
+                tx,
+                CreatePasswordResetsDAO {
+                    credential_id: credential.id,
+                    token_hash: hash_reset_token(&token),
+                    expires_at: Utc::now() + ttl,
+                },
+            )
+            .await?;
+
+            Ok::<_, ServerError>(Some(PasswordResetToken {
+                credential_id: credential.id,
+                email: credential.email,
+                token,
+                expires_at: reset.expires_at,
+            }))
+        })
+    })
+    .await?;
+
+    match (issued, &state.on_password_reset) {
+        (Some(token), Some(hook)) => {
+            tracing::info!(credential = %token.credential_id, "Password reset requested");
+            tokio::spawn(hook(token));
+        }
+        (Some(token), None) => {
+            tracing::warn!(credential = %token.credential_id, "No way to deliver the reset token");
+        }
+        (None, _) => {}
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Sets a new password with a token from [`request`]. The token is single use, and every
+/// session of the credential is signed out along with any sign-in lock.
+///
+/// Unknown, used and expired tokens answer `400` alike.
+pub async fn confirm<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Json(payload): Json<PasswordResetConfirmDTO>,
+) -> ServerResult<StatusCode>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: SignInAttempts<Db = DB, QueryOne = CredentialsBy>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+    PasswordResetsRepository: EntityRepository<
+            Db = DB,
+            Entity = PasswordResetsDAO,
+            QueryOne = PasswordResetsBy,
+            QueryMany = PasswordResetsWhere,
+        >,
+{
+    check_new_password(&state.config, &payload.new_password)?;
+
+    let password_storage = state.config.password_storage;
+    let argon2 = state.config.argon2;
+    let pepper = state.config.password_pepper.clone();
+    let token_hash = hash_reset_token(&payload.token);
+
+    let (id, revoked_sessions) =
+        AuthDatabase::named_transaction(&state.pool, "password_reset_confirm", |tx| {
+            Box::pin(async move {
+                let invalid = || ServerError::BadRequest("Invalid Or Expired Token".to_string());
+
+                let Some(reset) =
+                    PasswordResetsRepository::try_get(tx, PasswordResetsBy::TokenHash(token_hash))
+                        .await?
+                        .filter(|reset| reset.active && reset.expires_at > Utc::now())
+                else {
+                    return Err(invalid());
+                };
+
+                let Some(credential) =
+                    CredentialsRepository::try_get(tx, CredentialsBy::Id(reset.credential_id))
+                        .await?
+                        .filter(|credential| credential.active)
+                else {
+                    return Err(invalid());
+                };
+
+                invalidate_resets(tx, credential.id).await?;
+
+                let hash = hash_password(&payload.new_password, &argon2, pepper.as_ref())?;
+                CredentialsRepository::update(
+                    tx,
+                    CredentialsBy::Id(credential.id),
+                    UpdateCredentialsDAO {
+                        password: hash,
+                        active: credential.active,
+                        role: credential.role,
+                        password_storage,
+                        version: credential.version,
+                    },
+                )
+                .await?;
+
+                CredentialsRepository::reset_failures(tx, credential.id).await?;
+                let revoked_sessions = session::revoke_all(tx, credential.id).await?;
+
+                Ok((credential.id, revoked_sessions))
+            })
+        })
+        .await?;
+
+    tracing::info!(credential = %id, revoked_sessions, "Password reset");
+
+    Ok(StatusCode::OK)
+}
+
+/// Deactivates the unused reset tokens of `credential_id`.
+async fn invalidate_resets<DB>(
+    tx: &mut Transaction<'_, DB>,
+    credential_id: Uuid,
+) -> Result<(), auth_database::traits::DatabaseError>
+where
+    DB: sqlx::Database,
+    PasswordResetsRepository: EntityRepository<
+            Db = DB,
+            Entity = PasswordResetsDAO,
+            QueryOne = PasswordResetsBy,
+            QueryMany = PasswordResetsWhere,
+        >,
+{
+    let resets =
+        PasswordResetsRepository::get_all(tx, PasswordResetsWhere::CredentialId(credential_id))
+            .await?;
+
+    for reset in resets.into_iter().filter(|reset| reset.active) {
+        PasswordResetsRepository::delete(tx, PasswordResetsBy::Id(reset.id)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::PasswordResetToken;
+    use crate::common::SESSION_KEY;
+    use crate::config::{AuthConfig, PasswordResetConfig};
+    use crate::server::{App, AppState};
+    use auth_database::AuthDatabase;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+    const NEW_PASSWORD: &str = "Lq8!vX2m#Tz5pW";
+
+    /// App handing reset tokens to the returned receiver.
+    async fn setup(
+        ttl: Duration,
+    ) -> (
+        RouterIntoService<Body>,
+        UnboundedReceiver<PasswordResetToken>,
+    ) {
+        #[cfg(feature = "unit")]
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        #[cfg(feature = "integration")]
+        let pool = {
+            dotenvy::dotenv().ok();
+            let database_url = std::env::var("AUTH_DATABASE_URL")
+                .expect("AUTH_DATABASE_URL must be set for integration tests");
+            AuthDatabase::connect(&database_url).await.unwrap()
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        let config = AuthConfig {
+            password_reset: PasswordResetConfig { ttl },
+            ..AuthConfig::default()
+        };
+        let state = AppState::new(pool)
+            .with_config(config)
+            .with_on_password_reset(std::sync::Arc::new(move |token| {
+                let sender = sender.clone();
+                Box::pin(async move {
+                    sender.send(token).unwrap();
+                })
+            }));
+
+        (App::router(state).await.into_service(), receiver)
+    }
+
+    async fn post(
+        app: &mut RouterIntoService<Body>,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> axum::response::Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    /// Signs `email` up and in with [`PASSWORD`], returning the session cookie.
+    async fn sign_up(app: &mut RouterIntoService<Body>, email: &str) -> String {
+        let body = serde_json::json!({ "email": email, "password": PASSWORD });
+        assert_eq!(
+            post(app, "/sign_up", body.clone()).await.status(),
+            StatusCode::OK
+        );
+
+        let response = post(app, "/sign_in", body).await;
+        let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        Cookie::parse(cookie.to_string())
+            .unwrap()
+            .value()
+            .to_string()
+    }
+
+    async fn request_token(
+        app: &mut RouterIntoService<Body>,
+        receiver: &mut UnboundedReceiver<PasswordResetToken>,
+        email: &str,
+    ) -> String {
+        let response = post(
+            app,
+            "/password_reset/request",
+            serde_json::json!({ "email": email }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        receiver.recv().await.unwrap().token
+    }
+
+    async fn confirm(app: &mut RouterIntoService<Body>, token: &str) -> StatusCode {
+        let body = serde_json::json!({ "token": token, "new_password": NEW_PASSWORD });
+        post(app, "/password_reset/confirm", body).await.status()
+    }
+
+    async fn sign_in_status(
+        app: &mut RouterIntoService<Body>,
+        email: &str,
+        password: &str,
+    ) -> StatusCode {
+        let body = serde_json::json!({ "email": email, "password": password });
+        post(app, "/sign_in", body).await.status()
+    }
+
+    #[tokio::test]
+    async fn reset_replaces_the_password_and_signs_sessions_out() {
+        let (mut app, mut receiver) = setup(Duration::from_secs(60 * 60)).await;
+        let email = "reset@gmail.com";
+        let session = sign_up(&mut app, email).await;
+
+        let token = request_token(&mut app, &mut receiver, email).await;
+        assert!(token.starts_with("pr_"));
+        assert_eq!(confirm(&mut app, &token).await, StatusCode::OK);
+
+        assert_eq!(
+            sign_in_status(&mut app, email, PASSWORD).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            sign_in_status(&mut app, email, NEW_PASSWORD).await,
+            StatusCode::OK
+        );
+
+        let request = Request::builder()
+            .uri("/me")
+            .header(header::COOKIE, format!("{SESSION_KEY}={session}"))
+            .body(Body::empty())
+            .unwrap();
+        let me = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(me.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn tokens_are_single_use() {
+        let (mut app, mut receiver) = setup(Duration::from_secs(60 * 60)).await;
+        let email = "single_use@gmail.com";
+        sign_up(&mut app, email).await;
+
+        let superseded = request_token(&mut app, &mut receiver, email).await;
+        let token = request_token(&mut app, &mut receiver, email).await;
+
+        assert_eq!(
+            confirm(&mut app, &superseded).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(confirm(&mut app, &token).await, StatusCode::OK);
+        assert_eq!(confirm(&mut app, &token).await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_rejected() {
+        let (mut app, mut receiver) = setup(Duration::ZERO).await;
+        let email = "expired@gmail.com";
+        sign_up(&mut app, email).await;
+
+        let token = request_token(&mut app, &mut receiver, email).await;
+
+        assert_eq!(confirm(&mut app, &token).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            sign_in_status(&mut app, email, PASSWORD).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_email_is_answered_like_a_known_one() {
+        let (mut app, mut receiver) = setup(Duration::from_secs(60 * 60)).await;
+
+        let body = serde_json::json!({ "email": "nobody@gmail.com" });
+        let response = post(&mut app, "/password_reset/request", body).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let delivered = tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await;
+        assert!(delivered.is_err());
+        assert_eq!(
+            confirm(&mut app, "pr_unknown").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+
+use crate::common::is_valid_email;
+use crate::extractors::Json;
+use crate::handlers::dto::{PrehashSaltDTO, PrehashSaltRequestDTO};
+use crate::server::{AppState, ServerError, ServerResult};
+
+/// Salt the client hashes the password of `email` with, see
+/// [`crate::config::ClientPrehashConfig`]. Derived without a lookup, so emails without an
+/// account get a salt like any other and can't be told apart.
+pub async fn prehash_salt<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Json(payload): Json<PrehashSaltRequestDTO>,
+) -> ServerResult<PrehashSaltDTO>
+where
+    DB: sqlx::Database,
+{
+    if !is_valid_email(&payload.email)? {
+        return Err(ServerError::BadRequest("Invalid Email Format".to_string()));
+    };
+
+    let Some(client_prehash) = &state.config.client_prehash else {
+        return Err(ServerError::NotFound("Not Found".to_string()));
+    };
+
+    Ok(PrehashSaltDTO {
+        salt: client_prehash.salt(&payload.email),
+    })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::config::{AuthConfig, ClientPrehashConfig};
+    use crate::handlers::dto::PrehashSaltDTO;
+    use crate::server::{App, AppState};
+    use auth_database::entities::credentials::CredentialsBy;
+    use auth_database::traits::{BaseDatabase, EntityRepository};
+    use auth_database::{AuthDatabase, CredentialsRepository};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use http_body_util::BodyExt;
+    use sha2::{Digest, Sha256};
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> sqlx::Pool<sqlx::Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> sqlx::Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            client_prehash: Some(ClientPrehashConfig::new("prehash secret")),
+            ..AuthConfig::default()
+        }
+    }
+
+    async fn post(
+        app: &mut RouterIntoService<Body>,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> axum::response::Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    /// What the client sends: the password hashed with the salt fetched for `email`.
+    async fn prehash(app: &mut RouterIntoService<Body>, email: &str, password: &str) -> String {
+        let response = post(app, "/prehash_salt", serde_json::json!({ "email": email })).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let PrehashSaltDTO { salt } = serde_json::from_slice(&body).unwrap();
+
+        hex::encode(Sha256::digest(format!("{password}{salt}")))
+    }
+
+    #[tokio::test]
+    async fn prehashed_password_round_trip() {
+        let pool = pool().await;
+        let state = AppState::new(pool.clone()).with_config(config());
+        let mut app = App::router(state).await.into_service();
+        let email = "prehash@gmail.com";
+
+        let password = prehash(&mut app, email, PASSWORD).await;
+        let credentials = serde_json::json!({ "email": email, "password": password });
+
+        let sign_up = post(&mut app, "/sign_up", credentials.clone()).await;
+        assert_eq!(sign_up.status(), StatusCode::OK);
+
+        let sign_in = post(&mut app, "/sign_in", credentials).await;
+        assert_eq!(sign_in.status(), StatusCode::OK);
+
+        let wrong = prehash(&mut app, email, "wrong-password").await;
+        let wrong_password = serde_json::json!({ "email": email, "password": wrong });
+        let refused = post(&mut app, "/sign_in", wrong_password).await;
+        assert_eq!(refused.status(), StatusCode::UNAUTHORIZED);
+
+        // Stored as an Argon2 hash of the pre-hash, a leaked row can't be replayed.
+        let stored = AuthDatabase::named_transaction(&pool, "stored_password", |tx| {
+            Box::pin(async move {
+                CredentialsRepository::get(tx, CredentialsBy::Email(email.to_string())).await
+            })
+        })
+        .await
+        .unwrap();
+        assert!(stored.password.starts_with("$argon2"));
+        assert!(!stored.password.contains(&password));
+    }
+
+    #[tokio::test]
+    async fn raw_passwords_are_rejected() {
+        let state = AppState::new(pool().await).with_config(config());
+        let mut app = App::router(state).await.into_service();
+        let credentials = serde_json::json!({ "email": "raw@gmail.com", "password": PASSWORD });
+
+        let sign_up = post(&mut app, "/sign_up", credentials.clone()).await;
+        let sign_in = post(&mut app, "/sign_in", credentials).await;
+
+        assert_eq!(sign_up.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(sign_in.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn salts_are_stable_per_email() {
+        let state = AppState::new(pool().await).with_config(config());
+        let mut app = App::router(state).await.into_service();
+
+        let salt = |email: &str| config().client_prehash.unwrap().salt(email);
+
+        assert_eq!(salt("someone@gmail.com"), salt("SomeOne@gmail.com"));
+        assert_ne!(salt("someone@gmail.com"), salt("another@gmail.com"));
+        assert_eq!(
+            prehash(&mut app, "someone@gmail.com", PASSWORD).await,
+            prehash(&mut app, "someone@gmail.com", PASSWORD).await
+        );
+
+        let invalid = post(
+            &mut app,
+            "/prehash_salt",
+            serde_json::json!({ "email": "nope" }),
+        )
+        .await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+
+        let mut without_prehash = App::router(AppState::new(pool().await))
+            .await
+            .into_service();
+        let unmounted = post(
+            &mut without_prehash,
+            "/prehash_salt",
+            serde_json::json!({ "email": "someone@gmail.com" }),
+        )
+        .await;
+        assert_eq!(unmounted.status(), StatusCode::NOT_FOUND);
+    }
+}
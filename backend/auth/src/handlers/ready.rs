@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use auth_database::{AuthDatabase, DB};
+use axum::{extract::State, http::StatusCode};
+
+use crate::server::{AppState, ServerError, ServerResult};
+
+/// Readiness probe, `503` while the database can't serve requests.
+///
+/// Always pings the database, and also performs a throwaway write when
+/// [`crate::config::AuthConfig::readiness_write_check`] is set.
+pub async fn ready(State(state): State<Arc<AppState<DB>>>) -> ServerResult<StatusCode> {
+    if let Err(e) = AuthDatabase::ping(&state.pool).await {
+        tracing::warn!("Readiness ping failed: {:?}", e);
+        return Err(ServerError::ServiceUnavailable(
+            "Database Unavailable".to_string(),
+        ));
+    }
+
+    let writes = if state.config.readiness_write_check {
+        AuthDatabase::check_writes(&state.pool).await
+    } else {
+        Ok(())
+    };
+
+    if let Err(e) = writes {
+        tracing::warn!("Readiness write check failed: {:?}", e);
+        return Err(ServerError::ServiceUnavailable(
+            "Database Writes Unavailable".to_string(),
+        ));
+    }
+
+    Ok(StatusCode::OK)
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::config::AuthConfig;
+    use crate::server::{App, AppState};
+    use auth_database::AuthDatabase;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> sqlx::Pool<sqlx::Sqlite> {
+        AuthDatabase::connect(":memory:").await.unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> sqlx::Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        AuthDatabase::connect(&database_url).await.unwrap()
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("/ready")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn ready_with_write_check() {
+        let config = AuthConfig {
+            readiness_write_check: true,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool().await).with_config(config))
+            .await
+            .into_service();
+
+        let response = app.ready().await.unwrap().call(request()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "unit")]
+    #[tokio::test]
+    async fn ready_reports_blocked_writes() {
+        use http_body_util::BodyExt;
+        use serde_json::Value;
+
+        let pool = pool().await;
+        sqlx::query("CREATE TRIGGER block_writes BEFORE INSERT ON health_checks BEGIN SELECT RAISE(ABORT, 'read-only'); END;")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut app = App::router(AppState::new(pool.clone()))
+            .await
+            .into_service();
+        let response = app.ready().await.unwrap().call(request()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config = AuthConfig {
+            readiness_write_check: true,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let response = app.ready().await.unwrap().call(request()).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json.get("message").unwrap(), "Database Writes Unavailable");
+    }
+
+    #[tokio::test]
+    async fn ready_reports_unreachable_database() {
+        let pool = pool().await;
+        let mut app = App::router(AppState::new(pool.clone()))
+            .await
+            .into_service();
+        pool.close().await;
+
+        let response = app.ready().await.unwrap().call(request()).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,492 @@
+use std::{sync::Arc, time::Duration};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use auth_database::entities::credentials::CredentialsBy;
+use auth_database::entities::refresh_tokens::{
+    CreateRefreshTokensDAO, RefreshTokensBy, RefreshTokensDAO, RefreshTokensWhere,
+};
+use auth_database::entities::sessions::{SessionsBy, SessionsDAO};
+use auth_database::traits::{BaseDatabase, DatabaseError, EntityRepository};
+use auth_database::{
+    AuthDatabase, CredentialsRepository, RefreshTokensRepository, SessionsRepository,
+};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderMap, Response};
+use axum::response::IntoResponse;
+use session::{NewSession, SessionSecret};
+use sha2::{Digest, Sha256};
+use sqlx::Transaction;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::config::RefreshTokenConfig;
+use crate::cookies::{CookieAction, clear_refresh_cookie, parse_refresh_cookie};
+use crate::extractors::ClientInfo;
+use crate::handlers::sign_in::signed_in_response;
+use crate::server::{AppState, ServerError, ServerResult};
+
+const TOKEN_PREFIX: &str = "rt_";
+const TOKEN_BYTES: usize = 32;
+
+/// Refresh token as handed to the client, the database only keeps its hash.
+#[derive(Debug, Clone)]
+pub struct IssuedRefreshToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What `refresh_tokens.token_hash` stores for `token`, see
+/// [`crate::handlers::api_keys::hash_api_key`].
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn generate_refresh_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Stores a new token of `family_id` bound to `session`, valid for `ttl`.
+pub async fn issue<DB>(
+    tx: &mut Transaction<'_, DB>,
+    ttl: Duration,
+    session: &SessionsDAO,
+    family_id: Uuid,
+) -> Result<IssuedRefreshToken, DatabaseError>
+where
+    DB: sqlx::Database,
+    RefreshTokensRepository:
+        EntityRepository<Db = DB, Entity = RefreshTokensDAO, CreateInput = CreateRefreshTokensDAO>,
+{
+    let token = generate_refresh_token();
+    let stored = RefreshTokensRepository::insert(
+        tx,
+        CreateRefreshTokensDAO {
+            credential_id: session.credential_id,
+            family_id,
+            session_id: session.id,
+            token_hash: hash_refresh_token(&token),
+            expires_at: Utc::now() + ttl,
+        },
+    )
+    .await?;
+
+    Ok(IssuedRefreshToken {
+        token,
+        expires_at: stored.expires_at,
+    })
+}
+
+enum RefreshOutcome {
+    Rotated(SessionsDAO, IssuedRefreshToken),
+    /// An already used token was presented again, its family is revoked.
+    Reused(Uuid),
+    Refused,
+}
+
+/// Exchanges the refresh token cookie for a new session and a new token of the same
+/// family, the presented token and its session are revoked.
+///
+/// Tokens are single use, so presenting a used one means it leaked: every token of its
+/// family and their sessions are revoked, signing out both the thief and the owner.
+/// A token is also refused once its session was revoked, by signing out for instance, or
+/// no longer exists.
+pub async fn refresh<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    headers: HeaderMap,
+    client: ClientInfo,
+) -> ServerResult<Response<Body>>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+    SessionsRepository: EntityRepository<Db = DB, Entity = SessionsDAO, QueryOne = SessionsBy>,
+    RefreshTokensRepository: EntityRepository<
+            Db = DB,
+            Entity = RefreshTokensDAO,
+            CreateInput = CreateRefreshTokensDAO,
+            QueryOne = RefreshTokensBy,
+            QueryMany = RefreshTokensWhere,
+        >,
+{
+    let Some(config) = &state.config.refresh_token else {
+        return Err(ServerError::Unauthorized);
+    };
+
+    let Some(token) = parse_refresh_cookie(&headers, config) else {
+        return Err(ServerError::Unauthorized);
+    };
+
+    let session_ttl = state.config.session.ttl;
+    let refresh_ttl = config.ttl;
+    let token_hash = hash_refresh_token(&token);
+
+    let outcome = AuthDatabase::named_transaction(&state.pool, "refresh", |tx| {
+        Box::pin(async move {
+            let maybe_token =
+                RefreshTokensRepository::try_get(tx, RefreshTokensBy::TokenHash(token_hash))
+                    .await?;
+
+            let Some(current) = maybe_token else {
+                return Ok(RefreshOutcome::Refused);
+            };
+
+            // Revoking through `Ok` commits it along with the refusal.
+            if !current.active {
+                revoke_family(tx, current.family_id).await?;
+                return Ok(RefreshOutcome::Reused(current.family_id));
+            }
+
+            if current.expires_at <= Utc::now() {
+                return Ok(RefreshOutcome::Refused);
+            }
+
+            let session =
+                SessionsRepository::try_get(tx, SessionsBy::Id(current.session_id)).await?;
+            // A gone session counts as revoked, the purge only keeps sessions that back a
+            // live token.
+            if !session.is_some_and(|session| session.active) {
+                RefreshTokensRepository::delete(tx, RefreshTokensBy::Id(current.id)).await?;
+                return Ok(RefreshOutcome::Refused);
+            }
+
+            let credential =
+                CredentialsRepository::try_get(tx, CredentialsBy::Id(current.credential_id))
+                    .await?;
+            if !credential.is_some_and(|credential| credential.active) {
+                return Ok(RefreshOutcome::Refused);
+            }
+
+            RefreshTokensRepository::delete(tx, RefreshTokensBy::Id(current.id)).await?;
+            revoke_session(tx, current.session_id).await?;
+
+            let new_session = NewSession {
+                credential_id: current.credential_id,
+                ip: client.ip.map(|ip| ip.to_string()),
+                user_agent: client.user_agent,
+                fingerprint: client.fingerprint,
+            };
+            let session = session::create(tx, new_session, session_ttl).await?;
+            let issued = issue(tx, refresh_ttl, &session, current.family_id).await?;
+
+            Ok::<_, ServerError>(RefreshOutcome::Rotated(session, issued))
+        })
+    })
+    .await?;
+
+    let (session, issued) = match outcome {
+        RefreshOutcome::Rotated(session, issued) => (session, issued),
+        RefreshOutcome::Reused(family_id) => {
+            tracing::warn!(family = %family_id, "Refresh token reused, family revoked");
+            return Ok(refused(&state, config));
+        }
+        RefreshOutcome::Refused => return Ok(refused(&state, config)),
+    };
+
+    tracing::debug!(session = %SessionSecret::from(session.id), "Session refreshed");
+    signed_in_response(&state, &session, CookieAction::rotated, Some(&issued))
+}
+
+/// `401` that also drops the refresh token cookie, it can't be used anymore.
+fn refused<DB>(state: &AppState<DB>, config: &RefreshTokenConfig) -> Response<Body>
+where
+    DB: sqlx::Database,
+{
+    let cookie = clear_refresh_cookie(&state.config.cookie, config);
+    let mut response = ServerError::Unauthorized.into_response();
+    if let Ok(value) = cookie.to_string().parse() {
+        response.headers_mut().append(SET_COOKIE, value);
+    }
+    response
+}
+
+/// Deactivates every token of `family_id` and the sessions they were issued with.
+async fn revoke_family<DB>(
+    tx: &mut Transaction<'_, DB>,
+    family_id: Uuid,
+) -> Result<(), DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryOne = SessionsBy>,
+    RefreshTokensRepository: EntityRepository<
+            Db = DB,
+            Entity = RefreshTokensDAO,
+            QueryOne = RefreshTokensBy,
+            QueryMany = RefreshTokensWhere,
+        >,
+{
+    let family =
+        RefreshTokensRepository::get_all(tx, RefreshTokensWhere::FamilyId(family_id)).await?;
+
+    for token in family {
+        if token.active {
+            RefreshTokensRepository::delete(tx, RefreshTokensBy::Id(token.id)).await?;
+        }
+        revoke_session(tx, token.session_id).await?;
+    }
+
+    Ok(())
+}
+
+/// Expired sessions are purged, so the session of an old token may be gone already.
+async fn revoke_session<DB>(
+    tx: &mut Transaction<'_, DB>,
+    session_id: Uuid,
+) -> Result<(), DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryOne = SessionsBy>,
+{
+    match SessionsRepository::delete(tx, SessionsBy::Id(session_id)).await {
+        Ok(_) => Ok(()),
+        Err(e) if matches!(e.kind(), DatabaseError::NotFound(_)) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::common::{REFRESH_KEY, SESSION_KEY};
+    use crate::config::{AuthConfig, RefreshTokenConfig};
+    use crate::server::{App, AppState};
+    use auth_database::entities::sessions::ActiveSessions;
+    use auth_database::traits::BaseDatabase;
+    use auth_database::{AuthDatabase, DB, SessionsRepository};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+        response::Response,
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use sqlx::Pool;
+    use sqlx::types::chrono::Utc;
+    use std::time::Duration;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    async fn setup() -> (Pool<DB>, RouterIntoService<Body>) {
+        #[cfg(feature = "unit")]
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        #[cfg(feature = "integration")]
+        let pool = {
+            dotenvy::dotenv().ok();
+            let database_url = std::env::var("AUTH_DATABASE_URL")
+                .expect("AUTH_DATABASE_URL must be set for integration tests");
+            AuthDatabase::connect(&database_url).await.unwrap()
+        };
+
+        let config = AuthConfig {
+            refresh_token: Some(RefreshTokenConfig::default()),
+            ..AuthConfig::default()
+        };
+
+        let app = App::router(AppState::new(pool.clone()).with_config(config))
+            .await
+            .into_service();
+        (pool, app)
+    }
+
+    async fn post(
+        app: &mut RouterIntoService<Body>,
+        uri: &str,
+        body: Body,
+        cookie: &str,
+    ) -> Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::COOKIE, cookie)
+            .body(body)
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    /// Cookies set by `response`, by name.
+    fn cookies(response: &Response) -> HashMap<String, String> {
+        response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| Cookie::parse(value.to_str().unwrap().to_string()).ok())
+            .map(|cookie| (cookie.name().to_string(), cookie.value().to_string()))
+            .collect()
+    }
+
+    /// Signs a new credential up and in, returning its session id and refresh token.
+    async fn sign_in(app: &mut RouterIntoService<Body>, email: &str) -> (String, String) {
+        let body = serde_json::json!({ "email": email, "password": PASSWORD }).to_string();
+        let response = post(app, "/sign_up", Body::from(body.clone()), "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = post(app, "/sign_in", Body::from(body), "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookies = cookies(&response);
+        (cookies[SESSION_KEY].clone(), cookies[REFRESH_KEY].clone())
+    }
+
+    async fn refresh(app: &mut RouterIntoService<Body>, token: &str) -> Response {
+        post(
+            app,
+            "/refresh",
+            Body::empty(),
+            &format!("{REFRESH_KEY}={token}"),
+        )
+        .await
+    }
+
+    async fn me_status(app: &mut RouterIntoService<Body>, session: &str) -> StatusCode {
+        let request = Request::builder()
+            .uri("/me")
+            .header(header::COOKIE, format!("{SESSION_KEY}={session}"))
+            .body(Body::empty())
+            .unwrap();
+
+        app.ready()
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_the_token_and_the_session() {
+        let (_, mut app) = setup().await;
+        let (session, token) = sign_in(&mut app, "refresh@gmail.com").await;
+        assert!(token.starts_with("rt_"));
+
+        let response = refresh(&mut app, &token).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookies = cookies(&response);
+        let (new_session, new_token) = (&cookies[SESSION_KEY], &cookies[REFRESH_KEY]);
+        assert_ne!(new_session, &session);
+        assert_ne!(new_token, &token);
+
+        assert_eq!(
+            me_status(&mut app, &session).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(me_status(&mut app, new_session).await, StatusCode::OK);
+
+        let response = refresh(&mut app, new_token).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reused_token_revokes_its_family() {
+        let (_, mut app) = setup().await;
+        let (_, token) = sign_in(&mut app, "reuse@gmail.com").await;
+        let (_, other_token) = sign_in(&mut app, "bystander@gmail.com").await;
+
+        let rotated = cookies(&refresh(&mut app, &token).await);
+        let (new_session, new_token) = (&rotated[SESSION_KEY], &rotated[REFRESH_KEY]);
+
+        let response = refresh(&mut app, &token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(cookies(&response)[REFRESH_KEY], "");
+
+        assert_eq!(
+            me_status(&mut app, new_session).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let response = refresh(&mut app, new_token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = refresh(&mut app, &other_token).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn token_of_a_signed_out_session_is_refused() {
+        let (_, mut app) = setup().await;
+        let (session, token) = sign_in(&mut app, "signed_out@gmail.com").await;
+
+        let cookie = format!("{SESSION_KEY}={session}");
+        let response = post(&mut app, "/sign_out", Body::empty(), &cookie).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = refresh(&mut app, &token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = refresh(&mut app, "rt_unknown").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// Runs the expired session purge as if it were two days from now, past the session
+    /// TTL but well within the refresh token's.
+    async fn purge_later(pool: &Pool<DB>) {
+        AuthDatabase::named_transaction(pool, "purge_expired_sessions", |tx| {
+            Box::pin(async move {
+                SessionsRepository::delete_expired(
+                    tx,
+                    Utc::now() + Duration::from_secs(60 * 60 * 48),
+                )
+                .await
+            })
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn signed_out_token_stays_refused_once_its_session_is_purged() {
+        let (pool, mut app) = setup().await;
+        let (session, token) = sign_in(&mut app, "purged@gmail.com").await;
+        let (_, live_token) = sign_in(&mut app, "kept@gmail.com").await;
+
+        let cookie = format!("{SESSION_KEY}={session}");
+        let response = post(&mut app, "/sign_out", Body::empty(), &cookie).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(cookies(&response)[REFRESH_KEY], "");
+
+        purge_later(&pool).await;
+
+        let response = refresh(&mut app, &token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // The session of a token still in use outlives the purge.
+        let response = refresh(&mut app, &live_token).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn tokens_of_other_devices_are_refused_after_a_password_change() {
+        let (_, mut app) = setup().await;
+        let (session, token) = sign_in(&mut app, "other_devices@gmail.com").await;
+        let body = serde_json::json!({ "email": "other_devices@gmail.com", "password": PASSWORD });
+        let response = post(&mut app, "/sign_in", Body::from(body.to_string()), "").await;
+        let other_token = cookies(&response)[REFRESH_KEY].clone();
+
+        let body = serde_json::json!({
+            "old_password": PASSWORD,
+            "new_password": "Lq8!vX2m#Tz5pW",
+        });
+        let cookie = format!("{SESSION_KEY}={session}");
+        let response = post(
+            &mut app,
+            "/change_password",
+            Body::from(body.to_string()),
+            &cookie,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = refresh(&mut app, &other_token).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = refresh(&mut app, &token).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
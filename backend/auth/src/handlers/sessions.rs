@@ -0,0 +1,378 @@
+use std::sync::Arc;
+
+use auth_database::{
+    AuthDatabase, CredentialsRepository, RefreshTokensRepository, SessionsRepository,
+    entities::{
+        refresh_tokens::RefreshTokenRevocation,
+        sessions::{ActiveSessions, SessionsWhere},
+    },
+    traits::{BaseDatabase, EntityRepository},
+};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{Response, StatusCode},
+};
+use session::SessionSecret;
+
+use crate::{
+    cookies::CookieAction,
+    extractors::{AuthSession, Authenticated},
+    handlers::dto::{ActiveSessionsDTO, DeviceSessionDTO, DeviceSessionsDTO},
+    server::{AppState, ServerError, ServerResult},
+};
+
+/// Number of active, unexpired sessions of the signed in credential.
+pub async fn count<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Authenticated(credential): Authenticated,
+) -> ServerResult<ActiveSessionsDTO>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: ActiveSessions<Db = DB>,
+{
+    let active_sessions =
+        AuthDatabase::named_transaction(&state.pool, "count_active_sessions", |tx| {
+            Box::pin(async move { SessionsRepository::count_active(tx, credential.id).await })
+        })
+        .await?;
+
+    Ok(ActiveSessionsDTO { active_sessions })
+}
+
+/// Active sessions of the signed in credential, newest first, each with the public id
+/// [`revoke`] takes.
+pub async fn list<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    auth: AuthSession,
+) -> ServerResult<DeviceSessionsDTO>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+{
+    let sessions = AuthDatabase::named_transaction(&state.pool, "list_sessions", |tx| {
+        Box::pin(async move { session::list_active(tx, auth.credential_id).await })
+    })
+    .await?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|session| DeviceSessionDTO {
+            id: SessionSecret::from(session.id).public_id(),
+            created_at: session.created_at.to_string(),
+            expires_at: session.expires_at.to_string(),
+            ip: session.ip,
+            user_agent: session.user_agent,
+            current: session.id == auth.session.expose(),
+        })
+        .collect();
+
+    Ok(DeviceSessionsDTO { sessions })
+}
+
+/// Signs one of the caller's devices out by the public id from [`list`]. Sessions of
+/// other credentials answer `404` like unknown ids. Revoking the current session also
+/// clears its cookies, like `/sign_out`.
+///
+/// Mounted at `DELETE /sessions/{id}` and `POST /sessions/revoke/{id}`, for clients that
+/// can't send `DELETE`.
+pub async fn revoke<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    auth: AuthSession,
+    Path(id): Path<String>,
+) -> ServerResult<Response<Body>>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    let revoked = AuthDatabase::named_transaction(&state.pool, "revoke_session", |tx| {
+        Box::pin(async move {
+            session::revoke_by_public_id(tx, auth.credential_id, &id)
+                .await?
+                .ok_or_else(|| ServerError::NotFound("Not Found".to_string()))
+        })
+    })
+    .await?;
+
+    let mut response = Response::builder().status(StatusCode::NO_CONTENT);
+
+    if revoked.id == auth.session.expose() {
+        response = CookieAction::Cleared.apply(&state.config, response);
+    }
+
+    response.body(Body::empty()).map_err(|e| {
+        tracing::error!("Error building request: {:#?}", e);
+        ServerError::InternalServerError("Internal Server Error".to_string())
+    })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::server::App;
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, Response, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use cookie::Cookie;
+    use http_body_util::BodyExt;
+    use serde_json::Value;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    async fn pool() -> sqlx::Pool<sqlx::Sqlite> {
+        auth_database::AuthDatabase::connect(":memory:")
+            .await
+            .unwrap()
+    }
+
+    #[cfg(feature = "integration")]
+    async fn pool() -> sqlx::Pool<sqlx::Postgres> {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        auth_database::AuthDatabase::connect(&database_url)
+            .await
+            .unwrap()
+    }
+
+    async fn setup() -> Router {
+        App::app(pool().await).await
+    }
+
+    #[tokio::test]
+    async fn count_active_sessions() {
+        let mut app = setup().await.into_service();
+        let body = serde_json::json!({
+            "email": "sessions@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let mut cookie = String::new();
+        for _ in 0..3 {
+            let request = Request::builder()
+                .method("POST")
+                .uri("/sign_in")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+            cookie = Cookie::parse(set_cookie.to_str().unwrap().to_string())
+                .unwrap()
+                .stripped()
+                .to_string();
+        }
+
+        let request = Request::builder()
+            .uri("/sessions/count")
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(json.get("active_sessions").unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn count_active_sessions_requires_session() {
+        let mut app = setup().await.into_service();
+        let request = Request::builder()
+            .uri("/sessions/count")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    async fn sign_in(app: &mut RouterIntoService<Body>, email: &str) -> String {
+        let body = serde_json::json!({ "email": email, "password": "Ej4a2fkj!yI!Cj9" });
+        for uri in ["/sign_up", "/sign_in"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            if uri == "/sign_in" {
+                let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+                return Cookie::parse(set_cookie.to_str().unwrap().to_string())
+                    .unwrap()
+                    .stripped()
+                    .to_string();
+            }
+        }
+        unreachable!()
+    }
+
+    async fn call(
+        app: &mut RouterIntoService<Body>,
+        method: &str,
+        uri: &str,
+        cookie: &str,
+    ) -> Response<Body> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    async fn list(app: &mut RouterIntoService<Body>, cookie: &str) -> Vec<Value> {
+        let response = call(app, "GET", "/sessions", cookie).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        json["sessions"].as_array().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn revoke_another_device() {
+        let mut app = setup().await.into_service();
+        let current = sign_in(&mut app, "devices@gmail.com").await;
+        let other = sign_in(&mut app, "devices@gmail.com").await;
+        let stranger = sign_in(&mut app, "devices-stranger@gmail.com").await;
+
+        let sessions = list(&mut app, &current).await;
+        assert_eq!(sessions.len(), 2);
+        for session in &sessions {
+            let id = session["id"].as_str().unwrap();
+            assert!(!current.contains(id) && !other.contains(id), "{id}");
+        }
+        let other_id = sessions
+            .iter()
+            .find(|session| session["current"] == false)
+            .unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = call(
+            &mut app,
+            "DELETE",
+            &format!("/sessions/{other_id}"),
+            &stranger,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = call(
+            &mut app,
+            "DELETE",
+            &format!("/sessions/{other_id}"),
+            &current,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+        let response = call(&mut app, "GET", "/sessions/count", &current).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(&mut app, "GET", "/sessions/count", &other).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let sessions = list(&mut app, &current).await;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["current"], true);
+
+        let response = call(
+            &mut app,
+            "DELETE",
+            &format!("/sessions/{other_id}"),
+            &current,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn revoke_current_device_clears_cookie() {
+        let mut app = setup().await.into_service();
+        let current = sign_in(&mut app, "devices-current@gmail.com").await;
+        let sessions = list(&mut app, &current).await;
+        let id = sessions[0]["id"].as_str().unwrap();
+
+        let response = call(&mut app, "DELETE", &format!("/sessions/{id}"), &current).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let set_cookie = response.headers().get(header::SET_COOKIE).unwrap();
+        let cookie = Cookie::parse(set_cookie.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(cookie.value(), "");
+
+        let response = call(&mut app, "GET", "/sessions", &current).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn revoke_by_post_checks_ownership() {
+        let mut app = setup().await.into_service();
+        let owner = sign_in(&mut app, "devices-post@gmail.com").await;
+        let stranger = sign_in(&mut app, "devices-post-stranger@gmail.com").await;
+        let id = list(&mut app, &owner).await[0]["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let uri = format!("/sessions/revoke/{id}");
+
+        let response = call(&mut app, "POST", &uri, &stranger).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(list(&mut app, &owner).await.len(), 1);
+        assert_eq!(list(&mut app, &stranger).await.len(), 1);
+
+        let response = call(&mut app, "POST", &uri, &owner).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = call(&mut app, "GET", "/sessions", &owner).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn list_excludes_expired_sessions() {
+        use crate::config::{AuthConfig, SessionConfig};
+        use crate::server::AppState;
+        use std::time::Duration;
+
+        let pool = pool().await;
+        let expired_config = AuthConfig {
+            session: SessionConfig {
+                ttl: Duration::ZERO,
+                ..SessionConfig::default()
+            },
+            ..AuthConfig::default()
+        };
+        let mut expiring = App::router(AppState::new(pool.clone()).with_config(expired_config))
+            .await
+            .into_service();
+        let mut app = App::app(pool).await.into_service();
+
+        sign_in(&mut expiring, "devices-expired@gmail.com").await;
+        let current = sign_in(&mut app, "devices-expired@gmail.com").await;
+
+        let sessions = list(&mut app, &current).await;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["current"], true);
+    }
+}
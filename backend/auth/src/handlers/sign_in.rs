@@ -1,133 +1,345 @@
 use std::sync::Arc;
-use std::time::Duration;
 
-use auth_database::entities::sessions::CreateSessionsDAO;
-use auth_database::{AuthDatabase, CredentialsRepository, SessionsRepository};
+use auth_database::entities::credentials::CredentialsDAO;
+use auth_database::entities::refresh_tokens::{CreateRefreshTokensDAO, RefreshTokensDAO};
+use auth_database::entities::sessions::SessionsDAO;
 use auth_database::{
-    entities::credentials::CredentialsBy,
+    AuthDatabase, CredentialsRepository, RefreshTokensRepository, SessionsRepository,
+};
+use auth_database::{
+    entities::credentials::{CredentialsBy, SignInAttempts},
     traits::{BaseDatabase, EntityRepository},
 };
 use axum::body::Body;
+use axum::extract::State;
+#[cfg(feature = "jwt")]
+use axum::http::header::CONTENT_TYPE;
 use axum::http::header::SET_COOKIE;
-use axum::http::{Response, StatusCode};
-use axum::{Json, extract::State};
-use cookie::Cookie;
-use cookie::time::OffsetDateTime;
-use sqlx::types::chrono::{DateTime, Utc};
-
-use crate::common::{MIN_LEN_PASSOWRD, SESSION_KEY, verify_password};
-use crate::handlers::dto::SignInDTO;
+use axum::http::{HeaderMap, Response, StatusCode};
+use axum::response::IntoResponse;
+use session::{NewSession, SessionSecret, find_valid_session};
+use sqlx::types::Uuid;
+use sqlx::types::chrono::Utc;
+
+use crate::common::{MIN_LEN_PASSOWRD, check_prehashed, verify_password};
+use crate::config::ExistingSessionPolicy;
+use crate::cookies::{ChronoToTime, CookieAction, build_refresh_cookie, parse_session_secret};
+use crate::extractors::{ClientInfo, Json, session_fingerprint};
+use crate::handlers::dto::{SessionsDTO, SignInDTO};
+use crate::handlers::refresh::IssuedRefreshToken;
 use crate::{
     common::is_valid_email,
-    server::{AppState, ServerError},
+    server::{AppState, ServerError, ServerResult},
 };
 
-const ONE_DAY_IN_SECONDS: u64 = 60 * 60 * 24;
+/// Counter of refused sign-ins, labeled by `reason`.
+pub const SIGN_IN_FAILURES_TOTAL: &str = "sign_in_failures_total";
 
-pub trait ChronoToTime {
-    fn to_offset_datetime(&self) -> Result<OffsetDateTime, ServerError>;
+/// Why a sign-in was refused.
+///
+/// Clients get the same `401` for every reason so they can't probe which emails exist,
+/// the reason only reaches logs and the [`SIGN_IN_FAILURES_TOTAL`] counter. The exceptions
+/// are [`SignInFailure::InactiveAccount`] and [`SignInFailure::EmailNotVerified`], only
+/// reported after the password matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignInFailure {
+    UnknownEmail,
+    InactiveAccount,
+    WrongPassword,
+    /// Too many wrong passwords in a row, see [`crate::config::LockoutConfig`].
+    Locked,
+    /// See [`crate::config::EmailVerificationConfig::required`].
+    EmailNotVerified,
 }
 
-impl ChronoToTime for DateTime<Utc> {
-    fn to_offset_datetime(&self) -> Result<OffsetDateTime, ServerError> {
-        OffsetDateTime::from_unix_timestamp(self.timestamp())
-            .and_then(|dt| dt.replace_nanosecond(self.timestamp_subsec_nanos()))
-            .map_err(|e| ServerError::InternalServerError(e.to_string()))
+enum SignInOutcome {
+    Session(Box<CredentialsDAO>, SessionsDAO, Option<IssuedRefreshToken>),
+    Refused(SignInFailure),
+}
+
+impl SignInFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignInFailure::UnknownEmail => "unknown_email",
+            SignInFailure::InactiveAccount => "inactive_account",
+            SignInFailure::WrongPassword => "wrong_password",
+            SignInFailure::Locked => "locked",
+            SignInFailure::EmailNotVerified => "email_not_verified",
+        }
+    }
+
+    /// Logs and counts the failure, then collapses it into the generic `401`, or the
+    /// `403` of a deactivated account.
+    fn reject(self) -> ServerError {
+        tracing::info!(reason = self.as_str(), "Sign-in refused");
+        metrics::counter!(SIGN_IN_FAILURES_TOTAL, "reason" => self.as_str()).increment(1);
+
+        match self {
+            SignInFailure::InactiveAccount => ServerError::AccountDeactivated,
+            SignInFailure::EmailNotVerified => ServerError::EmailNotVerified,
+            _ => ServerError::Unauthorized,
+        }
     }
 }
 
 pub async fn sign_in<DB>(
     State(state): State<Arc<AppState<DB>>>,
-    Json(payload): Json<SignInDTO>,
-) -> Result<Response<Body>, ServerError>
+    headers: HeaderMap,
+    client: ClientInfo,
+    Json(SignInDTO { email, password }): Json<SignInDTO>,
+) -> ServerResult<Response<Body>>
 where
     DB: sqlx::Database,
-    CredentialsRepository: EntityRepository<Db = DB>,
+    CredentialsRepository: SignInAttempts<Db = DB>,
     SessionsRepository: EntityRepository<Db = DB>,
+    RefreshTokensRepository:
+        EntityRepository<Db = DB, Entity = RefreshTokensDAO, CreateInput = CreateRefreshTokensDAO>,
 {
-    if !is_valid_email(&payload.email)? {
+    if !is_valid_email(&email)? {
         return Err(ServerError::BadRequest("Invalid Email Format".to_string()));
     };
 
-    if payload.password.len() < MIN_LEN_PASSOWRD {
+    check_prehashed(&state.config, &password)?;
+
+    if password.len() < MIN_LEN_PASSOWRD {
         return Err(ServerError::BadRequest(format!(
             "Password must be at least {MIN_LEN_PASSOWRD} characters long",
         )));
     }
 
-    AuthDatabase::transaction(&state.pool, |tx| {
+    if let Some(response) = existing_session_response(&state, &headers).await? {
+        return Ok(response);
+    }
+
+    let lockout = state.config.lockout;
+    let session_ttl = state.config.session.ttl;
+    let pepper = state.config.password_pepper.clone();
+    let refresh_ttl = state.config.refresh_token.as_ref().map(|config| config.ttl);
+    let require_verified = state.config.email_verification.required;
+
+    let outcome = AuthDatabase::named_transaction(&state.pool, "sign_in", |tx| {
         Box::pin(async move {
             let maybe_credential =
-                CredentialsRepository::try_get(tx, CredentialsBy::Email(payload.email.clone()))
-                    .await?;
+                CredentialsRepository::try_get(tx, CredentialsBy::Email(email)).await?;
 
             let Some(credential) = maybe_credential else {
-                return Err(ServerError::Unauthorized);
+                return Ok(SignInOutcome::Refused(SignInFailure::UnknownEmail));
             };
 
-            if !credential.active {
-                return Err(ServerError::Unauthorized);
-            };
+            let now = Utc::now();
+            if credential.locked_until.is_some_and(|until| until > now) {
+                return Ok(SignInOutcome::Refused(SignInFailure::Locked));
+            }
 
-            let is_correct_password = verify_password(&payload.password, &credential.password)?;
+            let is_correct_password =
+                verify_password(&password, &credential.password, pepper.as_ref())?;
 
             if !is_correct_password {
-                return Err(ServerError::Unauthorized);
+                // Refusing through `Ok` commits the failure count along with it.
+                if lockout.threshold > 0 {
+                    let threshold = i32::try_from(lockout.threshold).unwrap_or(i32::MAX);
+                    let locked_until = CredentialsRepository::record_failure(
+                        tx,
+                        credential.id,
+                        threshold,
+                        now + lockout.duration,
+                    )
+                    .await?;
+
+                    if locked_until.is_some_and(|until| until > now) {
+                        tracing::warn!(credential = %credential.id, "Credential locked");
+                    }
+                }
+
+                return Ok(SignInOutcome::Refused(SignInFailure::WrongPassword));
+            };
+
+            // Checked after the password so only its owner learns the account is deactivated.
+            if !credential.active {
+                return Ok(SignInOutcome::Refused(SignInFailure::InactiveAccount));
             };
 
-            let session = CreateSessionsDAO {
+            if require_verified && !credential.verified {
+                return Ok(SignInOutcome::Refused(SignInFailure::EmailNotVerified));
+            }
+
+            if credential.failed_attempts > 0 || credential.locked_until.is_some() {
+                CredentialsRepository::reset_failures(tx, credential.id).await?;
+            }
+
+            let session = NewSession {
                 credential_id: credential.id,
-                expires_at: Utc::now() + Duration::from_secs(ONE_DAY_IN_SECONDS),
+                ip: client.ip.map(|ip| ip.to_string()),
+                user_agent: client.user_agent,
+                fingerprint: client.fingerprint,
             };
 
-            let session = SessionsRepository::insert(tx, session)
-                .await
-                .map_err(ServerError::from)?;
+            let session = session::create(tx, session, session_ttl).await?;
 
-            let id = session.id.to_string();
-            let cookie = cookie(&id, session.expires_at.to_offset_datetime()?);
+            // Each sign-in starts a new family, see `crate::handlers::refresh`.
+            let refresh_token = match refresh_ttl {
+                Some(ttl) => {
+                    Some(crate::handlers::refresh::issue(tx, ttl, &session, Uuid::new_v4()).await?)
+                }
+                None => None,
+            };
 
-            let response = Response::builder()
-                .status(StatusCode::OK)
-                .header(SET_COOKIE, cookie.to_string())
-                .body(Body::empty())
+            Ok::<_, ServerError>(SignInOutcome::Session(
+                Box::new(credential),
+                session,
+                refresh_token,
+            ))
+        })
+    })
+    .await?;
+
+    let (credential, session, refresh_token) = match outcome {
+        SignInOutcome::Session(credential, session, refresh_token) => {
+            (credential, session, refresh_token)
+        }
+        SignInOutcome::Refused(failure) => return Err(failure.reject()),
+    };
+
+    let new_device_hook = state
+        .on_new_device
+        .as_ref()
+        .filter(|_| session.is_new_device);
+
+    if let Some(hook) = new_device_hook {
+        hook(&session).await;
+    }
+
+    state.events.on_signed_in(&credential, &session).await;
+
+    tracing::debug!(session = %SessionSecret::from(session.id), "Signed in");
+    signed_in_response(
+        &state,
+        &session,
+        CookieAction::fresh,
+        refresh_token.as_ref(),
+    )
+}
+
+/// `200` setting the cookies of `session` through `cookie`, e.g. [`CookieAction::fresh`],
+/// with the refresh token cookie and the access token when enabled.
+pub(crate) fn signed_in_response<DB>(
+    state: &AppState<DB>,
+    session: &SessionsDAO,
+    cookie: fn(&SessionsDAO) -> CookieAction,
+    refresh_token: Option<&IssuedRefreshToken>,
+) -> ServerResult<Response<Body>>
+where
+    DB: sqlx::Database,
+{
+    let response = Response::builder().status(StatusCode::OK);
+    let mut response = cookie(session).apply(&state.config, response);
+
+    if let (Some(refresh), Some(issued)) = (&state.config.refresh_token, refresh_token) {
+        let refresh_cookie = build_refresh_cookie(
+            &state.config.cookie,
+            refresh,
+            &issued.token,
+            issued.expires_at.to_offset_datetime(),
+        );
+        response = response.header(SET_COOKIE, refresh_cookie.to_string());
+    }
+
+    #[cfg(feature = "jwt")]
+    let body = match &state.config.jwt {
+        Some(jwt) => {
+            let access_token = crate::jwt::issue_jwt(jwt, session.credential_id, Utc::now())
                 .map_err(|e| {
-                    tracing::error!("Error building request: {:#?}", e);
+                    tracing::error!("Error issuing access token: {:#?}", e);
                     ServerError::InternalServerError("Internal Server Error".to_string())
                 })?;
+            let token = crate::handlers::dto::AccessTokenDTO {
+                access_token,
+                token_type: "Bearer".to_string(),
+                expires_in: jwt.ttl.as_secs(),
+            };
 
-            Ok(response)
-        })
+            response = response.header(CONTENT_TYPE, "application/json");
+            Body::from(serde_json::to_vec(&token).map_err(|e| {
+                tracing::error!("Error serializing access token: {:#?}", e);
+                ServerError::InternalServerError("Internal Server Error".to_string())
+            })?)
+        }
+        None => Body::empty(),
+    };
+    #[cfg(not(feature = "jwt"))]
+    let body = Body::empty();
+
+    response.body(body).map_err(|e| {
+        tracing::error!("Error building request: {:#?}", e);
+        ServerError::InternalServerError("Internal Server Error".to_string())
     })
-    .await
 }
 
-fn cookie(value: &str, expires_at: OffsetDateTime) -> Cookie<'_> {
-    Cookie::build((SESSION_KEY, value))
-        .path("/")
-        .secure(true)
-        .http_only(true)
-        .expires(expires_at)
-        .build()
+async fn existing_session_response<DB>(
+    state: &AppState<DB>,
+    headers: &HeaderMap,
+) -> ServerResult<Option<Response<Body>>>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    let policy = state.config.existing_session_policy;
+    if policy == ExistingSessionPolicy::CreateNew {
+        return Ok(None);
+    }
+
+    let Some(secret) = parse_session_secret(headers, &state.config.cookie) else {
+        return Ok(None);
+    };
+
+    let fingerprint = session_fingerprint(state, headers);
+    let session = AuthDatabase::named_transaction(&state.pool, "existing_session", |tx| {
+        Box::pin(
+            async move { find_valid_session(tx, secret.expose(), fingerprint.as_deref()).await },
+        )
+    })
+    .await?;
+
+    match (policy, session) {
+        (_, None) | (ExistingSessionPolicy::CreateNew, _) => Ok(None),
+        (ExistingSessionPolicy::Reuse, Some(session)) => {
+            Ok(Some(axum::Json(SessionsDTO::from(session)).into_response()))
+        }
+        (ExistingSessionPolicy::Reject, Some(_)) => {
+            Err(ServerError::Conflict("Already Authenticated".to_string()))
+        }
+    }
 }
 
 #[cfg(any(feature = "unit", feature = "integration"))]
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::server::App;
+    use crate::common::{CSRF_KEY, SESSION_KEY};
+    use crate::config::{
+        Argon2Params, AuthConfig, CsrfConfig, LockoutConfig, RateLimitConfig, SessionConfig,
+    };
+    use crate::cookies::verify_csrf_token;
+    use crate::server::{App, AppState};
     use auth_database::{
         AuthDatabase, CredentialsRepository,
-        entities::credentials::{CreateCredentialsDAO, CredentialsBy},
+        entities::{
+            credentials::{CreateCredentialsDAO, CredentialsBy, PasswordStorage, Role},
+            sessions::SessionsBy,
+        },
         traits::{BaseDatabase, EntityRepository},
     };
     use axum::{
         Router,
         body::Body,
         http::{Request, StatusCode, header},
+        routing::RouterIntoService,
     };
+    use cookie::Cookie;
     use http_body_util::BodyExt;
     use serde_json::Value;
+    use sqlx::types::Uuid;
+    use std::time::Duration;
 
     use sqlx::Pool;
     use tower::Service;
@@ -234,8 +446,15 @@ mod tests {
         AuthDatabase::transaction(&pool, |tx| {
             Box::pin(async move {
                 let credential = CreateCredentialsDAO {
-                    email: "test@gmail.com".to_string(),
-                    password: "Ej42fkj!yI!Cj9".to_string(),
+                    email: "deactivated@gmail.com".to_string(),
+                    password: crate::common::hash_password(
+                        "Ej42fkj!yI!Cj9",
+                        &Argon2Params::default(),
+                        None,
+                    )
+                    .unwrap(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
                 };
                 let credential = CredentialsRepository::insert(tx, credential).await.unwrap();
 
@@ -251,23 +470,32 @@ mod tests {
         .expect("Could not setup deactived account for this test");
 
         let mut app = app.into_service();
-        let body = serde_json::json!({
-            "email": "test@gmail.com",
-            "password": "Ej42fkj!yI!Cj9"
-        });
-        let request = Request::builder()
-            .method("POST")
-            .uri("/sign_in")
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(Body::from(body.to_string()))
-            .unwrap();
-        let response = app.ready().await.unwrap().call(request).await.unwrap();
-        let (parts, body) = response.into_parts();
-        let bytes = body.collect().await.unwrap().to_bytes();
-        let json: Value = serde_json::from_slice(&bytes).unwrap();
+        for (password, status, message) in [
+            (
+                "Ej42fkj!yI!Cj9",
+                StatusCode::FORBIDDEN,
+                "Account Deactivated",
+            ),
+            ("Wrong!Password1", StatusCode::UNAUTHORIZED, "Unauthorized"),
+        ] {
+            let body = serde_json::json!({
+                "email": "deactivated@gmail.com",
+                "password": password
+            });
+            let request = Request::builder()
+                .method("POST")
+                .uri("/sign_in")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            let (parts, body) = response.into_parts();
+            let bytes = body.collect().await.unwrap().to_bytes();
+            let json: Value = serde_json::from_slice(&bytes).unwrap();
 
-        assert_eq!(parts.status, StatusCode::UNAUTHORIZED);
-        assert_eq!(json.get("message").unwrap(), "Unauthorized");
+            assert_eq!(parts.status, status, "{password}");
+            assert_eq!(json.get("message").unwrap(), message, "{password}");
+        }
     }
 
     #[tokio::test]
@@ -306,6 +534,10 @@ mod tests {
         let json: Value = serde_json::from_slice(&bytes).unwrap();
 
         assert_eq!(parts.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            parts.headers.get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
         assert_eq!(json.get("message").unwrap(), "Unauthorized");
     }
 
@@ -349,14 +581,765 @@ mod tests {
         assert_eq!(cookie.path(), Some("/"));
         assert!(cookie.secure().unwrap());
         assert!(cookie.http_only().unwrap());
-        let expected = Utc::now() + Duration::from_secs(ONE_DAY_IN_SECONDS);
+        let expected = Utc::now() + SessionConfig::default().ttl;
         let expires = cookie
             .expires_datetime()
             .expect("cookie must have an expiration");
-        let diff = (expires - expected.to_offset_datetime().unwrap())
+        let diff = (expires - expected.to_offset_datetime())
             .whole_seconds()
             .abs();
         assert!(diff <= 1, "Max-Age is not ~24h");
         assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_TYPE).is_none());
+    }
+
+    #[tokio::test]
+    async fn sign_in_uses_configured_session_ttl() {
+        let (pool, _) = setup().await;
+        let ttl = Duration::from_secs(60 * 60);
+        let config = AuthConfig {
+            session: SessionConfig {
+                ttl,
+                ..SessionConfig::default()
+            },
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "short-lived@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        for uri in ["/sign_up", "/sign_in"] {
+            let request = Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            if uri == "/sign_in" {
+                let cookie_header = response.headers().get(header::SET_COOKIE).unwrap();
+                let cookie = Cookie::parse(cookie_header.to_str().unwrap()).unwrap();
+                let expected = (Utc::now() + ttl).to_offset_datetime();
+                let expires = cookie
+                    .expires_datetime()
+                    .expect("cookie must have an expiration");
+
+                assert!((expires - expected).whole_seconds().abs() <= 1);
+                assert_eq!(cookie.max_age(), Some(ttl.try_into().unwrap()));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_in_issues_csrf_cookie_pair() {
+        let (pool, _) = setup().await;
+        let csrf = CsrfConfig::new("csrf-secret");
+        let config = AuthConfig {
+            csrf: Some(csrf.clone()),
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "csrf@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookies: Vec<Cookie> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .map(|value| Cookie::parse(value.to_str().unwrap().to_string()).unwrap())
+            .collect();
+        assert_eq!(cookies.len(), 2);
+
+        let session = cookies.iter().find(|c| c.name() == SESSION_KEY).unwrap();
+        let csrf_cookie = cookies.iter().find(|c| c.name() == CSRF_KEY).unwrap();
+
+        assert!(session.http_only().unwrap());
+        assert!(!csrf_cookie.http_only().unwrap_or(false));
+        assert!(verify_csrf_token(
+            &csrf,
+            session.value(),
+            csrf_cookie.value()
+        ));
+    }
+
+    /// Signs in twice, the second time carrying the first session cookie.
+    /// Returns the first session id and the second response.
+    async fn sign_in_with_existing_session(
+        policy: ExistingSessionPolicy,
+    ) -> (String, Response<Body>) {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            existing_session_policy: policy,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": format!("{policy:?}@gmail.com").to_lowercase(),
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let cookie = Cookie::parse(
+            response
+                .headers()
+                .get(header::SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+        .unwrap();
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::COOKIE, cookie.stripped().to_string())
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        (cookie.value().to_string(), response)
+    }
+
+    #[tokio::test]
+    async fn sign_in_existing_session_create_new() {
+        let (previous, response) =
+            sign_in_with_existing_session(ExistingSessionPolicy::CreateNew).await;
+        let cookie = Cookie::parse(
+            response
+                .headers()
+                .get(header::SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(cookie.value(), previous);
+    }
+
+    #[tokio::test]
+    async fn sign_in_existing_session_reuse() {
+        let (previous, response) =
+            sign_in_with_existing_session(ExistingSessionPolicy::Reuse).await;
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        // The cookie is HttpOnly, scripts reading the body must not get its value.
+        let public_id = SessionSecret::parse(&previous).unwrap().public_id();
+        assert_eq!(json.get("id").unwrap(), public_id.as_str());
+        assert!(!String::from_utf8_lossy(&bytes).contains(&previous));
+    }
+
+    #[tokio::test]
+    async fn sign_in_existing_session_reject() {
+        let (_, response) = sign_in_with_existing_session(ExistingSessionPolicy::Reject).await;
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::CONFLICT);
+        assert_eq!(json.get("message").unwrap(), "Already Authenticated");
+    }
+
+    async fn sign_in_from(
+        app: &mut axum::routing::RouterIntoService<Body>,
+        body: &Value,
+        ip: &str,
+    ) -> Uuid {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-forwarded-for", format!("{ip}, 10.0.0.1"))
+            .header(header::USER_AGENT, "integration-test/1.0")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookie = Cookie::parse(
+            response
+                .headers()
+                .get(header::SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+        .unwrap();
+
+        Uuid::parse_str(cookie.value()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sign_in_flags_new_device() {
+        let (pool, _) = setup().await;
+        let alerts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = alerts.clone();
+        let config = AuthConfig {
+            trust_proxy: true,
+            ..AuthConfig::default()
+        };
+        let state = AppState::new(pool.clone())
+            .with_config(config)
+            .with_on_new_device(Arc::new(move |session| {
+                let recorder = recorder.clone();
+                let ip = session.ip.clone();
+                Box::pin(async move { recorder.lock().unwrap().push(ip) })
+            }));
+        let mut app = App::router(state).await.into_service();
+        let body = serde_json::json!({
+            "email": "newdevice@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let first = sign_in_from(&mut app, &body, "203.0.113.7").await;
+        let repeat = sign_in_from(&mut app, &body, "203.0.113.7").await;
+        let other = sign_in_from(&mut app, &body, "198.51.100.23").await;
+
+        let sessions = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                let mut sessions = Vec::new();
+                for id in [first, repeat, other] {
+                    sessions.push(SessionsRepository::get(tx, SessionsBy::Id(id)).await?);
+                }
+                Ok::<_, auth_database::traits::DatabaseError>(sessions)
+            })
+        })
+        .await
+        .unwrap();
+
+        assert!(sessions[0].is_new_device);
+        assert!(!sessions[1].is_new_device);
+        assert!(sessions[2].is_new_device);
+        assert_eq!(sessions[0].ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(
+            sessions[0].user_agent.as_deref(),
+            Some("integration-test/1.0")
+        );
+        assert_eq!(
+            *alerts.lock().unwrap(),
+            vec![
+                Some("203.0.113.7".to_string()),
+                Some("198.51.100.23".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_in_ignores_forwarded_for_without_trust_proxy() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let body = serde_json::json!({
+            "email": "untrusted@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let id = sign_in_from(&mut app, &body, "203.0.113.7").await;
+        let session = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move { SessionsRepository::get(tx, SessionsBy::Id(id)).await })
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(session.ip, None);
+        assert!(!session.is_new_device);
+        assert_eq!(session.user_agent.as_deref(), Some("integration-test/1.0"));
+    }
+
+    #[tokio::test]
+    async fn sign_in_with_separate_password_storage() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            password_storage: PasswordStorage::Separate,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool.clone()).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "split@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let row_password =
+            sqlx::query_scalar::<_, String>("SELECT password FROM credentials WHERE email = $1;")
+                .bind("split@gmail.com")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+
+        assert_eq!(row_password, "");
+    }
+
+    #[tokio::test]
+    async fn sign_in_failures_record_their_reason() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                for email in ["reasons@gmail.com", "reasons-inactive@gmail.com"] {
+                    let credential = CreateCredentialsDAO {
+                        email: email.to_string(),
+                        password: crate::common::hash_password(
+                            "Ej42fkj!yI!Cj9",
+                            &Argon2Params::default(),
+                            None,
+                        )
+                        .unwrap(),
+                        role: Role::User,
+                        password_storage: PasswordStorage::Inline,
+                    };
+                    CredentialsRepository::insert(tx, credential).await?;
+                }
+                CredentialsRepository::delete(
+                    tx,
+                    CredentialsBy::Email("reasons-inactive@gmail.com".to_string()),
+                )
+                .await?;
+
+                Ok::<(), auth_database::traits::DatabaseError>(())
+            })
+        })
+        .await
+        .unwrap();
+
+        let unauthorized = (StatusCode::UNAUTHORIZED, "Unauthorized");
+        let attempts = [
+            (
+                "reasons-unknown@gmail.com",
+                "Ej42fkj!yI!Cj9",
+                "unknown_email",
+                unauthorized,
+            ),
+            (
+                "reasons-inactive@gmail.com",
+                "Ej42fkj!yI!Cj9",
+                "inactive_account",
+                (StatusCode::FORBIDDEN, "Account Deactivated"),
+            ),
+            (
+                "reasons@gmail.com",
+                "Wrong4a2fkj!yI",
+                "wrong_password",
+                unauthorized,
+            ),
+        ];
+
+        for (email, password, reason, (status, message)) in attempts {
+            let body = serde_json::json!({ "email": email, "password": password });
+            let request = Request::builder()
+                .method("POST")
+                .uri("/sign_in")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            let (parts, body) = response.into_parts();
+            let bytes = body.collect().await.unwrap().to_bytes();
+            let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+            assert_eq!(parts.status, status, "{reason}");
+            assert_eq!(json, serde_json::json!({ "message": message }), "{reason}");
+
+            let recorded =
+                snapshotter
+                    .snapshot()
+                    .into_vec()
+                    .into_iter()
+                    .find_map(|(key, _, _, value)| {
+                        let key = key.key();
+                        let matches = key.name() == SIGN_IN_FAILURES_TOTAL
+                            && key
+                                .labels()
+                                .any(|l| l.key() == "reason" && l.value() == reason);
+
+                        match value {
+                            DebugValue::Counter(count) if matches => Some(count),
+                            _ => None,
+                        }
+                    });
+            assert_eq!(recorded, Some(1), "{reason}");
+        }
+    }
+
+    const LOCKOUT_PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    /// App locking after 3 failures, with one credential signing in with [`LOCKOUT_PASSWORD`].
+    async fn lockout_app(pool: Pool<auth_database::DB>, email: &str) -> RouterIntoService<Body> {
+        let email = email.to_string();
+        AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                let credential = CreateCredentialsDAO {
+                    email,
+                    password: crate::common::hash_password(
+                        LOCKOUT_PASSWORD,
+                        &Argon2Params::default(),
+                        None,
+                    )
+                    .unwrap(),
+                    role: Role::User,
+                    password_storage: PasswordStorage::Inline,
+                };
+
+                CredentialsRepository::insert(tx, credential).await
+            })
+        })
+        .await
+        .unwrap();
+
+        let config = AuthConfig {
+            lockout: LockoutConfig {
+                threshold: 3,
+                duration: Duration::from_secs(15 * 60),
+            },
+            ..AuthConfig::default()
+        };
+        App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service()
+    }
+
+    async fn sign_in_status(
+        app: &mut RouterIntoService<Body>,
+        email: &str,
+        password: &str,
+    ) -> StatusCode {
+        let body = serde_json::json!({ "email": email, "password": password });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        app.ready()
+            .await
+            .unwrap()
+            .call(request)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn sign_in_locks_after_repeated_failures() {
+        let (pool, _) = setup().await;
+        let email = "locked@gmail.com";
+        let mut app = lockout_app(pool.clone(), email).await;
+
+        for _ in 0..2 {
+            let status = sign_in_status(&mut app, email, "Wrong4a2fkj!yI").await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        // A success in between starts the count over.
+        let status = sign_in_status(&mut app, email, LOCKOUT_PASSWORD).await;
+        assert_eq!(status, StatusCode::OK);
+
+        for _ in 0..3 {
+            let status = sign_in_status(&mut app, email, "Wrong4a2fkj!yI").await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        let status = sign_in_status(&mut app, email, LOCKOUT_PASSWORD).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let credential = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                CredentialsRepository::get(tx, CredentialsBy::Email(email.to_string())).await
+            })
+        })
+        .await
+        .unwrap();
+        let locked_until = credential.locked_until.unwrap();
+        assert!(locked_until > Utc::now() + Duration::from_secs(14 * 60));
+        assert_eq!(credential.failed_attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn sign_in_clears_an_expired_lock() {
+        let (pool, _) = setup().await;
+        let email = "unlocked@gmail.com";
+        let mut app = lockout_app(pool.clone(), email).await;
+
+        let status = sign_in_status(&mut app, email, "Wrong4a2fkj!yI").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                let credential =
+                    CredentialsRepository::get(tx, CredentialsBy::Email(email.to_string())).await?;
+                CredentialsRepository::record_failure(
+                    tx,
+                    credential.id,
+                    1,
+                    Utc::now() - Duration::from_secs(60),
+                )
+                .await
+            })
+        })
+        .await
+        .unwrap();
+
+        let status = sign_in_status(&mut app, email, LOCKOUT_PASSWORD).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let credential = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                CredentialsRepository::get(tx, CredentialsBy::Email(email.to_string())).await
+            })
+        })
+        .await
+        .unwrap();
+        assert_eq!(credential.locked_until, None);
+        assert_eq!(credential.failed_attempts, 0);
+    }
+
+    /// App trusting `x-forwarded-for` that lets `limit` sign-ins through per minute.
+    async fn rate_limited_app(by_email: bool, limit: u32) -> RouterIntoService<Body> {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            trust_proxy: true,
+            ..AuthConfig::default()
+        };
+        let state = AppState::new(pool)
+            .with_config(config)
+            .with_rate_limit(RateLimitConfig {
+                limit,
+                window: Duration::from_secs(60),
+                by_email,
+            });
+
+        App::router(state).await.into_service()
+    }
+
+    async fn sign_in_response(
+        app: &mut RouterIntoService<Body>,
+        email: &str,
+        ip: &str,
+    ) -> axum::response::Response {
+        let body = serde_json::json!({ "email": email, "password": LOCKOUT_PASSWORD });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-forwarded-for", ip)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn sign_in_is_rate_limited_per_ip() {
+        let limit = 3;
+        let mut app = rate_limited_app(false, limit).await;
+
+        for attempt in 0..limit {
+            let email = format!("user{attempt}@gmail.com");
+            let response = sign_in_response(&mut app, &email, "203.0.113.7").await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let response = sign_in_response(&mut app, "another@gmail.com", "203.0.113.7").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "60");
+
+        let response = sign_in_response(&mut app, "another@gmail.com", "198.51.100.23").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn sign_in_is_rate_limited_per_email_across_ips() {
+        let limit = 3;
+        let mut app = rate_limited_app(true, limit).await;
+
+        for attempt in 0..limit {
+            let ip = format!("203.0.113.{attempt}");
+            let response = sign_in_response(&mut app, "target@gmail.com", &ip).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let response = sign_in_response(&mut app, " Target@Gmail.com", "198.51.100.23").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(parts.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(json.get("message").unwrap(), "Too Many Requests");
+    }
+
+    #[cfg(feature = "jwt")]
+    async fn me_with_bearer(
+        app: &mut RouterIntoService<Body>,
+        token: &str,
+    ) -> axum::response::Response {
+        let request = Request::builder()
+            .uri("/me")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    #[cfg(feature = "jwt")]
+    #[tokio::test]
+    async fn sign_in_issues_an_access_token_accepted_as_bearer() {
+        use crate::jwt::{JwtConfig, issue_jwt, verify_jwt};
+
+        let (pool, _) = setup().await;
+        let jwt = JwtConfig::new(
+            *b"0123456789abcdef0123456789abcdef",
+            Duration::from_secs(900),
+        );
+        let config = AuthConfig {
+            jwt: Some(jwt.clone()),
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "bearer@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::SET_COOKIE));
+
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(json["token_type"], "Bearer");
+        assert_eq!(json["expires_in"], 900);
+
+        let token = json["access_token"].as_str().unwrap();
+        let me = me_with_bearer(&mut app, token).await;
+        assert_eq!(me.status(), StatusCode::OK);
+
+        let me = me_with_bearer(&mut app, &format!("{token}x")).await;
+        assert_eq!(me.status(), StatusCode::UNAUTHORIZED);
+
+        let credential_id = verify_jwt(&jwt, token).unwrap().credential_id().unwrap();
+        let issued_an_hour_ago = Utc::now() - Duration::from_secs(60 * 60);
+        let expired = issue_jwt(&jwt, credential_id, issued_an_hour_ago).unwrap();
+        let me = me_with_bearer(&mut app, &expired).await;
+        assert_eq!(me.status(), StatusCode::UNAUTHORIZED);
     }
 }
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use auth_database::entities::refresh_tokens::RefreshTokenRevocation;
+use auth_database::traits::{BaseDatabase, DatabaseError, EntityRepository};
+use auth_database::{AuthDatabase, RefreshTokensRepository, SessionsRepository};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, Response, StatusCode};
+
+use crate::cookies::{CookieAction, parse_session_secret};
+use crate::server::{AppState, ServerError, ServerResult};
+
+/// Deactivates the session behind the request's cookie and tells the browser to drop it.
+pub async fn sign_out<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    headers: HeaderMap,
+) -> ServerResult<Response<Body>>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    let cookie_config = &state.config.cookie;
+    let Some(secret) = parse_session_secret(&headers, cookie_config) else {
+        return Err(ServerError::Unauthorized);
+    };
+
+    AuthDatabase::named_transaction(&state.pool, "sign_out", |tx| {
+        Box::pin(async move {
+            match session::revoke(tx, secret).await {
+                Ok(_) => Ok(()),
+                Err(e) if matches!(e.kind(), DatabaseError::NotFound(_)) => {
+                    Err(ServerError::Unauthorized)
+                }
+                Err(e) => Err(ServerError::from(e)),
+            }
+        })
+    })
+    .await?;
+    tracing::debug!(session = %secret, "Signed out");
+
+    let response = Response::builder().status(StatusCode::OK);
+
+    CookieAction::Cleared
+        .apply(&state.config, response)
+        .body(Body::empty())
+        .map_err(|e| {
+            tracing::error!("Error building request: {:#?}", e);
+            ServerError::InternalServerError("Internal Server Error".to_string())
+        })
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use crate::common::SESSION_KEY;
+    use crate::server::App;
+    use auth_database::{
+        AuthDatabase, SessionsRepository,
+        entities::sessions::SessionsBy,
+        traits::{BaseDatabase, EntityRepository},
+    };
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use cookie::{Cookie, time::OffsetDateTime};
+    use sqlx::Pool;
+    use sqlx::types::Uuid;
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    #[cfg(feature = "unit")]
+    use sqlx::Sqlite;
+
+    #[cfg(feature = "integration")]
+    use sqlx::Postgres;
+
+    #[cfg(feature = "unit")]
+    async fn setup() -> (Pool<Sqlite>, Router) {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    #[cfg(feature = "integration")]
+    async fn setup() -> (Pool<Postgres>, Router) {
+        dotenvy::dotenv().ok();
+        let database_url = std::env::var("AUTH_DATABASE_URL")
+            .expect("AUTH_DATABASE_URL must be set for integration tests");
+
+        let pool = AuthDatabase::connect(&database_url).await.unwrap();
+        (pool.clone(), App::app(pool).await)
+    }
+
+    fn sign_out_request(cookie: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method("POST").uri("/sign_out");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn sign_out_success() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        let body = serde_json::json!({
+            "email": "signout@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let session = Cookie::parse(
+            response
+                .headers()
+                .get(header::SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+        .unwrap();
+        let session_id = Uuid::parse_str(session.value()).unwrap();
+
+        let request = sign_out_request(Some(&session.stripped().to_string()));
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_TYPE).is_none());
+
+        let cleared = Cookie::parse(
+            response
+                .headers()
+                .get(header::SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(cleared.name(), SESSION_KEY);
+        assert_eq!(cleared.value(), "");
+        assert!(cleared.expires_datetime().unwrap() < OffsetDateTime::now_utc());
+
+        let session = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move { SessionsRepository::get(tx, SessionsBy::Id(session_id)).await })
+        })
+        .await
+        .unwrap();
+        assert!(!session.active);
+    }
+
+    #[tokio::test]
+    async fn sign_out_without_cookie() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(sign_out_request(None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn sign_out_with_malformed_cookie() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+        let cookie = format!("{SESSION_KEY}=not-a-uuid");
+
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(sign_out_request(Some(&cookie)))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn sign_out_with_unknown_session() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+        let cookie = format!("{SESSION_KEY}={}", Uuid::new_v4());
+
+        let response = app
+            .ready()
+            .await
+            .unwrap()
+            .call(sign_out_request(Some(&cookie)))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
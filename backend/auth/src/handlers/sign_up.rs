@@ -1,66 +1,136 @@
 use std::sync::Arc;
 
-use auth_database::{AuthDatabase, CredentialsRepository};
+use auth_database::{AuthDatabase, CredentialsRepository, EmailVerificationsRepository};
 use auth_database::{
-    entities::credentials::{CreateCredentialsDAO, CredentialsBy},
+    entities::credentials::{
+        CreateCredentialsDAO, CredentialsBy, EmailVerification, SignInAttempts,
+        UpdateCredentialsDAO,
+    },
+    entities::email_verifications::{
+        CreateEmailVerificationsDAO, EmailVerificationsBy, EmailVerificationsDAO,
+        EmailVerificationsWhere,
+    },
     traits::{BaseDatabase, EntityRepository},
 };
-use axum::{Json, extract::State};
+use axum::extract::State;
 
 use crate::{
-    common::{hash_password, is_valid_email, is_valid_password},
+    common::{check_new_password, hash_password, is_valid_email},
+    extractors::Json,
     handlers::dto::{CreateCredentialDTO, CredentialsDTO},
-    server::{AppState, ServerError},
+    server::{AppState, ServerError, ServerResult},
 };
 
+/// Creates a credential, or with [`crate::config::AuthConfig::reactivate_on_sign_up`]
+/// reactivates the deactivated one holding the email. An email held by an active
+/// credential is rejected either way. A reactivated credential starts over unverified
+/// and unlocked, nothing the previous owner proved or triggered carries over.
 pub async fn sign_up<DB>(
     State(state): State<Arc<AppState<DB>>>,
     Json(payload): Json<CreateCredentialDTO>,
-) -> Result<CredentialsDTO, ServerError>
+) -> ServerResult<CredentialsDTO>
 where
     DB: sqlx::Database,
-    CredentialsRepository: EntityRepository<Db = DB>,
+    CredentialsRepository: SignInAttempts<Db = DB> + EmailVerification,
+    EmailVerificationsRepository: EntityRepository<
+            Db = DB,
+            Entity = EmailVerificationsDAO,
+            CreateInput = CreateEmailVerificationsDAO,
+            QueryOne = EmailVerificationsBy,
+            QueryMany = EmailVerificationsWhere,
+        >,
 {
     if !is_valid_email(&payload.email)? {
         return Err(ServerError::BadRequest("Invalid Email Format".to_string()));
     };
 
-    if !is_valid_password(&payload.password) {
-        return Err(ServerError::BadRequest(
-            "Invalid Password Format".to_string(),
+    let allowed = &state.config.allowed_email_domains;
+    if !allowed.is_empty() && !allowed.contains(&payload.email) {
+        return Err(ServerError::UnprocessableEntity(
+            "Email Domain Is Not Allowed".to_string(),
         ));
     }
 
-    AuthDatabase::transaction(&state.pool, |tx| {
+    if state.config.blocked_email_domains.contains(&payload.email) {
+        return Err(ServerError::UnprocessableEntity(
+            "Email Domain Is Blocked".to_string(),
+        ));
+    }
+
+    check_new_password(&state.config, &payload.password)?;
+
+    let on_sign_up = state.on_sign_up.clone();
+    let role = state.config.default_role;
+    let password_storage = state.config.password_storage;
+    let argon2 = state.config.argon2;
+    let pepper = state.config.password_pepper.clone();
+    let reactivate = state.config.reactivate_on_sign_up;
+
+    let credential = AuthDatabase::named_transaction(&state.pool, "sign_up", |tx| {
         Box::pin(async move {
-            let exists =
-                CredentialsRepository::exists(tx, CredentialsBy::Email(payload.email.clone()))
+            let existing =
+                CredentialsRepository::try_get(tx, CredentialsBy::Email(payload.email.clone()))
                     .await?;
 
-            if exists {
-                return Err(ServerError::Unauthorized);
+            let hash = match existing {
+                Some(credential) if credential.active || !reactivate => {
+                    return Err(ServerError::Unauthorized);
+                }
+                _ => hash_password(&payload.password, &argon2, pepper.as_ref())?,
             };
 
-            let hash = hash_password(&payload.password)?;
-            let credential_dao = CreateCredentialsDAO {
-                email: payload.email,
-                password: hash,
+            let create_credential = match existing {
+                Some(credential) => {
+                    // Neither bumps the version, so the update below still applies.
+                    CredentialsRepository::reset_failures(tx, credential.id).await?;
+                    CredentialsRepository::mark_unverified(tx, credential.id).await?;
+                    CredentialsRepository::update(
+                        tx,
+                        CredentialsBy::Id(credential.id),
+                        UpdateCredentialsDAO {
+                            password: hash,
+                            active: true,
+                            role,
+                            password_storage,
+                            version: credential.version,
+                        },
+                    )
+                    .await?
+                }
+                None => {
+                    let credential_dao = CreateCredentialsDAO {
+                        email: payload.email,
+                        password: hash,
+                        role,
+                        password_storage,
+                    };
+
+                    CredentialsRepository::insert(tx, credential_dao).await?
+                }
             };
 
-            let create_credential = CredentialsRepository::insert(tx, credential_dao)
-                .await
-                .map_err(ServerError::from)?;
+            if let Some(hook) = on_sign_up {
+                hook(&create_credential).await?;
+            }
 
-            Ok(CredentialsDTO::from(create_credential))
+            Ok(create_credential)
         })
     })
-    .await
+    .await?;
+
+    crate::handlers::verify_email::issue(&state, &credential).await;
+    state.events.on_signed_up(&credential).await;
+
+    Ok(CredentialsDTO::from(credential))
 }
 
 #[cfg(any(feature = "unit", feature = "integration"))]
 #[cfg(test)]
 mod tests {
-    use crate::server::App;
+    use crate::common::{PasswordBlocklist, PasswordPolicy};
+    use crate::config::{AuthConfig, EmailVerificationConfig, FeatureFlags};
+    use crate::server::{App, AppState, ServerError};
+    use std::sync::{Arc, Mutex};
 
     use argon2::{Argon2, PasswordHash, PasswordVerifier};
     use axum::{
@@ -81,7 +151,12 @@ mod tests {
     #[cfg(feature = "integration")]
     use sqlx::Postgres;
 
-    use auth_database::AuthDatabase;
+    use auth_database::{
+        AuthDatabase, CredentialsRepository,
+        entities::credentials::{CredentialsBy, EmailVerification, Role, SignInAttempts},
+        traits::{BaseDatabase, EntityRepository},
+    };
+    use sqlx::types::chrono::Utc;
 
     #[cfg(feature = "unit")]
     async fn setup() -> (Pool<Sqlite>, Router) {
@@ -142,7 +217,10 @@ mod tests {
         let json: Value = serde_json::from_slice(&bytes).unwrap();
 
         assert_eq!(parts.status, StatusCode::BAD_REQUEST);
-        assert_eq!(json.get("message").unwrap(), "Invalid Password Format");
+        assert_eq!(
+            json.get("message").unwrap(),
+            "Invalid Password Format: needs at least 6 characters"
+        );
     }
 
     #[tokio::test]
@@ -207,9 +285,10 @@ mod tests {
         assert_eq!(parts.status, StatusCode::OK);
         assert_eq!(json.get("email").unwrap(), "asdfasdfasdf@mail.com");
         assert_eq!(json.get("active").unwrap(), true);
+        assert_eq!(json.get("role").unwrap(), "user");
 
         let hash = json.get("password").unwrap().as_str().unwrap();
-        let parsed_hash = PasswordHash::new(&hash).unwrap();
+        let parsed_hash = PasswordHash::new(hash).unwrap();
 
         assert!(
             Argon2::default()
@@ -217,4 +296,557 @@ mod tests {
                 .is_ok()
         );
     }
+
+    #[tokio::test]
+    async fn sign_up_hook_receives_created_credential() {
+        let (pool, _) = setup().await;
+        let seen: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let recorder = seen.clone();
+        let state = AppState::new(pool).with_on_sign_up(Arc::new(move |credential| {
+            let recorder = recorder.clone();
+            let email = credential.email.clone();
+            Box::pin(async move {
+                recorder.lock().unwrap().push(email);
+                Ok(())
+            })
+        }));
+
+        let mut app = App::router(state).await.into_service();
+        let body = serde_json::json!({
+            "email": "hooked@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*seen.lock().unwrap(), vec!["hooked@mail.com".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn sign_up_hook_error_rolls_back() {
+        let (pool, _) = setup().await;
+        let state = AppState::new(pool.clone()).with_on_sign_up(Arc::new(|_| {
+            Box::pin(async {
+                Err(ServerError::InternalServerError(
+                    "provisioning failed".to_string(),
+                ))
+            })
+        }));
+
+        let mut app = App::router(state).await.into_service();
+        let body = serde_json::json!({
+            "email": "rolledback@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let exists = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                CredentialsRepository::exists(
+                    tx,
+                    CredentialsBy::Email("rolledback@mail.com".to_string()),
+                )
+                .await
+            })
+        })
+        .await
+        .unwrap();
+
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn sign_up_legacy_validation_ok() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            legacy_validation_ok: true,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "owkmail.com",
+            "password": "ondfauhdf77364"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(json.get("ok").unwrap(), false);
+        assert_eq!(json.get("error").unwrap(), "Invalid Email Format");
+    }
+
+    #[tokio::test]
+    async fn sign_up_legacy_validation_ok_keeps_success_and_conflicts() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            legacy_validation_ok: true,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "legacy@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, response_body) = response.into_parts();
+        let bytes = response_body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(json.get("email").unwrap(), "legacy@mail.com");
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn sign_up_assigns_configured_default_role() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            default_role: Role::Pending,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "pending@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::OK);
+        assert_eq!(json.get("role").unwrap(), "pending");
+    }
+
+    #[tokio::test]
+    async fn sign_up_password_policy_lists_violations() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            password_policy: PasswordPolicy {
+                min_len: 10,
+                require_upper: true,
+                require_digit: true,
+                require_symbol: true,
+                ..PasswordPolicy::default()
+            },
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+
+        for (password, status, message) in [
+            (
+                "lowercase only",
+                StatusCode::BAD_REQUEST,
+                Some("Invalid Password Format: needs an uppercase letter, a digit, a symbol"),
+            ),
+            (
+                "Sh0rt!",
+                StatusCode::BAD_REQUEST,
+                Some("Invalid Password Format: needs at least 10 characters"),
+            ),
+            ("Str0ng!Password", StatusCode::OK, None),
+        ] {
+            let body = serde_json::json!({
+                "email": "policy@mail.com",
+                "password": password
+            });
+            let request = Request::builder()
+                .method("POST")
+                .uri("/sign_up")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let response = app.ready().await.unwrap().call(request).await.unwrap();
+            let (parts, body) = response.into_parts();
+            let bytes = body.collect().await.unwrap().to_bytes();
+            let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+            assert_eq!(parts.status, status, "{password}");
+            if let Some(message) = message {
+                assert_eq!(json.get("message").unwrap(), message, "{password}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sign_up_password_blocklist() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            password_blocklist: Some(Arc::new(
+                ["123456", "password123", "qwertyuiop"]
+                    .into_iter()
+                    .collect::<PasswordBlocklist>(),
+            )),
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+
+        let body = serde_json::json!({
+            "email": "common@mail.com",
+            "password": "Password123"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, response_body) = response.into_parts();
+        let bytes = response_body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json.get("message").unwrap(), "Password Is Too Common");
+
+        let body = serde_json::json!({
+            "email": "common@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    async fn sign_up_status(
+        app: &mut axum::routing::RouterIntoService<Body>,
+        email: &str,
+    ) -> (StatusCode, Value) {
+        let body = serde_json::json!({ "email": email, "password": "asdjfnaksdf87" });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, response_body) = response.into_parts();
+        let bytes = response_body.collect().await.unwrap().to_bytes();
+
+        (parts.status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn sign_up_with(
+        app: &mut axum::routing::RouterIntoService<Body>,
+        email: &str,
+        password: &str,
+    ) -> (StatusCode, Value) {
+        let body = serde_json::json!({ "email": email, "password": password });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, response_body) = response.into_parts();
+        let bytes = response_body.collect().await.unwrap().to_bytes();
+
+        (parts.status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn sign_up_reactivates_deactivated_credential() {
+        let (pool, app) = setup().await;
+        let mut default_app = app.into_service();
+        let config = AuthConfig {
+            reactivate_on_sign_up: true,
+            default_role: Role::Pending,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool.clone()).with_config(config))
+            .await
+            .into_service();
+
+        let (status, created) = sign_up_status(&mut default_app, "comeback@mail.com").await;
+        assert_eq!(status, StatusCode::OK);
+        let id = created["id"].as_str().unwrap().parse().unwrap();
+        AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move { CredentialsRepository::delete(tx, CredentialsBy::Id(id)).await })
+        })
+        .await
+        .unwrap();
+
+        let (status, _) = sign_up_with(&mut default_app, "comeback@mail.com", "N3w!password").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = sign_up_with(&mut app, "comeback@mail.com", "444").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, reactivated) =
+            sign_up_with(&mut app, "comeback@mail.com", "N3w!password").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reactivated["id"], created["id"]);
+        assert_eq!(reactivated["active"], true);
+        assert_eq!(reactivated["role"], "pending");
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::get(&mut tx, CredentialsBy::Id(id))
+            .await
+            .unwrap();
+        assert!(credential.active);
+        assert!(
+            crate::common::verify_password("N3w!password", &credential.password, None).unwrap()
+        );
+        assert!(
+            !crate::common::verify_password("asdjfnaksdf87", &credential.password, None).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_up_reactivation_still_rejects_active_duplicates() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            reactivate_on_sign_up: true,
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool.clone()).with_config(config))
+            .await
+            .into_service();
+
+        let (status, created) = sign_up_status(&mut app, "taken@mail.com").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = sign_up_with(&mut app, "taken@mail.com", "N3w!password").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential =
+            CredentialsRepository::get(&mut tx, CredentialsBy::Email("taken@mail.com".to_string()))
+                .await
+                .unwrap();
+        assert_eq!(credential.id.to_string(), created["id"].as_str().unwrap());
+        assert!(
+            crate::common::verify_password("asdjfnaksdf87", &credential.password, None).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_up_reactivation_starts_unverified_and_unlocked() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            reactivate_on_sign_up: true,
+            email_verification: EmailVerificationConfig {
+                required: true,
+                ..EmailVerificationConfig::default()
+            },
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool.clone()).with_config(config))
+            .await
+            .into_service();
+
+        let (status, created) = sign_up_status(&mut app, "previous-owner@mail.com").await;
+        assert_eq!(status, StatusCode::OK);
+        let id = created["id"].as_str().unwrap().parse().unwrap();
+        AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                CredentialsRepository::mark_verified(tx, id).await?;
+                let lock_until = Utc::now() + std::time::Duration::from_secs(60 * 60);
+                CredentialsRepository::record_failure(tx, id, 1, lock_until).await?;
+                CredentialsRepository::delete(tx, CredentialsBy::Id(id)).await
+            })
+        })
+        .await
+        .unwrap();
+
+        let (status, _) = sign_up_with(&mut app, "previous-owner@mail.com", "N3w!password").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential = CredentialsRepository::get(&mut tx, CredentialsBy::Id(id))
+            .await
+            .unwrap();
+        assert!(!credential.verified);
+        assert_eq!(credential.failed_attempts, 0);
+        assert_eq!(credential.locked_until, None);
+        AuthDatabase::rollback(tx).await.unwrap();
+
+        let body = serde_json::json!({
+            "email": "previous-owner@mail.com",
+            "password": "N3w!password"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn sign_up_email_domain_lists() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            allowed_email_domains: ["acme.com", "*.acme.io"].into_iter().collect(),
+            blocked_email_domains: ["*.contractors.acme.io"].into_iter().collect(),
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+
+        let (status, _) = sign_up_status(&mut app, "jane@acme.com").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = sign_up_status(&mut app, "jane@eu.acme.io").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, json) = sign_up_status(&mut app, "jane@gmail.com").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json.get("message").unwrap(), "Email Domain Is Not Allowed");
+
+        let (status, json) = sign_up_status(&mut app, "jane@eu.contractors.acme.io").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json.get("message").unwrap(), "Email Domain Is Blocked");
+    }
+
+    #[tokio::test]
+    async fn sign_up_empty_email_domain_lists_allow_any_domain() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+
+        let (status, _) = sign_up_status(&mut app, "anyone@mailinator.com").await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn sign_up_pool_closed_returns_service_unavailable() {
+        let (pool, app) = setup().await;
+        let mut app = app.into_service();
+        pool.close().await;
+
+        let body = serde_json::json!({
+            "email": "shutdown@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn sign_up_disabled_endpoint_is_not_mounted() {
+        let (pool, _) = setup().await;
+        let config = AuthConfig {
+            features: FeatureFlags::default().disable("sign_up"),
+            ..AuthConfig::default()
+        };
+        let mut app = App::router(AppState::new(pool).with_config(config))
+            .await
+            .into_service();
+        let body = serde_json::json!({
+            "email": "disabled@mail.com",
+            "password": "asdjfnaksdf87"
+        });
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_in")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn sign_up_malformed_body_returns_json_error() {
+        let (_, app) = setup().await;
+        let mut app = app.into_service();
+        let request = Request::builder()
+            .method("POST")
+            .uri("/sign_up")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"email": "owk@mail.com""#))
+            .unwrap();
+        let response = app.ready().await.unwrap().call(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = body.collect().await.unwrap().to_bytes();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(parts.status, StatusCode::BAD_REQUEST);
+        assert!(json.get("message").unwrap().as_str().is_some());
+    }
 }
@@ -0,0 +1,461 @@
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use auth_database::entities::credentials::{CredentialsBy, CredentialsDAO, EmailVerification};
+use auth_database::entities::email_verifications::{
+    CreateEmailVerificationsDAO, EmailVerificationsBy, EmailVerificationsDAO,
+    EmailVerificationsWhere,
+};
+use auth_database::traits::{BaseDatabase, DatabaseError, EntityRepository};
+use auth_database::{AuthDatabase, CredentialsRepository, EmailVerificationsRepository};
+use axum::extract::State;
+use axum::http::StatusCode;
+use sha2::{Digest, Sha256};
+use sqlx::Transaction;
+use sqlx::types::Uuid;
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::common::is_valid_email;
+use crate::extractors::Json;
+use crate::handlers::dto::{ResendVerificationDTO, VerifyEmailDTO};
+use crate::server::{AppState, ServerError, ServerResult};
+
+const TOKEN_PREFIX: &str = "ev_";
+const TOKEN_BYTES: usize = 32;
+
+/// Token handed to [`crate::server::EmailVerificationHook`], the database only keeps its
+/// hash.
+#[derive(Clone)]
+pub struct EmailVerificationToken {
+    pub credential_id: Uuid,
+    pub email: String,
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// What `email_verifications.token_hash` stores for `token`, see
+/// [`crate::handlers::api_keys::hash_api_key`].
+pub fn hash_verification_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn generate_verification_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", hex::encode(bytes))
+}
+
+/// Issues a verification token for `credential`, invalidating the ones issued before, and
+/// hands it to [`crate::server::AppState::on_email_verification`], if set. Called once
+/// the sign-up is committed, so a failure is logged rather than failing it.
+pub async fn issue<DB>(state: &AppState<DB>, credential: &CredentialsDAO)
+where
+    DB: sqlx::Database,
+    EmailVerificationsRepository: EntityRepository<
+            Db = DB,
+            Entity = EmailVerificationsDAO,
+            CreateInput = CreateEmailVerificationsDAO,
+            QueryOne = EmailVerificationsBy,
+            QueryMany = EmailVerificationsWhere,
+        >,
+{
+    let Some(hook) = &state.on_email_verification else {
+        return;
+    };
+
+    let ttl = state.config.email_verification.ttl;
+    let token = generate_verification_token();
+    let token_hash = hash_verification_token(&token);
+    let credential_id = credential.id;
+
+    let stored = AuthDatabase::named_transaction(&state.pool, "email_verification_issue", |tx| {
+        Box::pin(async move {
+            invalidate_verifications(tx, credential_id).await?;
+            EmailVerificationsRepository::insert(
+                tx,
+                CreateEmailVerificationsDAO {
+                    credential_id,
+                    token_hash,
+                    expires_at: Utc::now() + ttl,
+                },
+            )
+            .await
+        })
+    })
+    .await;
+
+    let verification = match stored {
+        Ok(verification) => verification,
+        Err(e) => {
+            tracing::error!(credential = %credential.id, "Error storing the verification token: {:?}", e);
+            return;
+        }
+    };
+
+    tokio::spawn(hook(EmailVerificationToken {
+        credential_id: credential.id,
+        email: credential.email.clone(),
+        token,
+        expires_at: verification.expires_at,
+    }));
+}
+
+/// Sends a new token to `email` when it belongs to an active, unverified credential,
+/// invalidating the one sent before. Answers `200` either way, so it can't be used to
+/// tell which emails have accounts.
+pub async fn resend<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Json(ResendVerificationDTO { email }): Json<ResendVerificationDTO>,
+) -> ServerResult<StatusCode>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+    EmailVerificationsRepository: EntityRepository<
+            Db = DB,
+            Entity = EmailVerificationsDAO,
+            CreateInput = CreateEmailVerificationsDAO,
+            QueryOne = EmailVerificationsBy,
+            QueryMany = EmailVerificationsWhere,
+        >,
+{
+    if !is_valid_email(&email)? {
+        return Err(ServerError::BadRequest("Invalid Email Format".to_string()));
+    };
+
+    let credential = AuthDatabase::named_transaction(&state.pool, "resend_verification", |tx| {
+        Box::pin(
+            async move { CredentialsRepository::try_get(tx, CredentialsBy::Email(email)).await },
+        )
+    })
+    .await?;
+
+    if let Some(credential) =
+        credential.filter(|credential| credential.active && !credential.verified)
+    {
+        issue(&state, &credential).await;
+    }
+
+    Ok(StatusCode::OK)
+}
+
+/// Marks the credential a token from [`issue`] was sent for as verified. The token is
+/// single use, unknown, used and expired tokens answer `400` alike.
+pub async fn verify_email<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    Json(payload): Json<VerifyEmailDTO>,
+) -> ServerResult<StatusCode>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EmailVerification<Db = DB>,
+    EmailVerificationsRepository: EntityRepository<
+            Db = DB,
+            Entity = EmailVerificationsDAO,
+            QueryOne = EmailVerificationsBy,
+            QueryMany = EmailVerificationsWhere,
+        >,
+{
+    let token_hash = hash_verification_token(&payload.token);
+
+    let credential_id = AuthDatabase::named_transaction(&state.pool, "verify_email", |tx| {
+        Box::pin(async move {
+            let invalid = || ServerError::BadRequest("Invalid Or Expired Token".to_string());
+
+            let Some(verification) = EmailVerificationsRepository::try_get(
+                tx,
+                EmailVerificationsBy::TokenHash(token_hash),
+            )
+            .await?
+            .filter(|verification| verification.active && verification.expires_at > Utc::now())
+            else {
+                return Err(invalid());
+            };
+
+            invalidate_verifications(tx, verification.credential_id).await?;
+            if !CredentialsRepository::mark_verified(tx, verification.credential_id).await? {
+                return Err(invalid());
+            }
+
+            Ok(verification.credential_id)
+        })
+    })
+    .await?;
+
+    tracing::info!(credential = %credential_id, "Email verified");
+    Ok(StatusCode::OK)
+}
+
+/// Deactivates the unused verification tokens of `credential_id`.
+async fn invalidate_verifications<DB>(
+    tx: &mut Transaction<'_, DB>,
+    credential_id: Uuid,
+) -> Result<(), DatabaseError>
+where
+    DB: sqlx::Database,
+    EmailVerificationsRepository: EntityRepository<
+            Db = DB,
+            Entity = EmailVerificationsDAO,
+            QueryOne = EmailVerificationsBy,
+            QueryMany = EmailVerificationsWhere,
+        >,
+{
+    let verifications = EmailVerificationsRepository::get_all(
+        tx,
+        EmailVerificationsWhere::CredentialId(credential_id),
+    )
+    .await?;
+
+    for verification in verifications
+        .into_iter()
+        .filter(|verification| verification.active)
+    {
+        EmailVerificationsRepository::delete(tx, EmailVerificationsBy::Id(verification.id)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(any(feature = "unit", feature = "integration"))]
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::EmailVerificationToken;
+    use crate::config::{AuthConfig, EmailVerificationConfig, RateLimitConfig};
+    use crate::server::{App, AppState};
+    use auth_database::{AuthDatabase, DB};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+        routing::RouterIntoService,
+    };
+    use http_body_util::BodyExt;
+    use sqlx::Pool;
+    use std::time::Duration;
+    use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+    use tower::Service;
+    use tower::util::ServiceExt;
+
+    const EMAIL: &str = "verify@gmail.com";
+    const PASSWORD: &str = "Ej4a2fkj!yI!Cj9";
+
+    /// App requiring verified emails, handing tokens to the returned receiver.
+    async fn setup() -> (
+        RouterIntoService<Body>,
+        UnboundedReceiver<EmailVerificationToken>,
+    ) {
+        setup_with(None).await
+    }
+
+    async fn setup_with(
+        rate_limit: Option<RateLimitConfig>,
+    ) -> (
+        RouterIntoService<Body>,
+        UnboundedReceiver<EmailVerificationToken>,
+    ) {
+        app(pool().await, rate_limit).await
+    }
+
+    async fn pool() -> Pool<DB> {
+        #[cfg(feature = "unit")]
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+
+        #[cfg(feature = "integration")]
+        let pool = {
+            dotenvy::dotenv().ok();
+            let database_url = std::env::var("AUTH_DATABASE_URL")
+                .expect("AUTH_DATABASE_URL must be set for integration tests");
+            AuthDatabase::connect(&database_url).await.unwrap()
+        };
+
+        pool
+    }
+
+    async fn app(
+        pool: Pool<DB>,
+        rate_limit: Option<RateLimitConfig>,
+    ) -> (
+        RouterIntoService<Body>,
+        UnboundedReceiver<EmailVerificationToken>,
+    ) {
+        let (sender, receiver) = unbounded_channel();
+        let config = AuthConfig {
+            email_verification: EmailVerificationConfig {
+                required: true,
+                ..EmailVerificationConfig::default()
+            },
+            ..AuthConfig::default()
+        };
+        let mut state = AppState::new(pool).with_config(config);
+        if let Some(rate_limit) = rate_limit {
+            state = state.with_rate_limit(rate_limit);
+        }
+        let state = state.with_on_email_verification(Arc::new(move |token| {
+            let sender = sender.clone();
+            Box::pin(async move {
+                sender.send(token).unwrap();
+            })
+        }));
+
+        (App::router(state).await.into_service(), receiver)
+    }
+
+    async fn post(
+        app: &mut RouterIntoService<Body>,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> axum::response::Response {
+        let request = Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        app.ready().await.unwrap().call(request).await.unwrap()
+    }
+
+    async fn sign_in(
+        app: &mut RouterIntoService<Body>,
+        password: &str,
+    ) -> axum::response::Response {
+        let body = serde_json::json!({ "email": EMAIL, "password": password });
+        post(app, "/sign_in", body).await
+    }
+
+    async fn verify(app: &mut RouterIntoService<Body>, token: &str) -> StatusCode {
+        let body = serde_json::json!({ "token": token });
+        post(app, "/verify_email", body).await.status()
+    }
+
+    #[tokio::test]
+    async fn unverified_sign_in_is_refused() {
+        let (mut app, mut receiver) = setup().await;
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        assert_eq!(
+            post(&mut app, "/sign_up", body).await.status(),
+            StatusCode::OK
+        );
+        let token = receiver.recv().await.unwrap();
+        assert_eq!(token.email, EMAIL);
+
+        let refused = sign_in(&mut app, PASSWORD).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let body = refused.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "Email Not Verified");
+
+        // Only the owner of the password learns the email is unverified.
+        let wrong_password = sign_in(&mut app, "wrong-password").await;
+        assert_eq!(wrong_password.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn verified_email_can_sign_in() {
+        let (mut app, mut receiver) = setup().await;
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        assert_eq!(
+            post(&mut app, "/sign_up", body).await.status(),
+            StatusCode::OK
+        );
+        let token = receiver.recv().await.unwrap();
+
+        assert!(token.token.starts_with("ev_"));
+        assert_eq!(
+            verify(&mut app, "ev_unknown").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(verify(&mut app, &token.token).await, StatusCode::OK);
+        assert_eq!(sign_in(&mut app, PASSWORD).await.status(), StatusCode::OK);
+
+        // Single use.
+        assert_eq!(
+            verify(&mut app, &token.token).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn token_is_accepted_by_another_instance() {
+        let pool = pool().await;
+        let (mut issuer, mut receiver) = app(pool.clone(), None).await;
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        assert_eq!(
+            post(&mut issuer, "/sign_up", body).await.status(),
+            StatusCode::OK
+        );
+        let token = receiver.recv().await.unwrap();
+
+        // A replica, or the same server after a restart, shares nothing but the database.
+        let (mut other, _) = app(pool, None).await;
+        assert_eq!(verify(&mut other, &token.token).await, StatusCode::OK);
+        assert_eq!(sign_in(&mut other, PASSWORD).await.status(), StatusCode::OK);
+    }
+
+    async fn resend(app: &mut RouterIntoService<Body>, email: &str) -> StatusCode {
+        let body = serde_json::json!({ "email": email });
+        post(app, "/verify_email/resend", body).await.status()
+    }
+
+    /// Fails if a token reaches the hook within a short wait.
+    async fn assert_no_token(receiver: &mut UnboundedReceiver<EmailVerificationToken>) {
+        let token = tokio::time::timeout(Duration::from_millis(100), receiver.recv()).await;
+        assert!(token.is_err(), "unexpected token");
+    }
+
+    #[tokio::test]
+    async fn resend_replaces_the_previous_token() {
+        let (mut app, mut receiver) = setup().await;
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        assert_eq!(
+            post(&mut app, "/sign_up", body).await.status(),
+            StatusCode::OK
+        );
+        let first = receiver.recv().await.unwrap();
+
+        assert_eq!(resend(&mut app, EMAIL).await, StatusCode::OK);
+        let second = receiver.recv().await.unwrap();
+        assert_eq!(second.email, EMAIL);
+        assert_ne!(second.token, first.token);
+
+        assert_eq!(
+            verify(&mut app, &first.token).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(verify(&mut app, &second.token).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn resend_answers_alike_without_a_token() {
+        let (mut app, mut receiver) = setup().await;
+        let body = serde_json::json!({ "email": EMAIL, "password": PASSWORD });
+        assert_eq!(
+            post(&mut app, "/sign_up", body).await.status(),
+            StatusCode::OK
+        );
+        let token = receiver.recv().await.unwrap();
+        assert_eq!(verify(&mut app, &token.token).await, StatusCode::OK);
+
+        // Already verified.
+        assert_eq!(resend(&mut app, EMAIL).await, StatusCode::OK);
+        assert_no_token(&mut receiver).await;
+
+        // No such account.
+        assert_eq!(resend(&mut app, "nobody@gmail.com").await, StatusCode::OK);
+        assert_no_token(&mut receiver).await;
+    }
+
+    #[tokio::test]
+    async fn resend_is_rate_limited() {
+        let rate_limit = RateLimitConfig {
+            limit: 2,
+            window: Duration::from_secs(60),
+            by_email: true,
+        };
+        let (mut app, _receiver) = setup_with(Some(rate_limit)).await;
+
+        for _ in 0..2 {
+            assert_eq!(resend(&mut app, EMAIL).await, StatusCode::OK);
+        }
+        assert_eq!(resend(&mut app, EMAIL).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+}
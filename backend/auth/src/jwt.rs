@@ -0,0 +1,144 @@
+//! Signed access tokens for clients that can't keep cookies, e.g. mobile apps.
+//!
+//! `/sign_in` returns an HS256 JWT next to the session cookie, accepted as
+//! `Authorization: Bearer <token>` by [`crate::middleware::jwt_auth`]. Tokens are
+//! stateless, signing out or revoking the session doesn't invalidate them, so their
+//! lifetime should stay short.
+
+use std::time::Duration;
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, errors::Error};
+use serde::{Deserialize, Serialize};
+use sqlx::types::{
+    Uuid,
+    chrono::{DateTime, Utc},
+};
+
+/// Secret and lifetime of issued access tokens.
+#[derive(Clone)]
+pub struct JwtConfig {
+    pub secret: Vec<u8>,
+    pub ttl: Duration,
+}
+
+impl JwtConfig {
+    /// Shortest secret accepted at startup, the size of the HS256 digest.
+    pub const MIN_SECRET_LEN: usize = 32;
+
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+}
+
+impl std::fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtConfig")
+            .field("secret", &"<redacted>")
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/// Payload of an access token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// Id of the credential the token was issued to.
+    pub sub: String,
+    /// Unix seconds.
+    pub iat: i64,
+    /// Unix seconds, the token is rejected from then on.
+    pub exp: i64,
+}
+
+impl Claims {
+    pub fn credential_id(&self) -> Option<Uuid> {
+        Uuid::parse_str(&self.sub).ok()
+    }
+}
+
+/// Signs a token for `credential_id` valid for [`JwtConfig::ttl`] from `now`.
+pub fn issue_jwt(
+    config: &JwtConfig,
+    credential_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<String, Error> {
+    let ttl = i64::try_from(config.ttl.as_secs()).unwrap_or(i64::MAX);
+    let claims = Claims {
+        sub: credential_id.to_string(),
+        iat: now.timestamp(),
+        exp: now.timestamp().saturating_add(ttl),
+    };
+
+    jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(&config.secret),
+    )
+}
+
+/// Claims of `token` when it's signed with the configured secret and not expired.
+pub fn verify_jwt(config: &JwtConfig, token: &str) -> Result<Claims, Error> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.leeway = 0;
+    validation.set_required_spec_claims(&["exp", "sub"]);
+
+    jsonwebtoken::decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(&config.secret),
+        &validation,
+    )
+    .map(|data| data.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use jsonwebtoken::errors::ErrorKind;
+
+    fn config() -> JwtConfig {
+        JwtConfig::new(
+            *b"0123456789abcdef0123456789abcdef",
+            Duration::from_secs(900),
+        )
+    }
+
+    #[test]
+    fn issued_token_verifies_to_its_credential() {
+        let credential_id = Uuid::new_v4();
+        let now = Utc::now();
+        let token = issue_jwt(&config(), credential_id, now).unwrap();
+
+        let claims = verify_jwt(&config(), &token).unwrap();
+
+        assert_eq!(claims.credential_id(), Some(credential_id));
+        assert_eq!(claims.exp - claims.iat, 900);
+        assert_eq!(claims.iat, now.timestamp());
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let issued_at = Utc::now() - Duration::from_secs(901);
+        let token = issue_jwt(&config(), Uuid::new_v4(), issued_at).unwrap();
+
+        let error = verify_jwt(&config(), &token).unwrap_err();
+
+        assert_eq!(error.kind(), &ErrorKind::ExpiredSignature);
+    }
+
+    #[test]
+    fn token_signed_with_another_secret_is_rejected() {
+        let other = JwtConfig::new(
+            *b"fedcba9876543210fedcba9876543210",
+            Duration::from_secs(900),
+        );
+        let token = issue_jwt(&other, Uuid::new_v4(), Utc::now()).unwrap();
+
+        let error = verify_jwt(&config(), &token).unwrap_err();
+
+        assert_eq!(error.kind(), &ErrorKind::InvalidSignature);
+    }
+}
@@ -1,11 +1,44 @@
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+
 use dotenvy::dotenv;
 
-use clap::{Parser, command};
+use auth_database::{
+    entities::credentials::{PasswordStorage, Role},
+    pool::PoolConfig,
+    ssl::SslOptions,
+};
+use axum::http::HeaderValue;
+use clap::{ArgAction, Parser};
+use cookie::SameSite;
+use sqlx::postgres::PgSslMode;
 
-use crate::server::App;
+use crate::{
+    common::{PasswordBlocklist, PasswordPolicy, SESSION_KEY},
+    config::{
+        Argon2Params, AuthConfig, ClientPrehashConfig, CookieConfig, CsrfConfig,
+        EmailVerificationConfig, ExistingSessionPolicy, FeatureFlags, JsonCase, LockoutConfig,
+        LogFormat, PasswordResetConfig, Pepper, RateLimitConfig, RefreshTokenConfig,
+        ResponseJitterConfig, SecurityProfile, SessionConfig, ShutdownConfig, TrailingSlash,
+    },
+    secrets::load_secret,
+    server::App,
+};
 
 pub mod common;
+pub mod config;
+pub mod cookies;
+pub mod events;
+pub mod extractors;
 pub mod handlers;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod middleware;
+#[cfg(feature = "mtls")]
+pub mod mtls;
+pub mod nonce;
+pub mod rate_limit;
+pub mod scopes;
+pub mod secrets;
 pub mod server;
 
 #[derive(Parser, Debug)]
@@ -15,20 +48,1355 @@ pub struct Args {
     #[arg(long, env = "AUTH_SERVER_ADDRESS")]
     address: String,
 
-    #[arg(long, env = "AUTH_DATABASE_URL")]
-    database_url: String,
+    #[arg(
+        long,
+        env = "AUTH_DATABASE_URL",
+        required_unless_present = "database_url_file"
+    )]
+    database_url: Option<String>,
+
+    /// File holding the database url, read when `--database-url` is not set
+    #[arg(long, env = "AUTH_DATABASE_URL_FILE")]
+    database_url_file: Option<PathBuf>,
+
+    /// Postgres sslmode (disable, allow, prefer, require, verify-ca or verify-full), overrides the url
+    #[arg(long, env = "AUTH_DATABASE_SSL_MODE", value_parser = PgSslMode::from_str)]
+    database_ssl_mode: Option<PgSslMode>,
+
+    /// CA certificate used to verify the Postgres server, overrides the url's sslrootcert
+    #[arg(long, env = "AUTH_DATABASE_SSL_ROOT_CERT")]
+    database_ssl_root_cert: Option<PathBuf>,
+
+    /// Most connections the database pool opens
+    #[arg(long, env = "AUTH_DATABASE_MAX_CONNECTIONS", default_value_t = 10)]
+    database_max_connections: u32,
+
+    /// Connections the database pool keeps open while idle
+    #[arg(long, env = "AUTH_DATABASE_MIN_CONNECTIONS", default_value_t = 0)]
+    database_min_connections: u32,
+
+    /// Seconds a query waits for a free connection before failing with `503`
+    #[arg(
+        long,
+        env = "AUTH_DATABASE_ACQUIRE_TIMEOUT_SECONDS",
+        default_value_t = 30
+    )]
+    database_acquire_timeout_seconds: u64,
+
+    /// Seconds before an idle connection above the minimum is closed, 0 keeps them open
+    #[arg(
+        long,
+        env = "AUTH_DATABASE_IDLE_TIMEOUT_SECONDS",
+        default_value_t = 600
+    )]
+    database_idle_timeout_seconds: u64,
+
+    /// Log the SQL of every statement at info level, bind values are never logged
+    #[arg(long, env = "AUTH_DATABASE_LOG_STATEMENTS", default_value_t = false)]
+    database_log_statements: bool,
+
+    /// Return 200 `{ ok: false, error }` for validation errors on every route (legacy clients only)
+    #[arg(long, env = "AUTH_LEGACY_VALIDATION_OK", default_value_t = false)]
+    legacy_validation_ok: bool,
+
+    /// Name of the session cookie
+    #[arg(long, env = "AUTH_SESSION_COOKIE_NAME", default_value = SESSION_KEY)]
+    session_cookie_name: String,
+
+    /// Domain attribute of the session cookie, host-only when omitted
+    #[arg(long, env = "AUTH_SESSION_COOKIE_DOMAIN")]
+    session_cookie_domain: Option<String>,
+
+    /// Defaults of the hardening options below, each can still be set on its own
+    #[arg(long, env = "AUTH_SECURITY_PROFILE", value_enum, default_value_t = SecurityProfile::Balanced)]
+    security_profile: SecurityProfile,
+
+    /// Whether the session cookie is only sent over HTTPS, off in the dev profile
+    #[arg(long, env = "AUTH_SESSION_COOKIE_SECURE", action = ArgAction::Set)]
+    session_cookie_secure: Option<bool>,
+
+    /// SameSite attribute of the session cookie (strict, lax or none), strict in the
+    /// strict profile and lax otherwise
+    #[arg(long, env = "AUTH_SESSION_COOKIE_SAME_SITE", value_parser = parse_same_site)]
+    session_cookie_same_site: Option<SameSite>,
+
+    /// Answer malformed request bodies without the parser's details, on in the strict profile
+    #[arg(long, env = "AUTH_HIDE_PARSE_ERRORS", action = ArgAction::Set)]
+    hide_parse_errors: Option<bool>,
+
+    /// Secret used to derive the double-submit CSRF cookie, the cookie is not issued when omitted
+    #[arg(long, env = "AUTH_CSRF_SECRET")]
+    csrf_secret: Option<String>,
+
+    /// File holding the CSRF secret, read when `--csrf-secret` is not set
+    #[arg(long, env = "AUTH_CSRF_SECRET_FILE")]
+    csrf_secret_file: Option<PathBuf>,
+
+    /// Secret the per-email salts of client pre-hashed passwords are derived from. Clients
+    /// then send `hex(SHA-256(password + salt))` with the salt from `/prehash_salt`
+    #[arg(long, env = "AUTH_CLIENT_PREHASH_SECRET")]
+    client_prehash_secret: Option<String>,
+
+    /// File holding the client pre-hash secret, read when `--client-prehash-secret` is not set
+    #[arg(long, env = "AUTH_CLIENT_PREHASH_SECRET_FILE")]
+    client_prehash_secret_file: Option<PathBuf>,
+
+    /// Role given to new credentials on sign-up (user, pending or admin)
+    #[arg(long, env = "AUTH_DEFAULT_ROLE", default_value_t = Role::User)]
+    default_role: Role,
+
+    /// What `/sign_in` does when the request already carries a valid session
+    #[arg(long, env = "AUTH_EXISTING_SESSION_POLICY", value_enum, default_value_t = ExistingSessionPolicy::CreateNew)]
+    existing_session_policy: ExistingSessionPolicy,
+
+    /// Field naming of JSON responses, `camel` for JavaScript clients
+    #[arg(long, env = "AUTH_JSON_CASE", value_enum, default_value_t = JsonCase::Snake)]
+    json_case: JsonCase,
+
+    /// Whether `/path/` is routed like `/path` (trim) or answered with `404` (strict)
+    #[arg(long, env = "AUTH_TRAILING_SLASH", value_enum, default_value_t = TrailingSlash::Trim)]
+    trailing_slash: TrailingSlash,
+
+    /// Format of the logs, `json` writes one object per line for log collectors
+    #[arg(long, env = "AUTH_LOG_FORMAT", value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    /// Minimum length in characters of new passwords, 12 in the strict profile
+    #[arg(long, env = "AUTH_PASSWORD_MIN_LENGTH")]
+    password_min_length: Option<usize>,
+
+    /// Require an uppercase letter in new passwords, on in the strict profile
+    #[arg(long, env = "AUTH_PASSWORD_REQUIRE_UPPER", num_args = 0..=1, default_missing_value = "true")]
+    password_require_upper: Option<bool>,
+
+    /// Require a lowercase letter in new passwords, on in the strict profile
+    #[arg(long, env = "AUTH_PASSWORD_REQUIRE_LOWER", num_args = 0..=1, default_missing_value = "true")]
+    password_require_lower: Option<bool>,
+
+    /// Require a digit in new passwords, on in the strict profile
+    #[arg(long, env = "AUTH_PASSWORD_REQUIRE_DIGIT", num_args = 0..=1, default_missing_value = "true")]
+    password_require_digit: Option<bool>,
+
+    /// Require a symbol, anything but letters, digits and whitespace, in new passwords, on
+    /// in the strict profile
+    #[arg(long, env = "AUTH_PASSWORD_REQUIRE_SYMBOL", num_args = 0..=1, default_missing_value = "true")]
+    password_require_symbol: Option<bool>,
+
+    /// File with one disallowed password per line, checked on sign-up
+    #[arg(long, env = "AUTH_PASSWORD_BLOCKLIST")]
+    password_blocklist: Option<PathBuf>,
+
+    /// Comma separated domains sign-up is restricted to, `*.example.com` matches subdomains
+    #[arg(long, env = "AUTH_ALLOWED_EMAIL_DOMAINS", value_delimiter = ',')]
+    allowed_email_domains: Vec<String>,
+
+    /// Comma separated domains refused on sign-up, `*.example.com` matches subdomains
+    #[arg(long, env = "AUTH_BLOCKED_EMAIL_DOMAINS", value_delimiter = ',')]
+    blocked_email_domains: Vec<String>,
+
+    /// Comma separated endpoints to leave unmounted, e.g. `sign_up,metrics`
+    #[arg(
+        long,
+        env = "AUTH_DISABLED_ENDPOINTS",
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(FeatureFlags::ENDPOINTS)
+    )]
+    disabled_endpoints: Vec<String>,
+
+    /// Comma separated origins browsers may call the API from with the session cookie, e.g.
+    /// `https://app.example.com`. Same-origin only when empty
+    #[arg(long, env = "AUTH_ALLOWED_ORIGINS", value_delimiter = ',')]
+    allowed_origins: Vec<String>,
+
+    /// Take the client ip from `X-Forwarded-For`, only safe behind a trusted proxy. Without
+    /// it the rate limit sees a proxy's ip as the only client
+    #[arg(long, env = "AUTH_TRUST_PROXY", default_value_t = false)]
+    trust_proxy: bool,
+
+    /// Write password hashes to `credential_secrets` instead of the `credentials` table
+    #[arg(long, env = "AUTH_SEPARATE_PASSWORD_TABLE", default_value_t = false)]
+    separate_password_table: bool,
+
+    /// Let signing up with the email of a deactivated account reactivate it
+    #[arg(long, env = "AUTH_REACTIVATE_ON_SIGN_UP", default_value_t = false)]
+    reactivate_on_sign_up: bool,
+
+    /// Make `/ready` verify the database accepts writes, not only reads
+    #[arg(long, env = "AUTH_READINESS_WRITE_CHECK", default_value_t = false)]
+    readiness_write_check: bool,
+
+    /// Argon2 variant for new password hashes (argon2id, argon2i or argon2d)
+    #[arg(long, env = "AUTH_ARGON2_ALGORITHM", default_value = "argon2id", value_parser = parse_argon2_algorithm)]
+    argon2_algorithm: argon2::Algorithm,
+
+    /// Argon2 version for new password hashes, 19 (0x13) or 16 (0x10)
+    #[arg(long, env = "AUTH_ARGON2_VERSION", default_value_t = 19)]
+    argon2_version: u32,
+
+    /// Argon2 memory cost in KiB for new password hashes
+    #[arg(long, env = "AUTH_ARGON2_M_COST", default_value_t = argon2::Params::DEFAULT_M_COST)]
+    argon2_m_cost: u32,
+
+    /// Argon2 iterations for new password hashes
+    #[arg(long, env = "AUTH_ARGON2_T_COST", default_value_t = argon2::Params::DEFAULT_T_COST)]
+    argon2_t_cost: u32,
+
+    /// Argon2 parallelism for new password hashes
+    #[arg(long, env = "AUTH_ARGON2_P_COST", default_value_t = argon2::Params::DEFAULT_P_COST)]
+    argon2_p_cost: u32,
+
+    /// Secret mixed into password hashes, hashes made with it stop verifying if it changes
+    #[arg(long, env = "AUTH_PASSWORD_PEPPER")]
+    password_pepper: Option<String>,
+
+    /// File holding the password pepper, read when `--password-pepper` is not set
+    #[arg(long, env = "AUTH_PASSWORD_PEPPER_FILE")]
+    password_pepper_file: Option<PathBuf>,
+
+    /// How long issued sessions stay valid
+    #[arg(long, env = "AUTH_SESSION_TTL_SECONDS", default_value_t = 86400)]
+    session_ttl_seconds: u64,
+
+    /// How often expired sessions are deleted from the database, 0 disables the purge
+    #[arg(
+        long,
+        env = "AUTH_SESSION_PURGE_INTERVAL_SECONDS",
+        default_value_t = 3600
+    )]
+    session_purge_interval_seconds: u64,
+
+    /// Reject session cookies sent by a client other than the one that signed in, told
+    /// apart by its user agent and a few other headers
+    #[arg(long, env = "AUTH_SESSION_BIND_FINGERPRINT", default_value_t = false)]
+    session_bind_fingerprint: bool,
+
+    /// Issues single-use refresh tokens, exchanged at `/refresh`, lasting this many days. 0
+    /// disables refresh tokens
+    #[arg(long, env = "AUTH_REFRESH_TOKEN_TTL_DAYS", default_value_t = 0)]
+    refresh_token_ttl_days: u64,
+
+    /// How long password reset tokens can be used
+    #[arg(long, env = "AUTH_PASSWORD_RESET_TTL_MINUTES", default_value_t = 60)]
+    password_reset_ttl_minutes: u64,
+
+    /// Consecutive wrong passwords before a credential is locked, 0 disables the lockout
+    #[arg(long, env = "AUTH_LOCKOUT_THRESHOLD", default_value_t = 5)]
+    lockout_threshold: u32,
+
+    /// How long a locked credential can't sign in
+    #[arg(long, env = "AUTH_LOCKOUT_MINUTES", default_value_t = 15)]
+    lockout_minutes: u64,
+
+    /// Requests per client ip to `/sign_in` and `/sign_up` within the window, 0 disables the
+    /// limit. Off by default, 5 in the strict profile. The ip is the peer's unless
+    /// `--trust-proxy` is set, so behind a reverse proxy every client shares one limit
+    /// without it
+    #[arg(long, env = "AUTH_RATE_LIMIT")]
+    rate_limit: Option<u32>,
+
+    /// Length of the rate limit's sliding window
+    #[arg(long, env = "AUTH_RATE_LIMIT_WINDOW_SECONDS", default_value_t = 60)]
+    rate_limit_window_seconds: u64,
+
+    /// Also apply the rate limit per posted email
+    #[arg(long, env = "AUTH_RATE_LIMIT_BY_EMAIL", default_value_t = true, action = ArgAction::Set)]
+    rate_limit_by_email: bool,
+
+    /// Hours an email verification token stays valid
+    #[arg(long, env = "AUTH_EMAIL_VERIFICATION_TTL_HOURS", default_value_t = 24)]
+    email_verification_ttl_hours: u64,
+
+    /// Refuse to sign in credentials whose email is not verified yet
+    #[arg(long, env = "AUTH_REQUIRE_VERIFIED_EMAIL", default_value_t = false)]
+    require_verified_email: bool,
+
+    /// Seconds in-flight requests get to finish on shutdown before their connections are closed
+    #[arg(long, env = "AUTH_SHUTDOWN_GRACE_SECONDS", default_value_t = 30)]
+    shutdown_grace_seconds: u64,
+
+    /// Least milliseconds of random delay added to every response
+    #[arg(long, env = "AUTH_RESPONSE_JITTER_MIN_MS", default_value_t = 0)]
+    response_jitter_min_ms: u64,
+
+    /// Most milliseconds of random delay added to every response, 0 disables the jitter.
+    /// At most 1000
+    #[arg(long, env = "AUTH_RESPONSE_JITTER_MAX_MS", default_value_t = 0)]
+    response_jitter_max_ms: u64,
+
+    /// Unix domain socket to serve on instead of `--address`, TLS settings don't apply to it
+    #[cfg(unix)]
+    #[arg(long, env = "AUTH_UNIX_SOCKET")]
+    unix_socket: Option<PathBuf>,
+
+    /// PEM certificate chain served over TLS, enables mutual TLS together with the key and CA
+    #[cfg(feature = "mtls")]
+    #[arg(long, env = "AUTH_TLS_CERT", requires_all = ["tls_key", "tls_client_ca"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate
+    #[cfg(feature = "mtls")]
+    #[arg(long, env = "AUTH_TLS_KEY", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM trust store client certificates must be signed by
+    #[cfg(feature = "mtls")]
+    #[arg(long, env = "AUTH_TLS_CLIENT_CA", requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// HS256 secret of the access tokens returned by `/sign_in`, at least 32 bytes.
+    /// No tokens are issued when unset
+    #[cfg(feature = "jwt")]
+    #[arg(long, env = "AUTH_JWT_SECRET")]
+    jwt_secret: Option<String>,
+
+    /// File holding the access token secret, read when `--jwt-secret` is not set
+    #[cfg(feature = "jwt")]
+    #[arg(long, env = "AUTH_JWT_SECRET_FILE")]
+    jwt_secret_file: Option<PathBuf>,
+
+    /// How long access tokens are valid, they can't be revoked before that
+    #[cfg(feature = "jwt")]
+    #[arg(long, env = "AUTH_JWT_TTL_SECONDS", default_value_t = 15 * 60)]
+    jwt_ttl_seconds: u64,
+}
+
+fn parse_same_site(value: &str) -> Result<SameSite, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "strict" => Ok(SameSite::Strict),
+        "lax" => Ok(SameSite::Lax),
+        "none" => Ok(SameSite::None),
+        _ => Err(format!("expected strict, lax or none, got `{value}`")),
+    }
+}
+
+fn parse_argon2_algorithm(value: &str) -> Result<argon2::Algorithm, String> {
+    argon2::Algorithm::from_str(value).map_err(|e| e.to_string())
+}
+
+impl Args {
+    /// Replaces each secret option with its value, read from its `*_FILE` counterpart
+    /// when not set directly, see [`crate::secrets`].
+    pub async fn load_secrets(&mut self) -> std::io::Result<()> {
+        self.database_url =
+            load_secret(self.database_url.take(), self.database_url_file.as_deref()).await?;
+        self.csrf_secret =
+            load_secret(self.csrf_secret.take(), self.csrf_secret_file.as_deref()).await?;
+        self.client_prehash_secret = load_secret(
+            self.client_prehash_secret.take(),
+            self.client_prehash_secret_file.as_deref(),
+        )
+        .await?;
+        self.password_pepper = load_secret(
+            self.password_pepper.take(),
+            self.password_pepper_file.as_deref(),
+        )
+        .await?;
+
+        #[cfg(feature = "jwt")]
+        {
+            self.jwt_secret =
+                load_secret(self.jwt_secret.take(), self.jwt_secret_file.as_deref()).await?;
+        }
+
+        Ok(())
+    }
+
+    pub fn config(&self) -> std::io::Result<AuthConfig> {
+        let password_blocklist = match &self.password_blocklist {
+            Some(path) => Some(Arc::new(PasswordBlocklist::from_file(path)?)),
+            None => None,
+        };
+
+        // The server only sees pre-hashes, which never match a listed password.
+        if self.client_prehash_secret.is_some() && password_blocklist.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the password blocklist can't be checked when clients pre-hash passwords",
+            ));
+        }
+
+        let version = argon2::Version::try_from(self.argon2_version)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+        let argon2 = Argon2Params {
+            algorithm: self.argon2_algorithm,
+            version,
+            m_cost: self.argon2_m_cost,
+            t_cost: self.argon2_t_cost,
+            p_cost: self.argon2_p_cost,
+        };
+        argon2
+            .to_params()
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e.to_string()))?;
+
+        if self.database_max_connections == 0
+            || self.database_min_connections > self.database_max_connections
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "database max connections must be at least 1 and the min connections",
+            ));
+        }
+
+        let profile = self.security_profile;
+        let rate_limit = self.rate_limit.unwrap_or(profile.rate_limit());
+        if rate_limit > 0 && self.rate_limit_window_seconds == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "rate limit window must be at least 1 second",
+            ));
+        }
+
+        let response_jitter = Some(ResponseJitterConfig {
+            min: Duration::from_millis(self.response_jitter_min_ms),
+            max: Duration::from_millis(self.response_jitter_max_ms),
+        })
+        .filter(|jitter| !jitter.max.is_zero());
+        if response_jitter
+            .is_some_and(|jitter| jitter.min > jitter.max || jitter.max > ResponseJitterConfig::MAX)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "response jitter must be at most 1000 ms and its min at most its max",
+            ));
+        }
+
+        // Credentialed CORS can't use a wildcard, and browsers send origins without a path.
+        let allowed_origins = self
+            .allowed_origins
+            .iter()
+            .map(|origin| origin.trim().trim_end_matches('/'))
+            .map(|origin| match origin {
+                "*" => Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "allowed origins must be listed, `*` can't be used with cookies",
+                )),
+                origin => HeaderValue::from_str(origin).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("invalid allowed origin `{origin}`"),
+                    )
+                }),
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        #[cfg(feature = "jwt")]
+        let jwt = match &self.jwt_secret {
+            Some(secret) if secret.len() < crate::jwt::JwtConfig::MIN_SECRET_LEN => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "jwt secret must be at least {} bytes",
+                        crate::jwt::JwtConfig::MIN_SECRET_LEN
+                    ),
+                ));
+            }
+            Some(_) if self.jwt_ttl_seconds == 0 => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "jwt ttl must be at least 1 second",
+                ));
+            }
+            Some(secret) => Some(crate::jwt::JwtConfig::new(
+                secret.as_bytes(),
+                Duration::from_secs(self.jwt_ttl_seconds),
+            )),
+            None => None,
+        };
+
+        let password_policy = profile.password_policy();
+        let mut features = FeatureFlags::default();
+        for endpoint in &self.disabled_endpoints {
+            features.set(endpoint, false);
+        }
+
+        Ok(AuthConfig {
+            legacy_validation_ok: self.legacy_validation_ok,
+            hide_parse_errors: self
+                .hide_parse_errors
+                .unwrap_or(profile.hide_parse_errors()),
+            cookie: CookieConfig {
+                name: self.session_cookie_name.clone(),
+                domain: self.session_cookie_domain.clone(),
+                secure: self
+                    .session_cookie_secure
+                    .unwrap_or(profile.cookie_secure()),
+                same_site: Some(
+                    self.session_cookie_same_site
+                        .unwrap_or(profile.cookie_same_site()),
+                ),
+                ..CookieConfig::default()
+            },
+            csrf: self.csrf_secret.as_deref().map(CsrfConfig::new),
+            client_prehash: self
+                .client_prehash_secret
+                .as_deref()
+                .map(ClientPrehashConfig::new),
+            default_role: self.default_role,
+            existing_session_policy: self.existing_session_policy,
+            json_case: self.json_case,
+            trailing_slash: self.trailing_slash,
+            password_policy: PasswordPolicy {
+                min_len: self.password_min_length.unwrap_or(password_policy.min_len),
+                require_upper: self
+                    .password_require_upper
+                    .unwrap_or(password_policy.require_upper),
+                require_lower: self
+                    .password_require_lower
+                    .unwrap_or(password_policy.require_lower),
+                require_digit: self
+                    .password_require_digit
+                    .unwrap_or(password_policy.require_digit),
+                require_symbol: self
+                    .password_require_symbol
+                    .unwrap_or(password_policy.require_symbol),
+            },
+            password_blocklist,
+            allowed_email_domains: self.allowed_email_domains.iter().collect(),
+            blocked_email_domains: self.blocked_email_domains.iter().collect(),
+            features,
+            allowed_origins,
+            trust_proxy: self.trust_proxy,
+            database_ssl: SslOptions {
+                mode: self.database_ssl_mode,
+                root_cert: self.database_ssl_root_cert.clone(),
+            },
+            database_pool: PoolConfig {
+                max_connections: self.database_max_connections,
+                min_connections: self.database_min_connections,
+                acquire_timeout: Duration::from_secs(self.database_acquire_timeout_seconds),
+                idle_timeout: Some(self.database_idle_timeout_seconds)
+                    .filter(|seconds| *seconds > 0)
+                    .map(Duration::from_secs),
+                log_statements: self.database_log_statements,
+            },
+            argon2,
+            password_pepper: self
+                .password_pepper
+                .as_deref()
+                .filter(|pepper| !pepper.is_empty())
+                .map(Pepper::new),
+            password_storage: if self.separate_password_table {
+                PasswordStorage::Separate
+            } else {
+                PasswordStorage::Inline
+            },
+            readiness_write_check: self.readiness_write_check,
+            reactivate_on_sign_up: self.reactivate_on_sign_up,
+            session: SessionConfig {
+                ttl: Duration::from_secs(self.session_ttl_seconds),
+                purge_interval: Some(self.session_purge_interval_seconds)
+                    .filter(|seconds| *seconds > 0)
+                    .map(Duration::from_secs),
+                bind_fingerprint: self.session_bind_fingerprint,
+            },
+            refresh_token: Some(self.refresh_token_ttl_days)
+                .filter(|days| *days > 0)
+                .map(|days| RefreshTokenConfig {
+                    ttl: Duration::from_secs(days * 24 * 60 * 60),
+                    ..RefreshTokenConfig::default()
+                }),
+            password_reset: PasswordResetConfig {
+                ttl: Duration::from_secs(self.password_reset_ttl_minutes * 60),
+            },
+            email_verification: EmailVerificationConfig {
+                ttl: Duration::from_secs(self.email_verification_ttl_hours * 60 * 60),
+                required: self.require_verified_email,
+            },
+            lockout: LockoutConfig {
+                threshold: self.lockout_threshold,
+                duration: Duration::from_secs(self.lockout_minutes * 60),
+            },
+            rate_limit: Some(RateLimitConfig {
+                limit: rate_limit,
+                window: Duration::from_secs(self.rate_limit_window_seconds),
+                by_email: self.rate_limit_by_email,
+            })
+            .filter(|rate_limit| rate_limit.limit > 0),
+            shutdown: ShutdownConfig {
+                grace: Duration::from_secs(self.shutdown_grace_seconds),
+            },
+            response_jitter,
+            #[cfg(unix)]
+            unix_socket: self.unix_socket.clone(),
+            #[cfg(feature = "jwt")]
+            jwt,
+            #[cfg(feature = "mtls")]
+            mtls: match (&self.tls_cert, &self.tls_key, &self.tls_client_ca) {
+                (Some(cert), Some(key), Some(client_ca)) => Some(crate::mtls::MtlsConfig {
+                    cert: cert.clone(),
+                    key: key.clone(),
+                    client_ca: client_ca.clone(),
+                }),
+                _ => None,
+            },
+        })
+    }
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
-    tracing_subscriber::fmt()
-        .pretty()
-        .with_max_level(tracing::Level::TRACE)
-        .init();
+    let mut args = Args::parse();
+
+    let logs = tracing_subscriber::fmt().with_max_level(tracing::Level::TRACE);
+    match args.log_format {
+        LogFormat::Pretty => logs.pretty().init(),
+        LogFormat::Json => logs.json().init(),
+    }
+
+    if let Err(e) = args.load_secrets().await {
+        tracing::error!("Could not load secrets: {:?}", e);
+        return;
+    }
+
+    let config = match args.config() {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Invalid configuration: {:?}", e);
+            return;
+        }
+    };
+
+    let database_url = args.database_url.unwrap_or_default();
+    App::run(&database_url, &args.address, config).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: [&str; 5] = [
+        "auth",
+        "--address",
+        "0.0.0.0:3000",
+        "--database-url",
+        "sqlite::memory:",
+    ];
+
+    #[test]
+    fn default_role_defaults_to_user() {
+        let args = Args::try_parse_from(REQUIRED).unwrap();
+
+        assert_eq!(args.config().unwrap().default_role, Role::User);
+    }
+
+    #[test]
+    fn default_role_is_configurable() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain(["--default-role", "pending"]))
+            .unwrap();
+
+        assert_eq!(args.config().unwrap().default_role, Role::Pending);
+    }
+
+    #[test]
+    fn reactivate_on_sign_up_is_opt_in() {
+        let args =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--reactivate-on-sign-up"])).unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert!(args.config().unwrap().reactivate_on_sign_up);
+        assert!(!default.reactivate_on_sign_up);
+    }
+
+    #[test]
+    fn rate_limit_is_configurable_and_zero_disables_it() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--rate-limit",
+            "5",
+            "--rate-limit-window-seconds",
+            "30",
+            "--rate-limit-by-email",
+            "false",
+        ]))
+        .unwrap();
+        let disabled =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--rate-limit", "0"])).unwrap();
+        let no_window = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--rate-limit",
+            "5",
+            "--rate-limit-window-seconds",
+            "0",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            args.config().unwrap().rate_limit,
+            Some(RateLimitConfig {
+                limit: 5,
+                window: Duration::from_secs(30),
+                by_email: false,
+            })
+        );
+        assert_eq!(disabled.config().unwrap().rate_limit, None);
+        assert!(no_window.config().is_err());
+    }
+
+    #[test]
+    fn allowed_origins_are_listed_explicitly() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--allowed-origins",
+            "https://app.example.com/, http://localhost:3000",
+        ]))
+        .unwrap();
+        let wildcard =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--allowed-origins", "*"])).unwrap();
+
+        assert_eq!(
+            args.config().unwrap().allowed_origins,
+            ["https://app.example.com", "http://localhost:3000"]
+        );
+        assert!(wildcard.config().is_err());
+        assert!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .allowed_origins
+                .is_empty()
+        );
+    }
+
+    #[cfg(feature = "jwt")]
+    #[test]
+    fn jwt_needs_a_long_enough_secret() {
+        let secret = "0123456789abcdef0123456789abcdef";
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--jwt-secret",
+            secret,
+            "--jwt-ttl-seconds",
+            "60",
+        ]))
+        .unwrap();
+        let short =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--jwt-secret", "secret"])).unwrap();
+
+        let jwt = args.config().unwrap().jwt.unwrap();
+        assert_eq!(jwt.secret, secret.as_bytes());
+        assert_eq!(jwt.ttl, Duration::from_secs(60));
+        assert!(short.config().is_err());
+        assert!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .jwt
+                .is_none()
+        );
+    }
+
+    fn profile(name: &str, overrides: &[&'static str]) -> AuthConfig {
+        let args = REQUIRED
+            .into_iter()
+            .chain(["--security-profile", name])
+            .chain(overrides.iter().copied());
+
+        Args::try_parse_from(args).unwrap().config().unwrap()
+    }
+
+    #[test]
+    fn security_profiles_set_the_hardening_defaults() {
+        let dev = profile("dev", &[]);
+        assert!(!dev.cookie.secure);
+        assert_eq!(dev.cookie.same_site, Some(SameSite::Lax));
+        assert_eq!(dev.rate_limit, None);
+        assert_eq!(dev.password_policy, PasswordPolicy::default());
+        assert!(!dev.hide_parse_errors);
+
+        let balanced = profile("balanced", &[]);
+        assert!(balanced.cookie.secure);
+        assert_eq!(balanced.cookie.same_site, Some(SameSite::Lax));
+        assert_eq!(balanced.rate_limit, None);
+        assert_eq!(balanced.password_policy, PasswordPolicy::default());
+        assert!(!balanced.hide_parse_errors);
+
+        let strict = profile("strict", &[]);
+        assert!(strict.cookie.secure);
+        assert_eq!(strict.cookie.same_site, Some(SameSite::Strict));
+        assert_eq!(strict.rate_limit.map(|r| r.limit), Some(5));
+        assert_eq!(
+            strict.password_policy,
+            PasswordPolicy {
+                min_len: 12,
+                require_upper: true,
+                require_lower: true,
+                require_digit: true,
+                require_symbol: true,
+            }
+        );
+        assert!(strict.hide_parse_errors);
+
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+        assert_eq!(default.cookie.secure, balanced.cookie.secure);
+        assert_eq!(default.rate_limit, balanced.rate_limit);
+    }
+
+    #[test]
+    fn options_override_their_security_profile() {
+        let strict = profile(
+            "strict",
+            &[
+                "--session-cookie-same-site",
+                "lax",
+                "--rate-limit",
+                "0",
+                "--password-min-length",
+                "20",
+                "--password-require-symbol=false",
+                "--hide-parse-errors",
+                "false",
+            ],
+        );
+        assert_eq!(strict.cookie.same_site, Some(SameSite::Lax));
+        assert_eq!(strict.rate_limit, None);
+        assert_eq!(strict.password_policy.min_len, 20);
+        assert!(!strict.password_policy.require_symbol);
+        assert!(strict.password_policy.require_upper);
+        assert!(!strict.hide_parse_errors);
+
+        let dev = profile("dev", &["--session-cookie-secure", "true"]);
+        assert!(dev.cookie.secure);
+    }
+
+    #[test]
+    fn unknown_default_role_is_rejected_at_startup() {
+        let error =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--default-role", "superuser"]))
+                .unwrap_err();
+
+        assert!(error.to_string().contains("Unknown role `superuser`"));
+    }
+
+    #[test]
+    fn password_policy_is_parsed() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--password-min-length",
+            "12",
+            "--password-require-upper",
+            "--password-require-digit",
+        ]))
+        .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            args.config().unwrap().password_policy,
+            PasswordPolicy {
+                min_len: 12,
+                require_upper: true,
+                require_digit: true,
+                ..PasswordPolicy::default()
+            }
+        );
+        assert_eq!(default.password_policy, PasswordPolicy::default());
+    }
+
+    #[test]
+    fn missing_password_blocklist_fails_config() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--password-blocklist", "/nonexistent/blocklist.txt"]),
+        )
+        .unwrap();
+
+        assert!(args.config().is_err());
+    }
+
+    #[test]
+    fn client_prehash_excludes_the_password_blocklist() {
+        let blocklist = std::env::temp_dir().join(format!("blocklist-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&blocklist, "password\n").unwrap();
+        let prehash = ["--client-prehash-secret", "prehash secret"];
+
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain(prehash)).unwrap();
+        let with_blocklist = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(prehash)
+                .chain(["--password-blocklist", blocklist.to_str().unwrap()]),
+        )
+        .unwrap();
+
+        assert!(args.config().unwrap().client_prehash.is_some());
+        assert!(with_blocklist.config().is_err());
+        std::fs::remove_file(blocklist).unwrap();
+    }
+
+    #[test]
+    fn disabled_endpoints_are_parsed() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--disabled-endpoints", "sign_up,metrics"]),
+        )
+        .unwrap();
+        let features = args.config().unwrap().features;
+
+        assert!(!features.is_enabled("sign_up"));
+        assert!(!features.is_enabled("metrics"));
+        assert!(features.is_enabled("sign_in"));
+    }
+
+    #[test]
+    fn email_domains_are_parsed() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--allowed-email-domains",
+            "acme.com,*.acme.io",
+            "--blocked-email-domains",
+            "mailinator.com",
+        ]))
+        .unwrap();
+        let config = args.config().unwrap();
+
+        assert!(config.allowed_email_domains.contains("jane@eu.acme.io"));
+        assert!(config.blocked_email_domains.contains("jane@mailinator.com"));
+        assert!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .allowed_email_domains
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn unknown_disabled_endpoint_is_rejected() {
+        let result = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--disabled-endpoints", "magic_link"]),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn database_ssl_options_are_parsed() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--database-ssl-mode",
+            "verify-full",
+            "--database-ssl-root-cert",
+            "/etc/ssl/rds.pem",
+        ]))
+        .unwrap();
+        let ssl = args.config().unwrap().database_ssl;
+
+        assert!(matches!(ssl.mode, Some(PgSslMode::VerifyFull)));
+        assert_eq!(ssl.root_cert, Some(PathBuf::from("/etc/ssl/rds.pem")));
+    }
+
+    #[test]
+    fn database_pool_options_are_parsed_and_validated() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--database-max-connections",
+            "20",
+            "--database-min-connections",
+            "2",
+            "--database-acquire-timeout-seconds",
+            "5",
+            "--database-idle-timeout-seconds",
+            "0",
+            "--database-log-statements",
+        ]))
+        .unwrap();
+        let invalid = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--database-max-connections",
+            "2",
+            "--database-min-connections",
+            "3",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            args.config().unwrap().database_pool,
+            PoolConfig {
+                max_connections: 20,
+                min_connections: 2,
+                acquire_timeout: Duration::from_secs(5),
+                idle_timeout: None,
+                log_statements: true,
+            }
+        );
+        assert_eq!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .database_pool,
+            PoolConfig::default()
+        );
+        assert!(invalid.config().is_err());
+    }
+
+    #[test]
+    fn argon2_params_are_parsed_and_validated() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--argon2-m-cost",
+            "8192",
+            "--argon2-t-cost",
+            "1",
+            "--argon2-p-cost",
+            "2",
+        ]))
+        .unwrap();
+        let invalid =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--argon2-m-cost", "1"])).unwrap();
+
+        assert_eq!(
+            args.config().unwrap().argon2,
+            Argon2Params {
+                m_cost: 8192,
+                t_cost: 1,
+                p_cost: 2,
+                ..Argon2Params::default()
+            }
+        );
+        assert!(invalid.config().is_err());
+    }
+
+    #[test]
+    fn argon2_variant_is_parsed_and_validated() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--argon2-algorithm",
+            "argon2i",
+            "--argon2-version",
+            "16",
+        ]))
+        .unwrap();
+        let argon2 = args.config().unwrap().argon2;
+        let invalid_version =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--argon2-version", "18"])).unwrap();
+
+        assert_eq!(argon2.algorithm, argon2::Algorithm::Argon2i);
+        assert_eq!(argon2.version, argon2::Version::V0x10);
+        assert_eq!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .argon2,
+            Argon2Params::default()
+        );
+        assert!(invalid_version.config().is_err());
+        assert!(
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--argon2-algorithm", "bcrypt"]))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn secrets_can_be_read_from_files() {
+        let dir = std::env::temp_dir();
+        let file = |name: &str, contents: &str| {
+            let path = dir.join(format!("{name}-{}", uuid::Uuid::new_v4()));
+            std::fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let database_url = file("database_url", "sqlite::memory:\n");
+        let pepper = file("pepper", "pepper from file\n");
+        let csrf = file("csrf", "csrf from file");
+
+        let mut args = Args::try_parse_from([
+            "auth",
+            "--address",
+            "0.0.0.0:3000",
+            "--database-url-file",
+            &database_url,
+            "--password-pepper-file",
+            &pepper,
+            "--csrf-secret",
+            "csrf from flag",
+            "--csrf-secret-file",
+            &csrf,
+        ])
+        .unwrap();
+        args.load_secrets().await.unwrap();
+        let config = args.config().unwrap();
+
+        assert_eq!(args.database_url.as_deref(), Some("sqlite::memory:"));
+        assert_eq!(
+            config.password_pepper.unwrap().as_bytes(),
+            b"pepper from file"
+        );
+        assert_eq!(config.csrf.unwrap().secret, b"csrf from flag");
+        assert!(Args::try_parse_from(["auth", "--address", "0.0.0.0:3000"]).is_err());
+
+        for path in [database_url, pepper, csrf] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn password_pepper_is_optional() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--password-pepper", "server-side secret"]),
+        )
+        .unwrap();
+        let pepper = args.config().unwrap().password_pepper.unwrap();
+        let empty =
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--password-pepper", ""])).unwrap();
+
+        assert_eq!(pepper.as_bytes(), b"server-side secret");
+        assert_eq!(format!("{pepper:?}"), "Pepper(<redacted>)");
+        assert!(empty.config().unwrap().password_pepper.is_none());
+    }
+
+    #[test]
+    fn session_ttl_is_configurable() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--session-ttl-seconds", "3600"]),
+        )
+        .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            args.config().unwrap().session.ttl,
+            Duration::from_secs(3600)
+        );
+        assert_eq!(default.session.ttl, Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn refresh_tokens_are_opt_in() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--refresh-token-ttl-days", "7"]),
+        )
+        .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            args.config().unwrap().refresh_token.unwrap().ttl,
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+        assert!(default.refresh_token.is_none());
+    }
+
+    #[test]
+    fn password_reset_ttl_is_configurable() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--password-reset-ttl-minutes", "15"]),
+        )
+        .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            args.config().unwrap().password_reset.ttl,
+            Duration::from_secs(15 * 60)
+        );
+        assert_eq!(default.password_reset, PasswordResetConfig::default());
+    }
+
+    #[test]
+    fn trailing_slash_is_trimmed_by_default() {
+        let trailing_slash = |args: &[&'static str]| {
+            Args::try_parse_from(REQUIRED.into_iter().chain(args.iter().copied()))
+                .unwrap()
+                .config()
+                .unwrap()
+                .trailing_slash
+        };
+
+        assert_eq!(trailing_slash(&[]), TrailingSlash::Trim);
+        assert_eq!(
+            trailing_slash(&["--trailing-slash", "strict"]),
+            TrailingSlash::Strict
+        );
+    }
+
+    #[test]
+    fn email_verification_is_opt_in() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--require-verified-email",
+            "--email-verification-ttl-hours",
+            "2",
+        ]))
+        .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            args.config().unwrap().email_verification,
+            EmailVerificationConfig {
+                ttl: Duration::from_secs(2 * 60 * 60),
+                required: true,
+            }
+        );
+        assert_eq!(
+            default.email_verification,
+            EmailVerificationConfig::default()
+        );
+    }
+
+    #[test]
+    fn log_format_defaults_to_pretty() {
+        let format = |format: &str| {
+            Args::try_parse_from(REQUIRED.into_iter().chain(["--log-format", format]))
+                .map(|args| args.log_format)
+        };
+
+        assert_eq!(
+            Args::try_parse_from(REQUIRED).unwrap().log_format,
+            LogFormat::Pretty
+        );
+        assert_eq!(format("json").unwrap(), LogFormat::Json);
+        assert!(format("xml").is_err());
+    }
+
+    #[test]
+    fn session_binding_is_opt_in() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain(["--session-bind-fingerprint"]))
+            .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert!(args.config().unwrap().session.bind_fingerprint);
+        assert!(!default.session.bind_fingerprint);
+    }
+
+    #[test]
+    fn session_purge_interval_is_configurable() {
+        let purge_interval = |seconds: &str| {
+            Args::try_parse_from(
+                REQUIRED
+                    .into_iter()
+                    .chain(["--session-purge-interval-seconds", seconds]),
+            )
+            .unwrap()
+            .config()
+            .unwrap()
+            .session
+            .purge_interval
+        };
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(purge_interval("60"), Some(Duration::from_secs(60)));
+        assert_eq!(purge_interval("0"), None);
+        assert_eq!(
+            default.session.purge_interval,
+            Some(Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn lockout_options_are_parsed() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--lockout-threshold",
+            "3",
+            "--lockout-minutes",
+            "30",
+        ]))
+        .unwrap();
+        let lockout = args.config().unwrap().lockout;
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(lockout.threshold, 3);
+        assert_eq!(lockout.duration, Duration::from_secs(30 * 60));
+        assert_eq!(default.lockout, LockoutConfig::default());
+    }
+
+    #[test]
+    fn shutdown_grace_is_configurable() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--shutdown-grace-seconds", "5"]),
+        )
+        .unwrap();
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            args.config().unwrap().shutdown.grace,
+            Duration::from_secs(5)
+        );
+        assert_eq!(default.shutdown, ShutdownConfig::default());
+    }
+
+    #[test]
+    fn response_jitter_is_opt_in_and_bounded() {
+        let jitter = |min: &str, max: &str| {
+            Args::try_parse_from(REQUIRED.into_iter().chain([
+                "--response-jitter-min-ms",
+                min,
+                "--response-jitter-max-ms",
+                max,
+            ]))
+            .unwrap()
+            .config()
+            .map(|config| config.response_jitter)
+        };
+        let default = Args::try_parse_from(REQUIRED).unwrap().config().unwrap();
+
+        assert_eq!(
+            jitter("10", "50").unwrap(),
+            Some(ResponseJitterConfig {
+                min: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+            })
+        );
+        assert_eq!(default.response_jitter, None);
+        assert!(jitter("50", "10").is_err());
+        assert!(jitter("0", "5000").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_is_parsed() {
+        let args = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--unix-socket", "/run/auth/auth.sock"]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            args.config().unwrap().unix_socket,
+            Some(PathBuf::from("/run/auth/auth.sock"))
+        );
+        assert!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .unix_socket
+                .is_none()
+        );
+    }
 
-    let args = Args::parse();
+    #[cfg(feature = "mtls")]
+    #[test]
+    fn mtls_requires_cert_key_and_client_ca() {
+        let args = Args::try_parse_from(REQUIRED.into_iter().chain([
+            "--tls-cert",
+            "/etc/auth/cert.pem",
+            "--tls-key",
+            "/etc/auth/key.pem",
+            "--tls-client-ca",
+            "/etc/auth/clients.pem",
+        ]))
+        .unwrap();
+        let mtls = args.config().unwrap().mtls.unwrap();
+        let partial = Args::try_parse_from(
+            REQUIRED
+                .into_iter()
+                .chain(["--tls-cert", "/etc/auth/cert.pem"]),
+        );
 
-    App::run(&args.database_url, &args.address).await;
+        assert_eq!(mtls.client_ca, PathBuf::from("/etc/auth/clients.pem"));
+        assert!(partial.is_err());
+        assert!(
+            Args::try_parse_from(REQUIRED)
+                .unwrap()
+                .config()
+                .unwrap()
+                .mtls
+                .is_none()
+        );
+    }
 }
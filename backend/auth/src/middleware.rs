@@ -0,0 +1,919 @@
+use std::{sync::Arc, time::Instant};
+
+use auth_database::{
+    ApiKeysRepository, AuthDatabase, CredentialsRepository, DB,
+    entities::{api_keys::ApiKeysBy, credentials::CredentialsBy},
+    traits::{BaseDatabase, DatabaseError, EntityRepository},
+};
+use axum::{
+    Json,
+    body::{Body, HttpBody, to_bytes},
+    extract::{FromRequestParts, Request, State},
+    http::{
+        HeaderValue, Method, StatusCode,
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::types::Uuid;
+use tracing::Instrument;
+
+use crate::{
+    common::CSRF_HEADER,
+    config::{JsonCase, ResponseJitterConfig},
+    cookies::{parse_session_secret, verify_csrf_token},
+    extractors::{AuthMethod, Authenticated, ClientInfo, Principal, TxSlot},
+    handlers::api_keys::hash_api_key,
+    scopes::Scope,
+    server::{AppState, BoxFuture, RejectedBody, ServerError},
+};
+
+/// Ends the [`crate::extractors::Tx`] a handler extracted, committing it when the
+/// response is a success or redirect and rolling it back otherwise. Requests that didn't
+/// extract one pass through untouched.
+pub async fn transaction<Db>(mut request: Request, next: Next) -> Response
+where
+    Db: sqlx::Database,
+{
+    let slot = TxSlot::<Db>::default();
+    request.extensions_mut().insert(slot.clone());
+
+    let response = next.run(request).await;
+    let Some(tx) = slot.take().await else {
+        return response;
+    };
+
+    let status = response.status();
+    if !status.is_success() && !status.is_redirection() {
+        // The response is an error already, a failed rollback only discards the connection.
+        let _ = AuthDatabase::rollback(tx).await;
+        return response;
+    }
+
+    match AuthDatabase::commit(tx).await {
+        Ok(()) => response,
+        Err(e) => {
+            tracing::error!("Error committing request transaction: {:#?}", e);
+            ServerError::from(DatabaseError::CommitFailed(Box::new(e))).into_response()
+        }
+    }
+}
+
+/// Rewrites validation failures (400/422) into `200 { ok: false, error }`.
+pub async fn legacy_validation_ok(request: Request, next: Next) -> Response {
+    #[derive(Serialize)]
+    struct LegacyErrorResponse {
+        ok: bool,
+        error: String,
+    }
+
+    let response = next.run(request).await;
+
+    if !matches!(
+        response.status(),
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY
+    ) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return Response::from_parts(parts, Body::empty());
+    };
+
+    let error = serde_json::from_slice::<Value>(&bytes)
+        .ok()
+        .and_then(|json| json.get("message")?.as_str().map(str::to_string))
+        .unwrap_or_else(|| String::from_utf8_lossy(&bytes).into_owned());
+
+    (
+        StatusCode::OK,
+        Json(LegacyErrorResponse { ok: false, error }),
+    )
+        .into_response()
+}
+
+/// Replaces the parser's message on responses to malformed bodies, which echoes field
+/// names and positions, with a generic one. The status is kept.
+pub async fn hide_parse_errors(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    if response.extensions().get::<RejectedBody>().is_none() {
+        return response;
+    }
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    let body = serde_json::json!({ "message": "Invalid Request Body" }).to_string();
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Gives every error response the `{ message }` JSON body [`crate::server::ServerError`]
+/// uses and drops `Content-Type` from empty bodies.
+///
+/// Errors produced outside the handlers, such as unmatched routes, wrong methods or
+/// extractor rejections, are otherwise empty or plain text.
+pub async fn consistent_content_type(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let status = response.status();
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+
+    if (status.is_client_error() || status.is_server_error()) && !is_json {
+        let (mut parts, body) = response.into_parts();
+        let bytes = to_bytes(body, usize::MAX).await.unwrap_or_default();
+        let message = if bytes.is_empty() {
+            status.canonical_reason().unwrap_or("Error").to_string()
+        } else {
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let body = serde_json::json!({ "message": message }).to_string();
+
+        return Response::from_parts(parts, Body::from(body));
+    }
+
+    if response.body().size_hint().exact() == Some(0) {
+        response.headers_mut().remove(CONTENT_TYPE);
+    }
+
+    response
+}
+
+/// Renames the keys of JSON responses to `case`, see [`JsonCase`]. Streamed bodies, whose
+/// length isn't known upfront, are passed through untouched rather than buffered whole.
+pub async fn json_case(State(case): State<JsonCase>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    let is_streamed = response.body().size_hint().exact().is_none();
+    if case == JsonCase::Snake || !is_json || is_streamed {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = to_bytes(body, usize::MAX).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(case.rename(value).to_string()))
+}
+
+/// Holds every response back for a random [`ResponseJitterConfig::sample`] on top of the
+/// time it took, so timings tell less about which endpoint or branch answered.
+pub async fn response_jitter(
+    State(jitter): State<ResponseJitterConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    tokio::time::sleep(jitter.sample()).await;
+
+    response
+}
+
+/// Header the id of [`request_id`] is returned in.
+pub const X_REQUEST_ID: &str = "x-request-id";
+
+/// Id given to a request by [`request_id`], found in its extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestId(pub Uuid);
+
+/// Runs the request inside a `request` span carrying a new `request_id`, so the logs of a
+/// request can be told apart from those of concurrent ones. The id is also returned in
+/// the `X-Request-Id` header, for clients to quote.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = Uuid::new_v4();
+    request.extensions_mut().insert(RequestId(id));
+
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = request.uri().path(),
+    );
+    let mut response = next.run(request).instrument(span).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id.to_string()) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+/// Authenticates requests carrying `Authorization: ApiKey <key>` as the key's
+/// credential, for [`Authenticated`] and [`Principal`] to pick up like a session.
+///
+/// Unknown or revoked keys, and keys of inactive credentials, are rejected with `401`.
+/// Requests without the header pass through untouched.
+pub async fn api_key_auth<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    mut request: Request,
+    next: Next,
+) -> Response
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+    ApiKeysRepository: EntityRepository<Db = DB, QueryOne = ApiKeysBy>,
+{
+    let Some(key) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("ApiKey "))
+    else {
+        return next.run(request).await;
+    };
+
+    let key_hash = hash_api_key(key.trim());
+    let authenticated =
+        AuthDatabase::named_transaction(&state.pool, "authenticate_api_key", |tx| {
+            Box::pin(async move {
+                let Some(api_key) = ApiKeysRepository::try_get(tx, ApiKeysBy::KeyHash(key_hash))
+                    .await?
+                    .filter(|api_key| api_key.active)
+                else {
+                    return Ok(None);
+                };
+
+                let credential =
+                    CredentialsRepository::try_get(tx, CredentialsBy::Id(api_key.credential_id))
+                        .await?
+                        .filter(|credential| credential.active);
+
+                Ok::<_, ServerError>(credential.map(|credential| (credential, api_key.scopes)))
+            })
+        })
+        .await;
+
+    let (credential, scopes) = match authenticated {
+        Ok(Some(authenticated)) => authenticated,
+        Ok(None) => return ServerError::Unauthorized.into_response(),
+        Err(e) => return e.into_response(),
+    };
+
+    // the role may have been lowered since the key was minted
+    let role_scopes = Scope::of_role(credential.role);
+    request.extensions_mut().insert(Principal {
+        credential_id: credential.id,
+        role: credential.role,
+        auth_method: AuthMethod::ApiKey,
+        session_id: None,
+        scopes: Scope::parse_all(&scopes)
+            .intersection(&role_scopes)
+            .copied()
+            .collect(),
+    });
+    request.extensions_mut().insert(Authenticated(credential));
+
+    next.run(request).await
+}
+
+/// Authenticates requests carrying `Authorization: Bearer <token>` with an access token
+/// from `/sign_in`, like [`api_key_auth`] does for API keys. The token's credential gets
+/// its role's scopes.
+///
+/// Invalid or expired tokens, and tokens of inactive credentials, are rejected with `401`.
+#[cfg(feature = "jwt")]
+pub async fn jwt_auth<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    mut request: Request,
+    next: Next,
+) -> Response
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB, QueryOne = CredentialsBy>,
+{
+    let Some(jwt) = &state.config.jwt else {
+        return next.run(request).await;
+    };
+
+    let Some(token) = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return next.run(request).await;
+    };
+
+    let credential_id = match crate::jwt::verify_jwt(jwt, token.trim()) {
+        Ok(claims) => claims.credential_id(),
+        Err(e) => {
+            tracing::debug!("Rejected access token: {e}");
+            None
+        }
+    };
+    let Some(credential_id) = credential_id else {
+        return ServerError::Unauthorized.into_response();
+    };
+
+    let credential = AuthDatabase::named_transaction(&state.pool, "authenticate_jwt", |tx| {
+        Box::pin(async move {
+            CredentialsRepository::try_get(tx, CredentialsBy::Id(credential_id)).await
+        })
+    })
+    .await
+    .map_err(ServerError::from);
+
+    let credential = match credential {
+        Ok(Some(credential)) if credential.active => credential,
+        Ok(_) => return ServerError::Unauthorized.into_response(),
+        Err(e) => return e.into_response(),
+    };
+
+    request.extensions_mut().insert(Principal {
+        credential_id: credential.id,
+        role: credential.role,
+        auth_method: AuthMethod::Jwt,
+        session_id: None,
+        scopes: Scope::of_role(credential.role),
+    });
+    request.extensions_mut().insert(Authenticated(credential));
+
+    next.run(request).await
+}
+
+/// Largest body [`rate_limit`] reads the email from, axum's default body limit.
+const RATE_LIMIT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Counts the request against [`AppState::rate_limiter`] under the client ip and, when
+/// configured, the `email` of the JSON body. Over the limit it's answered with `429` and
+/// a `Retry-After` header instead of reaching the handler.
+///
+/// Requests whose ip is unknown, e.g. over a Unix socket, are only limited per email.
+pub async fn rate_limit<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    request: Request,
+    next: Next,
+) -> Response
+where
+    DB: sqlx::Database,
+{
+    let Some(limiter) = state.rate_limiter.clone() else {
+        return next.run(request).await;
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let Ok(client) = ClientInfo::from_request_parts(&mut parts, &state).await;
+    let mut keys: Vec<String> = client.ip.map(|ip| format!("ip:{ip}")).into_iter().collect();
+
+    let body = if limiter.config().by_email {
+        let Ok(bytes) = to_bytes(body, RATE_LIMIT_MAX_BODY_BYTES).await else {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        };
+        let email = serde_json::from_slice::<Value>(&bytes)
+            .ok()
+            .and_then(|json| json.get("email")?.as_str().map(str::to_lowercase));
+        keys.extend(email.map(|email| format!("email:{}", email.trim())));
+
+        Body::from(bytes)
+    } else {
+        body
+    };
+
+    if let Err(wait) = limiter.check(&keys, Instant::now()) {
+        tracing::warn!(keys = ?keys, "rate limited");
+        return ServerError::TooManyRequests(wait).into_response();
+    }
+
+    next.run(Request::from_parts(parts, body)).await
+}
+
+/// Double-submit check for [`crate::config::AuthConfig::csrf`]: `POST`, `PUT`, `PATCH`
+/// and `DELETE` requests carrying a session cookie are rejected with `403` unless the
+/// [`CSRF_HEADER`] echoes the CSRF token of that session. Other requests, and those
+/// authenticated without the cookie, pass through.
+pub async fn csrf<DB>(
+    State(state): State<Arc<AppState<DB>>>,
+    request: Request,
+    next: Next,
+) -> Response
+where
+    DB: sqlx::Database,
+{
+    let Some(csrf) = &state.config.csrf else {
+        return next.run(request).await;
+    };
+
+    let unsafe_method = matches!(
+        *request.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    );
+    let Some(secret) =
+        parse_session_secret(request.headers(), &state.config.cookie).filter(|_| unsafe_method)
+    else {
+        return next.run(request).await;
+    };
+
+    let echoed = request
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+    match echoed {
+        Some(token) if verify_csrf_token(csrf, &secret.expose().to_string(), token) => {
+            next.run(request).await
+        }
+        _ => {
+            tracing::warn!(method = %request.method(), path = request.uri().path(), "CSRF check failed");
+            ServerError::Forbidden.into_response()
+        }
+    }
+}
+
+/// Route layer rejecting callers whose [`Principal`] lacks `scope` with `403`, and
+/// unauthenticated ones with `401` before the handler runs.
+pub fn require_scope(
+    scope: Scope,
+) -> impl Fn(State<Arc<AppState<DB>>>, Request, Next) -> BoxFuture<'static, Response>
++ Clone
++ Send
++ Sync
++ 'static {
+    move |State(state), request, next| {
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let principal = match Principal::from_request_parts(&mut parts, &state).await {
+                Ok(principal) => principal,
+                Err(e) => return e.into_response(),
+            };
+
+            if !principal.scopes.contains(&scope) {
+                return ServerError::Forbidden.into_response();
+            }
+
+            next.run(Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerError;
+
+    use axum::{
+        Router,
+        extract::Path,
+        http::header::RETRY_AFTER,
+        middleware::{from_fn, from_fn_with_state},
+        routing::{get, post},
+    };
+    use futures_util::StreamExt;
+    use http_body_util::BodyExt;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/error",
+                get(|| async { ServerError::ServiceUnavailable("Busy".to_string()) }),
+            )
+            .route(
+                "/items/{id}",
+                get(|Path(id): Path<u32>| async move { id.to_string() }),
+            )
+            .route(
+                "/empty",
+                post(|| async { ([(CONTENT_TYPE, "text/plain")], "") }),
+            )
+            .layer(from_fn(consistent_content_type))
+    }
+
+    async fn call(method: &str, uri: &str) -> Response {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+
+        app().oneshot(request).await.unwrap()
+    }
+
+    async fn message(response: Response) -> String {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        json.get("message").unwrap().as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn error_responses_are_json() {
+        let cases = [
+            ("GET", "/error", StatusCode::SERVICE_UNAVAILABLE),
+            ("GET", "/items/not-a-number", StatusCode::BAD_REQUEST),
+            ("DELETE", "/error", StatusCode::METHOD_NOT_ALLOWED),
+            ("GET", "/missing", StatusCode::NOT_FOUND),
+        ];
+
+        for (method, uri, status) in cases {
+            let response = call(method, uri).await;
+
+            assert_eq!(response.status(), status, "{method} {uri}");
+            assert_eq!(
+                response.headers().get(CONTENT_TYPE).unwrap(),
+                "application/json",
+                "{method} {uri}"
+            );
+            assert!(!message(response).await.is_empty(), "{method} {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rewritten_errors_keep_their_headers_and_text() {
+        let response = call("DELETE", "/error").await;
+        assert_eq!(response.headers().get("allow").unwrap(), "GET,HEAD");
+        assert_eq!(message(response).await, "Method Not Allowed");
+
+        let response = call("GET", "/error").await;
+        assert!(response.headers().get(RETRY_AFTER).is_some());
+        assert_eq!(message(response).await, "Busy");
+
+        let response = call("GET", "/items/not-a-number").await;
+        assert!(message(response).await.contains("Cannot parse"));
+    }
+
+    #[tokio::test]
+    async fn empty_bodies_have_no_content_type() {
+        let response = call("POST", "/empty").await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(CONTENT_TYPE).is_none());
+
+        let response = call("GET", "/items/7").await;
+        assert!(response.headers().get(CONTENT_TYPE).is_some());
+    }
+
+    fn session() -> crate::handlers::dto::SessionsDTO {
+        crate::handlers::dto::SessionsDTO {
+            id: "1".to_string(),
+            credential_id: "2".to_string(),
+            expires_at: "2026-01-02T00:00:00Z".to_string(),
+            created_at: "2026-01-01T00:00:00Z".to_string(),
+            active: true,
+            is_new_device: false,
+        }
+    }
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys: Vec<&str> = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn dtos_serialize_in_both_cases() {
+        let snake = JsonCase::Snake.to_value(&session()).unwrap();
+        let camel = JsonCase::Camel.to_value(&session()).unwrap();
+
+        assert_eq!(
+            keys(&snake),
+            [
+                "active",
+                "created_at",
+                "credential_id",
+                "expires_at",
+                "id",
+                "is_new_device"
+            ]
+        );
+        assert_eq!(
+            keys(&camel),
+            [
+                "active",
+                "createdAt",
+                "credentialId",
+                "expiresAt",
+                "id",
+                "isNewDevice"
+            ]
+        );
+        assert_eq!(camel["credentialId"], snake["credential_id"]);
+    }
+
+    #[tokio::test]
+    async fn json_case_renames_nested_response_fields() {
+        let app = Router::new()
+            .route(
+                "/sessions",
+                get(|| async { Json(serde_json::json!({ "sessions": [session()] })) }),
+            )
+            .layer(from_fn_with_state(JsonCase::Camel, json_case));
+        let request = Request::builder()
+            .uri("/sessions")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&bytes).unwrap();
+
+        assert_eq!(json["sessions"][0]["credentialId"], "2");
+        assert_eq!(json["sessions"][0]["isNewDevice"], false);
+        assert!(json["sessions"][0].get("credential_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn json_case_passes_streamed_bodies_through() {
+        let app = Router::new()
+            .route(
+                "/export",
+                get(|| async {
+                    // Never finishes, so buffering the body would never answer.
+                    let chunks = futures_util::stream::once(async {
+                        Ok::<_, std::io::Error>(r#"{"api_keys":["#)
+                    })
+                    .chain(futures_util::stream::pending());
+                    (
+                        [(CONTENT_TYPE, "application/json")],
+                        Body::from_stream(chunks),
+                    )
+                }),
+            )
+            .layer(from_fn_with_state(JsonCase::Camel, json_case));
+        let request = Request::builder()
+            .uri("/export")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let mut body = response.into_body();
+        let frame = tokio::time::timeout(Duration::from_secs(1), body.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(frame.into_data().unwrap(), r#"{"api_keys":["#);
+    }
+
+    #[tokio::test]
+    async fn parse_errors_can_be_hidden() {
+        let app = Router::new()
+            .route(
+                "/sign_in",
+                post(|_: crate::extractors::Json<crate::handlers::dto::SignInDTO>| async {}),
+            )
+            .route(
+                "/invalid",
+                post(|| async { ServerError::BadRequest("Invalid Email Format".to_string()) }),
+            );
+        let request = |uri: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"email":"jane@mail.com"}"#))
+                .unwrap()
+        };
+
+        let exposed = app.clone().oneshot(request("/sign_in")).await.unwrap();
+        assert!(message(exposed).await.contains("password"));
+
+        let app = app.layer(from_fn(hide_parse_errors));
+        let hidden = app.clone().oneshot(request("/sign_in")).await.unwrap();
+        assert_eq!(hidden.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(message(hidden).await, "Invalid Request Body");
+
+        let validation = app.oneshot(request("/invalid")).await.unwrap();
+        assert_eq!(message(validation).await, "Invalid Email Format");
+    }
+
+    #[tokio::test]
+    async fn response_jitter_delays_within_bounds() {
+        use std::time::Duration;
+
+        let jitter = ResponseJitterConfig {
+            min: Duration::from_millis(40),
+            max: Duration::from_millis(80),
+        };
+        for _ in 0..1000 {
+            let delay = jitter.sample();
+            assert!(jitter.min <= delay && delay <= jitter.max, "{delay:?}");
+        }
+
+        let timed = |app: Router| async move {
+            let started = Instant::now();
+            let request = Request::builder()
+                .uri("/items/1")
+                .body(Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            started.elapsed()
+        };
+
+        let delayed = timed(app().layer(from_fn_with_state(jitter, response_jitter))).await;
+        // Scheduling can only add to the sleep, so the upper bound gets some slack.
+        assert!(delayed >= jitter.min, "{delayed:?}");
+        assert!(
+            delayed < jitter.max + Duration::from_millis(40),
+            "{delayed:?}"
+        );
+
+        let undelayed = timed(app()).await;
+        assert!(undelayed < jitter.min, "{undelayed:?}");
+    }
+
+    /// Fields of the `request` spans, and the span each event was logged in.
+    #[derive(Clone, Default)]
+    struct RequestSpans {
+        spans: Arc<std::sync::Mutex<Vec<(tracing::span::Id, String)>>>,
+        events: Arc<std::sync::Mutex<Vec<Option<tracing::span::Id>>>>,
+    }
+
+    struct RequestIdField(String);
+
+    impl tracing::field::Visit for RequestIdField {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "request_id" {
+                self.0 = format!("{value:?}");
+            }
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for RequestSpans
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if attrs.metadata().name() == "request" {
+                let mut field = RequestIdField(String::new());
+                attrs.record(&mut field);
+                self.spans.lock().unwrap().push((id.clone(), field.0));
+            }
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let span = ctx.event_span(event).map(|span| span.id());
+            self.events.lock().unwrap().push(span);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_id_is_carried_by_the_request_span() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let layer = RequestSpans::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(layer.clone()));
+
+        let app = Router::new()
+            .route(
+                "/logged",
+                get(|axum::Extension(RequestId(id))| async move {
+                    tracing::info!("handled");
+                    id.to_string()
+                }),
+            )
+            .layer(from_fn(request_id));
+        let call = || {
+            let request = Request::builder()
+                .uri("/logged")
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let first = call().await.unwrap();
+        let second = call().await.unwrap();
+        let header = |response: &Response| {
+            response.headers()[X_REQUEST_ID]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let (first_id, second_id) = (header(&first), header(&second));
+        assert_ne!(first_id, second_id);
+        let body = to_bytes(first.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, first_id.as_bytes());
+
+        let spans = layer.spans.lock().unwrap().clone();
+        let ids: Vec<_> = spans.iter().map(|(_, id)| id.clone()).collect();
+        assert_eq!(ids, [first_id, second_id]);
+
+        // The handler's log line is inside the span of its request.
+        let events = layer.events.lock().unwrap().clone();
+        assert_eq!(events, [Some(spans[0].0.clone()), Some(spans[1].0.clone())]);
+    }
+
+    #[cfg(feature = "unit")]
+    #[tokio::test]
+    async fn csrf_header_is_required_with_a_session_cookie() {
+        use crate::common::{CSRF_KEY, SESSION_KEY};
+        use crate::config::{AuthConfig, CsrfConfig};
+        use crate::server::App;
+        use axum::http::header::{COOKIE, SET_COOKIE};
+        use cookie::Cookie;
+
+        let pool = auth_database::AuthDatabase::connect(":memory:")
+            .await
+            .unwrap();
+        let config = AuthConfig {
+            csrf: Some(CsrfConfig::new("csrf-secret")),
+            ..AuthConfig::default()
+        };
+        let app = App::router(AppState::new(pool).with_config(config)).await;
+
+        let credentials = serde_json::json!({
+            "email": "csrf-layer@gmail.com",
+            "password": "Ej4a2fkj!yI!Cj9"
+        })
+        .to_string();
+        let post = |uri: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(credentials.clone()))
+                .unwrap()
+        };
+        let signed_up = app.clone().oneshot(post("/sign_up")).await.unwrap();
+        assert_eq!(signed_up.status(), StatusCode::OK);
+        let signed_in = app.clone().oneshot(post("/sign_in")).await.unwrap();
+        assert_eq!(signed_in.status(), StatusCode::OK);
+
+        let cookies: Vec<_> = signed_in
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| Cookie::parse(value.to_str().unwrap().to_string()).unwrap())
+            .collect();
+        let value = |name: &str| {
+            cookies
+                .iter()
+                .find(|cookie| cookie.name() == name)
+                .unwrap()
+                .value()
+                .to_string()
+        };
+        let (session, token) = (value(SESSION_KEY), value(CSRF_KEY));
+        let cookie = format!("{SESSION_KEY}={session}; {CSRF_KEY}={token}");
+
+        let request = |method: &str, uri: &str, header: Option<&str>| {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(COOKIE, &cookie);
+            if let Some(header) = header {
+                request = request.header(CSRF_HEADER, header);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        for header in [None, Some("00"), Some("not-hex")] {
+            let response = app
+                .clone()
+                .oneshot(request("POST", "/sign_out", header))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{header:?}");
+        }
+
+        // Reads don't need the header.
+        let me = app
+            .clone()
+            .oneshot(request("GET", "/me", None))
+            .await
+            .unwrap();
+        assert_eq!(me.status(), StatusCode::OK);
+
+        let signed_out = app
+            .clone()
+            .oneshot(request("POST", "/sign_out", Some(&token)))
+            .await
+            .unwrap();
+        assert_eq!(signed_out.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,306 @@
+//! Mutual TLS for machine clients.
+//!
+//! The server terminates TLS itself and only completes handshakes with clients
+//! presenting a certificate signed by the configured CA. The verified certificate's
+//! subject is attached to every request of the connection as [`ClientCertificate`].
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{Extension, extract::FromRequestParts, http::request::Parts, middleware::AddExtension};
+use axum_server::{
+    accept::{Accept, DefaultAcceptor},
+    tls_rustls::{RustlsAcceptor, RustlsConfig},
+};
+use rustls::{
+    RootCertStore, ServerConfig, ServerConnection,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tower_layer::Layer;
+
+use crate::server::{BoxFuture, ServerError};
+
+/// Certificate, key and client trust store used to serve mutual TLS.
+#[derive(Debug, Clone)]
+pub struct MtlsConfig {
+    /// PEM chain presented to clients.
+    pub cert: PathBuf,
+    /// PEM private key of `cert`.
+    pub key: PathBuf,
+    /// PEM file with the CA certificates client certificates must be signed by.
+    pub client_ca: PathBuf,
+}
+
+impl MtlsConfig {
+    /// Loads the PEM files into a rustls config that requires a trusted client certificate.
+    pub fn server_config(&self) -> io::Result<Arc<ServerConfig>> {
+        let mut roots = RootCertStore::empty();
+        for ca in load_certs(&self.client_ca)? {
+            roots.add(ca).map_err(invalid_data)?;
+        }
+
+        let provider = Arc::new(ring::default_provider());
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(invalid_data)?;
+
+        let key = PrivateKeyDer::from_pem_file(&self.key).map_err(invalid_data)?;
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_data)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(load_certs(&self.cert)?, key)
+            .map_err(invalid_data)?;
+
+        Ok(Arc::new(config))
+    }
+
+    /// Acceptor doing the handshake and tagging requests with the client's certificate.
+    pub fn acceptor(&self) -> io::Result<ClientCertAcceptor> {
+        Ok(ClientCertAcceptor {
+            inner: RustlsAcceptor::new(RustlsConfig::from_config(self.server_config()?)),
+        })
+    }
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(invalid_data)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_data)?;
+
+    if certs.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificate found in {}",
+            path.display()
+        )));
+    }
+
+    Ok(certs)
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Verified client certificate of the connection the request came in on.
+///
+/// Only present when serving with [`MtlsConfig`]; extracting it anywhere else is
+/// rejected with `401`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    /// Distinguished name of the certificate subject, e.g. `CN=billing`.
+    pub subject: String,
+}
+
+impl ClientCertificate {
+    fn from_connection(connection: &ServerConnection) -> io::Result<Self> {
+        let der = connection
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .ok_or_else(|| invalid_data("client did not present a certificate"))?;
+
+        let (_, cert) = x509_parser::parse_x509_certificate(der).map_err(invalid_data)?;
+
+        Ok(ClientCertificate {
+            subject: cert.subject().to_string(),
+        })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientCertificate {
+    type Rejection = ServerError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientCertificate>()
+            .cloned()
+            .ok_or(ServerError::Unauthorized)
+    }
+}
+
+/// [`RustlsAcceptor`] that adds the peer's [`ClientCertificate`] to the connection's
+/// service once the handshake succeeds.
+#[derive(Debug, Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor<DefaultAcceptor>,
+}
+
+impl<S> Accept<TcpStream, S> for ClientCertAcceptor
+where
+    S: Send + 'static,
+{
+    type Stream = TlsStream<TcpStream>;
+    type Service = AddExtension<S, ClientCertificate>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let accept = self.inner.accept(stream, service);
+
+        Box::pin(async move {
+            let (stream, service) = accept.await?;
+            let certificate = ClientCertificate::from_connection(stream.get_ref().1)?;
+
+            Ok((stream, Extension(certificate).layer(service)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    use axum::{Router, routing::get};
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose,
+        IsCa, KeyPair,
+    };
+    use rustls::ClientConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    fn ca(name: &str) -> CertifiedIssuer<'static, KeyPair> {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap()
+    }
+
+    /// Certificate chain and key, as PEM, for `name` signed by `ca`.
+    fn leaf(
+        ca: &CertifiedIssuer<'static, KeyPair>,
+        name: &str,
+        usage: ExtendedKeyUsagePurpose,
+    ) -> (String, String) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.distinguished_name.push(DnType::CommonName, name);
+        params.extended_key_usages = vec![usage];
+        let cert = params.signed_by(&key, ca).unwrap();
+
+        (cert.pem(), key.serialize_pem())
+    }
+
+    /// Starts the server with a CA-signed certificate, returning its address and the CA
+    /// clients must trust.
+    async fn serve(client_ca: &CertifiedIssuer<'static, KeyPair>) -> (SocketAddr, String) {
+        let server_ca = ca("server ca");
+        let (cert, key) = leaf(&server_ca, "localhost", ExtendedKeyUsagePurpose::ServerAuth);
+
+        let dir = std::env::temp_dir().join(format!("auth-mtls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = MtlsConfig {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+            client_ca: dir.join("client_ca.pem"),
+        };
+        std::fs::write(&config.cert, cert).unwrap();
+        std::fs::write(&config.key, key).unwrap();
+        std::fs::write(&config.client_ca, client_ca.pem()).unwrap();
+        let acceptor = config.acceptor().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let app = Router::new().route(
+            "/whoami",
+            get(|certificate: ClientCertificate| async move { certificate.subject }),
+        );
+        let handle = axum_server::Handle::new();
+        let server = axum_server::bind("127.0.0.1:0".parse().unwrap())
+            .acceptor(acceptor)
+            .handle(handle.clone());
+        tokio::spawn(server.serve(app.into_make_service()));
+
+        (handle.listening().await.unwrap(), server_ca.pem())
+    }
+
+    /// Sends `GET /whoami` presenting the given client certificate, returning the raw response.
+    async fn whoami(
+        address: SocketAddr,
+        server_ca: &str,
+        (cert, key): (String, String),
+    ) -> io::Result<String> {
+        let mut roots = RootCertStore::empty();
+        for ca in CertificateDer::pem_slice_iter(server_ca.as_bytes()) {
+            roots.add(ca.unwrap()).unwrap();
+        }
+        let chain = CertificateDer::pem_slice_iter(cert.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(chain, key)
+            .unwrap();
+
+        let stream = TcpStream::connect(address).await?;
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect("localhost".try_into().unwrap(), stream)
+            .await?;
+        stream
+            .write_all(b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn accepts_client_signed_by_the_trusted_ca() {
+        let client_ca = ca("client ca");
+        let (address, server_ca) = serve(&client_ca).await;
+
+        let response = whoami(
+            address,
+            &server_ca,
+            leaf(&client_ca, "billing", ExtendedKeyUsagePurpose::ClientAuth),
+        )
+        .await
+        .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("CN=billing"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn rejects_client_signed_by_another_ca() {
+        let (address, server_ca) = serve(&ca("client ca")).await;
+
+        let response = whoami(
+            address,
+            &server_ca,
+            leaf(
+                &ca("rogue ca"),
+                "billing",
+                ExtendedKeyUsagePurpose::ClientAuth,
+            ),
+        )
+        .await;
+
+        assert!(response.is_err(), "{response:?}");
+    }
+
+    #[tokio::test]
+    async fn missing_extension_is_unauthorized() {
+        let (mut parts, _) = axum::http::Request::new(()).into_parts();
+
+        let rejection = ClientCertificate::from_request_parts(&mut parts, &())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(rejection, ServerError::Unauthorized));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::server::{BoxFuture, ServerResult};
+
+/// Short-lived single-use values, such as magic link tokens, CSRF nonces or idempotency
+/// keys, shared by every feature that needs one instead of each keeping its own table.
+///
+/// A value is readable once: [`NonceCache::consume`] removes it, so of two concurrent
+/// consumers only one gets it. Values past their TTL behave as if they were never stored.
+pub trait NonceCache: Send + Sync {
+    /// Stores `value` under `key` for `ttl`. Returns `false`, leaving the stored value
+    /// untouched, when `key` is already held by a live value.
+    fn insert<'a>(
+        &'a self,
+        key: &'a str,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, ServerResult<bool>>;
+
+    /// Removes and returns the live value under `key`, if any.
+    fn consume<'a>(&'a self, key: &'a str) -> BoxFuture<'a, ServerResult<Option<String>>>;
+}
+
+/// [`NonceCache`] kept in the process, so it only works for a single instance.
+///
+/// Expired entries are dropped when they are read and swept on every insert, keeping the
+/// map bounded by the values inserted within one TTL.
+#[derive(Debug, Default)]
+pub struct MemoryNonceCache {
+    entries: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl MemoryNonceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, (String, Instant)>> {
+        // A panic while holding the lock can't leave the map half-updated, every
+        // operation is a single map call.
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl NonceCache for MemoryNonceCache {
+    fn insert<'a>(
+        &'a self,
+        key: &'a str,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, ServerResult<bool>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let mut entries = self.entries();
+            entries.retain(|_, (_, expires_at)| *expires_at > now);
+
+            if entries.contains_key(key) {
+                return Ok(false);
+            }
+            entries.insert(key.to_string(), (value, now + ttl));
+
+            Ok(true)
+        })
+    }
+
+    fn consume<'a>(&'a self, key: &'a str) -> BoxFuture<'a, ServerResult<Option<String>>> {
+        Box::pin(async move {
+            let entry = self.entries().remove(key);
+
+            Ok(entry
+                .filter(|(_, expires_at)| *expires_at > Instant::now())
+                .map(|(value, _)| value))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn consume_is_single_use() {
+        let cache = MemoryNonceCache::new();
+
+        assert!(
+            cache
+                .insert("nonce", "value".to_string(), TTL)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !cache
+                .insert("nonce", "other".to_string(), TTL)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(
+            cache.consume("nonce").await.unwrap().as_deref(),
+            Some("value")
+        );
+        assert_eq!(cache.consume("nonce").await.unwrap(), None);
+        assert_eq!(cache.consume("unknown").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn expired_values_are_gone() {
+        let cache = MemoryNonceCache::new();
+        let ttl = Duration::from_millis(20);
+
+        assert!(
+            cache
+                .insert("nonce", "value".to_string(), ttl)
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(ttl * 2).await;
+        assert_eq!(cache.consume("nonce").await.unwrap(), None);
+
+        assert!(
+            cache
+                .insert("swept", "value".to_string(), ttl)
+                .await
+                .unwrap()
+        );
+        tokio::time::sleep(ttl * 2).await;
+        assert!(
+            cache
+                .insert("swept", "again".to_string(), TTL)
+                .await
+                .unwrap()
+        );
+        assert_eq!(cache.entries().len(), 1);
+        assert_eq!(
+            cache.consume("swept").await.unwrap().as_deref(),
+            Some("again")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_consume_has_one_winner() {
+        let cache = Arc::new(MemoryNonceCache::new());
+        cache
+            .insert("nonce", "value".to_string(), TTL)
+            .await
+            .unwrap();
+
+        let consumers: Vec<_> = (0..32)
+            .map(|_| {
+                let cache = cache.clone();
+                tokio::spawn(async move { cache.consume("nonce").await.unwrap() })
+            })
+            .collect();
+
+        let mut winners = 0;
+        for consumer in consumers {
+            if consumer.await.unwrap().is_some() {
+                winners += 1;
+            }
+        }
+        assert_eq!(winners, 1);
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::RateLimitConfig;
+
+/// In-process sliding window counter of requests per key, so it only limits a single
+/// instance.
+///
+/// Each key keeps the instants of its requests inside the window. Keys without one are
+/// swept on every check, keeping the map bounded by the clients seen within one window.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Counts a request under every key in `keys`. When one of them already reached the
+    /// limit the request isn't counted and the error holds how long until it frees up.
+    pub fn check(&self, keys: &[String], now: Instant) -> Result<(), Duration> {
+        let window = self.config.window;
+        // A panic while holding the lock can't leave a window half-updated.
+        let mut hits = self
+            .hits
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        hits.retain(|_, instants| {
+            while instants
+                .front()
+                .is_some_and(|instant| now.duration_since(*instant) >= window)
+            {
+                instants.pop_front();
+            }
+            !instants.is_empty()
+        });
+
+        let retry_after = keys
+            .iter()
+            .filter_map(|key| hits.get(key))
+            .filter(|instants| instants.len() >= self.config.limit as usize)
+            .filter_map(|instants| instants.front())
+            .map(|oldest| window.saturating_sub(now.duration_since(*oldest)))
+            .max();
+
+        if let Some(retry_after) = retry_after {
+            return Err(retry_after);
+        }
+
+        for key in keys {
+            hits.entry(key.clone()).or_default().push_back(now);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limit: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            limit,
+            window: Duration::from_secs(60),
+            by_email: true,
+        })
+    }
+
+    #[test]
+    fn requests_over_the_limit_wait_for_the_oldest_to_leave_the_window() {
+        let limiter = limiter(2);
+        let start = Instant::now();
+        let keys = ["ip:10.0.0.1".to_string()];
+
+        assert_eq!(limiter.check(&keys, start), Ok(()));
+        assert_eq!(
+            limiter.check(&keys, start + Duration::from_secs(10)),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check(&keys, start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+
+        // the rejected request didn't count, the first one left the window
+        assert_eq!(
+            limiter.check(&keys, start + Duration::from_secs(60)),
+            Ok(())
+        );
+        assert!(
+            limiter
+                .check(&keys, start + Duration::from_secs(61))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn any_exhausted_key_rejects_the_request() {
+        let limiter = limiter(1);
+        let now = Instant::now();
+        let email = "email:jane@mail.com".to_string();
+
+        assert_eq!(
+            limiter.check(&["ip:a".to_string(), email.clone()], now),
+            Ok(())
+        );
+        assert!(limiter.check(&["ip:b".to_string(), email], now).is_err());
+        assert_eq!(limiter.check(&["ip:b".to_string()], now), Ok(()));
+    }
+}
@@ -0,0 +1,120 @@
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+use auth_database::entities::credentials::Role;
+
+/// Permission to call a group of endpoints, checked by
+/// [`crate::middleware::require_scope`].
+///
+/// Sessions get the scopes of their credential's role, API keys the ones they were
+/// minted with, capped by the same role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    CredentialsRead,
+    SessionsRead,
+    SessionsRevoke,
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [
+        Scope::CredentialsRead,
+        Scope::SessionsRead,
+        Scope::SessionsRevoke,
+        Scope::Admin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::CredentialsRead => "credentials:read",
+            Scope::SessionsRead => "sessions:read",
+            Scope::SessionsRevoke => "sessions:revoke",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// Everything a credential holding `role` may do.
+    pub fn of_role(role: Role) -> BTreeSet<Scope> {
+        match role {
+            Role::Pending => BTreeSet::from([Scope::CredentialsRead]),
+            Role::User => BTreeSet::from([
+                Scope::CredentialsRead,
+                Scope::SessionsRead,
+                Scope::SessionsRevoke,
+            ]),
+            Role::Admin => BTreeSet::from(Scope::ALL),
+        }
+    }
+
+    /// Scopes stored space separated on an API key, unknown ones are skipped.
+    pub fn parse_all(scopes: &str) -> BTreeSet<Scope> {
+        scopes
+            .split_whitespace()
+            .filter_map(|scope| scope.parse().ok())
+            .collect()
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnknownScope(pub String);
+
+impl fmt::Display for UnknownScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let known: Vec<&str> = Scope::ALL.iter().map(Scope::as_str).collect();
+        write!(
+            f,
+            "Unknown scope `{}`, expected one of: {}",
+            self.0,
+            known.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownScope {}
+
+impl FromStr for Scope {
+    type Err = UnknownScope;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == value)
+            .ok_or_else(|| UnknownScope(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in Scope::ALL {
+            assert_eq!(scope.as_str().parse::<Scope>(), Ok(scope));
+        }
+
+        assert_eq!(
+            "sessions:delete".parse::<Scope>(),
+            Err(UnknownScope("sessions:delete".to_string()))
+        );
+        assert_eq!(
+            Scope::parse_all("sessions:read  bogus credentials:read"),
+            BTreeSet::from([Scope::SessionsRead, Scope::CredentialsRead])
+        );
+    }
+
+    #[test]
+    fn only_admins_get_the_admin_scope() {
+        assert!(Scope::of_role(Role::Admin).contains(&Scope::Admin));
+        assert!(!Scope::of_role(Role::User).contains(&Scope::Admin));
+        assert_eq!(
+            Scope::of_role(Role::Pending),
+            BTreeSet::from([Scope::CredentialsRead])
+        );
+    }
+}
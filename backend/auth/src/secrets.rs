@@ -0,0 +1,216 @@
+//! Secrets given at startup, set directly, read from a file or fetched from Vault.
+//!
+//! Every secret option `--foo` (`AUTH_FOO`) has a `--foo-file` (`AUTH_FOO_FILE`)
+//! counterpart, the convention for Docker and Kubernetes secrets mounted as files. The
+//! value set directly takes precedence over the file. With the `vault` feature, a value
+//! of the form `vault:<path>#<field>` is fetched from Vault instead, see [`VaultRef`].
+
+use std::{io, path::Path};
+
+/// Resolves a secret option: `value` when set, else the contents of `file` without
+/// trailing newlines, else `None`.
+pub async fn load_secret(value: Option<String>, file: Option<&Path>) -> io::Result<Option<String>> {
+    match (value, file) {
+        #[cfg(feature = "vault")]
+        (Some(value), _) if value.starts_with(VaultRef::PREFIX) => {
+            let reference = value.parse::<VaultRef>()?;
+            VaultClient::from_env()?.fetch(&reference).await.map(Some)
+        }
+        (Some(value), _) => Ok(Some(value)),
+        (None, Some(file)) => read_secret_file(file).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+fn read_secret_file(file: &Path) -> io::Result<String> {
+    let contents = std::fs::read_to_string(file).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("could not read secret file `{}`: {e}", file.display()),
+        )
+    })?;
+
+    Ok(contents.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Field of a Vault secret, written `vault:<path>#<field>` where `path` follows `/v1/`,
+/// e.g. `vault:secret/data/auth#jwt_secret` for the KV v2 engine mounted at `secret`.
+#[cfg(feature = "vault")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultRef {
+    pub path: String,
+    pub field: String,
+}
+
+#[cfg(feature = "vault")]
+impl VaultRef {
+    pub const PREFIX: &str = "vault:";
+}
+
+#[cfg(feature = "vault")]
+impl std::str::FromStr for VaultRef {
+    type Err = io::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .strip_prefix(Self::PREFIX)
+            .and_then(|reference| reference.split_once('#'))
+            .map(|(path, field)| (path.trim_matches('/'), field))
+            .filter(|(path, field)| !path.is_empty() && !field.is_empty())
+            .map(|(path, field)| VaultRef {
+                path: path.to_string(),
+                field: field.to_string(),
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "vault secrets are written `vault:<path>#<field>`",
+                )
+            })
+    }
+}
+
+/// Reads secrets over Vault's HTTP API with a token.
+#[cfg(feature = "vault")]
+pub struct VaultClient {
+    address: String,
+    token: String,
+    http: reqwest::Client,
+}
+
+#[cfg(feature = "vault")]
+impl VaultClient {
+    pub fn new(address: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            address: address.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Client for `VAULT_ADDR` with `VAULT_TOKEN`, the variables the Vault CLI reads.
+    pub fn from_env() -> io::Result<Self> {
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{name} must be set to read secrets from vault"),
+                )
+            })
+        };
+
+        Ok(Self::new(var("VAULT_ADDR")?, var("VAULT_TOKEN")?))
+    }
+
+    /// Reads `reference.field` from the secret at `reference.path`, in `data.data` for the
+    /// KV v2 engine or `data` for KV v1.
+    pub async fn fetch(&self, reference: &VaultRef) -> io::Result<String> {
+        let url = format!("{}/v1/{}", self.address, reference.path);
+        let failed = |e: reqwest::Error| {
+            io::Error::other(format!(
+                "could not read vault secret `{}`: {e}",
+                reference.path
+            ))
+        };
+
+        let body = self
+            .http
+            .get(url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(failed)?
+            .json::<serde_json::Value>()
+            .await
+            .map_err(failed)?;
+
+        let data = &body["data"];
+        data["data"][&reference.field]
+            .as_str()
+            .or_else(|| data[&reference.field].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "vault secret `{}` has no `{}` field",
+                        reference.path, reference.field
+                    ),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn file_secret_is_read_without_trailing_newlines() {
+        let file = secret_file("secret", "s3cr3t value\r\n\n");
+
+        let secret = load_secret(None, Some(&file)).await.unwrap();
+
+        assert_eq!(secret.as_deref(), Some("s3cr3t value"));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn value_takes_precedence_over_file() {
+        let file = secret_file("secret", "from file");
+
+        let secret = load_secret(Some("from env".to_string()), Some(&file))
+            .await
+            .unwrap();
+        let missing = load_secret(None, None).await.unwrap();
+        let unreadable = load_secret(None, Some(Path::new("/nonexistent/secret"))).await;
+
+        assert_eq!(secret.as_deref(), Some("from env"));
+        assert_eq!(missing, None);
+        assert_eq!(unreadable.unwrap_err().kind(), io::ErrorKind::NotFound);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[cfg(feature = "vault")]
+    #[tokio::test]
+    async fn vault_secrets_are_read_from_kv_v1_and_v2() {
+        use axum::{Json, Router, http::HeaderMap, routing::get};
+
+        async fn kv(
+            headers: HeaderMap,
+            path: axum::extract::Path<String>,
+        ) -> Json<serde_json::Value> {
+            assert_eq!(headers["x-vault-token"], "token");
+            match path.0.as_str() {
+                "secret/data/auth" => {
+                    Json(serde_json::json!({ "data": { "data": { "jwt": "v2" } } }))
+                }
+                _ => Json(serde_json::json!({ "data": { "jwt": "v1" } })),
+            }
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().route("/v1/{*path}", get(kv));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = VaultClient::new(address, "token");
+        let fetch = |reference: &str| {
+            let reference = reference.parse::<VaultRef>().unwrap();
+            let client = &client;
+            async move { client.fetch(&reference).await }
+        };
+
+        assert_eq!(fetch("vault:secret/data/auth#jwt").await.unwrap(), "v2");
+        assert_eq!(fetch("vault:kv/auth#jwt").await.unwrap(), "v1");
+        assert!(fetch("vault:kv/auth#missing").await.is_err());
+        assert!("vault:no-field".parse::<VaultRef>().is_err());
+    }
+}
@@ -1,32 +1,121 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
 
 use axum::{
     Json, Router,
     extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{
+        HeaderName, HeaderValue, Method, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    },
+    middleware,
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{delete, get, post, put},
 };
+use axum_server::Handle;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use serde::Serialize;
 use sqlx::Pool;
+use sqlx::types::chrono::Utc;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::normalize_path::NormalizePath;
 
-use auth_database::{AuthDatabase, DB, traits::DatabaseError};
+use crate::config::{AuthConfig, JsonCase, RateLimitConfig, TrailingSlash};
+use crate::events::{AuthEventSink, NoopEventSink};
+use crate::handlers::password_reset::PasswordResetToken;
+use crate::handlers::verify_email::EmailVerificationToken;
+use crate::nonce::{MemoryNonceCache, NonceCache};
+use crate::rate_limit::RateLimiter;
+use crate::scopes::Scope;
+use auth_database::{
+    AuthDatabase, DB, SessionsRepository,
+    entities::{
+        credentials::CredentialsDAO,
+        sessions::{ActiveSessions, SessionsDAO},
+    },
+    metrics::DB_OPERATION_DURATION_SECONDS,
+    traits::{BaseDatabase, DatabaseError},
+};
 
 #[derive(Debug)]
 pub enum ServerError {
     JsonRejection(JsonRejection),
     InternalServerError(String),
     Unauthorized,
+    Forbidden,
+    /// Right password for a soft-deleted credential, answered with `403` so clients can
+    /// tell it apart from a failed sign-in.
+    AccountDeactivated,
+    /// Right password for a credential that still has to verify its email, see
+    /// [`crate::config::EmailVerificationConfig::required`].
+    EmailNotVerified,
     BadRequest(String),
+    Conflict(String),
+    NotFound(String),
+    ServiceUnavailable(String),
+    UnprocessableEntity(String),
+    /// Rate limited, the caller can try again after the duration.
+    TooManyRequests(Duration),
+}
+
+pub type ServerResult<T> = Result<T, ServerError>;
+
+impl From<JsonRejection> for ServerError {
+    fn from(value: JsonRejection) -> Self {
+        ServerError::JsonRejection(value)
+    }
 }
 
 impl From<DatabaseError> for ServerError {
     fn from(value: DatabaseError) -> Self {
-        tracing::error!("DatabaseError: {:?}", value);
-        ServerError::InternalServerError("Internal Server Error".to_string())
+        match value {
+            DatabaseError::NotFound(_) => ServerError::NotFound("Not Found".to_string()),
+            DatabaseError::UniqueViolation(_) => {
+                ServerError::Conflict("Already Exists".to_string())
+            }
+            DatabaseError::Conflict(_) => {
+                ServerError::Conflict("Modified Concurrently".to_string())
+            }
+            DatabaseError::CommitFailed(e) => ServerError::from(*e),
+            DatabaseError::Source(kind, source) => {
+                let error = ServerError::from(*kind);
+                if matches!(error, ServerError::InternalServerError(_)) {
+                    tracing::error!("Caused by: {:?}", source);
+                }
+                error
+            }
+            DatabaseError::Busy
+            | DatabaseError::ConnectionNotAvailable
+            | DatabaseError::SerializationFailure => {
+                tracing::warn!("DatabaseError: {:?}", value);
+                ServerError::ServiceUnavailable("Service Unavailable".to_string())
+            }
+            DatabaseError::CommunicationError
+            | DatabaseError::ConnectionFailed
+            | DatabaseError::QueryFailed(_)
+            | DatabaseError::ColumnNotFound(_)
+            | DatabaseError::ProtocolNotSupported
+            | DatabaseError::NotImplemented
+            | DatabaseError::Unknown(_)
+            | DatabaseError::DatabaseInconsistence(_)
+            | DatabaseError::MigrationFailed(_)
+            | DatabaseError::InvalidConfiguration(_) => {
+                tracing::error!("DatabaseError: {:?}", value);
+                ServerError::InternalServerError("Internal Server Error".to_string())
+            }
+        }
     }
 }
 
+/// Marks responses to a body that couldn't be parsed, for
+/// [`crate::middleware::hide_parse_errors`].
+#[derive(Debug, Clone, Copy)]
+pub struct RejectedBody;
+
+/// Sent as `Retry-After` on `503` responses, e.g. while the pool is closing during shutdown.
+const RETRY_AFTER_SECONDS: u64 = 5;
+
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
         #[derive(Serialize)]
@@ -34,10 +123,12 @@ impl IntoResponse for ServerError {
             message: String,
         }
 
+        let mut retry_after = RETRY_AFTER_SECONDS;
+        let rejected_body = matches!(self, ServerError::JsonRejection(_));
         let (status, message) = match self {
             ServerError::JsonRejection(rejection) => {
                 tracing::error!("Invalid Request: {:?}", rejection);
-                (StatusCode::BAD_REQUEST, rejection.body_text())
+                (rejection.status(), rejection.body_text())
             }
             ServerError::InternalServerError(e) => {
                 tracing::error!("Internal Server Error: {:?}", e);
@@ -47,19 +138,89 @@ impl IntoResponse for ServerError {
                 )
             }
             ServerError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            ServerError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+            ServerError::AccountDeactivated => {
+                (StatusCode::FORBIDDEN, "Account Deactivated".to_string())
+            }
+            ServerError::EmailNotVerified => {
+                (StatusCode::FORBIDDEN, "Email Not Verified".to_string())
+            }
             ServerError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            ServerError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            ServerError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            ServerError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
+            ServerError::UnprocessableEntity(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
+            ServerError::TooManyRequests(wait) => {
+                // whole seconds, rounded up so retrying right on time succeeds
+                retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Too Many Requests".to_string(),
+                )
+            }
         };
 
-        (status, Json(ErrorResponse { message })).into_response()
+        let mut response = (status, Json(ErrorResponse { message })).into_response();
+        if rejected_body {
+            response.extensions_mut().insert(RejectedBody);
+        }
+        if matches!(
+            status,
+            StatusCode::SERVICE_UNAVAILABLE | StatusCode::TOO_MANY_REQUESTS
+        ) {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
     }
 }
 
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Runs inside the sign-up transaction right after the credential is inserted,
+/// an error rolls back the whole sign-up.
+pub type SignUpHook =
+    Arc<dyn for<'a> Fn(&'a CredentialsDAO) -> BoxFuture<'a, ServerResult<()>> + Send + Sync>;
+
+/// Runs after a sign-in from an ip the credential never signed in from, once the
+/// session is committed. Meant for alerts such as a webhook, it can't fail the sign-in.
+pub type NewDeviceHook = Arc<dyn for<'a> Fn(&'a SessionsDAO) -> BoxFuture<'a, ()> + Send + Sync>;
+
+/// Delivers a password reset token to the owner of the credential, e.g. by email, once
+/// the token is committed. Runs in the background, so `/password_reset/request` answers
+/// as fast whether the email has an account or not.
+pub type PasswordResetHook =
+    Arc<dyn Fn(PasswordResetToken) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Delivers an email verification token to the address it was issued for once the
+/// sign-up is committed. Runs in the background like [`PasswordResetHook`].
+pub type EmailVerificationHook =
+    Arc<dyn Fn(EmailVerificationToken) -> BoxFuture<'static, ()> + Send + Sync>;
+
 #[derive(Clone)]
 pub struct AppState<Db>
 where
     Db: sqlx::Database,
 {
     pub pool: Pool<Db>,
+    pub config: AuthConfig,
+    pub on_sign_up: Option<SignUpHook>,
+    pub on_new_device: Option<NewDeviceHook>,
+    /// The `/password_reset` routes are only mounted when set, tokens can't reach their
+    /// owner otherwise.
+    pub on_password_reset: Option<PasswordResetHook>,
+    /// Sign-ups are sent a verification token and `/verify_email` is mounted when set.
+    pub on_email_verification: Option<EmailVerificationHook>,
+    /// Notified of sign-ups and sign-ins, [`NoopEventSink`] by default.
+    pub events: Arc<dyn AuthEventSink>,
+    /// Renders the `/metrics` endpoint, the route is only mounted when set.
+    pub metrics: Option<PrometheusHandle>,
+    /// Single-use values with a TTL, shared by the features that need one.
+    pub nonces: Arc<dyn NonceCache>,
+    /// Limits `/sign_in` and `/sign_up` when set.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl<Db> AppState<Db>
@@ -67,7 +228,63 @@ where
     Db: sqlx::Database,
 {
     pub fn new(pool: Pool<Db>) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            config: AuthConfig::default(),
+            on_sign_up: None,
+            on_new_device: None,
+            on_password_reset: None,
+            on_email_verification: None,
+            events: Arc::new(NoopEventSink),
+            metrics: None,
+            nonces: Arc::new(MemoryNonceCache::new()),
+            rate_limiter: None,
+        }
+    }
+
+    pub fn with_metrics(mut self, handle: PrometheusHandle) -> Self {
+        self.metrics = Some(handle);
+        self
+    }
+
+    pub fn with_config(mut self, config: AuthConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_on_sign_up(mut self, hook: SignUpHook) -> Self {
+        self.on_sign_up = Some(hook);
+        self
+    }
+
+    pub fn with_on_new_device(mut self, hook: NewDeviceHook) -> Self {
+        self.on_new_device = Some(hook);
+        self
+    }
+
+    pub fn with_on_password_reset(mut self, hook: PasswordResetHook) -> Self {
+        self.on_password_reset = Some(hook);
+        self
+    }
+
+    pub fn with_on_email_verification(mut self, hook: EmailVerificationHook) -> Self {
+        self.on_email_verification = Some(hook);
+        self
+    }
+
+    pub fn with_event_sink(mut self, events: Arc<dyn AuthEventSink>) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn with_nonce_cache(mut self, nonces: Arc<dyn NonceCache>) -> Self {
+        self.nonces = nonces;
+        self
+    }
+
+    pub fn with_rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limiter = Some(Arc::new(RateLimiter::new(config)));
+        self
     }
 }
 
@@ -75,20 +292,275 @@ pub struct App;
 
 impl App {
     pub async fn app(pool: Pool<DB>) -> Router {
-        let app_state = Arc::new(AppState::new(pool));
+        App::router(AppState::new(pool)).await
+    }
+
+    pub async fn router(state: AppState<DB>) -> Router {
+        let state = Arc::new(state);
+        let scoped = |scope: Scope| {
+            middleware::from_fn_with_state(state.clone(), crate::middleware::require_scope(scope))
+        };
+
+        let mut sign_up = post(crate::handlers::sign_up::sign_up);
+        let mut sign_in = post(crate::handlers::sign_in::sign_in);
+        let mut password_reset = post(crate::handlers::password_reset::request);
+        let mut resend_verification = post(crate::handlers::verify_email::resend);
+        if state.rate_limiter.is_some() {
+            let rate_limit =
+                || middleware::from_fn_with_state(state.clone(), crate::middleware::rate_limit);
+            sign_up = sign_up.route_layer(rate_limit());
+            sign_in = sign_in.route_layer(rate_limit());
+            password_reset = password_reset.route_layer(rate_limit());
+            resend_verification = resend_verification.route_layer(rate_limit());
+        }
+
+        let features = &state.config.features;
+        let mut router = Router::new();
+
+        if features.is_enabled("sign_up") {
+            router = router.route("/sign_up", sign_up);
+        }
+
+        if features.is_enabled("sign_in") {
+            router = router.route("/sign_in", sign_in);
+        }
+
+        if state.config.refresh_token.is_some() && features.is_enabled("refresh") {
+            router = router.route("/refresh", post(crate::handlers::refresh::refresh));
+        }
+
+        if features.is_enabled("sign_out") {
+            router = router.route("/sign_out", post(crate::handlers::sign_out::sign_out));
+        }
+
+        if state.on_password_reset.is_some() && features.is_enabled("password_reset") {
+            router = router
+                .route("/password_reset/request", password_reset)
+                .route(
+                    "/password_reset/confirm",
+                    post(crate::handlers::password_reset::confirm),
+                );
+        }
+
+        if state.on_email_verification.is_some() && features.is_enabled("verify_email") {
+            router = router
+                .route(
+                    "/verify_email",
+                    post(crate::handlers::verify_email::verify_email),
+                )
+                .route("/verify_email/resend", resend_verification);
+        }
+
+        if state.config.client_prehash.is_some() && features.is_enabled("prehash_salt") {
+            router = router.route(
+                "/prehash_salt",
+                post(crate::handlers::prehash_salt::prehash_salt),
+            );
+        }
+
+        if features.is_enabled("change_password") {
+            router = router.route(
+                "/change_password",
+                post(crate::handlers::change_password::change_password),
+            );
+        }
+
+        if features.is_enabled("account") {
+            router = router.route("/account", delete(crate::handlers::account::delete_account));
+        }
+
+        if features.is_enabled("me") {
+            router = router.route(
+                "/me",
+                get(crate::handlers::me::me).route_layer(scoped(Scope::CredentialsRead)),
+            );
+        }
+
+        if features.is_enabled("me/export") {
+            router = router.route(
+                "/me/export",
+                get(crate::handlers::export::export)
+                    .route_layer(scoped(Scope::CredentialsRead))
+                    .route_layer(scoped(Scope::SessionsRead)),
+            );
+        }
+
+        if features.is_enabled("admin") {
+            router = router
+                .route(
+                    "/admin/credentials/{id}/role",
+                    put(crate::handlers::admin::update_role).route_layer(scoped(Scope::Admin)),
+                )
+                .route(
+                    "/admin/credentials/{id}/revoke_sessions",
+                    post(crate::handlers::admin::revoke_sessions)
+                        .route_layer(scoped(Scope::Admin))
+                        .route_layer(scoped(Scope::SessionsRevoke)),
+                )
+                .route(
+                    "/admin/sessions/expiry",
+                    put(crate::handlers::admin::set_sessions_expiry)
+                        .route_layer(scoped(Scope::Admin)),
+                );
+        }
 
-        Router::new()
-            .route("/sign_up", post(crate::handlers::sign_up::sign_up))
-            .route("/sign_in", post(crate::handlers::sign_in::sign_in))
-            .route(
+        if features.is_enabled("health_check") {
+            router = router.route(
                 "/health_check",
                 get(crate::handlers::health_check::health_check),
+            );
+        }
+
+        if features.is_enabled("health") {
+            router = router.route("/health", get(crate::handlers::health_check::health));
+        }
+
+        if features.is_enabled("ready") {
+            router = router.route("/ready", get(crate::handlers::ready::ready));
+        }
+
+        if features.is_enabled("sessions") {
+            router = router
+                .route(
+                    "/sessions",
+                    get(crate::handlers::sessions::list).route_layer(scoped(Scope::SessionsRead)),
+                )
+                .route(
+                    "/sessions/{id}",
+                    delete(crate::handlers::sessions::revoke)
+                        .route_layer(scoped(Scope::SessionsRevoke)),
+                )
+                .route(
+                    "/sessions/revoke/{id}",
+                    post(crate::handlers::sessions::revoke)
+                        .route_layer(scoped(Scope::SessionsRevoke)),
+                );
+        }
+
+        if features.is_enabled("sessions/count") {
+            router = router.route(
+                "/sessions/count",
+                get(crate::handlers::sessions::count).route_layer(scoped(Scope::SessionsRead)),
+            );
+        }
+
+        if features.is_enabled("api_keys") {
+            router = router
+                .route(
+                    "/api_keys",
+                    get(crate::handlers::api_keys::list).post(crate::handlers::api_keys::create),
+                )
+                .route("/api_keys/{id}", delete(crate::handlers::api_keys::revoke));
+        }
+
+        if state.metrics.is_some() && features.is_enabled("metrics") {
+            router = router.route("/metrics", get(crate::handlers::metrics::metrics));
+        }
+
+        // Innermost, so handlers' transactions end before the response is rewritten.
+        router = router.layer(middleware::from_fn(crate::middleware::transaction::<DB>));
+
+        // Outside the transaction, so a rewritten failure is still rolled back.
+        if state.config.legacy_validation_ok {
+            router = router.layer(middleware::from_fn(crate::middleware::legacy_validation_ok));
+        }
+
+        #[cfg(feature = "jwt")]
+        if state.config.jwt.is_some() {
+            router = router.layer(middleware::from_fn_with_state(
+                state.clone(),
+                crate::middleware::jwt_auth,
+            ));
+        }
+
+        if features.is_enabled("api_keys") {
+            router = router.layer(middleware::from_fn_with_state(
+                state.clone(),
+                crate::middleware::api_key_auth,
+            ));
+        }
+
+        // Outside the authentication layers, a forged request is refused before anything
+        // runs on its behalf.
+        if state.config.csrf.is_some() {
+            router = router.layer(middleware::from_fn_with_state(
+                state.clone(),
+                crate::middleware::csrf,
+            ));
+        }
+
+        if state.config.json_case != JsonCase::Snake {
+            router = router.layer(middleware::from_fn_with_state(
+                state.config.json_case,
+                crate::middleware::json_case,
+            ));
+        }
+
+        if state.config.hide_parse_errors {
+            router = router.layer(middleware::from_fn(crate::middleware::hide_parse_errors));
+        }
+
+        router = router.layer(middleware::from_fn(
+            crate::middleware::consistent_content_type,
+        ));
+
+        if let Some(jitter) = state.config.response_jitter {
+            router = router.layer(middleware::from_fn_with_state(
+                jitter,
+                crate::middleware::response_jitter,
+            ));
+        }
+
+        // Outside the other layers, so preflights are answered before routing.
+        if !state.config.allowed_origins.is_empty() {
+            router = router.layer(
+                CorsLayer::new()
+                    .allow_origin(AllowOrigin::list(state.config.allowed_origins.clone()))
+                    .allow_credentials(true)
+                    .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+                    .allow_headers([
+                        CONTENT_TYPE,
+                        AUTHORIZATION,
+                        HeaderName::from_static(crate::common::CSRF_HEADER),
+                    ])
+                    .expose_headers([HeaderName::from_static(crate::middleware::X_REQUEST_ID)]),
+            );
+        }
+
+        // Outermost, so what every other layer logs is inside the request span.
+        router = router.layer(middleware::from_fn(crate::middleware::request_id));
+
+        let trailing_slash = state.config.trailing_slash;
+        let router = router.with_state(state);
+
+        // Routing happens inside the router, so the path is rewritten by a service around it.
+        match trailing_slash {
+            TrailingSlash::Trim => {
+                Router::new().fallback_service(NormalizePath::trim_trailing_slash(router))
+            }
+            TrailingSlash::Strict => router,
+        }
+    }
+
+    fn install_metrics_recorder() -> Option<PrometheusHandle> {
+        PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full(DB_OPERATION_DURATION_SECONDS.to_string()),
+                &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5],
             )
-            .with_state(app_state)
+            .and_then(|builder| builder.install_recorder())
+            .inspect_err(|e| tracing::error!("Failed to install metrics recorder: {:?}", e))
+            .ok()
     }
 
-    pub async fn run(database_url: &str, address: &str) {
-        let pool: Pool<DB> = match AuthDatabase::connect(database_url).await {
+    pub async fn run(database_url: &str, address: &str, config: AuthConfig) {
+        let pool: Pool<DB> = match AuthDatabase::connect_with_ssl(
+            database_url,
+            &config.database_ssl,
+            &config.database_pool,
+        )
+        .await
+        {
             Ok(pool) => pool,
             Err(err) => {
                 tracing::error!("Failed to connect to the database: {:?}", err);
@@ -96,18 +568,794 @@ impl App {
             }
         };
 
-        let app = App::app(pool).await;
+        if let Some(interval) = config.session.purge_interval {
+            App::spawn_session_purge(pool.clone(), interval);
+        }
+
+        let closing = pool.clone();
+        let rate_limit = config.rate_limit;
+        let mut state = AppState::new(pool).with_config(config.clone());
+        if let Some(rate_limit) = rate_limit {
+            state = state.with_rate_limit(rate_limit);
+        }
+        if let Some(handle) = App::install_metrics_recorder() {
+            state = state.with_metrics(handle);
+        }
+
+        let app = App::router(state).await;
+        App::serve(app, address, &config).await;
+
+        // Waits for the connections still checked out, e.g. by requests cut off at the end
+        // of the grace period, to be returned.
+        tracing::info!("Closing the database pool");
+        closing.close().await;
+    }
+
+    /// Serves `app` on the listener `config` asks for until `Ctrl+C` or `SIGTERM`, then
+    /// drains in-flight requests for [`crate::config::ShutdownConfig::grace`].
+    async fn serve(app: Router, address: &str, config: &AuthConfig) {
+        let grace = config.shutdown.grace;
+
+        #[cfg(unix)]
+        if let Some(path) = &config.unix_socket {
+            return App::serve_unix(app, path, shutdown_signal(), grace).await;
+        }
+
+        let handle = App::shutdown_handle(shutdown_signal(), grace);
+
+        #[cfg(feature = "mtls")]
+        if let Some(mtls) = &config.mtls {
+            return App::serve_mtls(app, address, mtls, handle).await;
+        }
+
+        let listener = match tokio::net::TcpListener::bind(&address)
+            .await
+            .and_then(|listener| listener.into_std())
+        {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Error binding server to the address: {:?}", e);
+                return;
+            }
+        };
+
+        tracing::info!("Auth server running at https://{}", address);
+        if let Err(e) = axum_server::from_tcp(listener)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+        {
+            tracing::error!("Error starting auth microservice: {:?}", e);
+        }
+    }
+
+    /// Serves `app` over TLS, only accepting clients whose certificate is signed by the
+    /// configured CA.
+    #[cfg(feature = "mtls")]
+    async fn serve_mtls(
+        app: Router,
+        address: &str,
+        mtls: &crate::mtls::MtlsConfig,
+        handle: Handle,
+    ) {
+        let address: SocketAddr = match address.parse() {
+            Ok(address) => address,
+            Err(e) => {
+                tracing::error!("Invalid server address: {:?}", e);
+                return;
+            }
+        };
+
+        let acceptor = match mtls.acceptor() {
+            Ok(acceptor) => acceptor,
+            Err(e) => {
+                tracing::error!("Failed to load the TLS configuration: {:?}", e);
+                return;
+            }
+        };
 
-        match tokio::net::TcpListener::bind(&address).await {
-            Ok(listener) => {
-                tracing::info!("Auth server running at https://{}", address);
-                if let Err(e) = axum::serve(listener, app).await {
+        tracing::info!("Auth server running at https://{} with mutual TLS", address);
+        if let Err(e) = axum_server::bind(address)
+            .acceptor(acceptor)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+        {
+            tracing::error!("Error starting auth microservice: {:?}", e);
+        }
+    }
+
+    /// Serves `app` on a Unix domain socket at `path` until `signal` resolves, then gives
+    /// in-flight requests `grace` to finish. The socket file is removed on the way out.
+    ///
+    /// Peers have no ip, so [`crate::extractors::ClientInfo`] only has one with
+    /// `trust_proxy` and a forwarded header.
+    #[cfg(unix)]
+    pub async fn serve_unix(
+        app: Router,
+        path: &std::path::Path,
+        signal: impl Future<Output = ()> + Send + 'static,
+        grace: Duration,
+    ) {
+        if let Err(e) = remove_stale_socket(path) {
+            tracing::error!("Error removing the socket file: {:?}", e);
+            return;
+        }
+
+        let listener = match tokio::net::UnixListener::bind(path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!("Error binding server to the socket: {:?}", e);
+                return;
+            }
+        };
+
+        let (stopping, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server =
+            axum::serve(listener, app.into_make_service()).with_graceful_shutdown(async move {
+                signal.await;
+                tracing::info!(grace_seconds = grace.as_secs_f64(), "Shutting down");
+                let _ = stopping.send(());
+            });
+        let grace_elapsed = async move {
+            match stopped.await {
+                Ok(()) => tokio::time::sleep(grace).await,
+                Err(_) => std::future::pending().await,
+            }
+        };
+
+        tracing::info!("Auth server running at unix:{}", path.display());
+        tokio::select! {
+            served = server => {
+                if let Err(e) = served {
                     tracing::error!("Error starting auth microservice: {:?}", e);
                 }
             }
+            () = grace_elapsed => {
+                tracing::warn!("Grace period elapsed, closing open connections");
+            }
+        }
+
+        if let Err(e) = std::fs::remove_file(path) {
+            tracing::warn!("Failed to remove the socket file: {:?}", e);
+        }
+    }
+
+    /// Deletes expired sessions every `interval`, starting right away, for as long as the
+    /// process runs. Failures are logged and retried on the next tick.
+    pub fn spawn_session_purge(pool: Pool<DB>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                ticker.tick().await;
+                let purged =
+                    AuthDatabase::named_transaction(&pool, "purge_expired_sessions", |tx| {
+                        Box::pin(
+                            async move { SessionsRepository::delete_expired(tx, Utc::now()).await },
+                        )
+                    })
+                    .await;
+
+                match purged {
+                    Ok(0) => {}
+                    Ok(purged_sessions) => {
+                        tracing::info!(purged_sessions, "Expired sessions purged")
+                    }
+                    Err(e) => tracing::warn!("Failed to purge expired sessions: {:?}", e),
+                }
+            }
+        })
+    }
+
+    /// Handle for a server that stops accepting connections once `signal` resolves and
+    /// closes the ones still open `grace` later, so a hung request can't block the exit.
+    pub fn shutdown_handle(
+        signal: impl Future<Output = ()> + Send + 'static,
+        grace: Duration,
+    ) -> Handle {
+        let handle = Handle::new();
+        let watched = handle.clone();
+
+        tokio::spawn(async move {
+            signal.await;
+            tracing::info!(grace_seconds = grace.as_secs_f64(), "Shutting down");
+            watched.graceful_shutdown(None);
+
+            tokio::time::sleep(grace).await;
+            let connections = watched.connection_count();
+            if connections > 0 {
+                tracing::warn!(
+                    connections,
+                    "Grace period elapsed, closing open connections"
+                );
+            }
+            watched.shutdown();
+        });
+
+        handle
+    }
+}
+
+/// Removes a socket file a previous run left at `path`. Fails if another server still
+/// listens on it or the path is not a socket, rather than deleting it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        )),
+        Ok(_) if std::os::unix::net::UnixStream::connect(path).is_ok() => Err(Error::new(
+            ErrorKind::AddrInUse,
+            format!("{} is in use by another server", path.display()),
+        )),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Resolves on `Ctrl+C` or, on unix, `SIGTERM`.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
             Err(e) => {
-                tracing::error!("Error binding server to the address: {:?}", e);
+                tracing::error!("Failed to listen for SIGTERM: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_of(error: DatabaseError) -> StatusCode {
+        ServerError::from(error).into_response().status()
+    }
+
+    #[test]
+    fn database_error_statuses() {
+        let cases = [
+            (
+                DatabaseError::NotFound("row".to_string()),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                DatabaseError::UniqueViolation("email".to_string()),
+                StatusCode::CONFLICT,
+            ),
+            (
+                DatabaseError::Conflict("version".to_string()),
+                StatusCode::CONFLICT,
+            ),
+            (DatabaseError::Busy, StatusCode::SERVICE_UNAVAILABLE),
+            (
+                DatabaseError::from(sqlx::Error::RowNotFound),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                DatabaseError::from(sqlx::Error::Protocol("unexpected".to_string())),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::SerializationFailure,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                DatabaseError::CommitFailed(Box::new(DatabaseError::UniqueViolation(
+                    "email".to_string(),
+                ))),
+                StatusCode::CONFLICT,
+            ),
+            (
+                DatabaseError::CommitFailed(Box::new(DatabaseError::QueryFailed(
+                    "FOREIGN KEY constraint failed".to_string(),
+                ))),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::ConnectionNotAvailable,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                DatabaseError::CommunicationError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::ConnectionFailed,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::QueryFailed("syntax".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::ColumnNotFound("id".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::ProtocolNotSupported,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::NotImplemented,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::Unknown("?".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::DatabaseInconsistence("uuid".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::MigrationFailed("001".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                DatabaseError::InvalidConfiguration("sslrootcert".to_string()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+
+        for (error, status) in cases {
+            let description = format!("{error:?}");
+            assert_eq!(status_of(error), status, "failed for {description}");
+        }
+    }
+
+    #[test]
+    fn database_error_variants() {
+        assert!(matches!(
+            ServerError::from(DatabaseError::NotFound("row".to_string())),
+            ServerError::NotFound(_)
+        ));
+        assert!(matches!(
+            ServerError::from(DatabaseError::UniqueViolation("email".to_string())),
+            ServerError::Conflict(_)
+        ));
+        assert!(matches!(
+            ServerError::from(DatabaseError::Busy),
+            ServerError::ServiceUnavailable(_)
+        ));
+        assert!(matches!(
+            ServerError::from(DatabaseError::QueryFailed("syntax".to_string())),
+            ServerError::InternalServerError(_)
+        ));
+    }
+
+    #[test]
+    fn service_unavailable_sets_retry_after() {
+        let response = ServerError::from(DatabaseError::ConnectionNotAvailable).into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers().get(RETRY_AFTER).unwrap(),
+            &RETRY_AFTER_SECONDS.to_string()
+        );
+
+        let response = ServerError::from(DatabaseError::ConnectionFailed).into_response();
+
+        assert!(response.headers().get(RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn pool_closed_is_connection_not_available() {
+        assert!(matches!(
+            DatabaseError::from(sqlx::Error::PoolClosed),
+            DatabaseError::ConnectionNotAvailable
+        ));
+    }
+
+    fn parse_body(bytes: &[u8]) -> ServerResult<serde_json::Value> {
+        let Json(value) = Json::<serde_json::Value>::from_bytes(bytes)?;
+        Ok(value)
+    }
+
+    #[test]
+    fn json_rejection_converts_with_question_mark() {
+        let error = parse_body(b"{\"email\":").unwrap_err();
+        assert!(matches!(error, ServerError::JsonRejection(_)));
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(
+            parse_body(b"{\"ok\":true}").unwrap(),
+            serde_json::json!({ "ok": true })
+        );
+    }
+
+    #[tokio::test]
+    async fn hung_request_does_not_block_shutdown() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let grace = Duration::from_millis(200);
+        let app = Router::new().route("/hang", get(std::future::pending::<()>));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let handle = App::shutdown_handle(
+            async move {
+                stopped.await.ok();
+            },
+            grace,
+        );
+        let server = tokio::spawn(
+            axum_server::from_tcp(listener)
+                .handle(handle.clone())
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /hang HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        while handle.connection_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let started = std::time::Instant::now();
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server kept running past the grace period")
+            .unwrap()
+            .unwrap();
+
+        assert!(started.elapsed() >= grace);
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn in_flight_request_completes_on_shutdown() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (started, mut running) = tokio::sync::mpsc::channel::<()>(1);
+        let app = Router::new().route(
+            "/slow",
+            get(move || async move {
+                started.send(()).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done"
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let handle = App::shutdown_handle(
+            async move {
+                stopped.await.ok();
+            },
+            Duration::from_secs(5),
+        );
+        let server = tokio::spawn(
+            axum_server::from_tcp(listener)
+                .handle(handle)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>()),
+        );
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        running.recv().await.unwrap();
+
+        stop.send(()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("done"));
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server kept running after draining")
+            .unwrap()
+            .unwrap();
+        assert!(tokio::net::TcpStream::connect(address).await.is_err());
+    }
+
+    #[cfg(feature = "unit")]
+    #[tokio::test]
+    async fn session_purge_deletes_expired_sessions() {
+        use auth_database::{
+            CredentialsRepository,
+            entities::{
+                credentials::{CreateCredentialsDAO, PasswordStorage, Role},
+                sessions::{CreateSessionsDAO, SessionsBy},
+            },
+            traits::EntityRepository,
+        };
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let hour = Duration::from_secs(60 * 60);
+        let (expired, live) = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                let credential = CredentialsRepository::insert(
+                    tx,
+                    CreateCredentialsDAO {
+                        email: "purge@gmail.com".to_string(),
+                        password: "hash".to_string(),
+                        role: Role::User,
+                        password_storage: PasswordStorage::Inline,
+                    },
+                )
+                .await?;
+
+                let mut ids = Vec::new();
+                for expires_at in [Utc::now() - hour, Utc::now() + hour] {
+                    let session = CreateSessionsDAO {
+                        expires_at,
+                        credential_id: credential.id,
+                        ip: None,
+                        user_agent: None,
+                        is_new_device: false,
+                        fingerprint: None,
+                    };
+                    ids.push(SessionsRepository::insert(tx, session).await?.id);
+                }
+
+                Ok::<_, DatabaseError>((ids[0], ids[1]))
+            })
+        })
+        .await
+        .unwrap();
+
+        let purge = App::spawn_session_purge(pool.clone(), Duration::from_millis(10));
+        let exists = |id| {
+            let pool = pool.clone();
+            async move {
+                AuthDatabase::transaction(&pool, |tx| {
+                    Box::pin(
+                        async move { SessionsRepository::exists(tx, SessionsBy::Id(id)).await },
+                    )
+                })
+                .await
+                .unwrap()
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while exists(expired).await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("expired session was not purged");
+        purge.abort();
+
+        assert!(exists(live).await);
+    }
+
+    #[cfg(all(unix, feature = "unit"))]
+    #[tokio::test]
+    async fn sign_up_over_unix_socket() {
+        use auth_database::{
+            CredentialsRepository, entities::credentials::CredentialsBy, traits::EntityRepository,
+        };
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("auth-{}.sock", std::process::id()));
+        // a socket file left behind by a previous run
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let app = App::app(pool.clone()).await;
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn({
+            let path = path.clone();
+            async move {
+                App::serve_unix(
+                    app,
+                    &path,
+                    async move {
+                        stopped.await.ok();
+                    },
+                    Duration::from_secs(1),
+                )
+                .await
             }
+        });
+
+        let mut stream = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match tokio::net::UnixStream::connect(&path).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .expect("server did not listen on the socket");
+
+        let body = serde_json::json!({
+            "email": "unix@mail.com",
+            "password": "asdjfnaksdf87"
+        })
+        .to_string();
+        let request = format!(
+            "POST /sign_up HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        let created = AuthDatabase::transaction(&pool, |tx| {
+            Box::pin(async move {
+                CredentialsRepository::exists(tx, CredentialsBy::Email("unix@mail.com".to_string()))
+                    .await
+            })
+        })
+        .await
+        .unwrap();
+        assert!(created);
+
+        stop.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server kept running after shutdown")
+            .unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "unit")]
+    #[tokio::test]
+    async fn cors_allows_only_the_configured_origins() {
+        use axum::{
+            body::Body,
+            http::{
+                Request,
+                header::{
+                    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_ORIGIN,
+                    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN,
+                },
+            },
         };
+        use tower::ServiceExt;
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let config = AuthConfig {
+            allowed_origins: vec![HeaderValue::from_static("https://app.example.com")],
+            ..AuthConfig::default()
+        };
+        let app = App::router(AppState::new(pool.clone()).with_config(config)).await;
+        let preflight = |origin: &'static str| {
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/sign_in")
+                .header(ORIGIN, origin)
+                .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let allowed = app
+            .clone()
+            .oneshot(preflight("https://app.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert_eq!(
+            allowed.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            allowed
+                .headers()
+                .get(ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                .unwrap(),
+            "true"
+        );
+
+        let other = app
+            .oneshot(preflight("https://evil.example.com"))
+            .await
+            .unwrap();
+        assert!(!other.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let same_origin_only = App::app(pool)
+            .await
+            .oneshot(preflight("https://app.example.com"))
+            .await
+            .unwrap();
+        assert!(
+            !same_origin_only
+                .headers()
+                .contains_key(ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[cfg(feature = "unit")]
+    #[tokio::test]
+    async fn trailing_slash_is_trimmed_unless_strict() {
+        use axum::{
+            body::Body,
+            http::{Request, header::CONTENT_TYPE},
+        };
+        use tower::ServiceExt;
+
+        let sign_up = |uri: &str, email: &str| {
+            Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "email": email, "password": "Ej4a2fkj!yI!Cj9" })
+                        .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let app = App::app(pool.clone()).await;
+
+        let trimmed = app
+            .clone()
+            .oneshot(sign_up("/sign_up/", "slash@gmail.com"))
+            .await
+            .unwrap();
+        assert_eq!(trimmed.status(), StatusCode::OK);
+
+        let query = app
+            .oneshot(sign_up("/sign_up/?source=web", "query@gmail.com"))
+            .await
+            .unwrap();
+        assert_eq!(query.status(), StatusCode::OK);
+
+        let config = AuthConfig {
+            trailing_slash: TrailingSlash::Strict,
+            ..AuthConfig::default()
+        };
+        let strict = App::router(AppState::new(pool).with_config(config)).await;
+
+        let not_found = strict
+            .clone()
+            .oneshot(sign_up("/sign_up/", "strict@gmail.com"))
+            .await
+            .unwrap();
+        assert_eq!(not_found.status(), StatusCode::NOT_FOUND);
+
+        let exact = strict
+            .oneshot(sign_up("/sign_up", "strict@gmail.com"))
+            .await
+            .unwrap();
+        assert_eq!(exact.status(), StatusCode::OK);
     }
 }
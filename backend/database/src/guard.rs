@@ -0,0 +1,54 @@
+/// Passes `sql` through, asserting in debug builds that `UPDATE` and `DELETE`
+/// statements carry a `WHERE` clause so a missing predicate can't touch every row.
+///
+/// Repository queries go through this before reaching sqlx.
+pub fn checked(sql: &str) -> &str {
+    debug_assert!(
+        !is_unbounded_mutation(sql),
+        "UPDATE/DELETE without a WHERE clause: {sql}"
+    );
+    sql
+}
+
+fn is_unbounded_mutation(sql: &str) -> bool {
+    let mut words = sql.split_whitespace();
+    let is_mutation = words.next().is_some_and(|word| {
+        word.eq_ignore_ascii_case("UPDATE") || word.eq_ignore_ascii_case("DELETE")
+    });
+
+    is_mutation && !words.any(|word| word.eq_ignore_ascii_case("WHERE"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded_statements_pass() {
+        let statements = [
+            "SELECT id FROM credentials;",
+            "INSERT INTO sessions (expires_at) VALUES ($1);",
+            "UPDATE sessions SET active = false WHERE id = $1;",
+            "delete from sessions where id = $1;",
+            "UPDATE credentials\n    SET active = false\n    WHERE email = $1;",
+        ];
+
+        for sql in statements {
+            assert_eq!(checked(sql), sql);
+        }
+    }
+
+    #[test]
+    fn unbounded_mutations_are_detected() {
+        assert!(is_unbounded_mutation("UPDATE sessions SET active = false;"));
+        assert!(is_unbounded_mutation("  delete FROM credentials"));
+        assert!(!is_unbounded_mutation("SELECT * FROM sessions;"));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "UPDATE/DELETE without a WHERE clause")]
+    fn unbounded_update_panics_in_debug() {
+        checked("UPDATE sessions SET active = false RETURNING id;");
+    }
+}
@@ -1,2 +1,4 @@
+pub mod guard;
+pub mod metrics;
 pub mod traits;
 pub use async_trait;
@@ -0,0 +1,22 @@
+use std::time::Instant;
+
+/// Histogram of repository operation latency in seconds, labeled by `entity` and `operation`.
+pub const DB_OPERATION_DURATION_SECONDS: &str = "db_operation_duration_seconds";
+
+/// Awaits a repository operation and records how long it took.
+pub async fn observe<F, T>(entity: &'static str, operation: &'static str, future: F) -> T
+where
+    F: Future<Output = T>,
+{
+    let start = Instant::now();
+    let result = future.await;
+
+    metrics::histogram!(
+        DB_OPERATION_DURATION_SECONDS,
+        "entity" => entity,
+        "operation" => operation,
+    )
+    .record(start.elapsed().as_secs_f64());
+
+    result
+}
@@ -1,5 +1,10 @@
 use sqlx::{Database, Error as SqlxError, Pool, Transaction};
-use std::{fmt, pin::Pin};
+use std::{
+    fmt,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+use tracing::Instrument;
 
 #[derive(Debug)]
 pub enum DatabaseError {
@@ -14,6 +19,18 @@ pub enum DatabaseError {
     Unknown(String),
     DatabaseInconsistence(String),
     MigrationFailed(String),
+    UniqueViolation(String),
+    Busy,
+    InvalidConfiguration(String),
+    /// Postgres `serialization_failure`/`deadlock_detected`, the transaction can be retried.
+    SerializationFailure,
+    /// The row changed since it was read, e.g. an optimistic version check failed.
+    Conflict(String),
+    /// The transaction body succeeded but committing it failed, e.g. a deferred constraint.
+    CommitFailed(Box<DatabaseError>),
+    /// A classified driver error that keeps the original one as its
+    /// [`std::error::Error::source`]. Match on [`DatabaseError::kind`] for the classification.
+    Source(Box<DatabaseError>, Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl fmt::Display for DatabaseError {
@@ -32,34 +49,122 @@ impl fmt::Display for DatabaseError {
                 write!(f, "Database Inconsistency: {msg}")
             }
             DatabaseError::MigrationFailed(msg) => write!(f, "Migration Failed: {msg}"),
+            DatabaseError::UniqueViolation(msg) => write!(f, "Unique Violation: {msg}"),
+            DatabaseError::Busy => write!(f, "Database Busy"),
+            DatabaseError::InvalidConfiguration(msg) => write!(f, "Invalid Configuration: {msg}"),
+            DatabaseError::SerializationFailure => write!(f, "Serialization Failure"),
+            DatabaseError::Conflict(msg) => write!(f, "Conflict: {msg}"),
+            DatabaseError::CommitFailed(e) => write!(f, "Commit Failed: {e}"),
+            DatabaseError::Source(kind, _) => kind.fmt(f),
         }
     }
 }
 
 impl std::error::Error for DatabaseError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        // Since DatabaseError doesn't wrap other errors in the current implementation,
-        // we return None. If you add error chaining later, update this to return the source.
-        None
+        match self {
+            DatabaseError::CommitFailed(e) => Some(e.as_ref()),
+            DatabaseError::Source(_, source) => Some(source.as_ref()),
+            _ => None,
+        }
     }
 }
 
 impl From<SqlxError> for DatabaseError {
     fn from(value: SqlxError) -> Self {
-        match value {
-            SqlxError::ColumnNotFound(column_name) => Self::ColumnNotFound(column_name),
+        let kind = match &value {
+            SqlxError::ColumnNotFound(column_name) => {
+                return Self::ColumnNotFound(column_name.clone());
+            }
+            SqlxError::PoolTimedOut | SqlxError::PoolClosed => return Self::ConnectionNotAvailable,
+            SqlxError::RowNotFound => return Self::NotFound("Row Not Found".to_string()),
+            SqlxError::TypeNotFound { type_name } => {
+                return Self::DatabaseInconsistence(format!("TypeNotFound {type_name}"));
+            }
             SqlxError::Io(_) | SqlxError::Tls(_) => Self::CommunicationError,
-            SqlxError::PoolTimedOut => Self::ConnectionNotAvailable,
+            SqlxError::Database(e) if e.is_unique_violation() => {
+                Self::UniqueViolation(e.to_string())
+            }
+            SqlxError::Database(e) if is_serialization_failure(e.code().as_deref()) => {
+                Self::SerializationFailure
+            }
+            SqlxError::Database(e) if is_busy(e.code().as_deref()) => Self::Busy,
             SqlxError::Database(e) => Self::QueryFailed(e.to_string()),
             SqlxError::Protocol(_) => Self::ProtocolNotSupported,
-            SqlxError::TypeNotFound { type_name } => {
-                Self::DatabaseInconsistence(format!("TypeNotFound {type_name}"))
-            }
             _ => Self::ConnectionFailed,
+        };
+
+        Self::Source(Box::new(kind), Box::new(value))
+    }
+}
+
+impl DatabaseError {
+    /// The classification of the error, looking through [`DatabaseError::Source`].
+    pub fn kind(&self) -> &DatabaseError {
+        match self {
+            DatabaseError::Source(kind, _) => kind.kind(),
+            _ => self,
+        }
+    }
+
+    /// Whether running the transaction again may succeed.
+    pub fn is_serialization_failure(&self) -> bool {
+        match self.kind() {
+            DatabaseError::SerializationFailure => true,
+            DatabaseError::CommitFailed(e) => e.is_serialization_failure(),
+            _ => false,
+        }
+    }
+
+    /// Whether the failure is transient, a serialization failure or a lost connection,
+    /// rather than a logical error that would fail the same way again.
+    ///
+    /// A commit that failed on a lost connection may still have been applied, so only
+    /// its serialization failures count.
+    pub fn is_retryable(&self) -> bool {
+        match self.kind() {
+            DatabaseError::SerializationFailure
+            | DatabaseError::ConnectionNotAvailable
+            | DatabaseError::CommunicationError => true,
+            DatabaseError::CommitFailed(e) => e.is_serialization_failure(),
+            _ => false,
         }
     }
 }
 
+/// Wait before the first retry of [`BaseDatabase::transaction_with_retry`], doubled for
+/// every following one.
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// Upper bound of the wait between two attempts.
+pub const RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
+
+fn retry_delay(retry: u32) -> Duration {
+    2u32.checked_pow(retry)
+        .and_then(|factor| RETRY_BASE_DELAY.checked_mul(factor))
+        .map_or(RETRY_MAX_DELAY, |delay| delay.min(RETRY_MAX_DELAY))
+}
+
+/// Postgres `serialization_failure` and `deadlock_detected`.
+fn is_serialization_failure(code: Option<&str>) -> bool {
+    matches!(code, Some("40001" | "40P01"))
+}
+
+/// SQLite `SQLITE_BUSY`/`SQLITE_LOCKED` (and the busy snapshot extended code) and
+/// Postgres `lock_not_available`.
+fn is_busy(code: Option<&str>) -> bool {
+    matches!(code, Some("5" | "6" | "517" | "55P03"))
+}
+
+/// Window of rows returned by [`EntityRepository::get_page`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pagination {
+    /// Maximum number of rows to return.
+    pub limit: i64,
+    /// Number of rows to skip.
+    pub offset: i64,
+}
+
 #[async_trait::async_trait]
 pub trait EntityRepository {
     type Db: Database;
@@ -89,10 +194,24 @@ pub trait EntityRepository {
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryOne,
     ) -> Result<Option<Self::Entity>, DatabaseError>;
+    /// Returns every row matching `key`, newest first: ordered by `created_at DESC, id DESC`
+    /// so rows sharing a timestamp still come back in the same order on every call.
     async fn get_all(
         tx: &mut Transaction<'_, Self::Db>,
         key: Self::QueryMany,
     ) -> Result<Vec<Self::Entity>, DatabaseError>;
+    /// Like [`EntityRepository::get_all`], returning only the rows inside `page` of that
+    /// ordering.
+    async fn get_page(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+        page: Pagination,
+    ) -> Result<Vec<Self::Entity>, DatabaseError>;
+    /// Number of rows matching `key`, without fetching them.
+    async fn count(
+        tx: &mut Transaction<'_, Self::Db>,
+        key: Self::QueryMany,
+    ) -> Result<i64, DatabaseError>;
 
     async fn exists(
         tx: &mut Transaction<'_, Self::Db>,
@@ -108,21 +227,174 @@ where
     async fn transaction<F, T, E>(pool: &Pool<Db>, f: F) -> Result<T, E>
     where
         T: Send,
-        E: From<DatabaseError>,
+        E: From<DatabaseError> + Send,
+        F: for<'a> FnOnce(
+                &'a mut Transaction<'_, Db>,
+            ) -> Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>
+            + Send,
+    {
+        Self::named_transaction(pool, "transaction", f).await
+    }
+
+    /// Like [`BaseDatabase::transaction`], inside a `db.transaction` span labeled with
+    /// `operation` that records how long `BEGIN` and `COMMIT` took and whether the
+    /// transaction was rolled back.
+    async fn named_transaction<F, T, E>(
+        pool: &Pool<Db>,
+        operation: &'static str,
+        f: F,
+    ) -> Result<T, E>
+    where
+        T: Send,
+        E: From<DatabaseError> + Send,
         F: for<'a> FnOnce(
                 &'a mut Transaction<'_, Db>,
             ) -> Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'a>>
             + Send,
     {
-        let mut tx = pool
-            .begin()
-            .await
-            .map_err(|e| E::from(DatabaseError::from(e)))?;
-        let result = f(&mut tx).await?;
-
-        tx.commit()
-            .await
-            .map_err(|e| E::from(DatabaseError::from(e)))?;
-        Ok(result)
+        let span = tracing::debug_span!(
+            "db.transaction",
+            operation,
+            begin_seconds = tracing::field::Empty,
+            commit_seconds = tracing::field::Empty,
+            rolled_back = tracing::field::Empty,
+        );
+
+        async {
+            let span = tracing::Span::current();
+
+            let start = Instant::now();
+            let mut tx = Self::begin(pool).await.map_err(E::from)?;
+            span.record("begin_seconds", start.elapsed().as_secs_f64());
+
+            let result = match f(&mut tx).await {
+                Ok(result) => result,
+                Err(e) => {
+                    span.record("rolled_back", true);
+                    // The closure's error wins, a failed rollback only means the
+                    // connection is discarded instead of returned to the pool.
+                    let _ = Self::rollback(tx).await;
+                    return Err(e);
+                }
+            };
+
+            let start = Instant::now();
+            let committed = Self::commit(tx).await;
+            span.record("commit_seconds", start.elapsed().as_secs_f64());
+            span.record("rolled_back", committed.is_err());
+
+            committed.map_err(|e| E::from(DatabaseError::CommitFailed(Box::new(e))))?;
+            Ok(result)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Like [`BaseDatabase::transaction`], but runs `f` again in a fresh transaction, up
+    /// to `retries` more times, while it or the commit fails with a
+    /// [retryable](DatabaseError::is_retryable) error. Waits [`RETRY_BASE_DELAY`] before
+    /// the first retry and twice as long before each following one.
+    async fn transaction_with_retry<F, T>(
+        pool: &Pool<Db>,
+        retries: u32,
+        f: F,
+    ) -> Result<T, DatabaseError>
+    where
+        T: Send,
+        F: for<'a> Fn(
+                &'a mut Transaction<'_, Db>,
+            )
+                -> Pin<Box<dyn Future<Output = Result<T, DatabaseError>> + Send + 'a>>
+            + Send
+            + Sync,
+    {
+        let mut retry = 0;
+        loop {
+            match Self::transaction(pool, &f).await {
+                Err(e) if retry < retries && e.is_retryable() => {
+                    let delay = retry_delay(retry);
+                    tracing::warn!(error = %e, retry = retry + 1, ?delay, "retrying transaction");
+                    tokio::time::sleep(delay).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Begins a transaction that the caller drives imperatively.
+    ///
+    /// The transaction is rolled back when dropped unless [`BaseDatabase::commit`] is called.
+    async fn begin(pool: &Pool<Db>) -> Result<Transaction<'static, Db>, DatabaseError> {
+        pool.begin().await.map_err(DatabaseError::from)
+    }
+
+    async fn commit(tx: Transaction<'_, Db>) -> Result<(), DatabaseError> {
+        tx.commit().await.map_err(DatabaseError::from)
+    }
+
+    async fn rollback(tx: Transaction<'_, Db>) -> Result<(), DatabaseError> {
+        tx.rollback().await.map_err(DatabaseError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn sqlx_errors_keep_their_source() {
+        let error = DatabaseError::from(SqlxError::Protocol("unexpected message".to_string()));
+
+        assert!(matches!(error.kind(), DatabaseError::ProtocolNotSupported));
+        assert_eq!(error.to_string(), "Protocol Not Supported");
+        let source = error.source().expect("the sqlx error is kept");
+        assert!(matches!(
+            source.downcast_ref::<SqlxError>(),
+            Some(SqlxError::Protocol(message)) if message == "unexpected message"
+        ));
+
+        let error = DatabaseError::from(SqlxError::Io(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset by peer",
+        )));
+        assert!(matches!(error.kind(), DatabaseError::CommunicationError));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn only_transient_errors_are_retryable() {
+        assert!(DatabaseError::from(SqlxError::PoolTimedOut).is_retryable());
+        assert!(DatabaseError::CommunicationError.is_retryable());
+        assert!(DatabaseError::SerializationFailure.is_retryable());
+        assert!(
+            DatabaseError::CommitFailed(Box::new(DatabaseError::SerializationFailure))
+                .is_retryable()
+        );
+
+        assert!(
+            !DatabaseError::CommitFailed(Box::new(DatabaseError::CommunicationError))
+                .is_retryable()
+        );
+        assert!(!DatabaseError::QueryFailed("syntax error".to_string()).is_retryable());
+        assert!(!DatabaseError::Busy.is_retryable());
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_the_max() {
+        assert_eq!(retry_delay(0), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(2), RETRY_BASE_DELAY * 4);
+        assert_eq!(retry_delay(10), RETRY_MAX_DELAY);
+        assert_eq!(retry_delay(u32::MAX), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn self_describing_sqlx_errors_are_not_wrapped() {
+        let error = DatabaseError::from(SqlxError::RowNotFound);
+
+        assert!(matches!(error, DatabaseError::NotFound(_)));
+        assert!(error.source().is_none());
     }
 }
@@ -0,0 +1,568 @@
+//! Session lifecycle over the auth database, shared by every service that signs
+//! requests in with the session cookie.
+//!
+//! Nothing here knows about HTTP: callers parse the cookie into a [`SessionSecret`] and
+//! run these functions inside their own transaction.
+
+use auth_database::{
+    CredentialsRepository, RefreshTokensRepository, SessionsRepository,
+    entities::{
+        credentials::{CredentialsBy, CredentialsDAO},
+        refresh_tokens::RefreshTokenRevocation,
+        sessions::{ActiveSessions, CreateSessionsDAO, SessionsBy, SessionsDAO, SessionsWhere},
+    },
+    traits::{DatabaseError, EntityRepository},
+};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    Transaction,
+    types::{Uuid, chrono::Utc},
+};
+use std::{fmt, time::Duration};
+
+/// Characters of the session id kept when it is formatted.
+const VISIBLE_PREFIX: usize = 8;
+
+/// Session id carried by the session cookie.
+///
+/// The id is a bearer secret, so `Debug` and `Display` only show its first characters
+/// and logging it is safe. The full value is only reachable through [`Self::expose`],
+/// meant for writing the cookie and querying the database.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SessionSecret(Uuid);
+
+/// Length of a hyphenated uuid, the only form session ids are issued in.
+const HYPHENATED_LEN: usize = 36;
+
+impl SessionSecret {
+    /// Parses the hyphenated form written to the cookie. Anything else, however long, is
+    /// rejected by a length and charset check before it is parsed or reaches the database.
+    pub fn parse(value: &str) -> Option<Self> {
+        let is_uuid_shaped = value.len() == HYPHENATED_LEN
+            && value.bytes().all(|b| b.is_ascii_hexdigit() || b == b'-');
+        if !is_uuid_shaped {
+            return None;
+        }
+
+        Uuid::parse_str(value).ok().map(SessionSecret)
+    }
+
+    pub fn expose(&self) -> Uuid {
+        self.0
+    }
+
+    /// Stable identifier of the session that is safe to show, e.g. in a list of devices.
+    ///
+    /// It is derived from the secret with SHA-256, so it can't be turned back into a
+    /// cookie, and only identifies a session among those of its credential.
+    pub fn public_id(&self) -> String {
+        let digest = Sha256::digest(self.0.as_bytes());
+        hex::encode(&digest[..PUBLIC_ID_BYTES])
+    }
+}
+
+/// Bytes of the SHA-256 digest kept in [`SessionSecret::public_id`].
+const PUBLIC_ID_BYTES: usize = 16;
+
+impl From<Uuid> for SessionSecret {
+    fn from(value: Uuid) -> Self {
+        SessionSecret(value)
+    }
+}
+
+impl fmt::Display for SessionSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.0.simple().to_string();
+        write!(f, "{}...", &id[..VISIBLE_PREFIX])
+    }
+}
+
+impl fmt::Debug for SessionSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SessionSecret({self})")
+    }
+}
+
+/// Client a session is started for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NewSession {
+    pub credential_id: Uuid,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Client fingerprint to bind the session to, see [`find_valid_session`].
+    pub fingerprint: Option<String>,
+}
+
+/// Starts a session for `new` lasting `ttl`, flagging it as a new device when the
+/// credential never had a session from the same ip.
+pub async fn create<DB>(
+    tx: &mut Transaction<'_, DB>,
+    new: NewSession,
+    ttl: Duration,
+) -> Result<SessionsDAO, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    let is_new_device = match &new.ip {
+        Some(ip) => {
+            !SessionsRepository::exists(tx, SessionsBy::CredentialIp(new.credential_id, ip.clone()))
+                .await?
+        }
+        None => false,
+    };
+
+    SessionsRepository::insert(
+        tx,
+        CreateSessionsDAO {
+            credential_id: new.credential_id,
+            expires_at: Utc::now() + ttl,
+            ip: new.ip,
+            user_agent: new.user_agent,
+            is_new_device,
+            fingerprint: new.fingerprint,
+        },
+    )
+    .await
+}
+
+/// Loads a session only if it is still active and not expired.
+///
+/// When `fingerprint` is given, a session bound to a different one is treated as
+/// invalid too, a stolen cookie replayed from another client is useless. Sessions
+/// started unbound are accepted from any client.
+pub async fn find_valid_session<DB>(
+    tx: &mut Transaction<'_, DB>,
+    id: Uuid,
+    fingerprint: Option<&str>,
+) -> Result<Option<SessionsDAO>, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    let session = SessionsRepository::try_get(tx, SessionsBy::Id(id)).await?;
+
+    Ok(session.filter(|session| {
+        session.active && session.expires_at > Utc::now() && is_bound_to(session, fingerprint)
+    }))
+}
+
+fn is_bound_to(session: &SessionsDAO, fingerprint: Option<&str>) -> bool {
+    match (session.fingerprint.as_deref(), fingerprint) {
+        (Some(bound), Some(fingerprint)) => bound == fingerprint,
+        _ => true,
+    }
+}
+
+/// Loads the active credential behind a valid session, see [`find_valid_session`].
+pub async fn find_session_credential<DB>(
+    tx: &mut Transaction<'_, DB>,
+    session_id: Uuid,
+    fingerprint: Option<&str>,
+) -> Result<Option<CredentialsDAO>, DatabaseError>
+where
+    DB: sqlx::Database,
+    CredentialsRepository: EntityRepository<Db = DB>,
+    SessionsRepository: EntityRepository<Db = DB>,
+{
+    let Some(session) = find_valid_session(tx, session_id, fingerprint).await? else {
+        return Ok(None);
+    };
+
+    let credential =
+        CredentialsRepository::try_get(tx, CredentialsBy::Id(session.credential_id)).await?;
+
+    Ok(credential.filter(|credential| credential.active))
+}
+
+/// Active, unexpired sessions of `credential_id`, newest first.
+pub async fn list_active<DB>(
+    tx: &mut Transaction<'_, DB>,
+    credential_id: Uuid,
+) -> Result<Vec<SessionsDAO>, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+{
+    let now = Utc::now();
+    let sessions =
+        SessionsRepository::get_all(tx, SessionsWhere::CredentialId(credential_id)).await?;
+
+    Ok(sessions
+        .into_iter()
+        .filter(|session| session.active && session.expires_at > now)
+        .collect())
+}
+
+/// Deactivates the active session of `credential_id` whose [`SessionSecret::public_id`]
+/// is `public_id`. Returns `None` when there is none, including when the session
+/// belongs to another credential.
+pub async fn revoke_by_public_id<DB>(
+    tx: &mut Transaction<'_, DB>,
+    credential_id: Uuid,
+    public_id: &str,
+) -> Result<Option<SessionsDAO>, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB, QueryMany = SessionsWhere>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    let owned = list_active(tx, credential_id)
+        .await?
+        .into_iter()
+        .find(|session| SessionSecret::from(session.id).public_id() == public_id);
+
+    match owned {
+        Some(session) => revoke(tx, SessionSecret::from(session.id)).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Deactivates the session behind `secret`, and the refresh tokens issued with it,
+/// failing with [`DatabaseError::NotFound`] when there is none.
+pub async fn revoke<DB>(
+    tx: &mut Transaction<'_, DB>,
+    secret: SessionSecret,
+) -> Result<SessionsDAO, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: EntityRepository<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    let session = SessionsRepository::delete(tx, SessionsBy::Id(secret.expose())).await?;
+    RefreshTokensRepository::revoke_by_session(tx, session.id).await?;
+    Ok(session)
+}
+
+/// Deactivates every active session of `credential_id` and its refresh tokens,
+/// returning how many sessions were.
+pub async fn revoke_all<DB>(
+    tx: &mut Transaction<'_, DB>,
+    credential_id: Uuid,
+) -> Result<u64, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: ActiveSessions<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    RefreshTokensRepository::revoke_all(tx, credential_id).await?;
+    SessionsRepository::revoke_all(tx, credential_id).await
+}
+
+/// Deactivates every active session of `credential_id` except `keep`, and their refresh
+/// tokens, returning how many sessions were. Used to sign other devices out while
+/// keeping the caller's session.
+pub async fn revoke_others<DB>(
+    tx: &mut Transaction<'_, DB>,
+    credential_id: Uuid,
+    keep: SessionSecret,
+) -> Result<u64, DatabaseError>
+where
+    DB: sqlx::Database,
+    SessionsRepository: ActiveSessions<Db = DB>,
+    RefreshTokensRepository: RefreshTokenRevocation<Db = DB>,
+{
+    RefreshTokensRepository::revoke_others(tx, credential_id, keep.expose()).await?;
+    SessionsRepository::revoke_others(tx, credential_id, keep.expose()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_secret_is_redacted() {
+        let id = Uuid::new_v4();
+        let secret = SessionSecret::from(id);
+        let prefix = &id.simple().to_string()[..VISIBLE_PREFIX];
+
+        for formatted in [secret.to_string(), format!("{secret:?}")] {
+            assert!(formatted.contains(&format!("{prefix}...")), "{formatted}");
+            assert!(!formatted.contains(&id.to_string()), "{formatted}");
+            assert!(!formatted.contains(&id.simple().to_string()), "{formatted}");
+        }
+        assert_eq!(secret.expose(), id);
+    }
+
+    #[test]
+    fn public_id_is_stable_and_not_the_secret() {
+        let id = Uuid::new_v4();
+        let public_id = SessionSecret::from(id).public_id();
+
+        assert_eq!(public_id.len(), PUBLIC_ID_BYTES * 2);
+        assert_eq!(SessionSecret::from(id).public_id(), public_id);
+        assert_ne!(SessionSecret::from(Uuid::new_v4()).public_id(), public_id);
+        assert!(!public_id.contains(&id.simple().to_string()[..VISIBLE_PREFIX]));
+    }
+
+    #[test]
+    fn session_secret_parse() {
+        let id = Uuid::new_v4();
+
+        assert_eq!(
+            SessionSecret::parse(&id.to_string()),
+            Some(SessionSecret::from(id))
+        );
+        assert_eq!(SessionSecret::parse("not-a-uuid"), None);
+    }
+
+    #[test]
+    fn session_secret_parse_rejects_other_shapes() {
+        let id = Uuid::new_v4();
+
+        for value in [
+            id.simple().to_string(),
+            id.braced().to_string(),
+            id.urn().to_string(),
+            "zzzzzzzz-zzzz-zzzz-zzzz-zzzzzzzzzzzz".to_string(),
+            format!("{id}{id}"),
+            "a".repeat(10 * 1024),
+        ] {
+            assert_eq!(SessionSecret::parse(&value), None, "{value:.64}");
+        }
+    }
+}
+
+#[cfg(feature = "unit")]
+#[cfg(test)]
+mod unit_tests {
+    use super::*;
+    use auth_database::{
+        AuthDatabase, DB,
+        entities::credentials::{CreateCredentialsDAO, PasswordStorage, Role},
+        traits::BaseDatabase,
+    };
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    async fn credential(tx: &mut Transaction<'_, DB>, email: &str) -> Uuid {
+        CredentialsRepository::insert(
+            tx,
+            CreateCredentialsDAO {
+                email: email.to_string(),
+                password: "hash".to_string(),
+                role: Role::User,
+                password_storage: PasswordStorage::Inline,
+            },
+        )
+        .await
+        .unwrap()
+        .id
+    }
+
+    fn from_ip(credential_id: Uuid, ip: &str) -> NewSession {
+        NewSession {
+            credential_id,
+            ip: Some(ip.to_string()),
+            ..NewSession::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn created_session_is_valid() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential_id = credential(&mut tx, "create@gmail.com").await;
+
+        let session = create(&mut tx, from_ip(credential_id, "10.0.0.1"), HOUR)
+            .await
+            .unwrap();
+        assert!(session.active);
+        assert!(session.expires_at > Utc::now() + HOUR - Duration::from_secs(60));
+
+        let valid = find_valid_session(&mut tx, session.id, None).await.unwrap();
+        assert_eq!(valid.map(|session| session.id), Some(session.id));
+
+        let owner = find_session_credential(&mut tx, session.id, None)
+            .await
+            .unwrap();
+        assert_eq!(owner.map(|credential| credential.id), Some(credential_id));
+    }
+
+    #[tokio::test]
+    async fn create_flags_new_devices() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential_id = credential(&mut tx, "device@gmail.com").await;
+
+        for (ip, is_new_device) in [("10.0.0.1", true), ("10.0.0.1", false), ("10.0.0.2", true)] {
+            let session = create(&mut tx, from_ip(credential_id, ip), HOUR)
+                .await
+                .unwrap();
+            assert_eq!(session.is_new_device, is_new_device, "{ip}");
+        }
+
+        let without_ip = NewSession {
+            credential_id,
+            ..NewSession::default()
+        };
+        let session = create(&mut tx, without_ip, HOUR).await.unwrap();
+        assert!(!session.is_new_device);
+    }
+
+    #[tokio::test]
+    async fn expired_session_is_not_valid() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential_id = credential(&mut tx, "expire@gmail.com").await;
+
+        let session = create(&mut tx, from_ip(credential_id, "10.0.0.1"), Duration::ZERO)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            find_valid_session(&mut tx, session.id, None).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            find_session_credential(&mut tx, session.id, None)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn bound_session_is_only_valid_for_its_fingerprint() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential_id = credential(&mut tx, "bound@gmail.com").await;
+
+        let bound = NewSession {
+            fingerprint: Some("laptop".to_string()),
+            ..from_ip(credential_id, "10.0.0.1")
+        };
+        let bound = create(&mut tx, bound, HOUR).await.unwrap();
+        let unbound = create(&mut tx, from_ip(credential_id, "10.0.0.1"), HOUR)
+            .await
+            .unwrap();
+
+        for (id, fingerprint, valid) in [
+            (bound.id, Some("laptop"), true),
+            (bound.id, None, true),
+            (bound.id, Some("phone"), false),
+            // Sessions started before binding was enabled stay usable.
+            (unbound.id, Some("phone"), true),
+        ] {
+            let session = find_valid_session(&mut tx, id, fingerprint).await.unwrap();
+            assert_eq!(session.is_some(), valid, "{fingerprint:?}");
+        }
+
+        assert_eq!(
+            find_session_credential(&mut tx, bound.id, Some("phone"))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn revoked_sessions_are_not_valid() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let credential_id = credential(&mut tx, "revoke@gmail.com").await;
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            let session = create(&mut tx, from_ip(credential_id, "10.0.0.1"), HOUR)
+                .await
+                .unwrap();
+            sessions.push(session.id);
+        }
+
+        let revoked = revoke(&mut tx, SessionSecret::from(sessions[0]))
+            .await
+            .unwrap();
+        assert_eq!(revoked.id, sessions[0]);
+        assert_eq!(
+            find_valid_session(&mut tx, sessions[0], None)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            find_valid_session(&mut tx, sessions[1], None)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        assert_eq!(revoke_all(&mut tx, credential_id).await.unwrap(), 2);
+        for id in sessions {
+            assert_eq!(find_valid_session(&mut tx, id, None).await.unwrap(), None);
+        }
+
+        let kept = create(&mut tx, from_ip(credential_id, "10.0.0.1"), HOUR)
+            .await
+            .unwrap();
+        let other = create(&mut tx, from_ip(credential_id, "10.0.0.1"), HOUR)
+            .await
+            .unwrap();
+        assert_eq!(
+            revoke_others(&mut tx, credential_id, SessionSecret::from(kept.id))
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(
+            find_valid_session(&mut tx, kept.id, None)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            find_valid_session(&mut tx, other.id, None).await.unwrap(),
+            None
+        );
+
+        let unknown = revoke(&mut tx, SessionSecret::from(Uuid::new_v4()))
+            .await
+            .unwrap_err();
+        assert!(matches!(unknown.kind(), DatabaseError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn revoke_by_public_id_checks_ownership() {
+        let pool = AuthDatabase::connect(":memory:").await.unwrap();
+        let mut tx = AuthDatabase::begin(&pool).await.unwrap();
+        let owner = credential(&mut tx, "owner@gmail.com").await;
+        let other = credential(&mut tx, "other@gmail.com").await;
+        let kept = create(&mut tx, from_ip(owner, "10.0.0.1"), HOUR)
+            .await
+            .unwrap();
+        let revoked = create(&mut tx, from_ip(owner, "10.0.0.2"), HOUR)
+            .await
+            .unwrap();
+        let public_id = SessionSecret::from(revoked.id).public_id();
+
+        let listed: Vec<_> = list_active(&mut tx, owner)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|session| session.id)
+            .collect();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.contains(&kept.id) && listed.contains(&revoked.id));
+
+        assert_eq!(
+            revoke_by_public_id(&mut tx, other, &public_id)
+                .await
+                .unwrap(),
+            None
+        );
+        let session = revoke_by_public_id(&mut tx, owner, &public_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.id, revoked.id);
+        assert_eq!(
+            revoke_by_public_id(&mut tx, owner, &public_id)
+                .await
+                .unwrap(),
+            None
+        );
+
+        let listed = list_active(&mut tx, owner).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, kept.id);
+    }
+}